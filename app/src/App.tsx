@@ -1,16 +1,25 @@
 import { useEffect, useState, useCallback } from 'react';
-import { api, type DepositInfo } from './api';
+import { api, sessionAddress, type DepositInfo } from './api';
 import { ConnectWallet, DepositsTable, CreateDepositButton, RouteButton } from './components';
 import MinimalLayout from './components/ui/MinimalHero';
 
 const POLL_INTERVAL = 15000; // 15 seconds
 
+/** Routing needs an operator's API key, so it is only offered at /#operator */
+const isOperatorView = () => window.location.hash === '#operator';
+
 export default function App() {
   const [deposits, setDeposits] = useState<DepositInfo[]>([]);
   const [isLoading, setIsLoading] = useState(true);
   const [lastUpdated, setLastUpdated] = useState<Date | null>(null);
 
   const fetchDeposits = useCallback(async () => {
+    // Deposits are listed per signed-in wallet
+    if (!sessionAddress()) {
+      setDeposits([]);
+      setIsLoading(false);
+      return;
+    }
     try {
       const response = await api.listDeposits();
       setDeposits(response.deposits);
@@ -32,7 +41,7 @@ export default function App() {
 
   return (
     <MinimalLayout
-      headerRight={<ConnectWallet />}
+      headerRight={<ConnectWallet onSessionChange={fetchDeposits} />}
       heroContent={
         <div className="max-w-3xl">
           <div className="inline-flex items-center rounded-full border border-neutral-800 bg-neutral-900/50 backdrop-blur px-3 py-1 text-xs font-medium text-neutral-400 mb-6">
@@ -51,9 +60,11 @@ export default function App() {
             <div className="flex-1">
               <CreateDepositButton onSuccess={fetchDeposits} />
             </div>
-            <div className="flex-1">
-              <RouteButton onSuccess={fetchDeposits} />
-            </div>
+            {isOperatorView() && (
+              <div className="flex-1">
+                <RouteButton onSuccess={fetchDeposits} />
+              </div>
+            )}
           </div>
         </div>
       }
@@ -1,12 +1,17 @@
 import { describe, it, expect } from 'vitest';
+import { http, HttpResponse } from 'msw';
 import { api } from './client';
+import { server } from '../test/mocks/server';
 import {
   mockHealth,
   mockDeposits,
   mockCreateDeposit,
-  mockRouteResponse,
+  mockJob,
+  mockFinishedJob,
 } from '../test/mocks/handlers';
 
+const API_URL = import.meta.env.VITE_API_URL || 'http://localhost:3001';
+
 describe('API Client', () => {
   describe('health', () => {
     it('should fetch health status', async () => {
@@ -19,6 +24,20 @@ describe('API Client', () => {
   });
 
   describe('listDeposits', () => {
+    it('should send no credentials without a wallet session', async () => {
+      let authorization: string | null = 'unset';
+      server.use(
+        http.get(`${API_URL}/deposits`, ({ request }) => {
+          authorization = request.headers.get('Authorization');
+          return HttpResponse.json(mockDeposits);
+        })
+      );
+
+      await api.listDeposits();
+
+      expect(authorization).toBeNull();
+    });
+
     it('should fetch all deposits', async () => {
       const result = await api.listDeposits();
 
@@ -65,24 +84,28 @@ describe('API Client', () => {
   });
 
   describe('routeDeposits', () => {
-    it('should route all funded deposits', async () => {
-      const result = await api.routeDeposits();
+    it('should start a routing job', async () => {
+      const result = await api.routeDeposits('rk_operator');
 
-      expect(result).toEqual(mockRouteResponse);
-      expect(result.checked).toBe(3);
-      expect(result.funded).toBe(1);
-      expect(result.deployed).toBe(1);
-      expect(result.routed).toBe(1);
+      expect(result).toEqual(mockJob);
+      expect(result.status).toBe('running');
+      expect(result.result).toBeUndefined();
     });
+  });
+
+  describe('getJob', () => {
+    it('should fetch a finished job with its result', async () => {
+      const result = await api.getJob(mockJob.id, 'rk_operator');
 
-    it('should return transaction hashes', async () => {
-      const result = await api.routeDeposits();
+      expect(result).toEqual(mockFinishedJob);
+      expect(result.status).toBe('completed');
+      expect(result.routed).toBe(1);
+      expect(result.result?.deploy_tx_hash).toBeDefined();
+      expect(result.result?.route_tx_hashes).toHaveLength(1);
+    });
 
-      expect(result.deploy_tx_hash).toBeDefined();
-      expect(result.route_tx_hashes).toHaveLength(1);
-      expect(result.route_tx_hashes[0]).toHaveProperty('proxy_address');
-      expect(result.route_tx_hashes[0]).toHaveProperty('tx_hash');
-      expect(result.route_tx_hashes[0]).toHaveProperty('amount_wei');
+    it('should reject unknown jobs', async () => {
+      await expect(api.getJob(999, 'rk_operator')).rejects.toMatchObject({ status: 404 });
     });
   });
 });
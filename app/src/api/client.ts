@@ -3,12 +3,48 @@ import type {
   ListDepositsResponse,
   CreateDepositRequest,
   CreateDepositResponse,
-  RouteResponse,
+  RoutingJobInfo,
   DepositInfo,
+  NonceResponse,
+  SiweVerifyRequest,
+  SessionResponse,
 } from './types';
 
 const API_URL = import.meta.env.VITE_API_URL || 'http://localhost:3001';
 
+const SESSION_STORAGE_KEY = 'radhat.session';
+
+interface StoredSession {
+  token: string;
+  address: string;
+}
+
+function loadSession(): StoredSession | null {
+  try {
+    const raw = globalThis.localStorage?.getItem(SESSION_STORAGE_KEY);
+    return raw ? (JSON.parse(raw) as StoredSession) : null;
+  } catch {
+    return null;
+  }
+}
+
+let session: StoredSession | null = loadSession();
+
+/** Remember (or forget) the wallet session used for deposit endpoints */
+export function setSession(next: StoredSession | null) {
+  session = next;
+  if (next) {
+    globalThis.localStorage?.setItem(SESSION_STORAGE_KEY, JSON.stringify(next));
+  } else {
+    globalThis.localStorage?.removeItem(SESSION_STORAGE_KEY);
+  }
+}
+
+/** Address of the signed-in wallet, if any */
+export function sessionAddress(): string | null {
+  return session?.address ?? null;
+}
+
 class ApiError extends Error {
   constructor(
     public status: number,
@@ -19,11 +55,17 @@ class ApiError extends Error {
   }
 }
 
-async function fetchJson<T>(url: string, options?: RequestInit): Promise<T> {
+/**
+ * The bundle is public, so it carries no credentials: deposit endpoints use
+ * the wallet session (scoped to that wallet), routing an API key an operator
+ * types in at runtime.
+ */
+async function fetchJson<T>(url: string, options?: RequestInit, token?: string): Promise<T> {
   const response = await fetch(url, {
     ...options,
     headers: {
       'Content-Type': 'application/json',
+      ...(token ? { Authorization: `Bearer ${token}` } : {}),
       ...options?.headers,
     },
   });
@@ -33,6 +75,9 @@ async function fetchJson<T>(url: string, options?: RequestInit): Promise<T> {
     throw new ApiError(response.status, text || `HTTP ${response.status}`);
   }
 
+  if (response.status === 204) {
+    return undefined as T;
+  }
   return response.json();
 }
 
@@ -41,24 +86,45 @@ export const api = {
   health: (): Promise<HealthResponse> => fetchJson(`${API_URL}/health`),
 
   /** List all deposits */
-  listDeposits: (): Promise<ListDepositsResponse> => fetchJson(`${API_URL}/deposits`),
+  listDeposits: (): Promise<ListDepositsResponse> =>
+    fetchJson(`${API_URL}/deposits`, undefined, session?.token),
 
   /** Get single deposit by address */
   getDeposit: (address: string): Promise<DepositInfo> =>
-    fetchJson(`${API_URL}/deposits/${address}`),
+    fetchJson(`${API_URL}/deposits/${address}`, undefined, session?.token),
 
   /** Create new deposit address */
   createDeposit: (data: CreateDepositRequest): Promise<CreateDepositResponse> =>
-    fetchJson(`${API_URL}/deposit`, {
+    fetchJson(
+      `${API_URL}/deposit`,
+      {
+        method: 'POST',
+        body: JSON.stringify(data),
+      },
+      session?.token
+    ),
+
+  /** Start a routing job with an operator's `route` key; follow it with getJob */
+  routeDeposits: (apiKey: string): Promise<RoutingJobInfo> =>
+    fetchJson(`${API_URL}/router`, { method: 'POST' }, apiKey),
+
+  /** Get a routing job with its progress so far */
+  getJob: (id: number, apiKey: string): Promise<RoutingJobInfo> =>
+    fetchJson(`${API_URL}/jobs/${id}`, undefined, apiKey),
+
+  /** Nonce for a Sign-In with Ethereum message */
+  siweNonce: (): Promise<NonceResponse> => fetchJson(`${API_URL}/auth/nonce`),
+
+  /** Exchange a signed SIWE message for a session token */
+  siweVerify: (data: SiweVerifyRequest): Promise<SessionResponse> =>
+    fetchJson(`${API_URL}/auth/verify`, {
       method: 'POST',
       body: JSON.stringify(data),
     }),
 
-  /** Route all funded deposits to treasury */
-  routeDeposits: (): Promise<RouteResponse> =>
-    fetchJson(`${API_URL}/router`, {
-      method: 'POST',
-    }),
+  /** End the current wallet session */
+  logout: (): Promise<void> =>
+    fetchJson(`${API_URL}/auth/logout`, { method: 'POST' }, session?.token),
 };
 
 export { ApiError };
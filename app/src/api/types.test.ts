@@ -4,12 +4,20 @@ import type { DepositStatus } from './types';
 describe('API Types', () => {
   describe('DepositStatus', () => {
     it('should have all valid status values', () => {
-      const validStatuses: DepositStatus[] = ['pending', 'funded', 'deployed', 'routed', 'failed'];
+      const validStatuses: DepositStatus[] = [
+        'pending',
+        'funded',
+        'deployed',
+        'routed',
+        'stranded',
+        'failed',
+      ];
 
       expect(validStatuses).toContain('pending');
       expect(validStatuses).toContain('funded');
       expect(validStatuses).toContain('deployed');
       expect(validStatuses).toContain('routed');
+      expect(validStatuses).toContain('stranded');
       expect(validStatuses).toContain('failed');
     });
   });
@@ -18,11 +26,14 @@ describe('API Types', () => {
     it('documents the expected status transitions', () => {
       // Status flow: pending → funded → deployed → routed
       //                                         ↘ failed
+      // Stranded funds don't change the status; `stranded` is only set by
+      // earlier versions and routed like `deployed`
       const statusFlow = {
         pending: ['funded'],
         funded: ['deployed'],
         deployed: ['routed', 'failed'],
-        routed: [], // terminal state
+        routed: [], // routed again when its proxy forwards more
+        stranded: ['routed'],
         failed: [], // terminal state
       };
 
@@ -30,6 +41,7 @@ describe('API Types', () => {
       expect(statusFlow.funded).toContain('deployed');
       expect(statusFlow.deployed).toContain('routed');
       expect(statusFlow.deployed).toContain('failed');
+      expect(statusFlow.stranded).toContain('routed');
     });
   });
 });
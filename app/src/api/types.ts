@@ -1,9 +1,30 @@
 // API Types matching rust-backend responses
 
+export interface StartupCheck {
+  name: string;
+  ok: boolean;
+  detail: string;
+}
+
+export interface ComponentCheck {
+  name: string;
+  status: 'ok' | 'degraded' | 'down';
+  detail: string;
+  latency_ms?: number;
+}
+
 export interface HealthResponse {
   status: string;
   version: string;
   deployer_address: string;
+  init_code_hash: string;
+  signer_address: string;
+  startup_checks: {
+    mode: 'strict' | 'lenient';
+    ok: boolean;
+    checks: StartupCheck[];
+  };
+  components: ComponentCheck[];
 }
 
 export interface DepositInfo {
@@ -16,7 +37,13 @@ export interface DepositInfo {
   created_at: string;
 }
 
-export type DepositStatus = 'pending' | 'funded' | 'deployed' | 'routed' | 'failed';
+export type DepositStatus =
+  | 'pending'
+  | 'funded'
+  | 'deployed'
+  | 'routed'
+  | 'stranded'
+  | 'failed';
 
 export interface ListDepositsResponse {
   deposits: DepositInfo[];
@@ -31,6 +58,10 @@ export interface CreateDepositResponse {
   deposit_address: string;
   salt: string;
   nonce: number;
+  /** CREATE2 inputs the address derives from */
+  deployer_address: string;
+  init_code_hash: string;
+  signer_address: string;
   note: string;
 }
 
@@ -45,7 +76,45 @@ export interface RouteResponse {
   funded: number;
   deployed: number;
   routed: number;
+  stranded: number;
   deploy_tx_hash?: string;
   route_tx_hashes: RouteTransactionInfo[];
   errors: string[];
 }
+
+export type JobStatus = 'running' | 'completed' | 'interrupted';
+
+/** A routing job: POST /router response and GET /jobs/:id */
+export interface RoutingJobInfo {
+  id: number;
+  status: JobStatus;
+  requested_by?: string;
+  checked: number;
+  funded: number;
+  deployed: number;
+  routed: number;
+  stranded: number;
+  error_count: number;
+  created_at: string;
+  updated_at: string;
+  finished_at: string | null;
+  /** Progress so far; omitted from GET /jobs */
+  result?: RouteResponse;
+}
+
+export interface NonceResponse {
+  nonce: string;
+  expires_at: string;
+}
+
+export interface SiweVerifyRequest {
+  /** EIP-4361 message exactly as signed */
+  message: string;
+  signature: string;
+}
+
+export interface SessionResponse {
+  token: string;
+  address: string;
+  expires_at: string;
+}
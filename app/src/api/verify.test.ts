@@ -0,0 +1,44 @@
+import { describe, it, expect, vi, beforeEach } from 'vitest';
+import { verifyDepositAddress } from '../wasm/radhat-create2/radhat_create2';
+import { checkDepositAddress } from './verify';
+import { mockCreateDeposit } from '../test/mocks/handlers';
+
+vi.mock('../wasm/radhat-create2/radhat_create2', () => ({
+  default: vi.fn().mockResolvedValue({}),
+  verifyDepositAddress: vi.fn(),
+}));
+
+const verify = vi.mocked(verifyDepositAddress);
+
+describe('checkDepositAddress', () => {
+  beforeEach(() => verify.mockReset());
+
+  it('accepts an address the module recomputes', async () => {
+    verify.mockReturnValue(true);
+
+    expect(await checkDepositAddress(mockCreateDeposit)).toBeNull();
+    expect(verify).toHaveBeenCalledWith(
+      mockCreateDeposit.deployer_address,
+      mockCreateDeposit.init_code_hash,
+      mockCreateDeposit.signer_address,
+      mockCreateDeposit.salt,
+      mockCreateDeposit.deposit_address
+    );
+  });
+
+  it('warns about an address that does not match', async () => {
+    verify.mockReturnValue(false);
+
+    expect(await checkDepositAddress(mockCreateDeposit)).toMatch(/Do not send funds/);
+  });
+
+  it('warns when the address cannot be checked', async () => {
+    verify.mockImplementation(() => {
+      throw new Error('Invalid signer');
+    });
+
+    expect(await checkDepositAddress(mockCreateDeposit)).toMatch(
+      /could not be verified \(Invalid signer\)/
+    );
+  });
+});
@@ -0,0 +1,44 @@
+import init, { verifyDepositAddress } from '../wasm/radhat-create2/radhat_create2';
+import type { CreateDepositResponse } from './types';
+
+/** CREATE2 inputs pinned at build time; unlike API keys they are public */
+const PINNED = {
+  deployer: import.meta.env.VITE_DEPLOYER_ADDRESS,
+  initCodeHash: import.meta.env.VITE_INIT_CODE_HASH,
+  signer: import.meta.env.VITE_SIGNER_ADDRESS,
+};
+
+let ready: ReturnType<typeof init> | null = null;
+
+/**
+ * Recompute a new deposit address with the CREATE2 WebAssembly module before
+ * it is shown. Returns a warning for the user, or null if the address checks
+ * out.
+ *
+ * Pinned values win over the ones the backend reports, so a backend handing
+ * out addresses for another deployer or signer is caught.
+ */
+export async function checkDepositAddress(deposit: CreateDepositResponse): Promise<string | null> {
+  const deployer = PINNED.deployer || deposit.deployer_address;
+  const initCodeHash = PINNED.initCodeHash || deposit.init_code_hash;
+  const signer = PINNED.signer || deposit.signer_address;
+
+  try {
+    ready ??= init();
+    await ready;
+    const ok = verifyDepositAddress(
+      deployer,
+      initCodeHash,
+      signer,
+      deposit.salt,
+      deposit.deposit_address
+    );
+    return ok
+      ? null
+      : 'This address does not match the deployer and signer it should come from. Do not send funds to it.';
+  } catch (err) {
+    ready = null;
+    const reason = err instanceof Error ? err.message : String(err);
+    return `This address could not be verified (${reason}). Check it before sending funds.`;
+  }
+}
@@ -1,18 +1,73 @@
-import { useConnect, useAccount, useDisconnect } from 'wagmi';
+import { useState } from 'react';
+import { useConnect, useAccount, useDisconnect, useSignMessage } from 'wagmi';
+import { createSiweMessage } from 'viem/siwe';
+import { api, sessionAddress, setSession } from '../api/client';
 
-export function ConnectWallet() {
-  const { address, isConnected } = useAccount();
+interface ConnectWalletProps {
+  onSessionChange?: () => void;
+}
+
+export function ConnectWallet({ onSessionChange }: ConnectWalletProps) {
+  const { address, chainId, isConnected } = useAccount();
   const { connect, connectors, isPending } = useConnect();
   const { disconnect } = useDisconnect();
+  const { signMessageAsync } = useSignMessage();
+  const [signedIn, setSignedIn] = useState(sessionAddress());
+  const [isSigningIn, setIsSigningIn] = useState(false);
+
+  // Sign-In with Ethereum: the session token scopes deposit calls to this wallet
+  const signIn = async () => {
+    if (!address || !chainId) return;
+    setIsSigningIn(true);
+    try {
+      const { nonce } = await api.siweNonce();
+      const message = createSiweMessage({
+        domain: window.location.host,
+        address,
+        statement: 'Sign in to manage your deposit addresses.',
+        uri: window.location.origin,
+        version: '1',
+        chainId,
+        nonce,
+      });
+      const signature = await signMessageAsync({ message });
+      const session = await api.siweVerify({ message, signature });
+      setSession({ token: session.token, address: session.address });
+      setSignedIn(session.address);
+      onSessionChange?.();
+    } finally {
+      setIsSigningIn(false);
+    }
+  };
+
+  const signOut = async () => {
+    if (signedIn) {
+      await api.logout().catch(() => undefined);
+      setSession(null);
+      setSignedIn(null);
+      onSessionChange?.();
+    }
+    disconnect();
+  };
 
   if (isConnected && address) {
+    const isSignedIn = signedIn?.toLowerCase() === address.toLowerCase();
     return (
       <div className="flex items-center gap-3">
         <span className="text-sm text-neutral-500 font-mono">
           {address.slice(0, 6)}...{address.slice(-4)}
         </span>
+        {!isSignedIn && (
+          <button
+            onClick={() => signIn().catch(console.error)}
+            disabled={isSigningIn}
+            className="px-4 py-1.5 text-xs font-bold bg-white text-black hover:bg-neutral-200 disabled:bg-neutral-800 disabled:text-neutral-500 rounded-full transition-colors"
+          >
+            {isSigningIn ? 'SIGNING IN...' : 'SIGN IN'}
+          </button>
+        )}
         <button
-          onClick={() => disconnect()}
+          onClick={() => signOut()}
           className="px-4 py-1.5 text-xs font-medium border border-neutral-800 bg-neutral-900 hover:bg-neutral-800 text-white rounded-full transition-colors"
         >
           Disconnect
@@ -1,6 +1,7 @@
 import { useState } from 'react';
 import { useAccount } from 'wagmi';
 import { api, type CreateDepositResponse } from '../api';
+import { checkDepositAddress } from '../api/verify';
 
 interface CreateDepositButtonProps {
   onSuccess: () => void;
@@ -11,6 +12,7 @@ export function CreateDepositButton({ onSuccess }: CreateDepositButtonProps) {
   const [isLoading, setIsLoading] = useState(false);
   const [result, setResult] = useState<CreateDepositResponse | null>(null);
   const [error, setError] = useState<string | null>(null);
+  const [warning, setWarning] = useState<string | null>(null);
 
   const handleCreate = async () => {
     if (!address) return;
@@ -18,9 +20,11 @@ export function CreateDepositButton({ onSuccess }: CreateDepositButtonProps) {
     setIsLoading(true);
     setError(null);
     setResult(null);
+    setWarning(null);
 
     try {
       const response = await api.createDeposit({ user: address });
+      setWarning(await checkDepositAddress(response));
       setResult(response);
       onSuccess();
     } catch (err) {
@@ -54,7 +58,9 @@ export function CreateDepositButton({ onSuccess }: CreateDepositButtonProps) {
 
       {result && (
         <div className="p-4 border border-neutral-700 rounded bg-neutral-900 space-y-2">
-          <p className="text-white font-medium">✓ Deposit address created</p>
+          <p className="text-white font-medium">
+            {warning ? 'Deposit address created' : '✓ Deposit address created and verified'}
+          </p>
           <div className="space-y-1 text-sm">
             <p className="text-neutral-300">
               <span className="text-neutral-500">Address:</span>{' '}
@@ -62,6 +68,11 @@ export function CreateDepositButton({ onSuccess }: CreateDepositButtonProps) {
             </p>
             <p className="text-neutral-500">{result.note}</p>
           </div>
+          {warning && (
+            <p role="alert" className="p-3 border border-white rounded text-white text-sm">
+              ⚠ {warning}
+            </p>
+          )}
         </div>
       )}
     </div>
@@ -0,0 +1,70 @@
+import { describe, it, expect, vi } from 'vitest';
+import { http, HttpResponse } from 'msw';
+import { render, screen, fireEvent } from '../test/utils';
+import { server } from '../test/mocks/server';
+import { mockJob } from '../test/mocks/handlers';
+import { RouteButton } from './RouteButton';
+
+const API_URL = import.meta.env.VITE_API_URL || 'http://localhost:3001';
+
+function startRouting() {
+  fireEvent.change(screen.getByPlaceholderText(/Operator API key/), {
+    target: { value: 'rk_operator' },
+  });
+  fireEvent.click(screen.getByRole('button'));
+}
+
+describe('RouteButton', () => {
+  it('needs an operator API key', () => {
+    render(<RouteButton onSuccess={() => {}} />);
+
+    expect(screen.getByRole('button')).toBeDisabled();
+  });
+
+  it('follows the routing job until it completes', async () => {
+    const onSuccess = vi.fn();
+    let authorization: string | null = null;
+    server.use(
+      http.post(`${API_URL}/router`, ({ request }) => {
+        authorization = request.headers.get('Authorization');
+        return HttpResponse.json(mockJob, { status: 202 });
+      })
+    );
+    render(<RouteButton onSuccess={onSuccess} pollInterval={10} />);
+
+    startRouting();
+
+    expect(await screen.findByText('Routing complete')).toBeInTheDocument();
+    expect(screen.getByText(`(job #${mockJob.id})`)).toBeInTheDocument();
+    expect(screen.getByText('Route TXs:')).toBeInTheDocument();
+    expect(screen.getByRole('button')).toBeEnabled();
+    expect(onSuccess).toHaveBeenCalledTimes(1);
+    expect(authorization).toBe('Bearer rk_operator');
+  });
+
+  it('reports an interrupted job', async () => {
+    server.use(
+      http.get(`${API_URL}/jobs/:id`, () =>
+        HttpResponse.json({ ...mockJob, status: 'interrupted', finished_at: mockJob.created_at })
+      )
+    );
+    render(<RouteButton onSuccess={() => {}} pollInterval={10} />);
+
+    startRouting();
+
+    expect(await screen.findByText('Routing interrupted')).toBeInTheDocument();
+  });
+
+  it('shows why a job could not start', async () => {
+    server.use(
+      http.post(`${API_URL}/router`, () =>
+        HttpResponse.json({ code: 'ROUTING_IN_PROGRESS' }, { status: 409 })
+      )
+    );
+    render(<RouteButton onSuccess={() => {}} />);
+
+    startRouting();
+
+    expect(await screen.findByText(/ROUTING_IN_PROGRESS/)).toBeInTheDocument();
+  });
+});
@@ -1,39 +1,80 @@
-import { useState } from 'react';
-import { api, type RouteResponse } from '../api';
+import { useEffect, useState } from 'react';
+import { api, type RoutingJobInfo } from '../api';
+
+const JOB_POLL_INTERVAL = 2000; // 2 seconds
 
 interface RouteButtonProps {
   onSuccess: () => void;
+  /** How often to check on a running job */
+  pollInterval?: number;
 }
 
-export function RouteButton({ onSuccess }: RouteButtonProps) {
-  const [isLoading, setIsLoading] = useState(false);
-  const [result, setResult] = useState<RouteResponse | null>(null);
+const JOB_TITLES: Record<RoutingJobInfo['status'], string> = {
+  running: 'Routing in progress',
+  completed: 'Routing complete',
+  interrupted: 'Routing interrupted',
+};
+
+export function RouteButton({ onSuccess, pollInterval = JOB_POLL_INTERVAL }: RouteButtonProps) {
+  // Kept in memory only; the public bundle carries no API key
+  const [apiKey, setApiKey] = useState('');
+  const [isStarting, setIsStarting] = useState(false);
+  const [job, setJob] = useState<RoutingJobInfo | null>(null);
   const [error, setError] = useState<string | null>(null);
 
+  const isRunning = job?.status === 'running';
+
+  // POST /router only starts the job; follow it until it finishes
+  useEffect(() => {
+    if (!job || job.status !== 'running') {
+      return;
+    }
+    const timeout = setTimeout(async () => {
+      try {
+        const next = await api.getJob(job.id, apiKey);
+        setJob(next);
+        if (next.status !== 'running') {
+          onSuccess();
+        }
+      } catch (err) {
+        setError(err instanceof Error ? err.message : 'Failed to check routing job');
+      }
+    }, pollInterval);
+    return () => clearTimeout(timeout);
+  }, [job, apiKey, onSuccess, pollInterval]);
+
   const handleRoute = async () => {
-    setIsLoading(true);
+    setIsStarting(true);
     setError(null);
-    setResult(null);
+    setJob(null);
 
     try {
-      const response = await api.routeDeposits();
-      setResult(response);
-      onSuccess();
+      setJob(await api.routeDeposits(apiKey));
     } catch (err) {
       setError(err instanceof Error ? err.message : 'Failed to route deposits');
     } finally {
-      setIsLoading(false);
+      setIsStarting(false);
     }
   };
 
+  const result = job?.result;
+
   return (
     <div className="space-y-4">
+      <input
+        type="password"
+        value={apiKey}
+        onChange={(e) => setApiKey(e.target.value)}
+        placeholder="Operator API key (route role)"
+        autoComplete="off"
+        className="w-full px-5 py-3 border border-neutral-800 bg-neutral-950 text-white placeholder:text-neutral-600 rounded-full text-sm font-mono focus:outline-none focus:border-neutral-500"
+      />
       <button
         onClick={handleRoute}
-        disabled={isLoading}
+        disabled={!apiKey || isStarting || isRunning}
         className="w-full px-6 py-4 border border-neutral-700 bg-neutral-900/50 hover:bg-neutral-900 hover:border-white text-white disabled:border-neutral-800 disabled:text-neutral-600 disabled:bg-transparent disabled:cursor-not-allowed rounded-full font-bold text-sm tracking-wide transition-all duration-200"
       >
-        {isLoading ? 'ROUTING...' : 'ROUTE ALL FUNDS TO TREASURY'}
+        {isStarting || isRunning ? 'ROUTING...' : 'ROUTE ALL FUNDS TO TREASURY'}
       </button>
 
       {error && (
@@ -42,29 +83,32 @@ export function RouteButton({ onSuccess }: RouteButtonProps) {
         </div>
       )}
 
-      {result && (
+      {job && (
         <div className="p-4 border border-neutral-700 rounded bg-neutral-900 space-y-4">
-          <p className="text-white font-medium">Routing complete</p>
+          <p className="text-white font-medium">
+            {JOB_TITLES[job.status]}{' '}
+            <span className="text-neutral-500 font-normal">(job #{job.id})</span>
+          </p>
           <div className="grid grid-cols-2 sm:grid-cols-4 gap-4 text-sm">
             <div>
               <p className="text-neutral-500">Checked</p>
-              <p className="text-2xl font-bold text-neutral-400">{result.checked}</p>
+              <p className="text-2xl font-bold text-neutral-400">{job.checked}</p>
             </div>
             <div>
               <p className="text-neutral-500">Funded</p>
-              <p className="text-2xl font-bold text-neutral-300">{result.funded}</p>
+              <p className="text-2xl font-bold text-neutral-300">{job.funded}</p>
             </div>
             <div>
               <p className="text-neutral-500">Deployed</p>
-              <p className="text-2xl font-bold text-neutral-200">{result.deployed}</p>
+              <p className="text-2xl font-bold text-neutral-200">{job.deployed}</p>
             </div>
             <div>
               <p className="text-neutral-500">Routed</p>
-              <p className="text-2xl font-bold text-white">{result.routed}</p>
+              <p className="text-2xl font-bold text-white">{job.routed}</p>
             </div>
           </div>
 
-          {result.deploy_tx_hash && (
+          {result?.deploy_tx_hash && (
             <p className="text-sm text-neutral-500">
               Deploy TX:{' '}
               <a
@@ -78,7 +122,7 @@ export function RouteButton({ onSuccess }: RouteButtonProps) {
             </p>
           )}
 
-          {result.route_tx_hashes.length > 0 && (
+          {result && result.route_tx_hashes.length > 0 && (
             <div className="text-sm text-neutral-500">
               <p>Route TXs:</p>
               <ul className="list-disc list-inside">
@@ -98,7 +142,7 @@ export function RouteButton({ onSuccess }: RouteButtonProps) {
             </div>
           )}
 
-          {result.errors.length > 0 && (
+          {result && result.errors.length > 0 && (
             <div className="text-sm text-neutral-400">
               <p>Errors:</p>
               <ul className="list-disc list-inside">
@@ -21,6 +21,10 @@ const statusConfig: Record<DepositStatus, { label: string; className: string }>
     label: 'Routed',
     className: 'bg-white text-black border-white',
   },
+  stranded: {
+    label: 'Stranded',
+    className: 'bg-neutral-900 text-neutral-300 border-neutral-500 border-dashed',
+  },
   failed: {
     label: 'Failed',
     className: 'bg-neutral-900 text-neutral-500 border-neutral-800 line-through',
@@ -4,6 +4,9 @@ import type {
   ListDepositsResponse,
   CreateDepositResponse,
   RouteResponse,
+  RoutingJobInfo,
+  NonceResponse,
+  SessionResponse,
 } from '../../api/types';
 
 // Use the same API URL as the client (defaults to localhost:3001 in tests)
@@ -14,6 +17,14 @@ export const mockHealth: HealthResponse = {
   status: 'ok',
   version: '0.1.0',
   deployer_address: '0x2b05DAf67cc41957f60F74Ff7D3c4aB54840Fc8D',
+  init_code_hash: '0x1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c',
+  signer_address: '0x5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e',
+  startup_checks: {
+    mode: 'lenient',
+    ok: true,
+    checks: [],
+  },
+  components: [],
 };
 
 export const mockDeposits: ListDepositsResponse = {
@@ -53,6 +64,9 @@ export const mockCreateDeposit: CreateDepositResponse = {
   deposit_address: '0xnewdeposit1234567890newdeposit1234567890',
   salt: '0x0000000000000000000000000000000000000000000000000000000000000004',
   nonce: 4,
+  deployer_address: '0x2b05DAf67cc41957f60F74Ff7D3c4aB54840Fc8D',
+  init_code_hash: '0x1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c',
+  signer_address: '0x5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e',
   note: 'Send Sepolia ETH to this address. The address is deterministic and will be deployed when funds are routed.',
 };
 
@@ -61,6 +75,7 @@ export const mockRouteResponse: RouteResponse = {
   funded: 1,
   deployed: 1,
   routed: 1,
+  stranded: 0,
   deploy_tx_hash: '0xdeploytx1234567890deploytx1234567890deploytx1234567890',
   route_tx_hashes: [
     {
@@ -72,6 +87,44 @@ export const mockRouteResponse: RouteResponse = {
   errors: [],
 };
 
+export const mockJob: RoutingJobInfo = {
+  id: 7,
+  status: 'running',
+  requested_by: 'dashboard',
+  checked: 0,
+  funded: 0,
+  deployed: 0,
+  routed: 0,
+  stranded: 0,
+  error_count: 0,
+  created_at: '2026-02-01 12:15:00',
+  updated_at: '2026-02-01 12:15:00',
+  finished_at: null,
+};
+
+export const mockFinishedJob: RoutingJobInfo = {
+  ...mockJob,
+  status: 'completed',
+  checked: 3,
+  funded: 1,
+  deployed: 1,
+  routed: 1,
+  updated_at: '2026-02-01 12:15:04',
+  finished_at: '2026-02-01 12:15:04',
+  result: mockRouteResponse,
+};
+
+export const mockNonce: NonceResponse = {
+  nonce: 'a1b2c3d4e5f60718',
+  expires_at: '2026-02-01 12:10:00',
+};
+
+export const mockSession: SessionResponse = {
+  token: 'rs_0000000000000000000000000000000000000000000000000000000000000001',
+  address: '0x1234567890123456789012345678901234567890',
+  expires_at: '2026-02-02 12:00:00',
+};
+
 export const handlers = [
   // Health check
   http.get(`${API_URL}/health`, () => {
@@ -97,8 +150,29 @@ export const handlers = [
     return HttpResponse.json(mockCreateDeposit);
   }),
 
-  // Route deposits
+  // Start a routing job; it finishes in the background
   http.post(`${API_URL}/router`, () => {
-    return HttpResponse.json(mockRouteResponse);
+    return HttpResponse.json(mockJob, { status: 202 });
+  }),
+
+  // Get a routing job
+  http.get(`${API_URL}/jobs/:id`, ({ params }) => {
+    if (Number(params.id) === mockJob.id) {
+      return HttpResponse.json(mockFinishedJob);
+    }
+    return new HttpResponse(null, { status: 404 });
+  }),
+
+  // Sign-In with Ethereum
+  http.get(`${API_URL}/auth/nonce`, () => {
+    return HttpResponse.json(mockNonce);
+  }),
+
+  http.post(`${API_URL}/auth/verify`, () => {
+    return HttpResponse.json(mockSession);
+  }),
+
+  http.post(`${API_URL}/auth/logout`, () => {
+    return new HttpResponse(null, { status: 204 });
   }),
 ];
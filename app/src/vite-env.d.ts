@@ -3,6 +3,10 @@
 interface ImportMetaEnv {
   readonly VITE_API_URL: string;
   readonly VITE_WALLETCONNECT_PROJECT_ID: string;
+  /** Optional CREATE2 inputs new deposit addresses are checked against */
+  readonly VITE_DEPLOYER_ADDRESS?: string;
+  readonly VITE_INIT_CODE_HASH?: string;
+  readonly VITE_SIGNER_ADDRESS?: string;
 }
 
 interface ImportMeta {
@@ -20,6 +20,10 @@ contract FundRouter is IFundRouter {
     error ERC20TransferFailed();
     error PermissionCheckFailed();
 
+    /// @notice ETH received, e.g. forwarded by a proxy, which is then `from`.
+    /// @dev Lets off-chain routing attribute forwarded ETH to the proxy that sent it.
+    event EthReceived(address indexed from, uint256 amount);
+
     /// @dev External storage contract with allowlists.
     address public immutable STORAGE;
 
@@ -82,5 +86,7 @@ contract FundRouter is IFundRouter {
     }
 
     // Accept ETH so proxies can push value here via CALL.
-    receive() external payable {}
+    receive() external payable {
+        emit EthReceived(msg.sender, msg.value);
+    }
 }
@@ -0,0 +1,182 @@
+//! JS bindings for the WebAssembly build
+//!
+//! ```js
+//! import init, { verifyDepositAddress } from 'radhat-create2';
+//!
+//! await init();
+//! const ok = verifyDepositAddress(deployer, initCodeHash, signer, res.salt, res.deposit_address);
+//! if (!ok) warnCustomer();
+//! ```
+//!
+//! Addresses are returned as lowercase `0x`-prefixed hex.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    compute_deposit_address, compute_proxy_address, format_address, format_bytes32, parse_address,
+    parse_bytes32,
+};
+
+fn address_arg(name: &str, value: &str) -> Result<[u8; 20], String> {
+    parse_address(value).map_err(|e| format!("{}: {}", name, e))
+}
+
+fn bytes32_arg(name: &str, value: &str) -> Result<[u8; 32], String> {
+    parse_bytes32(value).map_err(|e| format!("{}: {}", name, e))
+}
+
+fn js_error(message: String) -> JsError {
+    JsError::new(&message)
+}
+
+fn proxy_address(
+    deployer: &str,
+    init_code_hash: &str,
+    signer: &str,
+    salt: &str,
+) -> Result<[u8; 20], String> {
+    Ok(compute_proxy_address(
+        &address_arg("deployer", deployer)?,
+        &bytes32_arg("initCodeHash", init_code_hash)?,
+        &address_arg("signer", signer)?,
+        &bytes32_arg("salt", salt)?,
+    ))
+}
+
+fn verify(
+    deployer: &str,
+    init_code_hash: &str,
+    signer: &str,
+    salt: &str,
+    deposit_address: &str,
+) -> Result<bool, String> {
+    let expected = proxy_address(deployer, init_code_hash, signer, salt)?;
+    Ok(expected == address_arg("depositAddress", deposit_address)?)
+}
+
+fn derive(
+    deployer: &str,
+    init_code_hash: &str,
+    signer: &str,
+    user: &str,
+    nonce: u64,
+) -> Result<DepositAddress, String> {
+    let (address, salt) = compute_deposit_address(
+        &address_arg("deployer", deployer)?,
+        &bytes32_arg("initCodeHash", init_code_hash)?,
+        &address_arg("signer", signer)?,
+        &address_arg("user", user)?,
+        nonce,
+    );
+    Ok(DepositAddress {
+        address: format_address(&address),
+        salt: format_bytes32(&salt),
+    })
+}
+
+/// Deposit address for a user salt, as deployed by `signer` via deployMultiple
+#[wasm_bindgen(js_name = depositAddressFromSalt)]
+pub fn deposit_address_from_salt(
+    deployer: &str,
+    init_code_hash: &str,
+    signer: &str,
+    salt: &str,
+) -> Result<String, JsError> {
+    proxy_address(deployer, init_code_hash, signer, salt)
+        .map(|address| format_address(&address))
+        .map_err(js_error)
+}
+
+/// Check that `deposit_address` derives from the given salt and signer
+#[wasm_bindgen(js_name = verifyDepositAddress)]
+pub fn verify_deposit_address(
+    deployer: &str,
+    init_code_hash: &str,
+    signer: &str,
+    salt: &str,
+    deposit_address: &str,
+) -> Result<bool, JsError> {
+    verify(deployer, init_code_hash, signer, salt, deposit_address).map_err(js_error)
+}
+
+/// Deposit address and user salt for a user's `nonce`-th deposit
+#[wasm_bindgen(getter_with_clone)]
+pub struct DepositAddress {
+    pub address: String,
+    pub salt: String,
+}
+
+/// Full derivation from user address and nonce, as `POST /deposit` does it
+#[wasm_bindgen(js_name = computeDepositAddress)]
+pub fn compute_deposit_address_js(
+    deployer: &str,
+    init_code_hash: &str,
+    signer: &str,
+    user: &str,
+    nonce: u64,
+) -> Result<DepositAddress, JsError> {
+    derive(deployer, init_code_hash, signer, user, nonce).map_err(js_error)
+}
+
+// `JsError` needs a JS host, so the error cases are checked on the plain
+// functions the bindings wrap
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEPLOYER: &str = "0xabababababababababababababababababababab";
+    const INIT_CODE_HASH: &str =
+        "0xefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefef";
+    const SIGNER: &str = "0x1111111111111111111111111111111111111111";
+    const USER: &str = "0x4242424242424242424242424242424242424242";
+
+    #[test]
+    fn test_bindings_match_the_backend_derivation() {
+        let (address, salt) =
+            compute_deposit_address(&[0xAB; 20], &[0xEF; 32], &[0x11; 20], &[0x42; 20], 3);
+
+        let derived = compute_deposit_address_js(DEPLOYER, INIT_CODE_HASH, SIGNER, USER, 3)
+            .unwrap_or_else(|_| panic!("derivation failed"));
+        assert_eq!(derived.address, format_address(&address));
+        assert_eq!(derived.salt, format_bytes32(&salt));
+
+        let from_salt = deposit_address_from_salt(DEPLOYER, INIT_CODE_HASH, SIGNER, &derived.salt)
+            .unwrap_or_else(|_| panic!("derivation failed"));
+        assert_eq!(from_salt, derived.address);
+    }
+
+    #[test]
+    fn test_verify_deposit_address() {
+        let derived = derive(DEPLOYER, INIT_CODE_HASH, SIGNER, USER, 0).unwrap();
+        let verify_with = |signer: &str, address: &str| {
+            verify_deposit_address(DEPLOYER, INIT_CODE_HASH, signer, &derived.salt, address)
+                .unwrap_or_else(|_| panic!("verification failed"))
+        };
+
+        assert!(verify_with(SIGNER, &derived.address));
+        // Upper-case hex is accepted
+        assert!(verify_with(
+            SIGNER,
+            &derived.address.to_uppercase().replace("0X", "0x")
+        ));
+        // An address derived for another signer is rejected
+        assert!(!verify_with(
+            "0x2222222222222222222222222222222222222222",
+            &derived.address
+        ));
+        let other = derive(DEPLOYER, INIT_CODE_HASH, SIGNER, USER, 1).unwrap();
+        assert!(!verify_with(SIGNER, &other.address));
+    }
+
+    #[test]
+    fn test_invalid_arguments_are_named() {
+        let err = verify("0x1234", INIT_CODE_HASH, SIGNER, INIT_CODE_HASH, USER).unwrap_err();
+        assert!(err.starts_with("deployer: "), "{}", err);
+        let err = proxy_address(DEPLOYER, "0x00", SIGNER, INIT_CODE_HASH).unwrap_err();
+        assert!(err.starts_with("initCodeHash: "), "{}", err);
+        let err = derive(DEPLOYER, INIT_CODE_HASH, SIGNER, "not an address", 0).unwrap_err();
+        assert!(err.starts_with("user: "), "{}", err);
+        let err = verify(DEPLOYER, INIT_CODE_HASH, SIGNER, INIT_CODE_HASH, "0x").unwrap_err();
+        assert!(err.starts_with("depositAddress: "), "{}", err);
+    }
+}
@@ -0,0 +1,417 @@
+//! API key authentication
+//!
+//! Keys are random tokens (`rh_<64 hex chars>`) shown once at creation; only
+//! their SHA-256 hash is stored. A key carries one or more [`Role`]s and each
+//! route group requires one of them through [`require`]. `admin` grants every
+//! role.
+//!
+//! Keys are sent as `Authorization: Bearer <key>` or `X-Api-Key: <key>`.
+//!
+//! User-scoped routes (deposits) go through [`require_user`] instead, which
+//! also accepts a wallet session token (`rs_...`) obtained through Sign-In
+//! with Ethereum; handlers then restrict the request to that wallet.
+
+use std::{fmt, str::FromStr};
+
+use alloy::hex;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+
+use crate::{db, error::AppError, telemetry, AppState};
+
+const KEY_PREFIX: &str = "rh_";
+
+/// Prefix of wallet session tokens issued by `POST /auth/verify`
+pub const SESSION_PREFIX: &str = "rs_";
+
+/// Characters of a key kept in the database to tell keys apart
+const DISPLAY_LEN: usize = KEY_PREFIX.len() + 8;
+
+/// What a key may do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Read deposits, stranded funds and routing jobs
+    Read,
+    /// Create deposit addresses
+    CreateDeposit,
+    /// Trigger routing and stranded scans (spends signer gas)
+    Route,
+    /// Manage API keys; implies every other role
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Read => "read",
+            Role::CreateDeposit => "create_deposit",
+            Role::Route => "route",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Role::Read),
+            "create_deposit" => Ok(Role::CreateDeposit),
+            "route" => Ok(Role::Route),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("unknown role '{}'", other)),
+        }
+    }
+}
+
+/// Roles as stored in the `roles` column
+pub fn format_roles(roles: &[Role]) -> String {
+    roles.iter().map(Role::as_str).collect::<Vec<_>>().join(",")
+}
+
+/// Parse the `roles` column, skipping roles this version doesn't know
+pub fn parse_roles(roles: &str) -> Vec<Role> {
+    roles.split(',').filter_map(|r| r.parse().ok()).collect()
+}
+
+/// The key a request was authenticated with
+///
+/// Inserted by [`require`]; handlers behind it can take `ApiKey` (or
+/// `Option<ApiKey>` when keys may be disabled) as an extractor.
+#[derive(Clone, Debug)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub roles: Vec<Role>,
+}
+
+impl ApiKey {
+    pub fn has(&self, role: Role) -> bool {
+        self.roles.contains(&Role::Admin) || self.roles.contains(&role)
+    }
+}
+
+/// A wallet signed in with SIWE
+///
+/// Inserted by [`require_user`] for session callers; handlers take
+/// `Option<Session>` and limit the request to `address` when it is set.
+#[derive(Clone, Debug)]
+pub struct Session {
+    /// Lowercase wallet address
+    pub address: String,
+    pub expires_at: String,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Session {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Session>()
+            .cloned()
+            .ok_or_else(|| AppError::Unauthorized("Sign in required".to_string()))
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ApiKey {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<ApiKey>()
+            .cloned()
+            .ok_or_else(|| AppError::Unauthorized("API key required".to_string()))
+    }
+}
+
+/// SHA-256 of a key or session token, hex encoded
+///
+/// Tokens carry 256 bits of randomness, so a fast unsalted hash is enough.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// A new random token with `prefix`
+pub fn random_token(prefix: &str) -> String {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    format!("{}{}", prefix, hex::encode(secret))
+}
+
+/// A new random API key
+pub fn generate_key() -> String {
+    random_token(KEY_PREFIX)
+}
+
+/// Create and store a key, returning its ID and the plaintext key
+pub async fn create_key(
+    pool: &SqlitePool,
+    name: &str,
+    roles: &[Role],
+    created_by: Option<&str>,
+) -> Result<(i64, String), sqlx::Error> {
+    let key = generate_key();
+    let id = db::insert_api_key(
+        pool,
+        name,
+        &hash_token(&key),
+        &key[..DISPLAY_LEN],
+        &format_roles(roles),
+        created_by,
+    )
+    .await?;
+    tracing::info!(key_id = id, name, roles = %format_roles(roles), "Created API key");
+    Ok((id, key))
+}
+
+/// Key or session token sent with a request, if any
+pub fn presented_token(headers: &HeaderMap) -> Option<&str> {
+    if let Some(key) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+        return Some(key.trim());
+    }
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Look up the key presented with a request
+pub async fn authenticate(pool: &SqlitePool, headers: &HeaderMap) -> Result<ApiKey, AppError> {
+    let key = presented_token(headers)
+        .ok_or_else(|| AppError::Unauthorized("API key required".to_string()))?;
+
+    let row = db::get_api_key_by_hash(pool, &hash_token(key))
+        .await?
+        .filter(|row| row.revoked_at.is_none())
+        .ok_or_else(|| AppError::Unauthorized("Invalid or revoked API key".to_string()))?;
+
+    Ok(ApiKey {
+        id: row.id,
+        name: row.name,
+        roles: parse_roles(&row.roles),
+    })
+}
+
+/// Look up the wallet session presented with a request
+pub async fn session(pool: &SqlitePool, headers: &HeaderMap) -> Result<Session, AppError> {
+    let token = presented_token(headers)
+        .filter(|token| token.starts_with(SESSION_PREFIX))
+        .ok_or_else(|| AppError::Unauthorized("Sign in required".to_string()))?;
+
+    let row = db::get_session(pool, &hash_token(token))
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("Invalid or expired session".to_string()))?;
+
+    Ok(Session {
+        address: row.address,
+        expires_at: row.expires_at,
+    })
+}
+
+/// Authenticate a key, check it has `role` and count the request
+async fn authorize_key(
+    state: &AppState,
+    role: Role,
+    headers: &HeaderMap,
+) -> Result<ApiKey, AppError> {
+    let key = authenticate(&state.db, headers).await?;
+    if !key.has(role) {
+        return Err(AppError::Forbidden(format!(
+            "API key '{}' lacks the '{}' role",
+            key.name, role
+        )));
+    }
+
+    if let Err(e) = db::record_api_key_use(&state.db, key.id).await {
+        tracing::warn!("Failed to record API key usage: {}", e);
+    }
+    tracing::Span::current().record("api_key", key.name.as_str());
+    Ok(key)
+}
+
+/// Middleware requiring a key with `role`
+///
+/// Installed per route group with
+/// `middleware::from_fn_with_state((state, role), auth::require)`. Passes
+/// everything through when `REQUIRE_API_KEYS=false`.
+pub async fn require(
+    State((state, role)): State<(AppState, Role)>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if !state.config.require_api_keys {
+        return Ok(next.run(request).await);
+    }
+
+    let key = authorize_key(&state, role, request.headers()).await?;
+    request.extensions_mut().insert(key);
+    Ok(next.run(request).await)
+}
+
+/// Middleware for user-scoped routes: a key with `role`, or a wallet session
+pub async fn require_user(
+    State((state, role)): State<(AppState, Role)>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if !state.config.require_api_keys {
+        return Ok(next.run(request).await);
+    }
+
+    let is_session =
+        presented_token(request.headers()).is_some_and(|t| t.starts_with(SESSION_PREFIX));
+    if is_session {
+        let session = session(&state.db, request.headers()).await?;
+        tracing::Span::current().record(
+            "wallet",
+            telemetry::redact_address(&session.address).as_str(),
+        );
+        request.extensions_mut().insert(session);
+    } else {
+        let key = authorize_key(&state, role, request.headers()).await?;
+        request.extensions_mut().insert(key);
+    }
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app, chain::fake::FakeChain, test_state};
+    use axum::{body::Body, http::StatusCode, Router};
+    use tower::ServiceExt;
+
+    async fn send(app: &Router, method: &str, uri: &str, key: Option<&str>) -> StatusCode {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(key) = key {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", key));
+        }
+        let request = request.body(Body::empty()).unwrap();
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[test]
+    fn test_roles_round_trip() {
+        let roles = [Role::Read, Role::CreateDeposit, Role::Route, Role::Admin];
+        assert_eq!(format_roles(&roles), "read,create_deposit,route,admin");
+        assert_eq!(parse_roles("read,create_deposit,route,admin"), roles);
+        assert_eq!(parse_roles("read,unknown"), [Role::Read]);
+    }
+
+    #[test]
+    fn test_generated_keys_are_unique_and_hashed() {
+        let (a, b) = (generate_key(), generate_key());
+        assert_ne!(a, b);
+        assert!(a.starts_with("rh_") && a.len() == 67, "{}", a);
+        assert_eq!(hash_token(&a).len(), 64);
+        assert_ne!(hash_token(&a), a);
+    }
+
+    #[test]
+    fn test_admin_implies_every_role() {
+        let key = ApiKey {
+            id: 1,
+            name: "ops".to_string(),
+            roles: vec![Role::Admin],
+        };
+        assert!(key.has(Role::Route));
+        assert!(key.has(Role::Read));
+    }
+
+    #[tokio::test]
+    async fn test_routes_require_matching_role() {
+        let state = test_state(FakeChain::new()).await;
+        let (_, reader) = create_key(&state.db, "dashboard", &[Role::Read], None)
+            .await
+            .unwrap();
+        let (_, admin) = create_key(&state.db, "ops", &[Role::Admin], None)
+            .await
+            .unwrap();
+        let app = app(state.clone());
+
+        assert_eq!(
+            send(&app, "GET", "/deposits", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send(&app, "GET", "/deposits", Some("rh_wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send(&app, "GET", "/deposits", Some(&reader)).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&app, "POST", "/router", Some(&reader)).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            send(&app, "GET", "/admin/keys", Some(&reader)).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            send(&app, "GET", "/admin/keys", Some(&admin)).await,
+            StatusCode::OK
+        );
+        assert_eq!(send(&app, "GET", "/health", None).await, StatusCode::OK);
+
+        let keys = db::list_api_keys(&state.db).await.unwrap();
+        let reader_row = keys.iter().find(|k| k.name == "dashboard").unwrap();
+        assert_eq!(reader_row.request_count, 1);
+        assert!(reader_row.last_used_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_revoked_key_is_rejected() {
+        let state = test_state(FakeChain::new()).await;
+        let (id, key) = create_key(&state.db, "old", &[Role::Read], None)
+            .await
+            .unwrap();
+        let app = app(state.clone());
+
+        assert_eq!(
+            send(&app, "GET", "/deposits", Some(&key)).await,
+            StatusCode::OK
+        );
+        assert!(db::revoke_api_key(&state.db, id).await.unwrap());
+        assert_eq!(
+            send(&app, "GET", "/deposits", Some(&key)).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keys_can_be_disabled() {
+        let mut state = test_state(FakeChain::new()).await;
+        let mut config = (*state.config).clone();
+        config.require_api_keys = false;
+        state.config = std::sync::Arc::new(config);
+
+        assert_eq!(
+            send(&app(state), "GET", "/deposits", None).await,
+            StatusCode::OK
+        );
+    }
+}
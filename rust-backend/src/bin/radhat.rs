@@ -0,0 +1,398 @@
+//! radhat - offline operations CLI
+//!
+//! Runs the same code paths as the HTTP server without going through it, so
+//! ops can derive addresses, inspect the database and route funds over SSH.
+
+use std::{io::Write, process::ExitCode};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use sqlx::SqlitePool;
+
+use radhat_backend::{
+    auth::{self, Role},
+    chain::ChainClient,
+    checks,
+    config::{Config, ConfigArgs},
+    create2::{
+        compute_deposit_address, format_address, format_bytes32, parse_address, parse_bytes32,
+    },
+    db,
+    lease::{LeaseError, RoutingLease},
+    models::DepositInfo,
+    routes::{admin, deposit::row_to_info},
+    routing::{self, RetryPolicy},
+    rpc::RpcClient,
+    signer::{self, Signer},
+};
+
+type CliResult = Result<ExitCode, Box<dyn std::error::Error>>;
+
+#[derive(Parser)]
+#[command(name = "radhat", version, about = "RADHAT deposit proxy operations")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compute a deposit address offline, exactly as POST /deposit does
+    Derive {
+        /// User address the deposit belongs to
+        #[arg(long)]
+        user: String,
+        /// Per-user deposit nonce
+        #[arg(long)]
+        nonce: u64,
+        /// DeterministicProxyDeployer address
+        #[arg(long, env = "DEPLOYER_ADDRESS")]
+        deployer: String,
+        /// Proxy init code hash
+        #[arg(long, env = "INIT_CODE_HASH")]
+        init_code_hash: String,
+        /// Account that sends deployMultiple (defaults to the configured signer)
+        #[arg(long, env = "SIGNER_ADDRESS")]
+        signer: Option<String>,
+    },
+    /// Show a deposit by its address, or all deposits of a user
+    Lookup {
+        /// Deposit or user address
+        address: String,
+    },
+    /// Run one routing pass, like POST /router, and wait for its result
+    Route,
+    /// Put a dead-lettered or failed deposit back in line for deployment
+    Requeue {
+        /// Deposit address
+        address: String,
+    },
+    /// Apply database migrations
+    Migrate,
+    /// Dump deposits
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// Only export deposits with this status (repeatable)
+        #[arg(long)]
+        status: Vec<String>,
+    },
+    /// Validate the configuration against the chain
+    CheckConfig,
+    /// Record the configured signer as the one deposit addresses are derived
+    /// from, after switching signers on purpose
+    AdoptSigner,
+    /// Manage API keys
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeysCommand {
+    /// Create a key and print it; it cannot be shown again
+    Create {
+        /// Label for the key, e.g. the service using it
+        #[arg(long)]
+        name: String,
+        /// Role to grant: read, create_deposit, route or admin (repeatable)
+        #[arg(long = "role", required = true)]
+        roles: Vec<Role>,
+    },
+    /// List keys with their usage
+    List,
+    /// Revoke a key by ID
+    Revoke { id: i64 },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Json,
+    Csv,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenvy::dotenv().ok();
+    let cli = Cli::parse();
+
+    match run(cli).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> CliResult {
+    match cli.command {
+        Command::Derive {
+            user,
+            nonce,
+            deployer,
+            init_code_hash,
+            signer,
+        } => {
+            derive(
+                &cli.config,
+                &user,
+                nonce,
+                &deployer,
+                &init_code_hash,
+                signer,
+            )
+            .await
+        }
+        Command::Lookup { address } => {
+            let database_url = Config::database_url(&cli.config)?;
+            lookup(&db::connect(&database_url).await?, &address).await
+        }
+        Command::Route => route(&cli.config).await,
+        Command::Requeue { address } => {
+            let database_url = Config::database_url(&cli.config)?;
+            let pool = db::connect(&database_url).await?;
+            db::run_migrations(&pool).await?;
+            requeue(&pool, &address).await
+        }
+        Command::Migrate => {
+            let database_url = Config::database_url(&cli.config)?;
+            let pool = db::connect(&database_url).await?;
+            db::run_migrations(&pool).await?;
+            println!("Migrations applied to {}", database_url);
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Export { format, status } => {
+            let database_url = Config::database_url(&cli.config)?;
+            export(&db::connect(&database_url).await?, format, &status).await
+        }
+        Command::CheckConfig => check_config(&cli.config).await,
+        Command::AdoptSigner => adopt_signer(&cli.config).await,
+        Command::Keys { command } => {
+            let database_url = Config::database_url(&cli.config)?;
+            let pool = db::connect(&database_url).await?;
+            db::run_migrations(&pool).await?;
+            keys(&pool, command).await
+        }
+    }
+}
+
+async fn derive(
+    args: &ConfigArgs,
+    user: &str,
+    nonce: u64,
+    deployer: &str,
+    init_code_hash: &str,
+    signer: Option<String>,
+) -> CliResult {
+    let user = parse_address(user).map_err(|e| format!("user: {}", e))?;
+    let deployer = parse_address(deployer).map_err(|e| format!("deployer: {}", e))?;
+    let init_code_hash =
+        parse_bytes32(init_code_hash).map_err(|e| format!("init code hash: {}", e))?;
+    let signer = match signer {
+        Some(signer) => parse_address(&signer).map_err(|e| format!("signer: {}", e))?,
+        None => configured_signer(args).await?,
+    };
+
+    let (address, salt) =
+        compute_deposit_address(&deployer, &init_code_hash, &signer, &user, nonce);
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&serde_json::json!({
+            "deposit_address": format_address(&address),
+            "salt": format_bytes32(&salt),
+            "nonce": nonce,
+            "signer": format_address(&signer),
+        }))?
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Address of the configured signer, for when --signer isn't given
+async fn configured_signer(args: &ConfigArgs) -> Result<[u8; 20], Box<dyn std::error::Error>> {
+    let config =
+        Config::load(args).map_err(|e| format!("pass --signer or configure a signer: {}", e))?;
+    let signer = Signer::from_config(&config.signer).await?;
+    Ok(signer.address().0 .0)
+}
+
+async fn lookup(pool: &SqlitePool, address: &str) -> CliResult {
+    let address = address.to_lowercase();
+
+    let deposits: Vec<DepositInfo> = match db::get_deposit_by_address(pool, &address).await? {
+        Some(row) => vec![row_to_info(row)],
+        None => db::get_deposits_by_user(pool, &address)
+            .await?
+            .into_iter()
+            .map(row_to_info)
+            .collect(),
+    };
+
+    if deposits.is_empty() {
+        eprintln!("No deposit or user found for {}", address);
+        return Ok(ExitCode::FAILURE);
+    }
+
+    println!("{}", serde_json::to_string_pretty(&deposits)?);
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn route(args: &ConfigArgs) -> CliResult {
+    let config = Config::load(args)?;
+    let pool = db::connect(&config.database_url).await?;
+    db::run_migrations(&pool).await?;
+    let chain = RpcClient::from_config(&config).await?;
+    signer::check_recorded(&pool, chain.signer_address()).await?;
+
+    // Shares the load with runs on a server through deposit claims
+    let lease = RoutingLease::new(config.routing_lease_ttl());
+    let response = match routing::route_exclusive(
+        &pool,
+        &chain,
+        &lease,
+        RetryPolicy::from_config(&config),
+        Some("cli"),
+    )
+    .await
+    {
+        Ok(response) => response,
+        Err(LeaseError::Busy(active)) => {
+            eprintln!(
+                "A routing run is already in progress (held by {} until {})",
+                active.holder, active.expires_at
+            );
+            return Ok(ExitCode::FAILURE);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    println!("{}", serde_json::to_string_pretty(&response)?);
+    Ok(if response.errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+async fn requeue(pool: &SqlitePool, address: &str) -> CliResult {
+    let address = address.to_lowercase();
+    if !db::requeue_deposit(pool, &address).await? {
+        eprintln!("No dead-lettered or failed deposit at {}", address);
+        return Ok(ExitCode::FAILURE);
+    }
+    println!("Requeued {}", address);
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn export(pool: &SqlitePool, format: ExportFormat, statuses: &[String]) -> CliResult {
+    let rows = if statuses.is_empty() {
+        db::get_all_deposits(pool).await?
+    } else {
+        let statuses: Vec<&str> = statuses.iter().map(String::as_str).collect();
+        db::get_deposits_by_statuses(pool, &statuses).await?
+    };
+    let deposits: Vec<DepositInfo> = rows.into_iter().map(row_to_info).collect();
+
+    let mut out = std::io::stdout().lock();
+    match format {
+        ExportFormat::Json => writeln!(out, "{}", serde_json::to_string_pretty(&deposits)?)?,
+        ExportFormat::Csv => {
+            writeln!(
+                out,
+                "id,user_address,deposit_address,salt,nonce,status,created_at"
+            )?;
+            for d in &deposits {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{}",
+                    d.id,
+                    d.user_address,
+                    d.deposit_address,
+                    d.salt,
+                    d.nonce,
+                    d.status,
+                    d.created_at
+                )?;
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn keys(pool: &SqlitePool, command: KeysCommand) -> CliResult {
+    match command {
+        KeysCommand::Create { name, roles } => {
+            let (id, key) = auth::create_key(pool, &name, &roles, Some("cli")).await?;
+            eprintln!(
+                "Created key {} ({}); store it now, it cannot be shown again",
+                id, name
+            );
+            println!("{}", key);
+        }
+        KeysCommand::List => {
+            let keys: Vec<_> = db::list_api_keys(pool)
+                .await?
+                .into_iter()
+                .map(admin::row_to_info)
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&keys)?);
+        }
+        KeysCommand::Revoke { id } => {
+            if !db::revoke_api_key(pool, id).await? {
+                eprintln!("No active key with ID {}", id);
+                return Ok(ExitCode::FAILURE);
+            }
+            println!("Revoked key {}", id);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn check_config(args: &ConfigArgs) -> CliResult {
+    let config = Config::load(args)?;
+    let chain = RpcClient::from_config(&config).await?;
+
+    let results = checks::check_config(&config, &chain).await;
+    for check in &results {
+        println!(
+            "[{}] {}: {}",
+            if check.ok { " ok " } else { "FAIL" },
+            check.name,
+            check.detail
+        );
+    }
+
+    Ok(if results.iter().all(|c| c.ok) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+async fn adopt_signer(args: &ConfigArgs) -> CliResult {
+    let config = Config::load(args)?;
+    let pool = db::connect(&config.database_url).await?;
+    db::run_migrations(&pool).await?;
+    let address = Signer::from_config(&config.signer).await?.address();
+
+    match signer::adopt(&pool, address).await? {
+        Some(previous) if previous == format!("{:#x}", address) => {
+            println!("{:#x} is already the recorded signer", address);
+        }
+        previous => {
+            println!(
+                "Recorded {:#x} as the signer (was {})",
+                address,
+                previous.as_deref().unwrap_or("none")
+            );
+            println!(
+                "Deposits without a proxy from the previous signer are flagged at the next startup"
+            );
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
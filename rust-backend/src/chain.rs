@@ -0,0 +1,508 @@
+//! Chain access abstraction
+//!
+//! Routing only needs a handful of chain operations. Putting them behind
+//! [`ChainClient`] lets handlers share one client through `AppState` and lets
+//! the routing logic run against an in-memory chain in tests.
+
+use alloy::primitives::{Address, Bytes, FixedBytes, U256};
+
+use crate::rpc::RpcError;
+
+/// ETH the FundRouter received from one sender in one block, per its
+/// `EthReceived` event
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RouterReceipt {
+    /// A deployed proxy when it forwarded ETH sent to it
+    pub from: Address,
+    pub block: u64,
+    pub wei: U256,
+}
+
+/// Chain operations used by the deposit routing flow
+///
+/// [`crate::rpc::RpcClient`] is the production implementation.
+#[async_trait::async_trait]
+pub trait ChainClient: Send + Sync {
+    /// Account that sends `deployMultiple`, and so the CREATE2 caller
+    fn signer_address(&self) -> Address;
+
+    /// Treasury funds are routed to unless another one is requested
+    fn treasury_address(&self) -> Address;
+
+    /// FundRouter proxies forward ETH to and transfers pay out of
+    fn router_address(&self) -> Address;
+
+    /// Get the balance of an address
+    async fn get_balance(&self, address: Address) -> Result<U256, RpcError>;
+
+    /// Balance of an address as of `block`; older blocks may need an archive
+    /// node
+    async fn balance_at(&self, address: Address, block: u64) -> Result<U256, RpcError>;
+
+    /// Unix timestamp of a block
+    async fn block_timestamp(&self, block: u64) -> Result<u64, RpcError>;
+
+    /// Get the deployed code at an address (empty if none)
+    async fn get_code(&self, address: Address) -> Result<Bytes, RpcError>;
+
+    /// Chain ID reported by the RPC endpoint
+    async fn chain_id(&self) -> Result<u64, RpcError>;
+
+    /// Latest block number
+    async fn block_number(&self) -> Result<u64, RpcError>;
+
+    /// `FUND_ROUTER_ADDRESS()` of a DeterministicProxyDeployer
+    async fn deployer_fund_router(&self, deployer: Address) -> Result<Address, RpcError>;
+
+    /// `getInitCodeHash()` of a DeterministicProxyDeployer
+    async fn deployer_init_code_hash(&self, deployer: Address) -> Result<FixedBytes<32>, RpcError>;
+
+    /// `STORAGE()` of a FundRouter
+    async fn router_storage(&self, router: Address) -> Result<Address, RpcError>;
+
+    /// Deploy proxies for the given salts in one `deployMultiple` transaction
+    /// and return its hash once mined
+    async fn deploy_multiple(&self, salts: Vec<FixedBytes<32>>)
+        -> Result<FixedBytes<32>, RpcError>;
+
+    /// ETH the FundRouter received in blocks `from_block` through
+    /// `to_block`, from its `EthReceived` events
+    ///
+    /// A deployed proxy forwards all ETH it receives, sent straight to it or
+    /// in an internal call, and is the sender of the event. ETH that reached
+    /// an address before its proxy was deployed never shows up.
+    async fn router_receipts(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<RouterReceipt>, RpcError>;
+
+    /// Call `transferFunds` on the FundRouter, paying `amount` wei of the ETH
+    /// proxies forwarded to it to `treasury`, and return the transaction hash
+    /// once mined
+    async fn transfer_from_router(
+        &self,
+        treasury: Address,
+        amount: U256,
+    ) -> Result<FixedBytes<32>, RpcError>;
+
+    /// Gas the signer's `deployMultiple` for the given salts would use
+    async fn estimate_deploy_gas(&self, salts: Vec<FixedBytes<32>>) -> Result<u64, RpcError>;
+
+    /// Gas the signer's `transferFunds` of `amount` wei to `treasury` would
+    /// use, against the current state
+    async fn estimate_transfer_gas(&self, treasury: Address, amount: U256)
+        -> Result<u64, RpcError>;
+
+    /// Current gas price in wei
+    async fn gas_price(&self) -> Result<u128, RpcError>;
+}
+
+#[cfg(test)]
+pub mod fake {
+    //! In-memory chain for routing tests
+
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Mutex;
+
+    use alloy::primitives::{keccak256, Address, Bytes, FixedBytes, U256};
+
+    use super::{ChainClient, RouterReceipt};
+    use crate::{create2::compute_proxy_address, rpc::RpcError};
+
+    /// Account the fake chain signs with
+    pub const SIGNER: Address = Address::repeat_byte(0x5e);
+
+    /// Default treasury, the one in [`crate::config::test_config`]
+    pub const TREASURY: Address = Address::repeat_byte(0x7e);
+
+    /// Contracts proxies are deployed with, the ones in
+    /// [`crate::config::test_config`]
+    pub const DEPLOYER: Address = Address::repeat_byte(0xde);
+    pub const ROUTER: Address = Address::repeat_byte(0x70);
+    pub const INIT_CODE_HASH: FixedBytes<32> = FixedBytes::repeat_byte(0x1c);
+
+    /// Runtime code placed on addresses marked as deployed
+    const PROXY_CODE: &[u8] = &[0x60, 0x00, 0x80, 0xfd];
+
+    /// Gas reported by the estimates: a fixed cost per transaction and per
+    /// deployed proxy
+    pub const TX_GAS: u64 = 30_000;
+    pub const DEPLOY_GAS_PER_PROXY: u64 = 60_000;
+
+    /// Gas price reported by the fake chain, 1 gwei
+    pub const GAS_PRICE: u128 = 1_000_000_000;
+
+    #[derive(Default)]
+    struct State {
+        balances: HashMap<Address, U256>,
+        /// Balances set by `fund` and `send`, with the block they were set in
+        balance_history: Vec<(u64, Address, U256)>,
+        block_timestamps: HashMap<u64, u64>,
+        code: HashMap<Address, Bytes>,
+        deployed_salts: HashSet<FixedBytes<32>>,
+        /// ETH the router received, in block order
+        receipts: Vec<RouterReceipt>,
+        tx_count: u64,
+        deploy_calls: usize,
+        /// Block ranges the router's receipts were requested for
+        receipt_scans: Vec<(u64, u64)>,
+        transfers: Vec<(Address, U256)>,
+        failing_balances: HashSet<Address>,
+        failing_transfers: HashSet<Address>,
+        deploy_error: Option<String>,
+        chain_id: Option<u64>,
+        offline: bool,
+        deployers: HashMap<Address, (Address, FixedBytes<32>)>,
+        router_storage: HashMap<Address, Address>,
+    }
+
+    /// A programmable chain that keeps balances and deployments in memory
+    ///
+    /// Like the real proxies, a deployed proxy forwards ETH sent to it (see
+    /// [`FakeChain::send`]) to [`ROUTER`], and transfers pay treasuries out
+    /// of the router's balance. ETH an address held before its proxy was
+    /// deployed stays where it is.
+    #[derive(Default)]
+    pub struct FakeChain {
+        state: Mutex<State>,
+    }
+
+    impl FakeChain {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Set the balance of an address, e.g. ETH that reached a deposit
+        /// address before its proxy was deployed
+        pub fn fund(&self, address: Address, wei: u64) {
+            let mut state = self.state.lock().unwrap();
+            let block = state.tx_count;
+            state.balances.insert(address, U256::from(wei));
+            state
+                .balance_history
+                .push((block, address, U256::from(wei)));
+        }
+
+        /// Send ETH to an address in a new block; a deployed proxy forwards
+        /// it to the router
+        pub fn send(&self, address: Address, wei: u64) {
+            let mut state = self.state.lock().unwrap();
+            state.tx_count += 1;
+            let block = state.tx_count;
+            let wei = U256::from(wei);
+            if state.code.contains_key(&address) {
+                *state.balances.entry(ROUTER).or_default() += wei;
+                state.receipts.push(RouterReceipt {
+                    from: address,
+                    block,
+                    wei,
+                });
+            } else {
+                let balance = state.balances.entry(address).or_default();
+                *balance += wei;
+                let balance = *balance;
+                state.balance_history.push((block, address, balance));
+            }
+        }
+
+        /// Mark an address as already having code
+        pub fn set_code(&self, address: Address) {
+            self.state
+                .lock()
+                .unwrap()
+                .code
+                .insert(address, Bytes::from_static(PROXY_CODE));
+        }
+
+        /// Make balance reads for an address fail with a transport error
+        pub fn fail_balance(&self, address: Address) {
+            self.state.lock().unwrap().failing_balances.insert(address);
+        }
+
+        /// Make `transferFunds` to a treasury revert
+        pub fn fail_transfer(&self, treasury: Address) {
+            self.state
+                .lock()
+                .unwrap()
+                .failing_transfers
+                .insert(treasury);
+        }
+
+        /// Make the next `deployMultiple` calls revert
+        pub fn fail_deploy(&self, reason: &str) {
+            self.state.lock().unwrap().deploy_error = Some(reason.to_string());
+        }
+
+        /// Report a chain ID other than the Hardhat default 31337
+        pub fn set_chain_id(&self, chain_id: u64) {
+            self.state.lock().unwrap().chain_id = Some(chain_id);
+        }
+
+        /// Make block number reads fail, as if the RPC endpoint were down
+        pub fn go_offline(&self) {
+            self.state.lock().unwrap().offline = true;
+        }
+
+        /// Answer the deployer's view functions for `deployer`
+        pub fn set_deployer(&self, deployer: Address, router: Address, hash: FixedBytes<32>) {
+            self.state
+                .lock()
+                .unwrap()
+                .deployers
+                .insert(deployer, (router, hash));
+        }
+
+        /// Answer `STORAGE()` for `router`
+        pub fn set_router_storage(&self, router: Address, storage: Address) {
+            self.state
+                .lock()
+                .unwrap()
+                .router_storage
+                .insert(router, storage);
+        }
+
+        pub fn balance_of(&self, address: Address) -> U256 {
+            self.state
+                .lock()
+                .unwrap()
+                .balances
+                .get(&address)
+                .copied()
+                .unwrap_or_default()
+        }
+
+        /// Balance of the default treasury
+        pub fn treasury_balance(&self) -> U256 {
+            self.balance_of(TREASURY)
+        }
+
+        pub fn deploy_calls(&self) -> usize {
+            self.state.lock().unwrap().deploy_calls
+        }
+
+        /// Block ranges the router's receipts were requested for
+        pub fn receipt_scans(&self) -> Vec<(u64, u64)> {
+            self.state.lock().unwrap().receipt_scans.clone()
+        }
+
+        /// Set the timestamp of a block; blocks without one report the
+        /// current time
+        pub fn set_block_timestamp(&self, block: u64, timestamp: u64) {
+            self.state
+                .lock()
+                .unwrap()
+                .block_timestamps
+                .insert(block, timestamp);
+        }
+
+        /// Mine `blocks` empty blocks
+        pub fn mine(&self, blocks: u64) {
+            self.state.lock().unwrap().tx_count += blocks;
+        }
+
+        /// Treasuries and amounts of the `transferFunds` calls sent
+        pub fn transfers(&self) -> Vec<(Address, U256)> {
+            self.state.lock().unwrap().transfers.clone()
+        }
+    }
+
+    impl State {
+        fn next_tx_hash(&mut self) -> FixedBytes<32> {
+            self.tx_count += 1;
+            keccak256(self.tx_count.to_be_bytes())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ChainClient for FakeChain {
+        fn signer_address(&self) -> Address {
+            SIGNER
+        }
+
+        fn treasury_address(&self) -> Address {
+            TREASURY
+        }
+
+        fn router_address(&self) -> Address {
+            ROUTER
+        }
+
+        async fn get_balance(&self, address: Address) -> Result<U256, RpcError> {
+            let state = self.state.lock().unwrap();
+            if state.failing_balances.contains(&address) {
+                return Err(RpcError::Transport("connection refused".to_string()));
+            }
+            Ok(state.balances.get(&address).copied().unwrap_or_default())
+        }
+
+        async fn balance_at(&self, address: Address, block: u64) -> Result<U256, RpcError> {
+            let state = self.state.lock().unwrap();
+            if state.failing_balances.contains(&address) {
+                return Err(RpcError::Transport("connection refused".to_string()));
+            }
+            Ok(state
+                .balance_history
+                .iter()
+                .rev()
+                .find(|(set_in, set_for, _)| *set_for == address && *set_in <= block)
+                .map(|(_, _, balance)| *balance)
+                .unwrap_or_default())
+        }
+
+        async fn block_timestamp(&self, block: u64) -> Result<u64, RpcError> {
+            let state = self.state.lock().unwrap();
+            Ok(state
+                .block_timestamps
+                .get(&block)
+                .copied()
+                .unwrap_or_else(|| {
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map_or(0, |elapsed| elapsed.as_secs())
+                }))
+        }
+
+        async fn get_code(&self, address: Address) -> Result<Bytes, RpcError> {
+            let state = self.state.lock().unwrap();
+            Ok(state.code.get(&address).cloned().unwrap_or_default())
+        }
+
+        async fn chain_id(&self) -> Result<u64, RpcError> {
+            Ok(self.state.lock().unwrap().chain_id.unwrap_or(31337))
+        }
+
+        async fn block_number(&self) -> Result<u64, RpcError> {
+            let state = self.state.lock().unwrap();
+            if state.offline {
+                return Err(RpcError::Transport("connection refused".to_string()));
+            }
+            // One block per transaction
+            Ok(state.tx_count)
+        }
+
+        async fn deployer_fund_router(&self, deployer: Address) -> Result<Address, RpcError> {
+            self.state
+                .lock()
+                .unwrap()
+                .deployers
+                .get(&deployer)
+                .map(|(router, _)| *router)
+                .ok_or_else(|| RpcError::ContractCall("execution reverted".to_string()))
+        }
+
+        async fn deployer_init_code_hash(
+            &self,
+            deployer: Address,
+        ) -> Result<FixedBytes<32>, RpcError> {
+            self.state
+                .lock()
+                .unwrap()
+                .deployers
+                .get(&deployer)
+                .map(|(_, hash)| *hash)
+                .ok_or_else(|| RpcError::ContractCall("execution reverted".to_string()))
+        }
+
+        async fn router_storage(&self, router: Address) -> Result<Address, RpcError> {
+            self.state
+                .lock()
+                .unwrap()
+                .router_storage
+                .get(&router)
+                .copied()
+                .ok_or_else(|| RpcError::ContractCall("execution reverted".to_string()))
+        }
+
+        async fn deploy_multiple(
+            &self,
+            salts: Vec<FixedBytes<32>>,
+        ) -> Result<FixedBytes<32>, RpcError> {
+            let mut state = self.state.lock().unwrap();
+            state.deploy_calls += 1;
+
+            if salts.is_empty() {
+                return Err(RpcError::ContractCall("No salts provided".to_string()));
+            }
+            if let Some(reason) = &state.deploy_error {
+                return Err(RpcError::TransactionFailed(reason.clone()));
+            }
+            // CREATE2 to an occupied address reverts the whole batch
+            if salts.iter().any(|s| state.deployed_salts.contains(s)) {
+                return Err(RpcError::TransactionFailed(
+                    "Transaction reverted".to_string(),
+                ));
+            }
+
+            for salt in &salts {
+                let proxy =
+                    compute_proxy_address(&DEPLOYER.0 .0, &INIT_CODE_HASH.0, &SIGNER.0 .0, &salt.0);
+                state
+                    .code
+                    .insert(Address::from(proxy), Bytes::from_static(PROXY_CODE));
+            }
+            state.deployed_salts.extend(salts);
+            Ok(state.next_tx_hash())
+        }
+
+        async fn router_receipts(
+            &self,
+            from_block: u64,
+            to_block: u64,
+        ) -> Result<Vec<RouterReceipt>, RpcError> {
+            let mut state = self.state.lock().unwrap();
+            state.receipt_scans.push((from_block, to_block));
+            Ok(state
+                .receipts
+                .iter()
+                .filter(|receipt| (from_block..=to_block).contains(&receipt.block))
+                .copied()
+                .collect())
+        }
+
+        async fn transfer_from_router(
+            &self,
+            treasury: Address,
+            amount: U256,
+        ) -> Result<FixedBytes<32>, RpcError> {
+            let mut state = self.state.lock().unwrap();
+            state.transfers.push((treasury, amount));
+
+            let router = state.balances.get(&ROUTER).copied().unwrap_or_default();
+            if state.failing_transfers.contains(&treasury) || router < amount {
+                return Err(RpcError::TransactionFailed(
+                    "Transaction reverted".to_string(),
+                ));
+            }
+            state.balances.insert(ROUTER, router - amount);
+            *state.balances.entry(treasury).or_default() += amount;
+
+            Ok(state.next_tx_hash())
+        }
+
+        async fn estimate_deploy_gas(&self, salts: Vec<FixedBytes<32>>) -> Result<u64, RpcError> {
+            let state = self.state.lock().unwrap();
+            if let Some(reason) = &state.deploy_error {
+                return Err(RpcError::ContractCall(reason.clone()));
+            }
+            Ok(TX_GAS + DEPLOY_GAS_PER_PROXY * salts.len() as u64)
+        }
+
+        async fn estimate_transfer_gas(
+            &self,
+            treasury: Address,
+            amount: U256,
+        ) -> Result<u64, RpcError> {
+            let state = self.state.lock().unwrap();
+            let router = state.balances.get(&ROUTER).copied().unwrap_or_default();
+            if state.failing_transfers.contains(&treasury) || router < amount {
+                return Err(RpcError::ContractCall("execution reverted".to_string()));
+            }
+            Ok(TX_GAS)
+        }
+
+        async fn gas_price(&self) -> Result<u128, RpcError> {
+            Ok(GAS_PRICE)
+        }
+    }
+}
@@ -0,0 +1,551 @@
+//! Configuration checks against the chain
+//!
+//! Catches the usual copy-paste mistakes when pointing the backend at a new
+//! deployment: a stale init code hash, addresses from another network, or a
+//! signer without gas money. The server runs them once at startup; in strict
+//! mode a failed check aborts startup, in lenient mode it is only logged. The
+//! report is kept for `/health`.
+//!
+//! At startup the stored deposit addresses are also recomputed from the
+//! configured deployer, init code hash and signer (see
+//! [`check_deposit_addresses`]).
+
+use std::str::FromStr;
+
+use alloy::primitives::{Address, FixedBytes, U256};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::{
+    chain::ChainClient,
+    config::Config,
+    create2::{self, compute_deposit_address, format_address, proxy_init_code_hash},
+    db,
+    webhooks::{self, WebhookEvent},
+};
+
+/// Statuses of deposits whose proxies aren't deployed yet, so a deployment
+/// would put them wherever the current configuration derives
+const UNDEPLOYED_STATUSES: &[&str] = &["pending", "funded", "dead_letter"];
+
+/// What a failed startup check does
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckMode {
+    /// Refuse to start
+    Strict,
+    /// Log a warning and start anyway
+    #[default]
+    Lenient,
+}
+
+impl FromStr for CheckMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(CheckMode::Strict),
+            "lenient" => Ok(CheckMode::Lenient),
+            other => Err(format!("unknown check mode '{}'", other)),
+        }
+    }
+}
+
+/// Outcome of a single check
+#[derive(Clone, Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            ok: true,
+            detail: detail.into(),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            ok: false,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Result of the startup verification phase
+#[derive(Clone, Debug, Serialize)]
+pub struct CheckReport {
+    pub mode: CheckMode,
+    pub ok: bool,
+    pub checks: Vec<Check>,
+}
+
+impl CheckReport {
+    pub fn new(mode: CheckMode, checks: Vec<Check>) -> Self {
+        Self {
+            mode,
+            ok: checks.iter().all(|c| c.ok),
+            checks,
+        }
+    }
+
+    /// Failed checks, for error messages
+    pub fn failures(&self) -> Vec<String> {
+        self.checks
+            .iter()
+            .filter(|c| !c.ok)
+            .map(|c| format!("{}: {}", c.name, c.detail))
+            .collect()
+    }
+}
+
+/// Run all checks and log the outcome
+///
+/// Failures are logged as errors in strict mode and as warnings in lenient
+/// mode; the caller decides whether to abort.
+pub async fn startup_checks(
+    pool: &SqlitePool,
+    config: &Config,
+    chain: &dyn ChainClient,
+) -> CheckReport {
+    let mut checks = check_config(config, chain).await;
+    checks.push(check_deposit_addresses(pool, config, chain, config.startup_checks).await);
+    let report = CheckReport::new(config.startup_checks, checks);
+
+    for check in &report.checks {
+        match (check.ok, report.mode) {
+            (true, _) => tracing::info!(check = check.name, "{}", check.detail),
+            (false, CheckMode::Strict) => tracing::error!(check = check.name, "{}", check.detail),
+            (false, CheckMode::Lenient) => tracing::warn!(check = check.name, "{}", check.detail),
+        }
+    }
+
+    report
+}
+
+/// Validate the configuration, then check it against the chain
+pub async fn check_config(config: &Config, chain: &dyn ChainClient) -> Vec<Check> {
+    let mut checks = vec![];
+
+    let deployer = config.deployer_bytes();
+    let router = config.router_bytes();
+    let init_code_hash = config.init_code_hash_bytes();
+
+    let storage = config.storage_bytes().transpose();
+
+    for (name, result) in [
+        ("deployer_address", deployer.as_ref().err()),
+        ("router_address", router.as_ref().err()),
+        ("init_code_hash", init_code_hash.as_ref().err()),
+        (
+            "storage_address",
+            storage.as_ref().and_then(|s| s.as_ref().err()),
+        ),
+    ] {
+        if let Some(e) = result {
+            checks.push(Check::fail(name, e.to_string()));
+        }
+    }
+    if config.treasury_bytes().is_err() {
+        checks.push(Check::fail("treasury_address", "Invalid address format"));
+    }
+    for treasury in &config.treasury_allowlist {
+        if Address::from_str(treasury).is_err() {
+            checks.push(Check::fail(
+                "treasury_allowlist",
+                format!("Invalid address format: {}", treasury),
+            ));
+        }
+    }
+
+    checks.push(match (chain.chain_id().await, config.chain_id) {
+        (Ok(actual), Some(expected)) if actual == expected => {
+            Check::pass("chain_id", actual.to_string())
+        }
+        (Ok(actual), Some(expected)) => Check::fail(
+            "chain_id",
+            format!("RPC reports chain {}, expected {}", actual, expected),
+        ),
+        (Ok(actual), None) => Check::pass(
+            "chain_id",
+            format!("{} (no expected chain ID configured)", actual),
+        ),
+        (Err(e), _) => Check::fail("chain_id", e.to_string()),
+    });
+
+    if let (Ok(router), Ok(hash)) = (&router, &init_code_hash) {
+        let expected = proxy_init_code_hash(router);
+        checks.push(if &expected == hash {
+            Check::pass("init_code_hash", "matches ROUTER_ADDRESS")
+        } else {
+            Check::fail(
+                "init_code_hash",
+                format!(
+                    "INIT_CODE_HASH does not match ROUTER_ADDRESS, expected 0x{}",
+                    hex::encode(expected)
+                ),
+            )
+        });
+    }
+
+    for (name, address) in [("deployer_code", &deployer), ("router_code", &router)] {
+        if let Ok(address) = address {
+            checks.push(match chain.get_code(Address::from(*address)).await {
+                Ok(code) if !code.is_empty() => {
+                    Check::pass(name, format!("{} bytes of code", code.len()))
+                }
+                Ok(_) => Check::fail(name, "no code at address"),
+                Err(e) => Check::fail(name, e.to_string()),
+            });
+        }
+    }
+
+    if let Ok(deployer) = &deployer {
+        let deployer = Address::from(*deployer);
+
+        if let Ok(router) = &router {
+            let router = Address::from(*router);
+            checks.push(match chain.deployer_fund_router(deployer).await {
+                Ok(actual) if actual == router => Check::pass(
+                    "deployer_router",
+                    "FUND_ROUTER_ADDRESS() matches ROUTER_ADDRESS",
+                ),
+                Ok(actual) => Check::fail(
+                    "deployer_router",
+                    format!("FUND_ROUTER_ADDRESS() is {}, expected {}", actual, router),
+                ),
+                Err(e) => Check::fail("deployer_router", e.to_string()),
+            });
+        }
+
+        if let Ok(hash) = &init_code_hash {
+            let hash = FixedBytes::from(*hash);
+            checks.push(match chain.deployer_init_code_hash(deployer).await {
+                Ok(actual) if actual == hash => Check::pass(
+                    "deployer_init_code_hash",
+                    "getInitCodeHash() matches INIT_CODE_HASH",
+                ),
+                Ok(actual) => Check::fail(
+                    "deployer_init_code_hash",
+                    format!("getInitCodeHash() is {}, expected {}", actual, hash),
+                ),
+                Err(e) => Check::fail("deployer_init_code_hash", e.to_string()),
+            });
+        }
+    }
+
+    if let Ok(router) = &router {
+        checks.push(match chain.router_storage(Address::from(*router)).await {
+            Ok(actual) => match &storage {
+                Some(Ok(expected)) if actual == Address::from(*expected) => {
+                    Check::pass("router_storage", "STORAGE() matches STORAGE_ADDRESS")
+                }
+                Some(Ok(expected)) => Check::fail(
+                    "router_storage",
+                    format!(
+                        "STORAGE() is {}, expected {}",
+                        actual,
+                        Address::from(*expected)
+                    ),
+                ),
+                // Without an expected address, at least require a contract there
+                _ => match chain.get_code(actual).await {
+                    Ok(code) if !code.is_empty() => Check::pass(
+                        "router_storage",
+                        format!("STORAGE() is {} (no STORAGE_ADDRESS configured)", actual),
+                    ),
+                    Ok(_) => Check::fail(
+                        "router_storage",
+                        format!("STORAGE() is {}, which has no code", actual),
+                    ),
+                    Err(e) => Check::fail("router_storage", e.to_string()),
+                },
+            },
+            Err(e) => Check::fail("router_storage", e.to_string()),
+        });
+    }
+
+    checks.push(match chain.get_balance(chain.signer_address()).await {
+        Ok(balance) if balance > U256::ZERO => {
+            Check::pass("signer_balance", format!("{} wei", balance))
+        }
+        Ok(_) => Check::fail("signer_balance", "signer has no ETH for gas"),
+        Err(e) => Check::fail("signer_balance", e.to_string()),
+    });
+
+    checks
+}
+
+/// Recompute the address of every deposit whose proxy isn't deployed yet
+///
+/// An address depends on the deployer, init code hash and signer it was
+/// derived from, so deposits created under another configuration, or before
+/// addresses were derived from the signer, would get their proxy deployed
+/// elsewhere and their funds never forwarded. In lenient mode such deposits
+/// are marked `failed` with the address a deployment would use; in strict
+/// mode nothing is written, since startup is refused anyway.
+pub async fn check_deposit_addresses(
+    pool: &SqlitePool,
+    config: &Config,
+    chain: &dyn ChainClient,
+    mode: CheckMode,
+) -> Check {
+    const NAME: &str = "deposit_addresses";
+    let (Ok(deployer), Ok(init_code_hash)) =
+        (config.deployer_bytes(), config.init_code_hash_bytes())
+    else {
+        return Check::fail(NAME, "DEPLOYER_ADDRESS or INIT_CODE_HASH is invalid");
+    };
+    let signer = chain.signer_address().0 .0;
+    let deposits = match db::get_deposits_by_statuses(pool, UNDEPLOYED_STATUSES).await {
+        Ok(deposits) => deposits,
+        Err(e) => return Check::fail(NAME, format!("Database error: {}", e)),
+    };
+
+    let mut mismatched = vec![];
+    for deposit in &deposits {
+        let expected = create2::parse_address(&deposit.user_address).map(|user| {
+            let nonce = u64::try_from(deposit.nonce).unwrap_or_default();
+            let (address, _) =
+                compute_deposit_address(&deployer, &init_code_hash, &signer, &user, nonce);
+            format_address(&address)
+        });
+        match &expected {
+            Ok(address) if *address == deposit.deposit_address => {}
+            _ => mismatched.push((deposit, expected)),
+        }
+    }
+    if mismatched.is_empty() {
+        return Check::pass(
+            NAME,
+            format!(
+                "{} undeployed deposits match the configuration",
+                deposits.len()
+            ),
+        );
+    }
+
+    if mode == CheckMode::Lenient {
+        for (deposit, expected) in &mismatched {
+            let error = match expected {
+                Ok(expected) => format!(
+                    "Address doesn't match the configured deployer, init code hash and signer, which derive {}",
+                    expected
+                ),
+                Err(e) => format!("Invalid user address: {}", e),
+            };
+            tracing::warn!(deposit = %deposit.deposit_address, "{}", error);
+            let flagged =
+                db::record_deposit_failure(pool, &deposit.deposit_address, "failed", &error, None)
+                    .await;
+            match flagged {
+                Ok(()) => {
+                    webhooks::notify(pool, &deposit.deposit_address, WebhookEvent::Failed).await
+                }
+                Err(e) => return Check::fail(NAME, format!("Database error: {}", e)),
+            }
+        }
+    }
+    Check::fail(
+        NAME,
+        format!(
+            "{} of {} undeployed deposits don't match the configured deployer, init code hash and signer{}",
+            mismatched.len(),
+            deposits.len(),
+            if mode == CheckMode::Lenient {
+                "; they were marked failed"
+            } else {
+                ""
+            }
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chain::fake::FakeChain, config::PartialConfig, create2::format_bytes32, signer::Secret,
+    };
+
+    const ROUTER: &str = "0x7238CA877BbAcC8C273C701636A2041F6569f266";
+    const DEPLOYER: &str = "0x2b05DAf67cc41957f60F74Ff7D3c4aB54840Fc8D";
+    const STORAGE: &str = "0x66C686Bc2DD44078f44B5E78b5106cFE16fC35f2";
+    const HASH: &str = "0x53610d10df2dbe6319490ceeb6b7252926cc1e0cea27682301027672215b2db1";
+    // Hardhat dev account #0
+    const KEY: &str = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+    fn config(init_code_hash: &str) -> Config {
+        PartialConfig {
+            rpc_url: Some("http://localhost:8545".to_string()),
+            deployer_address: Some(DEPLOYER.to_string()),
+            router_address: Some(ROUTER.to_string()),
+            treasury_address: Some(DEPLOYER.to_string()),
+            init_code_hash: Some(init_code_hash.to_string()),
+            private_key: Some(Secret::new(KEY)),
+            chain_id: Some(11155111),
+            storage_address: Some(STORAGE.to_string()),
+            startup_checks: Some(CheckMode::Strict),
+            ..PartialConfig::default()
+        }
+        .resolve()
+        .unwrap()
+    }
+
+    /// A chain matching the Sepolia deployment
+    fn sepolia() -> FakeChain {
+        let chain = FakeChain::new();
+        chain.set_chain_id(11155111);
+        chain.set_code(DEPLOYER.parse().unwrap());
+        chain.set_code(ROUTER.parse().unwrap());
+        chain.set_code(STORAGE.parse().unwrap());
+        chain.set_deployer(
+            DEPLOYER.parse().unwrap(),
+            ROUTER.parse().unwrap(),
+            HASH.parse().unwrap(),
+        );
+        chain.set_router_storage(ROUTER.parse().unwrap(), STORAGE.parse().unwrap());
+        chain.fund(chain.signer_address(), 1);
+        chain
+    }
+
+    fn failed(checks: &[Check]) -> Vec<&'static str> {
+        checks.iter().filter(|c| !c.ok).map(|c| c.name).collect()
+    }
+
+    #[tokio::test]
+    async fn test_consistent_config_passes() {
+        let checks = check_config(&config(HASH), &sepolia()).await;
+
+        assert!(failed(&checks).is_empty(), "{:?}", checks);
+        assert_eq!(checks.len(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_network_and_stale_hash_fail() {
+        let config = config(&format_bytes32(&[0u8; 32]));
+        let chain = FakeChain::new();
+
+        let checks = check_config(&config, &chain).await;
+
+        assert_eq!(
+            failed(&checks),
+            vec![
+                "chain_id",
+                "init_code_hash",
+                "deployer_code",
+                "router_code",
+                "deployer_router",
+                "deployer_init_code_hash",
+                "router_storage",
+                "signer_balance"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_contract_wiring_fails() {
+        let chain = sepolia();
+        let other = Address::repeat_byte(0x77);
+        chain.set_deployer(DEPLOYER.parse().unwrap(), other, FixedBytes::ZERO);
+        chain.set_router_storage(ROUTER.parse().unwrap(), other);
+
+        let checks = check_config(&config(HASH), &chain).await;
+
+        assert_eq!(
+            failed(&checks),
+            vec![
+                "deployer_router",
+                "deployer_init_code_hash",
+                "router_storage"
+            ]
+        );
+    }
+
+    /// Insert a deposit whose address is derived from `signer`
+    async fn seed(pool: &SqlitePool, signer: Address, nonce: u64, status: &str) -> String {
+        let user = [0x42u8; 20];
+        let (address, salt) = compute_deposit_address(
+            &DEPLOYER.parse::<Address>().unwrap().0 .0,
+            &HASH.parse::<FixedBytes<32>>().unwrap().0,
+            &signer.0 .0,
+            &user,
+            nonce,
+        );
+        let address = format_address(&address);
+        db::insert_deposit(
+            pool,
+            &format_address(&user),
+            &format_bytes32(&salt),
+            &address,
+            nonce,
+            None,
+        )
+        .await
+        .unwrap();
+        db::update_deposit_status(pool, &address, status)
+            .await
+            .unwrap();
+        address
+    }
+
+    async fn status_of(pool: &SqlitePool, address: &str) -> String {
+        db::get_deposit_by_address(pool, address)
+            .await
+            .unwrap()
+            .unwrap()
+            .status
+    }
+
+    #[tokio::test]
+    async fn test_deposits_from_another_signer_are_flagged() {
+        let pool = db::test_pool().await;
+        let chain = sepolia();
+        let other = Address::repeat_byte(0x77);
+        let current = seed(&pool, chain.signer_address(), 0, "funded").await;
+        let stale = seed(&pool, other, 1, "funded").await;
+        // Its proxy is deployed already, wherever the address came from
+        let routed = seed(&pool, other, 2, "routed").await;
+
+        // Strict mode refuses to start without touching anything
+        let check = check_deposit_addresses(&pool, &config(HASH), &chain, CheckMode::Strict).await;
+        assert!(!check.ok);
+        assert!(check.detail.starts_with("1 of 2"), "{}", check.detail);
+        assert_eq!(status_of(&pool, &stale).await, "funded");
+
+        let check = check_deposit_addresses(&pool, &config(HASH), &chain, CheckMode::Lenient).await;
+        assert!(!check.ok);
+        assert_eq!(status_of(&pool, &stale).await, "failed");
+        assert_eq!(status_of(&pool, &current).await, "funded");
+        assert_eq!(status_of(&pool, &routed).await, "routed");
+        let row = db::get_deposit_by_address(&pool, &stale)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(row.last_error.unwrap().starts_with("Address doesn't match"));
+
+        // Flagged deposits are left alone from then on
+        let check = check_deposit_addresses(&pool, &config(HASH), &chain, CheckMode::Lenient).await;
+        assert!(check.ok, "{}", check.detail);
+    }
+
+    #[tokio::test]
+    async fn test_storage_without_expected_address_needs_code() {
+        let mut config = config(HASH);
+        config.storage_address = None;
+        let chain = sepolia();
+        chain.set_router_storage(ROUTER.parse().unwrap(), Address::repeat_byte(0x77));
+
+        let report = startup_checks(&db::test_pool().await, &config, &chain).await;
+
+        assert!(!report.ok);
+        assert_eq!(report.mode, CheckMode::Strict);
+        assert_eq!(report.failures().len(), 1);
+        assert!(report.failures()[0].starts_with("router_storage"));
+    }
+}
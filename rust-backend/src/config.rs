@@ -1,4 +1,64 @@
-use std::env;
+//! Layered configuration
+//!
+//! Values are resolved from, highest precedence first:
+//! 1. command line flags ([`ConfigArgs`])
+//! 2. environment variables
+//! 3. the selected `[networks.<name>]` profile of the TOML config file
+//! 4. the profile's `deployments.json` entry (contracts, treasury, init code hash)
+//! 5. top-level values of the TOML config file
+//! 6. built-in defaults
+//!
+//! See `radhat.example.toml` for the file format.
+
+use alloy::primitives::U256;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    checks::CheckMode,
+    rate_limit::{Quota, RateLimitStore},
+    signer::{Secret, SignerConfig},
+    telemetry::LogFormat,
+};
+
+const DEFAULT_DATABASE_URL: &str = "sqlite://data.db";
+
+/// 0.01 ETH, enough for a few deployMultiple transactions on Sepolia
+const DEFAULT_MIN_SIGNER_BALANCE_WEI: u64 = 10_000_000_000_000_000;
+
+/// One day
+const DEFAULT_SESSION_TTL_SECS: u64 = 86_400;
+
+/// Routing runs renew their lease every third of this; a crashed run blocks
+/// routing for at most this long
+const DEFAULT_ROUTING_LEASE_SECS: u64 = 300;
+
+/// Stored idempotent responses are replayed for a day
+const DEFAULT_IDEMPOTENCY_TTL_SECS: u64 = 86_400;
+
+/// A deposit whose deployment keeps failing is retried after 1, 2, 4 and 8
+/// minutes, then dead-lettered
+const DEFAULT_RETRY_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_RETRY_BASE_DELAY_SECS: u64 = 60;
+
+/// A webhook delivery is retried after 30 seconds, doubling up to an hour
+/// between attempts, about a day in all before it fails
+const DEFAULT_WEBHOOK_MAX_ATTEMPTS: u32 = 12;
+const DEFAULT_WEBHOOK_BASE_DELAY_SECS: u64 = 30;
+
+/// Deposit creation limits; generous for people, tight for scripts
+const DEFAULT_RATE_LIMIT_PER_IP: Quota = Quota::per_minute(30);
+const DEFAULT_RATE_LIMIT_PER_KEY: Quota = Quota::per_minute(300);
+const DEFAULT_RATE_LIMIT_PER_USER: Quota = Quota::per_minute(10);
+const DEFAULT_MAX_PENDING_DEPOSITS: u64 = 20;
+/// Pending deposits older than a day no longer count towards the cap, so
+/// addresses that were never funded don't lock a user out
+const DEFAULT_PENDING_DEPOSIT_WINDOW_SECS: u64 = 86_400;
 
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -7,34 +67,149 @@ pub struct Config {
     pub deployer_address: String,
     pub router_address: String,
     pub treasury_address: String,
+    /// Other treasuries a single deposit may be routed to on request; the
+    /// configured treasury is always allowed
+    pub treasury_allowlist: Vec<String>,
     pub init_code_hash: String,
-    pub private_key: String,
+    pub signer: SignerConfig,
     pub host: String,
     pub port: u16,
+    /// Selected network profile, if any
+    pub network: Option<String>,
+    /// Expected chain ID, if known
+    pub chain_id: Option<u64>,
+    /// Expected FundRouter.STORAGE(), if known
+    pub storage_address: Option<String>,
+    /// Whether failed startup checks abort startup
+    pub startup_checks: CheckMode,
+    /// Signer balance below which health reports "degraded"
+    pub min_signer_balance_wei: U256,
+    /// Pending + funded deposits above which health reports "degraded"
+    pub max_deposit_backlog: u64,
+    /// Age of the last clean routing run above which health reports
+    /// "degraded"; unset when nothing routes on a schedule
+    pub max_routing_age_secs: Option<u64>,
+    /// OTLP/HTTP collector to export traces to, e.g. `http://localhost:4318`
+    pub otlp_endpoint: Option<String>,
+    /// Log line format on stdout
+    pub log_format: LogFormat,
+    /// Shorten user and contract addresses in logs and spans
+    pub redact_addresses: bool,
+    /// Reject requests without a valid API key (see `auth`)
+    pub require_api_keys: bool,
+    /// Bearer token `GET /metrics` requires; served to anyone while unset
+    pub metrics_token: Option<Secret>,
+    /// Origins allowed by CORS; `*` allows any
+    pub cors_origins: Vec<String>,
+    /// Domain SIWE messages must be issued for, e.g. `app.radhat.xyz`;
+    /// wallet sign-in is disabled while unset
+    pub siwe_domain: Option<String>,
+    /// Lifetime of a wallet session
+    pub session_ttl_secs: u64,
+    /// `POST /deposit` requests allowed per client IP
+    pub rate_limit_per_ip: Quota,
+    /// `POST /deposit` requests allowed per API key
+    pub rate_limit_per_key: Quota,
+    /// `POST /deposit` requests allowed per `user` address
+    pub rate_limit_per_user: Quota,
+    /// Pending deposits a user may hold before new ones are refused; 0 for
+    /// no cap
+    pub max_pending_deposits: u64,
+    /// How long a pending deposit counts towards `max_pending_deposits`
+    pub pending_deposit_window_secs: u64,
+    /// Where rate limit buckets are kept
+    pub rate_limit_store: RateLimitStore,
+    /// Take the client IP from `X-Forwarded-For` (behind a reverse proxy)
+    pub trust_forwarded_for: bool,
+    /// How long responses stored under an `Idempotency-Key` are replayed
+    pub idempotency_ttl_secs: u64,
+    /// Expiry of the routing lease and deposit claims without a heartbeat
+    pub routing_lease_secs: u64,
+    /// Failed attempts after which a deposit is dead-lettered
+    pub retry_max_attempts: u32,
+    /// Delay before the first retry; doubles with each further attempt
+    pub retry_base_delay_secs: u64,
+    /// Attempts at a webhook delivery before it is marked failed
+    pub webhook_max_attempts: u32,
+    /// Delay before a webhook delivery is retried; doubles with each further
+    /// attempt
+    pub webhook_base_delay_secs: u64,
+    /// Let webhooks target loopback, private and link-local addresses
+    pub webhook_allow_private_targets: bool,
 }
 
 impl Config {
+    /// Load configuration from environment variables only
     pub fn from_env() -> Result<Self, ConfigError> {
-        Ok(Self {
-            database_url: env::var("DATABASE_URL")
-                .unwrap_or_else(|_| "sqlite://data.db".to_string()),
-            rpc_url: env::var("RPC_URL").map_err(|_| ConfigError::MissingVar("RPC_URL"))?,
-            deployer_address: env::var("DEPLOYER_ADDRESS")
-                .map_err(|_| ConfigError::MissingVar("DEPLOYER_ADDRESS"))?,
-            router_address: env::var("ROUTER_ADDRESS")
-                .map_err(|_| ConfigError::MissingVar("ROUTER_ADDRESS"))?,
-            treasury_address: env::var("TREASURY_ADDRESS")
-                .map_err(|_| ConfigError::MissingVar("TREASURY_ADDRESS"))?,
-            init_code_hash: env::var("INIT_CODE_HASH")
-                .map_err(|_| ConfigError::MissingVar("INIT_CODE_HASH"))?,
-            private_key: env::var("PRIVATE_KEY")
-                .map_err(|_| ConfigError::MissingVar("PRIVATE_KEY"))?,
-            host: env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string()),
-            port: env::var("PORT")
-                .unwrap_or_else(|_| "3001".to_string())
-                .parse()
-                .map_err(|_| ConfigError::InvalidPort)?,
-        })
+        PartialConfig::from_env()?.resolve()
+    }
+
+    /// Load configuration from all layers
+    pub fn load(args: &ConfigArgs) -> Result<Self, ConfigError> {
+        Self::load_with_env(args, PartialConfig::from_env()?)
+    }
+
+    /// Load configuration from all layers, with `env` as the environment
+    /// variables
+    fn load_with_env(args: &ConfigArgs, env: PartialConfig) -> Result<Self, ConfigError> {
+        let (layered, network) = Self::layers(args, env)?;
+        let mut config = layered.resolve()?;
+        config.network = network;
+        Ok(config)
+    }
+
+    /// Resolve only the database URL, for commands that never touch the chain
+    pub fn database_url(args: &ConfigArgs) -> Result<String, ConfigError> {
+        let (layered, _) = Self::layers(args, PartialConfig::from_env()?)?;
+        Ok(layered
+            .database_url
+            .unwrap_or_else(|| DEFAULT_DATABASE_URL.to_string()))
+    }
+
+    /// Merge every layer, returning the values and the selected network
+    fn layers(
+        args: &ConfigArgs,
+        env: PartialConfig,
+    ) -> Result<(PartialConfig, Option<String>), ConfigError> {
+        let file = args.config.as_deref().map(FileConfig::read).transpose()?;
+        let network = args
+            .network
+            .clone()
+            .or_else(|| file.as_ref().and_then(|f| f.network.clone()));
+
+        let mut layered = args.overrides().or(env);
+
+        let mut deployments = args.deployments.clone();
+        let mut deployment_key = network.clone();
+        if let Some(file) = &file {
+            if let Some(name) = &network {
+                let profile = file
+                    .networks
+                    .get(name)
+                    .ok_or_else(|| ConfigError::UnknownNetwork(name.clone()))?;
+                layered = layered.or(profile.values.clone());
+                deployments = deployments.or_else(|| {
+                    profile
+                        .deployments
+                        .as_ref()
+                        .map(|path| file.base_dir.join(path))
+                });
+                deployment_key = profile.deployment.clone().or(deployment_key);
+            }
+        }
+
+        if let Some(path) = deployments {
+            let key = deployment_key.ok_or_else(|| {
+                ConfigError::Deployments("importing deployments.json needs a network".to_string())
+            })?;
+            layered = layered.or(PartialConfig::from_deployments(&path, &key)?);
+        }
+
+        if let Some(file) = file {
+            layered = layered.or(file.base);
+        }
+
+        Ok((layered, network))
     }
 
     /// Parse deployer address as bytes
@@ -42,6 +217,37 @@ impl Config {
         parse_address(&self.deployer_address)
     }
 
+    /// Parse router address as bytes
+    pub fn router_bytes(&self) -> Result<[u8; 20], ConfigError> {
+        parse_address(&self.router_address)
+    }
+
+    /// Parse treasury address as bytes
+    pub fn treasury_bytes(&self) -> Result<[u8; 20], ConfigError> {
+        parse_address(&self.treasury_address)
+    }
+
+    /// Whether funds may be routed to `treasury`: the configured treasury or
+    /// one on the allowlist
+    pub fn is_allowed_treasury(&self, treasury: &[u8; 20]) -> bool {
+        std::iter::once(&self.treasury_address)
+            .chain(&self.treasury_allowlist)
+            .any(|allowed| parse_address(allowed).is_ok_and(|a| &a == treasury))
+    }
+
+    /// Parse the expected storage address as bytes, if configured
+    pub fn storage_bytes(&self) -> Result<Option<[u8; 20]>, ConfigError> {
+        self.storage_address
+            .as_deref()
+            .map(parse_address)
+            .transpose()
+    }
+
+    /// Expiry of the routing lease
+    pub fn routing_lease_ttl(&self) -> Duration {
+        Duration::from_secs(self.routing_lease_secs)
+    }
+
     /// Parse init code hash as bytes
     pub fn init_code_hash_bytes(&self) -> Result<[u8; 32], ConfigError> {
         parse_bytes32(&self.init_code_hash)
@@ -70,14 +276,861 @@ fn parse_bytes32(s: &str) -> Result<[u8; 32], ConfigError> {
     Ok(arr)
 }
 
+/// Configuration values from a single source, all optional
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct PartialConfig {
+    pub database_url: Option<String>,
+    pub rpc_url: Option<String>,
+    pub deployer_address: Option<String>,
+    pub router_address: Option<String>,
+    pub treasury_address: Option<String>,
+    pub treasury_allowlist: Option<Vec<String>>,
+    pub init_code_hash: Option<String>,
+    /// Raw signing key (development only)
+    pub private_key: Option<Secret>,
+    /// Encrypted JSON keystore unlocked at startup
+    pub keystore_path: Option<PathBuf>,
+    pub keystore_password: Option<Secret>,
+    /// File holding the keystore password, e.g. a mounted secret
+    pub keystore_password_file: Option<PathBuf>,
+    /// Web3Signer-compatible remote signer
+    pub remote_signer_url: Option<String>,
+    pub remote_signer_address: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub chain_id: Option<u64>,
+    pub storage_address: Option<String>,
+    pub startup_checks: Option<CheckMode>,
+    /// Decimal wei amount
+    pub min_signer_balance_wei: Option<String>,
+    pub max_deposit_backlog: Option<u64>,
+    pub max_routing_age_secs: Option<u64>,
+    pub otlp_endpoint: Option<String>,
+    pub log_format: Option<LogFormat>,
+    pub redact_addresses: Option<bool>,
+    pub require_api_keys: Option<bool>,
+    pub metrics_token: Option<Secret>,
+    pub cors_origins: Option<Vec<String>>,
+    pub siwe_domain: Option<String>,
+    pub session_ttl_secs: Option<u64>,
+    pub rate_limit_per_ip: Option<Quota>,
+    pub rate_limit_per_key: Option<Quota>,
+    pub rate_limit_per_user: Option<Quota>,
+    pub max_pending_deposits: Option<u64>,
+    pub pending_deposit_window_secs: Option<u64>,
+    pub rate_limit_store: Option<RateLimitStore>,
+    pub trust_forwarded_for: Option<bool>,
+    pub idempotency_ttl_secs: Option<u64>,
+    pub routing_lease_secs: Option<u64>,
+    pub retry_max_attempts: Option<u32>,
+    pub retry_base_delay_secs: Option<u64>,
+    pub webhook_max_attempts: Option<u32>,
+    pub webhook_base_delay_secs: Option<u64>,
+    pub webhook_allow_private_targets: Option<bool>,
+}
+
+impl PartialConfig {
+    /// Keep values set here and take the rest from `lower`
+    pub fn or(self, lower: PartialConfig) -> Self {
+        Self {
+            database_url: self.database_url.or(lower.database_url),
+            rpc_url: self.rpc_url.or(lower.rpc_url),
+            deployer_address: self.deployer_address.or(lower.deployer_address),
+            router_address: self.router_address.or(lower.router_address),
+            treasury_address: self.treasury_address.or(lower.treasury_address),
+            treasury_allowlist: self.treasury_allowlist.or(lower.treasury_allowlist),
+            init_code_hash: self.init_code_hash.or(lower.init_code_hash),
+            private_key: self.private_key.or(lower.private_key),
+            keystore_path: self.keystore_path.or(lower.keystore_path),
+            keystore_password: self.keystore_password.or(lower.keystore_password),
+            keystore_password_file: self.keystore_password_file.or(lower.keystore_password_file),
+            remote_signer_url: self.remote_signer_url.or(lower.remote_signer_url),
+            remote_signer_address: self.remote_signer_address.or(lower.remote_signer_address),
+            host: self.host.or(lower.host),
+            port: self.port.or(lower.port),
+            chain_id: self.chain_id.or(lower.chain_id),
+            storage_address: self.storage_address.or(lower.storage_address),
+            startup_checks: self.startup_checks.or(lower.startup_checks),
+            min_signer_balance_wei: self.min_signer_balance_wei.or(lower.min_signer_balance_wei),
+            max_deposit_backlog: self.max_deposit_backlog.or(lower.max_deposit_backlog),
+            max_routing_age_secs: self.max_routing_age_secs.or(lower.max_routing_age_secs),
+            otlp_endpoint: self.otlp_endpoint.or(lower.otlp_endpoint),
+            log_format: self.log_format.or(lower.log_format),
+            redact_addresses: self.redact_addresses.or(lower.redact_addresses),
+            require_api_keys: self.require_api_keys.or(lower.require_api_keys),
+            metrics_token: self.metrics_token.or(lower.metrics_token),
+            cors_origins: self.cors_origins.or(lower.cors_origins),
+            siwe_domain: self.siwe_domain.or(lower.siwe_domain),
+            session_ttl_secs: self.session_ttl_secs.or(lower.session_ttl_secs),
+            rate_limit_per_ip: self.rate_limit_per_ip.or(lower.rate_limit_per_ip),
+            rate_limit_per_key: self.rate_limit_per_key.or(lower.rate_limit_per_key),
+            rate_limit_per_user: self.rate_limit_per_user.or(lower.rate_limit_per_user),
+            max_pending_deposits: self.max_pending_deposits.or(lower.max_pending_deposits),
+            pending_deposit_window_secs: self
+                .pending_deposit_window_secs
+                .or(lower.pending_deposit_window_secs),
+            rate_limit_store: self.rate_limit_store.or(lower.rate_limit_store),
+            trust_forwarded_for: self.trust_forwarded_for.or(lower.trust_forwarded_for),
+            idempotency_ttl_secs: self.idempotency_ttl_secs.or(lower.idempotency_ttl_secs),
+            routing_lease_secs: self.routing_lease_secs.or(lower.routing_lease_secs),
+            retry_max_attempts: self.retry_max_attempts.or(lower.retry_max_attempts),
+            retry_base_delay_secs: self.retry_base_delay_secs.or(lower.retry_base_delay_secs),
+            webhook_max_attempts: self.webhook_max_attempts.or(lower.webhook_max_attempts),
+            webhook_base_delay_secs: self
+                .webhook_base_delay_secs
+                .or(lower.webhook_base_delay_secs),
+            webhook_allow_private_targets: self
+                .webhook_allow_private_targets
+                .or(lower.webhook_allow_private_targets),
+        }
+    }
+
+    /// Read values from environment variables
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        Ok(Self {
+            database_url: var("DATABASE_URL"),
+            rpc_url: var("RPC_URL"),
+            deployer_address: var("DEPLOYER_ADDRESS"),
+            router_address: var("ROUTER_ADDRESS"),
+            treasury_address: var("TREASURY_ADDRESS"),
+            treasury_allowlist: var("TREASURY_ALLOWLIST").map(|treasuries| {
+                treasuries
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|t| !t.is_empty())
+                    .map(String::from)
+                    .collect()
+            }),
+            init_code_hash: var("INIT_CODE_HASH"),
+            private_key: var("PRIVATE_KEY").map(Secret::new),
+            keystore_path: var("KEYSTORE_PATH").map(PathBuf::from),
+            keystore_password: var("KEYSTORE_PASSWORD").map(Secret::new),
+            keystore_password_file: var("KEYSTORE_PASSWORD_FILE").map(PathBuf::from),
+            remote_signer_url: var("REMOTE_SIGNER_URL"),
+            remote_signer_address: var("REMOTE_SIGNER_ADDRESS"),
+            host: var("HOST"),
+            port: var("PORT")
+                .map(|p| p.parse().map_err(|_| ConfigError::InvalidPort))
+                .transpose()?,
+            chain_id: var("CHAIN_ID")
+                .map(|c| c.parse().map_err(|_| ConfigError::InvalidChainId))
+                .transpose()?,
+            storage_address: var("STORAGE_ADDRESS"),
+            startup_checks: var("STARTUP_CHECKS")
+                .map(|m| m.parse().map_err(ConfigError::InvalidCheckMode))
+                .transpose()?,
+            min_signer_balance_wei: var("MIN_SIGNER_BALANCE_WEI"),
+            max_deposit_backlog: var("MAX_DEPOSIT_BACKLOG")
+                .map(|n| {
+                    n.parse()
+                        .map_err(|_| ConfigError::InvalidNumber("MAX_DEPOSIT_BACKLOG"))
+                })
+                .transpose()?,
+            max_routing_age_secs: var("MAX_ROUTING_AGE_SECS")
+                .map(|n| {
+                    n.parse()
+                        .map_err(|_| ConfigError::InvalidNumber("MAX_ROUTING_AGE_SECS"))
+                })
+                .transpose()?,
+            otlp_endpoint: var("OTLP_ENDPOINT"),
+            log_format: var("LOG_FORMAT")
+                .map(|f| f.parse().map_err(ConfigError::InvalidLogFormat))
+                .transpose()?,
+            redact_addresses: var("LOG_REDACT_ADDRESSES")
+                .map(|b| parse_bool(&b).ok_or(ConfigError::InvalidBool("LOG_REDACT_ADDRESSES")))
+                .transpose()?,
+            require_api_keys: var("REQUIRE_API_KEYS")
+                .map(|b| parse_bool(&b).ok_or(ConfigError::InvalidBool("REQUIRE_API_KEYS")))
+                .transpose()?,
+            metrics_token: var("METRICS_TOKEN").map(Secret::new),
+            cors_origins: var("CORS_ORIGINS").map(|origins| {
+                origins
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|o| !o.is_empty())
+                    .map(String::from)
+                    .collect()
+            }),
+            siwe_domain: var("SIWE_DOMAIN"),
+            session_ttl_secs: var("SESSION_TTL_SECS")
+                .map(|n| {
+                    n.parse()
+                        .map_err(|_| ConfigError::InvalidNumber("SESSION_TTL_SECS"))
+                })
+                .transpose()?,
+            rate_limit_per_ip: var("RATE_LIMIT_PER_IP")
+                .map(|q| q.parse().map_err(ConfigError::InvalidRateLimit))
+                .transpose()?,
+            rate_limit_per_key: var("RATE_LIMIT_PER_KEY")
+                .map(|q| q.parse().map_err(ConfigError::InvalidRateLimit))
+                .transpose()?,
+            rate_limit_per_user: var("RATE_LIMIT_PER_USER")
+                .map(|q| q.parse().map_err(ConfigError::InvalidRateLimit))
+                .transpose()?,
+            max_pending_deposits: var("MAX_PENDING_DEPOSITS")
+                .map(|n| {
+                    n.parse()
+                        .map_err(|_| ConfigError::InvalidNumber("MAX_PENDING_DEPOSITS"))
+                })
+                .transpose()?,
+            pending_deposit_window_secs: var("PENDING_DEPOSIT_WINDOW_SECS")
+                .map(|n| {
+                    n.parse()
+                        .map_err(|_| ConfigError::InvalidNumber("PENDING_DEPOSIT_WINDOW_SECS"))
+                })
+                .transpose()?,
+            rate_limit_store: var("RATE_LIMIT_STORE")
+                .map(|s| s.parse().map_err(ConfigError::InvalidRateLimit))
+                .transpose()?,
+            trust_forwarded_for: var("TRUST_FORWARDED_FOR")
+                .map(|b| parse_bool(&b).ok_or(ConfigError::InvalidBool("TRUST_FORWARDED_FOR")))
+                .transpose()?,
+            idempotency_ttl_secs: var("IDEMPOTENCY_TTL_SECS")
+                .map(|n| {
+                    n.parse()
+                        .map_err(|_| ConfigError::InvalidNumber("IDEMPOTENCY_TTL_SECS"))
+                })
+                .transpose()?,
+            routing_lease_secs: var("ROUTING_LEASE_SECS")
+                .map(|n| {
+                    n.parse()
+                        .map_err(|_| ConfigError::InvalidNumber("ROUTING_LEASE_SECS"))
+                })
+                .transpose()?,
+            retry_max_attempts: var("RETRY_MAX_ATTEMPTS")
+                .map(|n| {
+                    n.parse()
+                        .map_err(|_| ConfigError::InvalidNumber("RETRY_MAX_ATTEMPTS"))
+                })
+                .transpose()?,
+            retry_base_delay_secs: var("RETRY_BASE_DELAY_SECS")
+                .map(|n| {
+                    n.parse()
+                        .map_err(|_| ConfigError::InvalidNumber("RETRY_BASE_DELAY_SECS"))
+                })
+                .transpose()?,
+            webhook_max_attempts: var("WEBHOOK_MAX_ATTEMPTS")
+                .map(|n| {
+                    n.parse()
+                        .map_err(|_| ConfigError::InvalidNumber("WEBHOOK_MAX_ATTEMPTS"))
+                })
+                .transpose()?,
+            webhook_base_delay_secs: var("WEBHOOK_BASE_DELAY_SECS")
+                .map(|n| {
+                    n.parse()
+                        .map_err(|_| ConfigError::InvalidNumber("WEBHOOK_BASE_DELAY_SECS"))
+                })
+                .transpose()?,
+            webhook_allow_private_targets: var("WEBHOOK_ALLOW_PRIVATE_TARGETS")
+                .map(|b| {
+                    parse_bool(&b).ok_or(ConfigError::InvalidBool("WEBHOOK_ALLOW_PRIVATE_TARGETS"))
+                })
+                .transpose()?,
+        })
+    }
+
+    /// Import contract addresses from a `deployments.json` entry written by
+    /// `scripts/deploy.ts`
+    pub fn from_deployments(path: &Path, network: &str) -> Result<Self, ConfigError> {
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::Deployments(format!("{}: {}", path.display(), e)))?;
+        let mut all: HashMap<String, Deployment> = serde_json::from_str(&raw)
+            .map_err(|e| ConfigError::Deployments(format!("{}: {}", path.display(), e)))?;
+        let deployment = all.remove(network).ok_or_else(|| {
+            ConfigError::Deployments(format!("no '{}' entry in {}", network, path.display()))
+        })?;
+
+        Ok(Self {
+            deployer_address: Some(deployment.contracts.deterministic_proxy_deployer),
+            router_address: Some(deployment.contracts.fund_router),
+            storage_address: deployment.contracts.fund_router_storage,
+            treasury_address: Some(deployment.treasury),
+            init_code_hash: Some(deployment.init_code_hash),
+            // deploy.ts records 0 when the network config has no chainId
+            chain_id: deployment.chain_id.filter(|id| *id != 0),
+            ..Self::default()
+        })
+    }
+
+    /// Apply defaults and check that all required values are present
+    pub fn resolve(self) -> Result<Config, ConfigError> {
+        let signer = self.signer()?;
+        Ok(Config {
+            database_url: self
+                .database_url
+                .unwrap_or_else(|| DEFAULT_DATABASE_URL.to_string()),
+            rpc_url: self.rpc_url.ok_or(ConfigError::MissingVar("RPC_URL"))?,
+            deployer_address: self
+                .deployer_address
+                .ok_or(ConfigError::MissingVar("DEPLOYER_ADDRESS"))?,
+            router_address: self
+                .router_address
+                .ok_or(ConfigError::MissingVar("ROUTER_ADDRESS"))?,
+            treasury_address: self
+                .treasury_address
+                .ok_or(ConfigError::MissingVar("TREASURY_ADDRESS"))?,
+            treasury_allowlist: self.treasury_allowlist.unwrap_or_default(),
+            init_code_hash: self
+                .init_code_hash
+                .ok_or(ConfigError::MissingVar("INIT_CODE_HASH"))?,
+            signer,
+            host: self.host.unwrap_or_else(|| "0.0.0.0".to_string()),
+            port: self.port.unwrap_or(3001),
+            network: None,
+            chain_id: self.chain_id,
+            storage_address: self.storage_address,
+            startup_checks: self.startup_checks.unwrap_or_default(),
+            min_signer_balance_wei: match self.min_signer_balance_wei {
+                Some(wei) => U256::from_str_radix(&wei, 10)
+                    .map_err(|_| ConfigError::InvalidNumber("MIN_SIGNER_BALANCE_WEI"))?,
+                None => U256::from(DEFAULT_MIN_SIGNER_BALANCE_WEI),
+            },
+            max_deposit_backlog: self.max_deposit_backlog.unwrap_or(100),
+            max_routing_age_secs: self.max_routing_age_secs,
+            otlp_endpoint: self.otlp_endpoint,
+            log_format: self.log_format.unwrap_or_default(),
+            redact_addresses: self.redact_addresses.unwrap_or(false),
+            require_api_keys: self.require_api_keys.unwrap_or(true),
+            metrics_token: self.metrics_token,
+            cors_origins: self.cors_origins.unwrap_or_default(),
+            siwe_domain: self.siwe_domain,
+            session_ttl_secs: self.session_ttl_secs.unwrap_or(DEFAULT_SESSION_TTL_SECS),
+            rate_limit_per_ip: self.rate_limit_per_ip.unwrap_or(DEFAULT_RATE_LIMIT_PER_IP),
+            rate_limit_per_key: self
+                .rate_limit_per_key
+                .unwrap_or(DEFAULT_RATE_LIMIT_PER_KEY),
+            rate_limit_per_user: self
+                .rate_limit_per_user
+                .unwrap_or(DEFAULT_RATE_LIMIT_PER_USER),
+            max_pending_deposits: self
+                .max_pending_deposits
+                .unwrap_or(DEFAULT_MAX_PENDING_DEPOSITS),
+            pending_deposit_window_secs: self
+                .pending_deposit_window_secs
+                .unwrap_or(DEFAULT_PENDING_DEPOSIT_WINDOW_SECS),
+            rate_limit_store: self.rate_limit_store.unwrap_or_default(),
+            trust_forwarded_for: self.trust_forwarded_for.unwrap_or(false),
+            idempotency_ttl_secs: self
+                .idempotency_ttl_secs
+                .unwrap_or(DEFAULT_IDEMPOTENCY_TTL_SECS),
+            routing_lease_secs: self
+                .routing_lease_secs
+                .unwrap_or(DEFAULT_ROUTING_LEASE_SECS),
+            retry_max_attempts: self
+                .retry_max_attempts
+                .unwrap_or(DEFAULT_RETRY_MAX_ATTEMPTS),
+            retry_base_delay_secs: self
+                .retry_base_delay_secs
+                .unwrap_or(DEFAULT_RETRY_BASE_DELAY_SECS),
+            webhook_max_attempts: self
+                .webhook_max_attempts
+                .unwrap_or(DEFAULT_WEBHOOK_MAX_ATTEMPTS),
+            webhook_base_delay_secs: self
+                .webhook_base_delay_secs
+                .unwrap_or(DEFAULT_WEBHOOK_BASE_DELAY_SECS),
+            webhook_allow_private_targets: self.webhook_allow_private_targets.unwrap_or(false),
+        })
+    }
+
+    /// Pick the signer backend; exactly one may be configured
+    fn signer(&self) -> Result<SignerConfig, ConfigError> {
+        let mut configured = vec![];
+
+        if let Some(path) = &self.keystore_path {
+            let password = match (&self.keystore_password, &self.keystore_password_file) {
+                (Some(password), _) => password.clone(),
+                (None, Some(file)) => {
+                    let raw =
+                        Secret::new(std::fs::read_to_string(file).map_err(|e| {
+                            ConfigError::File(format!("{}: {}", file.display(), e))
+                        })?);
+                    Secret::new(raw.expose().trim_end_matches(['\r', '\n']))
+                }
+                (None, None) => return Err(ConfigError::MissingVar("KEYSTORE_PASSWORD")),
+            };
+            configured.push(SignerConfig::Keystore {
+                path: path.clone(),
+                password,
+            });
+        }
+        if let Some(url) = &self.remote_signer_url {
+            configured.push(SignerConfig::Remote {
+                url: url.clone(),
+                address: self.remote_signer_address.clone(),
+            });
+        }
+        if let Some(key) = &self.private_key {
+            configured.push(SignerConfig::PrivateKey(key.clone()));
+        }
+
+        match configured.len() {
+            0 => Err(ConfigError::MissingVar(
+                "KEYSTORE_PATH, REMOTE_SIGNER_URL or PRIVATE_KEY",
+            )),
+            1 => Ok(configured.remove(0)),
+            _ => Err(ConfigError::ConflictingSigners),
+        }
+    }
+}
+
+/// Command line flags, the highest configuration layer
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct ConfigArgs {
+    /// TOML configuration file
+    #[arg(long, global = true, env = "RADHAT_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Network profile to use from the configuration file
+    #[arg(long, global = true, env = "RADHAT_NETWORK")]
+    pub network: Option<String>,
+    /// Import contract addresses from this deployments.json
+    #[arg(long, global = true)]
+    pub deployments: Option<PathBuf>,
+    /// SQLite database URL
+    #[arg(long, global = true)]
+    pub database_url: Option<String>,
+    /// Ethereum JSON-RPC endpoint
+    #[arg(long, global = true)]
+    pub rpc_url: Option<String>,
+    /// Address to bind the HTTP server to
+    #[arg(long, global = true)]
+    pub host: Option<String>,
+    /// Port to bind the HTTP server to
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+    /// Abort startup on failed chain checks (strict) or only warn (lenient)
+    #[arg(long, global = true)]
+    pub startup_checks: Option<CheckMode>,
+    /// Log format: text or json
+    #[arg(long, global = true)]
+    pub log_format: Option<LogFormat>,
+}
+
+impl ConfigArgs {
+    fn overrides(&self) -> PartialConfig {
+        PartialConfig {
+            database_url: self.database_url.clone(),
+            rpc_url: self.rpc_url.clone(),
+            host: self.host.clone(),
+            port: self.port,
+            startup_checks: self.startup_checks,
+            log_format: self.log_format,
+            ..PartialConfig::default()
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" => Some(true),
+        "0" | "false" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// TOML configuration file
+#[derive(Debug, Deserialize)]
+struct FileConfig {
+    /// Default network profile
+    network: Option<String>,
+    #[serde(flatten)]
+    base: PartialConfig,
+    #[serde(default)]
+    networks: HashMap<String, NetworkProfile>,
+    /// Directory relative paths in the file are resolved against
+    #[serde(skip)]
+    base_dir: PathBuf,
+}
+
+impl FileConfig {
+    fn read(path: &Path) -> Result<Self, ConfigError> {
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::File(format!("{}: {}", path.display(), e)))?;
+        let mut file: FileConfig = toml::from_str(&raw)
+            .map_err(|e| ConfigError::File(format!("{}: {}", path.display(), e)))?;
+        file.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(file)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NetworkProfile {
+    /// deployments.json to import contract addresses from
+    deployments: Option<PathBuf>,
+    /// Entry in deployments.json, defaults to the profile name
+    deployment: Option<String>,
+    #[serde(flatten)]
+    values: PartialConfig,
+}
+
+/// One network entry of deployments.json
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Deployment {
+    chain_id: Option<u64>,
+    treasury: String,
+    contracts: DeployedContracts,
+    init_code_hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeployedContracts {
+    #[serde(rename = "FundRouterStorage")]
+    fund_router_storage: Option<String>,
+    #[serde(rename = "FundRouter")]
+    fund_router: String,
+    #[serde(rename = "DeterministicProxyDeployer")]
+    deterministic_proxy_deployer: String,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigError {
-    #[error("Missing environment variable: {0}")]
+    #[error("Missing configuration value: {0}")]
     MissingVar(&'static str),
     #[error("Invalid port number")]
     InvalidPort,
+    #[error("Invalid chain ID")]
+    InvalidChainId,
+    #[error("Invalid number for {0}")]
+    InvalidNumber(&'static str),
+    #[error("Invalid startup check mode: {0}")]
+    InvalidCheckMode(String),
+    #[error("Invalid log format: {0}")]
+    InvalidLogFormat(String),
+    #[error("Invalid boolean for {0}")]
+    InvalidBool(&'static str),
+    #[error("Invalid rate limit: {0}")]
+    InvalidRateLimit(String),
     #[error("Invalid address format")]
     InvalidAddress,
     #[error("Invalid bytes32 format")]
     InvalidBytes32,
+    #[error("More than one signer backend configured")]
+    ConflictingSigners,
+    #[error("Config file error: {0}")]
+    File(String),
+    #[error("Unknown network profile: {0}")]
+    UnknownNetwork(String),
+    #[error("Deployments import failed: {0}")]
+    Deployments(String),
+}
+
+/// Complete configuration for unit tests; no value points at a real chain
+#[cfg(test)]
+pub fn test_config() -> Config {
+    PartialConfig {
+        database_url: Some("sqlite::memory:".to_string()),
+        rpc_url: Some("http://localhost:8545".to_string()),
+        deployer_address: Some(format!(
+            "{:#x}",
+            alloy::primitives::Address::repeat_byte(0xde)
+        )),
+        router_address: Some(format!(
+            "{:#x}",
+            alloy::primitives::Address::repeat_byte(0x70)
+        )),
+        treasury_address: Some(format!(
+            "{:#x}",
+            alloy::primitives::Address::repeat_byte(0x7e)
+        )),
+        init_code_hash: Some(format!("{:#x}", alloy::primitives::B256::repeat_byte(0x1c))),
+        private_key: Some(Secret::new(
+            "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
+        )),
+        ..PartialConfig::default()
+    }
+    .resolve()
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEPLOYMENTS: &str = r#"{
+        "sepolia": {
+            "chainId": 0,
+            "treasury": "0x2b0d92ad915cB5188bfb36c67Df440B6D32fBDD4",
+            "contracts": {
+                "FundRouter": "0x7238CA877BbAcC8C273C701636A2041F6569f266",
+                "DeterministicProxyDeployer": "0x2b05DAf67cc41957f60F74Ff7D3c4aB54840Fc8D"
+            },
+            "initCodeHash": "0x53610d10df2dbe6319490ceeb6b7252926cc1e0cea27682301027672215b2db1"
+        }
+    }"#;
+
+    fn complete() -> PartialConfig {
+        PartialConfig {
+            rpc_url: Some("http://localhost:8545".to_string()),
+            deployer_address: Some("0x01".to_string()),
+            router_address: Some("0x02".to_string()),
+            treasury_address: Some("0x03".to_string()),
+            init_code_hash: Some("0x04".to_string()),
+            private_key: Some(Secret::new("0x05")),
+            ..PartialConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_resolve_applies_defaults() {
+        let config = complete().resolve().unwrap();
+        assert_eq!(config.database_url, "sqlite://data.db");
+        assert_eq!(config.host, "0.0.0.0");
+        assert_eq!(config.port, 3001);
+        assert_eq!(config.chain_id, None);
+        assert!(config.require_api_keys);
+        assert!(config.cors_origins.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_reports_missing_value() {
+        let partial = PartialConfig {
+            rpc_url: None,
+            ..complete()
+        };
+        assert!(matches!(
+            partial.resolve(),
+            Err(ConfigError::MissingVar("RPC_URL"))
+        ));
+    }
+
+    #[test]
+    fn test_higher_layer_wins() {
+        let flags = PartialConfig {
+            port: Some(9000),
+            ..PartialConfig::default()
+        };
+        let env = PartialConfig {
+            port: Some(8000),
+            host: Some("127.0.0.1".to_string()),
+            ..PartialConfig::default()
+        };
+        let config = flags.or(env).or(complete()).resolve().unwrap();
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.host, "127.0.0.1");
+    }
+
+    #[test]
+    fn test_from_vars_parses_numbers() {
+        let vars = |name: &str| match name {
+            "PORT" => Some("8080".to_string()),
+            "CHAIN_ID" => Some("11155111".to_string()),
+            _ => None,
+        };
+        let partial = PartialConfig::from_vars(vars).unwrap();
+        assert_eq!(partial.port, Some(8080));
+        assert_eq!(partial.chain_id, Some(11155111));
+
+        let bad_port = |name: &str| (name == "PORT").then(|| "http".to_string());
+        assert!(matches!(
+            PartialConfig::from_vars(bad_port),
+            Err(ConfigError::InvalidPort)
+        ));
+    }
+
+    #[test]
+    fn test_from_vars_parses_log_settings() {
+        let vars = |name: &str| match name {
+            "LOG_FORMAT" => Some("json".to_string()),
+            "LOG_REDACT_ADDRESSES" => Some("true".to_string()),
+            _ => None,
+        };
+        let partial = PartialConfig::from_vars(vars).unwrap();
+        assert_eq!(partial.log_format, Some(LogFormat::Json));
+        assert_eq!(partial.redact_addresses, Some(true));
+
+        let bad = |name: &str| (name == "LOG_REDACT_ADDRESSES").then(|| "maybe".to_string());
+        assert!(matches!(
+            PartialConfig::from_vars(bad),
+            Err(ConfigError::InvalidBool("LOG_REDACT_ADDRESSES"))
+        ));
+    }
+
+    #[test]
+    fn test_from_vars_parses_rate_limits() {
+        let vars = |name: &str| match name {
+            "RATE_LIMIT_PER_IP" => Some("5/s".to_string()),
+            "RATE_LIMIT_PER_USER" => Some("off".to_string()),
+            "RATE_LIMIT_STORE" => Some("database".to_string()),
+            _ => None,
+        };
+        let config = PartialConfig::from_vars(vars)
+            .unwrap()
+            .or(complete())
+            .resolve()
+            .unwrap();
+        assert_eq!(config.rate_limit_per_ip.capacity, 5);
+        assert!(config.rate_limit_per_user.is_off());
+        assert_eq!(config.rate_limit_per_key, DEFAULT_RATE_LIMIT_PER_KEY);
+        assert_eq!(config.rate_limit_store, RateLimitStore::Database);
+
+        let bad = |name: &str| (name == "RATE_LIMIT_PER_KEY").then(|| "lots".to_string());
+        assert!(matches!(
+            PartialConfig::from_vars(bad),
+            Err(ConfigError::InvalidRateLimit(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_layers_file_deployments_profile_env_and_flags() {
+        let dir = std::env::temp_dir().join(format!("radhat-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("deployments.json"), DEPLOYMENTS).unwrap();
+        std::fs::write(
+            dir.join("radhat.toml"),
+            r#"
+                network = "sepolia"
+                rpc_url = "http://localhost:8545"
+                host = "127.0.0.2"
+                port = 4000
+                router_address = "0x00000000000000000000000000000000000000cc"
+                treasury_address = "0x00000000000000000000000000000000000000bb"
+                retry_max_attempts = 7
+
+                [networks.sepolia]
+                deployments = "deployments.json"
+                rpc_url = "https://sepolia.example"
+                treasury_address = "0x00000000000000000000000000000000000000aa"
+                database_url = "sqlite://profile.db"
+            "#,
+        )
+        .unwrap();
+
+        let env = PartialConfig::from_vars(|name| match name {
+            "HOST" => Some("127.0.0.3".to_string()),
+            "PORT" => Some("5000".to_string()),
+            "DATABASE_URL" => Some("sqlite://env.db".to_string()),
+            "INIT_CODE_HASH" => Some(format!("0x{}", "11".repeat(32))),
+            "PRIVATE_KEY" => Some("0x05".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        let args = ConfigArgs {
+            config: Some(dir.join("radhat.toml")),
+            port: Some(6000),
+            database_url: Some("sqlite://flag.db".to_string()),
+            ..ConfigArgs::default()
+        };
+        let config = Config::load_with_env(&args, env);
+
+        std::fs::remove_dir_all(&dir).ok();
+        let config = config.unwrap();
+
+        // The file's default profile is selected
+        assert_eq!(config.network.as_deref(), Some("sepolia"));
+        // file < deployments
+        assert_eq!(
+            config.router_address,
+            "0x7238CA877BbAcC8C273C701636A2041F6569f266"
+        );
+        assert_eq!(
+            config.deployer_address,
+            "0x2b05DAf67cc41957f60F74Ff7D3c4aB54840Fc8D"
+        );
+        // deployments < profile, file < profile
+        assert_eq!(
+            config.treasury_address,
+            "0x00000000000000000000000000000000000000aa"
+        );
+        assert_eq!(config.rpc_url, "https://sepolia.example");
+        // deployments < env, file < env
+        assert_eq!(config.init_code_hash, format!("0x{}", "11".repeat(32)));
+        assert_eq!(config.host, "127.0.0.3");
+        // profile < env < flags
+        assert_eq!(config.port, 6000);
+        assert_eq!(config.database_url, "sqlite://flag.db");
+        // Values only one layer sets come through
+        assert_eq!(config.retry_max_attempts, 7);
+        assert_eq!(config.storage_address, None);
+        // deploy.ts writes chainId 0 when it is unknown
+        assert_eq!(config.chain_id, None);
+    }
+
+    #[test]
+    fn test_flag_network_overrides_file_default() {
+        let dir = std::env::temp_dir().join(format!("radhat-network-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("radhat.toml"),
+            r#"
+                network = "sepolia"
+
+                [networks.sepolia]
+                rpc_url = "https://sepolia.example"
+            "#,
+        )
+        .unwrap();
+
+        let args = ConfigArgs {
+            config: Some(dir.join("radhat.toml")),
+            network: Some("mainnet".to_string()),
+            ..ConfigArgs::default()
+        };
+        let result = Config::load_with_env(&args, complete());
+
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(result, Err(ConfigError::UnknownNetwork(name)) if name == "mainnet"));
+    }
+
+    #[test]
+    fn test_signer_backend_selection() {
+        let keystore = PartialConfig {
+            private_key: None,
+            keystore_path: Some(PathBuf::from("key.json")),
+            keystore_password: Some(Secret::new("hunter2")),
+            ..complete()
+        };
+        assert!(matches!(
+            keystore.resolve().unwrap().signer,
+            SignerConfig::Keystore { .. }
+        ));
+
+        let remote = PartialConfig {
+            private_key: None,
+            remote_signer_url: Some("http://127.0.0.1:9000".to_string()),
+            ..complete()
+        };
+        assert!(matches!(
+            remote.resolve().unwrap().signer,
+            SignerConfig::Remote { address: None, .. }
+        ));
+
+        let both = PartialConfig {
+            remote_signer_url: Some("http://127.0.0.1:9000".to_string()),
+            ..complete()
+        };
+        assert!(matches!(
+            both.resolve(),
+            Err(ConfigError::ConflictingSigners)
+        ));
+
+        let no_password = PartialConfig {
+            private_key: None,
+            keystore_path: Some(PathBuf::from("key.json")),
+            ..complete()
+        };
+        assert!(matches!(
+            no_password.resolve(),
+            Err(ConfigError::MissingVar("KEYSTORE_PASSWORD"))
+        ));
+    }
+
+    #[test]
+    fn test_debug_output_hides_keys() {
+        let config = complete().resolve().unwrap();
+        assert!(!format!("{:?}", config).contains("0x05"));
+    }
+
+    #[test]
+    fn test_missing_deployment_entry() {
+        let path =
+            std::env::temp_dir().join(format!("radhat-deployments-{}.json", std::process::id()));
+        std::fs::write(&path, DEPLOYMENTS).unwrap();
+        let result = PartialConfig::from_deployments(&path, "mainnet");
+        std::fs::remove_file(&path).ok();
+        assert!(matches!(result, Err(ConfigError::Deployments(_))));
+    }
 }
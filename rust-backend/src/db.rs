@@ -1,6 +1,27 @@
 //! Database setup and migrations
 
-use sqlx::SqlitePool;
+use std::str::FromStr;
+
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    SqlitePool,
+};
+
+use crate::{lease::LeaseInfo, metrics, rate_limit::Bucket};
+
+/// Schema version recorded in `PRAGMA user_version` once migrations ran
+///
+/// Bump it whenever `run_migrations` gains a table or column.
+pub const SCHEMA_VERSION: i64 = 14;
+
+/// Open the connection pool, creating the database file if needed
+pub async fn connect(database_url: &str) -> Result<SqlitePool, sqlx::Error> {
+    let options = SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+    SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect_with(options)
+        .await
+}
 
 /// Run database migrations
 pub async fn run_migrations(pool: &SqlitePool) -> Result<(), sqlx::Error> {
@@ -64,49 +85,428 @@ pub async fn run_migrations(pool: &SqlitePool) -> Result<(), sqlx::Error> {
     .execute(pool)
     .await?;
 
+    // Create stranded_funds table to track ETH stuck on deployed proxies
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS stranded_funds (
+            deposit_address TEXT PRIMARY KEY,
+            balance_wei TEXT NOT NULL,
+            detected_at TEXT NOT NULL DEFAULT (datetime('now')),
+            last_checked_at TEXT NOT NULL DEFAULT (datetime('now'))
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create routing_runs table to track routing passes for health reporting
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS routing_runs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            checked INTEGER NOT NULL,
+            routed INTEGER NOT NULL,
+            stranded INTEGER NOT NULL,
+            error_count INTEGER NOT NULL,
+            finished_at TEXT NOT NULL DEFAULT (datetime('now'))
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create api_keys table; only the SHA-256 of each key is stored
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_keys (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            key_hash TEXT NOT NULL UNIQUE,
+            key_prefix TEXT NOT NULL,
+            roles TEXT NOT NULL,
+            created_by TEXT,
+            created_at TEXT NOT NULL DEFAULT (datetime('now')),
+            last_used_at TEXT,
+            request_count INTEGER NOT NULL DEFAULT 0,
+            revoked_at TEXT
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create siwe_nonces table; each nonce signs in at most once
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS siwe_nonces (
+            nonce TEXT PRIMARY KEY,
+            created_at TEXT NOT NULL DEFAULT (datetime('now')),
+            expires_at TEXT NOT NULL,
+            used_at TEXT
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create sessions table for signed-in wallets; only token hashes are stored
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS sessions (
+            token_hash TEXT PRIMARY KEY,
+            address TEXT NOT NULL,
+            created_at TEXT NOT NULL DEFAULT (datetime('now')),
+            expires_at TEXT NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create rate_limits table for token buckets shared between replicas;
+    // updated_at is Unix seconds
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS rate_limits (
+            key TEXT PRIMARY KEY,
+            tokens REAL NOT NULL,
+            updated_at REAL NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create idempotency_keys table; response_* are set once the first
+    // request with the key finished
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS idempotency_keys (
+            caller TEXT NOT NULL,
+            scope TEXT NOT NULL,
+            key TEXT NOT NULL,
+            request_hash TEXT NOT NULL,
+            response_status INTEGER,
+            response_body TEXT,
+            created_at TEXT NOT NULL DEFAULT (datetime('now')),
+            expires_at TEXT NOT NULL,
+            PRIMARY KEY (caller, scope, key)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_idempotency_keys_expires_at
+        ON idempotency_keys(expires_at)
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Deposits are claimed by the routing run processing them, until
+    // claimed_until (see claim_deposits)
+    add_column_if_missing(pool, "deposits", "claimed_by", "TEXT").await?;
+    add_column_if_missing(pool, "deposits", "claimed_until", "TEXT").await?;
+
+    // Create routing_leases table; a row is held by one process until it
+    // expires or is released
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS routing_leases (
+            name TEXT PRIMARY KEY,
+            holder TEXT NOT NULL,
+            acquired_at TEXT NOT NULL,
+            expires_at TEXT NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create routing_jobs table; progress is the run's RouteResponse as JSON,
+    // rewritten as the run goes
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS routing_jobs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            status TEXT NOT NULL DEFAULT 'running',
+            holder TEXT NOT NULL,
+            requested_by TEXT,
+            progress TEXT NOT NULL DEFAULT '{}',
+            created_at TEXT NOT NULL DEFAULT (datetime('now')),
+            updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+            finished_at TEXT
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Failed deployments are retried with backoff (see routing::RetryPolicy)
+    add_column_if_missing(pool, "deposits", "attempts", "INTEGER NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "deposits", "last_error", "TEXT").await?;
+    add_column_if_missing(pool, "deposits", "next_retry_at", "TEXT").await?;
+
+    // Treasuries funds can be routed to, and the rules picking them per
+    // deposit (see treasury); tenant is the API key that created the deposit
+    add_column_if_missing(pool, "deposits", "tenant", "TEXT").await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS treasuries (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE,
+            address TEXT NOT NULL UNIQUE,
+            active INTEGER NOT NULL DEFAULT 1,
+            created_at TEXT NOT NULL DEFAULT (datetime('now'))
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // splits is a JSON array of {"treasury": <name>, "share_bps": <n>}
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS routing_rules (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_address TEXT,
+            tenant TEXT,
+            asset TEXT,
+            splits TEXT NOT NULL,
+            created_at TEXT NOT NULL DEFAULT (datetime('now'))
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // One row per transferFunds call: which treasury got how much of a deposit
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS deposit_allocations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            deposit_address TEXT NOT NULL,
+            treasury_address TEXT NOT NULL,
+            share_bps INTEGER NOT NULL,
+            amount_wei TEXT NOT NULL,
+            tx_hash TEXT NOT NULL,
+            created_at TEXT NOT NULL DEFAULT (datetime('now'))
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_deposit_allocations_deposit_address
+        ON deposit_allocations(deposit_address)
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Invoice terms of a deposit and how it was paid (see invoice); metadata
+    // is caller-supplied JSON
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS invoices (
+            deposit_address TEXT PRIMARY KEY,
+            expected_amount_wei TEXT NOT NULL,
+            asset TEXT NOT NULL,
+            expires_at TEXT,
+            order_ref TEXT,
+            metadata TEXT,
+            description TEXT,
+            payment_status TEXT NOT NULL DEFAULT 'awaiting_payment',
+            received_wei TEXT NOT NULL DEFAULT '0',
+            paid_at TEXT,
+            created_at TEXT NOT NULL DEFAULT (datetime('now')),
+            updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Latest block when the invoice was created; its payment is searched
+    // for from there (see invoice)
+    add_column_if_missing(pool, "invoices", "created_block", "INTEGER").await?;
+
+    // Webhook subscriptions and their outbox (see webhooks); events is a
+    // comma-separated filter, a subscription without a tenant gets every
+    // deposit's events
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS webhook_subscriptions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            tenant TEXT,
+            url TEXT NOT NULL,
+            secret TEXT NOT NULL,
+            events TEXT NOT NULL,
+            active INTEGER NOT NULL DEFAULT 1,
+            created_at TEXT NOT NULL DEFAULT (datetime('now'))
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS webhook_deliveries (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            subscription_id INTEGER NOT NULL,
+            event TEXT NOT NULL,
+            deposit_address TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            attempts INTEGER NOT NULL DEFAULT 0,
+            next_attempt_at TEXT NOT NULL DEFAULT (datetime('now')),
+            last_status_code INTEGER,
+            last_error TEXT,
+            delivered_at TEXT,
+            created_at TEXT NOT NULL DEFAULT (datetime('now')),
+            updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_due
+        ON webhook_deliveries(status, next_attempt_at)
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // One row per delivery attempt, kept for the delivery log
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS webhook_attempts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            delivery_id INTEGER NOT NULL,
+            status_code INTEGER,
+            error TEXT,
+            duration_ms INTEGER NOT NULL,
+            attempted_at TEXT NOT NULL DEFAULT (datetime('now'))
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_webhook_attempts_delivery_id
+        ON webhook_attempts(delivery_id)
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // ETH each deployed proxy forwarded to the FundRouter, counted through
+    // scanned_block (see forwarded)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS forwarded_funds (
+            deposit_address TEXT PRIMARY KEY,
+            forwarded_wei TEXT NOT NULL DEFAULT '0',
+            scanned_block INTEGER NOT NULL,
+            updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Values the backend records about itself, e.g. the signer deposit
+    // addresses are derived from (see signer::check_recorded)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL,
+            updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // PRAGMA does not take bind parameters
+    sqlx::query(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Add a column to a table created by an earlier schema version
+async fn add_column_if_missing(
+    pool: &SqlitePool,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), sqlx::Error> {
+    let exists: bool =
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info(?) WHERE name = ?)")
+            .bind(table)
+            .bind(column)
+            .fetch_one(pool)
+            .await?;
+    if !exists {
+        // ALTER TABLE does not take bind parameters
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Check that the database answers queries
+pub async fn ping(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("ping");
+    sqlx::query("SELECT 1").execute(pool).await?;
     Ok(())
 }
 
+/// Schema version the database was last migrated to
+pub async fn schema_version(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    let _timer = metrics::db_timer("schema_version");
+    sqlx::query_scalar("PRAGMA user_version")
+        .fetch_one(pool)
+        .await
+}
+
 /// Get the next nonce for a user (and increment it)
 pub async fn get_and_increment_nonce(
     pool: &SqlitePool,
     user_address: &str,
 ) -> Result<u64, sqlx::Error> {
-    // Use a transaction to ensure atomicity
-    let mut tx = pool.begin().await?;
-
-    // Try to get existing nonce
-    let row: Option<(i64,)> =
-        sqlx::query_as("SELECT next_nonce FROM user_nonces WHERE user_address = ? FOR UPDATE")
-            .bind(user_address)
-            .fetch_optional(&mut *tx)
-            .await
-            .unwrap_or(None);
-
-    let nonce = match row {
-        Some((n,)) => {
-            // Increment existing nonce
-            sqlx::query(
-                "UPDATE user_nonces SET next_nonce = next_nonce + 1 WHERE user_address = ?",
-            )
-            .bind(user_address)
-            .execute(&mut *tx)
-            .await?;
-            n as u64
-        }
-        None => {
-            // Insert new user with nonce 0, return 0, set next to 1
-            sqlx::query("INSERT INTO user_nonces (user_address, next_nonce) VALUES (?, 1)")
-                .bind(user_address)
-                .execute(&mut *tx)
-                .await?;
-            0
-        }
-    };
-
-    tx.commit().await?;
+    let _timer = metrics::db_timer("get_and_increment_nonce");
+    // A single upsert is atomic; a new user gets nonce 0 and next_nonce 1
+    let next: i64 = sqlx::query_scalar(
+        r#"
+        INSERT INTO user_nonces (user_address, next_nonce) VALUES (?, 1)
+        ON CONFLICT(user_address) DO UPDATE SET next_nonce = next_nonce + 1
+        RETURNING next_nonce
+        "#,
+    )
+    .bind(user_address)
+    .fetch_one(pool)
+    .await?;
 
-    Ok(nonce)
+    Ok(next as u64 - 1)
 }
 
 /// Insert a new deposit record
@@ -116,31 +516,79 @@ pub async fn insert_deposit(
     salt: &str,
     deposit_address: &str,
     nonce: u64,
+    tenant: Option<&str>,
 ) -> Result<i64, sqlx::Error> {
+    let _timer = metrics::db_timer("insert_deposit");
     let result = sqlx::query(
         r#"
-        INSERT INTO deposits (user_address, salt, deposit_address, nonce, status)
-        VALUES (?, ?, ?, ?, 'pending')
+        INSERT INTO deposits (user_address, salt, deposit_address, nonce, status, tenant)
+        VALUES (?, ?, ?, ?, 'pending', ?)
         "#,
     )
     .bind(user_address)
     .bind(salt)
     .bind(deposit_address)
     .bind(nonce as i64)
+    .bind(tenant)
     .execute(pool)
     .await?;
 
     Ok(result.last_insert_rowid())
 }
 
+/// Most pending deposits a user may hold, counting those created within
+/// `window_secs`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PendingCap {
+    pub max: u64,
+    pub window_secs: u64,
+}
+
+/// Insert a new deposit record unless its user already holds `cap.max`
+/// recent pending deposits; `None` if refused
+pub async fn insert_deposit_capped(
+    pool: &SqlitePool,
+    user_address: &str,
+    salt: &str,
+    deposit_address: &str,
+    nonce: u64,
+    tenant: Option<&str>,
+    cap: PendingCap,
+) -> Result<Option<i64>, sqlx::Error> {
+    let _timer = metrics::db_timer("insert_deposit_capped");
+    // One statement, so concurrent requests cannot both take the last slot
+    let result = sqlx::query(
+        r#"
+        INSERT INTO deposits (user_address, salt, deposit_address, nonce, status, tenant)
+        SELECT ?1, ?2, ?3, ?4, 'pending', ?5
+        WHERE (SELECT COUNT(*) FROM deposits
+               WHERE user_address = ?1 AND status = 'pending'
+                 AND created_at > datetime('now', '-' || ?7 || ' seconds')) < ?6
+        "#,
+    )
+    .bind(user_address)
+    .bind(salt)
+    .bind(deposit_address)
+    .bind(nonce as i64)
+    .bind(tenant)
+    .bind(cap.max as i64)
+    .bind(cap.window_secs as i64)
+    .execute(pool)
+    .await?;
+
+    Ok((result.rows_affected() > 0).then(|| result.last_insert_rowid()))
+}
+
 /// Get a deposit by address
 pub async fn get_deposit_by_address(
     pool: &SqlitePool,
     deposit_address: &str,
 ) -> Result<Option<DepositRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_deposit_by_address");
     sqlx::query_as(
         r#"
-        SELECT id, user_address, salt, deposit_address, nonce, status, created_at, updated_at
+        SELECT id, user_address, salt, deposit_address, nonce, status, created_at, updated_at,
+               attempts, last_error, next_retry_at, tenant
         FROM deposits
         WHERE deposit_address = ?
         "#,
@@ -151,14 +599,15 @@ pub async fn get_deposit_by_address(
 }
 
 /// Get all deposits for a user
-#[allow(dead_code)]
 pub async fn get_deposits_by_user(
     pool: &SqlitePool,
     user_address: &str,
 ) -> Result<Vec<DepositRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_deposits_by_user");
     sqlx::query_as(
         r#"
-        SELECT id, user_address, salt, deposit_address, nonce, status, created_at, updated_at
+        SELECT id, user_address, salt, deposit_address, nonce, status, created_at, updated_at,
+               attempts, last_error, next_retry_at, tenant
         FROM deposits
         WHERE user_address = ?
         ORDER BY nonce ASC
@@ -171,9 +620,11 @@ pub async fn get_deposits_by_user(
 
 /// Get all deposits
 pub async fn get_all_deposits(pool: &SqlitePool) -> Result<Vec<DepositRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_all_deposits");
     sqlx::query_as(
         r#"
-        SELECT id, user_address, salt, deposit_address, nonce, status, created_at, updated_at
+        SELECT id, user_address, salt, deposit_address, nonce, status, created_at, updated_at,
+               attempts, last_error, next_retry_at, tenant
         FROM deposits
         ORDER BY created_at DESC
         "#,
@@ -188,6 +639,7 @@ pub async fn update_deposit_status(
     deposit_address: &str,
     status: &str,
 ) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("update_deposit_status");
     sqlx::query(
         r#"
         UPDATE deposits 
@@ -203,30 +655,85 @@ pub async fn update_deposit_status(
     Ok(())
 }
 
-/// Get deposits by status
-#[allow(dead_code)]
-pub async fn get_deposits_by_status(
+/// Record a failed attempt at a deposit and move it to `status`
+///
+/// With `retry_in_secs`, the deposit is skipped by routing runs until then.
+pub async fn record_deposit_failure(
     pool: &SqlitePool,
+    deposit_address: &str,
     status: &str,
-) -> Result<Vec<DepositRow>, sqlx::Error> {
-    sqlx::query_as(
+    error: &str,
+    retry_in_secs: Option<u64>,
+) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("record_deposit_failure");
+    sqlx::query(
         r#"
-        SELECT id, user_address, salt, deposit_address, nonce, status, created_at, updated_at
-        FROM deposits
-        WHERE status = ?
-        ORDER BY created_at ASC
+        UPDATE deposits
+        SET status = ?1, attempts = attempts + 1, last_error = ?2,
+            next_retry_at = CASE WHEN ?3 IS NULL THEN NULL
+                                 ELSE datetime('now', '+' || ?3 || ' seconds') END,
+            updated_at = datetime('now')
+        WHERE deposit_address = ?4
         "#,
     )
     .bind(status)
-    .fetch_all(pool)
-    .await
-}
-
-/// Get deposits by multiple statuses
+    .bind(error)
+    .bind(retry_in_secs.map(|secs| secs as i64))
+    .bind(deposit_address)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Put a dead-lettered or failed deposit back in line for deployment
+///
+/// Resets its attempts; returns false if no such deposit is in either state.
+pub async fn requeue_deposit(
+    pool: &SqlitePool,
+    deposit_address: &str,
+) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::db_timer("requeue_deposit");
+    let result = sqlx::query(
+        r#"
+        UPDATE deposits
+        SET status = 'funded', attempts = 0, next_retry_at = NULL, updated_at = datetime('now')
+        WHERE deposit_address = ? AND status IN ('dead_letter', 'failed')
+        "#,
+    )
+    .bind(deposit_address)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Get deposits by status
+pub async fn get_deposits_by_status(
+    pool: &SqlitePool,
+    status: &str,
+) -> Result<Vec<DepositRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_deposits_by_status");
+    sqlx::query_as(
+        r#"
+        SELECT id, user_address, salt, deposit_address, nonce, status, created_at, updated_at,
+               attempts, last_error, next_retry_at, tenant
+        FROM deposits
+        WHERE status = ?
+        ORDER BY created_at ASC
+        "#,
+    )
+    .bind(status)
+    .fetch_all(pool)
+    .await
+}
+
+/// Get deposits by multiple statuses
 pub async fn get_deposits_by_statuses(
     pool: &SqlitePool,
     statuses: &[&str],
 ) -> Result<Vec<DepositRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_deposits_by_statuses");
     if statuses.is_empty() {
         return Ok(vec![]);
     }
@@ -235,7 +742,8 @@ pub async fn get_deposits_by_statuses(
     let placeholders: Vec<&str> = statuses.iter().map(|_| "?").collect();
     let query = format!(
         r#"
-        SELECT id, user_address, salt, deposit_address, nonce, status, created_at, updated_at
+        SELECT id, user_address, salt, deposit_address, nonce, status, created_at, updated_at,
+               attempts, last_error, next_retry_at, tenant
         FROM deposits
         WHERE status IN ({})
         ORDER BY created_at ASC
@@ -251,15 +759,1725 @@ pub async fn get_deposits_by_statuses(
     query_builder.fetch_all(pool).await
 }
 
-#[derive(Debug, sqlx::FromRow)]
-pub struct DepositRow {
-    pub id: i64,
-    pub user_address: String,
-    pub salt: String,
-    pub deposit_address: String,
-    pub nonce: i64,
-    pub status: String,
-    pub created_at: String,
-    #[allow(dead_code)]
-    pub updated_at: String,
+/// Record (or refresh) the balance stuck on a deployed proxy
+pub async fn upsert_stranded(
+    pool: &SqlitePool,
+    deposit_address: &str,
+    balance_wei: &str,
+) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("upsert_stranded");
+    sqlx::query(
+        r#"
+        INSERT INTO stranded_funds (deposit_address, balance_wei)
+        VALUES (?, ?)
+        ON CONFLICT(deposit_address) DO UPDATE
+        SET balance_wei = excluded.balance_wei, last_checked_at = datetime('now')
+        "#,
+    )
+    .bind(deposit_address)
+    .bind(balance_wei)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Get all proxies with stranded funds
+pub async fn get_stranded(pool: &SqlitePool) -> Result<Vec<StrandedRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_stranded");
+    sqlx::query_as(
+        r#"
+        SELECT s.deposit_address, d.user_address, s.balance_wei, s.detected_at, s.last_checked_at
+        FROM stranded_funds s
+        JOIN deposits d ON d.deposit_address = s.deposit_address
+        ORDER BY s.detected_at ASC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Count deposits per status
+pub async fn count_deposits_by_status(
+    pool: &SqlitePool,
+) -> Result<Vec<(String, i64)>, sqlx::Error> {
+    let _timer = metrics::db_timer("count_deposits_by_status");
+    sqlx::query_as("SELECT status, COUNT(*) FROM deposits GROUP BY status")
+        .fetch_all(pool)
+        .await
+}
+
+/// Deposits a routing run by the holder bound to `?` may claim
+const CLAIMABLE: &str =
+    "(claimed_by IS NULL OR claimed_by = ? OR claimed_until <= datetime('now'))";
+
+/// Deposits not waiting for a retry
+const RETRY_DUE: &str = "(next_retry_at IS NULL OR next_retry_at <= datetime('now'))";
+
+/// Claim the unclaimed deposits in the given statuses for `holder`
+///
+/// Claims expire after `ttl_secs` unless extended, so a crashed worker's
+/// deposits are picked up again. Deposits waiting for a retry are skipped
+/// until `next_retry_at`. Returns the claimed deposits by ID.
+pub async fn claim_deposits(
+    pool: &SqlitePool,
+    statuses: &[&str],
+    holder: &str,
+    ttl_secs: u64,
+) -> Result<Vec<DepositRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("claim_deposits");
+    if statuses.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let placeholders: Vec<&str> = statuses.iter().map(|_| "?").collect();
+    let query = format!(
+        r#"
+        UPDATE deposits
+        SET claimed_by = ?, claimed_until = datetime('now', '+' || ? || ' seconds')
+        WHERE status IN ({})
+          AND {}
+          AND {}
+        RETURNING id, user_address, salt, deposit_address, nonce, status, created_at, updated_at,
+                  attempts, last_error, next_retry_at, tenant
+        "#,
+        placeholders.join(", "),
+        CLAIMABLE,
+        RETRY_DUE
+    );
+
+    let mut q = sqlx::query_as::<_, DepositRow>(&query)
+        .bind(holder)
+        .bind(ttl_secs as i64);
+    for status in statuses {
+        q = q.bind(*status);
+    }
+    let mut rows = q.bind(holder).fetch_all(pool).await?;
+    rows.sort_by_key(|row| row.id);
+    Ok(rows)
+}
+
+/// Claim one deposit for `holder` if it is in one of the given statuses
+/// and unclaimed
+///
+/// Unlike [`claim_deposits`], a deposit waiting for a retry is claimed too.
+pub async fn claim_deposit(
+    pool: &SqlitePool,
+    deposit_address: &str,
+    statuses: &[&str],
+    holder: &str,
+    ttl_secs: u64,
+) -> Result<Option<DepositRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("claim_deposit");
+    if statuses.is_empty() {
+        return Ok(None);
+    }
+
+    let placeholders: Vec<&str> = statuses.iter().map(|_| "?").collect();
+    let query = format!(
+        r#"
+        UPDATE deposits
+        SET claimed_by = ?, claimed_until = datetime('now', '+' || ? || ' seconds')
+        WHERE deposit_address = ?
+          AND status IN ({})
+          AND {}
+        RETURNING id, user_address, salt, deposit_address, nonce, status, created_at, updated_at,
+                  attempts, last_error, next_retry_at, tenant
+        "#,
+        placeholders.join(", "),
+        CLAIMABLE
+    );
+
+    let mut q = sqlx::query_as::<_, DepositRow>(&query)
+        .bind(holder)
+        .bind(ttl_secs as i64)
+        .bind(deposit_address);
+    for status in statuses {
+        q = q.bind(*status);
+    }
+    q.bind(holder).fetch_optional(pool).await
+}
+
+/// Deposits in the given statuses with what would keep a routing run from
+/// claiming them, without claiming anything
+///
+/// Uses the same conditions as [`claim_deposits`] for a run that holds no
+/// claims yet. Returns the deposits by ID.
+pub async fn get_routing_candidates(
+    pool: &SqlitePool,
+    statuses: &[&str],
+) -> Result<Vec<CandidateRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_routing_candidates");
+    if statuses.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let placeholders: Vec<&str> = statuses.iter().map(|_| "?").collect();
+    let query = format!(
+        r#"
+        SELECT id, user_address, salt, deposit_address, nonce, status, created_at, updated_at,
+               attempts, last_error, next_retry_at, tenant,
+               CASE WHEN {} THEN NULL ELSE claimed_by END AS claimed_by,
+               CASE WHEN {} THEN NULL ELSE claimed_until END AS claimed_until,
+               {} AS retry_due
+        FROM deposits
+        WHERE status IN ({})
+        ORDER BY id ASC
+        "#,
+        CLAIMABLE,
+        CLAIMABLE,
+        RETRY_DUE,
+        placeholders.join(", ")
+    );
+
+    // No run holds claims under the empty name
+    let mut q = sqlx::query_as::<_, CandidateRow>(&query).bind("").bind("");
+    for status in statuses {
+        q = q.bind(*status);
+    }
+    q.fetch_all(pool).await
+}
+
+/// Push back the expiry of every deposit claimed by `holder`
+pub async fn extend_deposit_claims(
+    pool: &SqlitePool,
+    holder: &str,
+    ttl_secs: u64,
+) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("extend_deposit_claims");
+    sqlx::query(
+        r#"
+        UPDATE deposits
+        SET claimed_until = datetime('now', '+' || ? || ' seconds')
+        WHERE claimed_by = ?
+        "#,
+    )
+    .bind(ttl_secs as i64)
+    .bind(holder)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Release every deposit claimed by `holder`
+pub async fn release_deposit_claims(pool: &SqlitePool, holder: &str) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("release_deposit_claims");
+    sqlx::query("UPDATE deposits SET claimed_by = NULL, claimed_until = NULL WHERE claimed_by = ?")
+        .bind(holder)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Take a lease for `holder` if it is free, expired or already theirs
+pub async fn acquire_lease(
+    pool: &SqlitePool,
+    name: &str,
+    holder: &str,
+    ttl_secs: u64,
+) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::db_timer("acquire_lease");
+    let acquired: Option<String> = sqlx::query_scalar(
+        r#"
+        INSERT INTO routing_leases (name, holder, acquired_at, expires_at)
+        VALUES (?, ?, datetime('now'), datetime('now', '+' || ? || ' seconds'))
+        ON CONFLICT(name) DO UPDATE SET
+            holder = excluded.holder,
+            acquired_at = excluded.acquired_at,
+            expires_at = excluded.expires_at
+        WHERE expires_at <= datetime('now') OR holder = excluded.holder
+        RETURNING holder
+        "#,
+    )
+    .bind(name)
+    .bind(holder)
+    .bind(ttl_secs as i64)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(acquired.is_some())
+}
+
+/// Current holder of a lease, if it has not expired
+pub async fn get_lease(pool: &SqlitePool, name: &str) -> Result<Option<LeaseInfo>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_lease");
+    sqlx::query_as(
+        r#"
+        SELECT holder, acquired_at, expires_at
+        FROM routing_leases
+        WHERE name = ? AND expires_at > datetime('now')
+        "#,
+    )
+    .bind(name)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Extend a lease; returns false if `holder` no longer holds it
+pub async fn renew_lease(
+    pool: &SqlitePool,
+    name: &str,
+    holder: &str,
+    ttl_secs: u64,
+) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::db_timer("renew_lease");
+    let result = sqlx::query(
+        r#"
+        UPDATE routing_leases
+        SET expires_at = datetime('now', '+' || ? || ' seconds')
+        WHERE name = ? AND holder = ?
+        "#,
+    )
+    .bind(ttl_secs as i64)
+    .bind(name)
+    .bind(holder)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Give up a lease held by `holder`
+pub async fn release_lease(pool: &SqlitePool, name: &str, holder: &str) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("release_lease");
+    sqlx::query("DELETE FROM routing_leases WHERE name = ? AND holder = ?")
+        .bind(name)
+        .bind(holder)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Record a finished routing pass
+pub async fn insert_routing_run(
+    pool: &SqlitePool,
+    checked: usize,
+    routed: usize,
+    stranded: usize,
+    error_count: usize,
+) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("insert_routing_run");
+    sqlx::query(
+        r#"
+        INSERT INTO routing_runs (checked, routed, stranded, error_count)
+        VALUES (?, ?, ?, ?)
+        "#,
+    )
+    .bind(checked as i64)
+    .bind(routed as i64)
+    .bind(stranded as i64)
+    .bind(error_count as i64)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Seconds since the last routing pass that finished without errors
+pub async fn seconds_since_last_clean_run(pool: &SqlitePool) -> Result<Option<i64>, sqlx::Error> {
+    let _timer = metrics::db_timer("seconds_since_last_clean_run");
+    sqlx::query_scalar(
+        r#"
+        SELECT CAST((julianday('now') - julianday(finished_at)) * 86400 AS INTEGER)
+        FROM routing_runs
+        WHERE error_count = 0
+        ORDER BY id DESC
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Record a routing job started by the lease holder `holder`
+pub async fn insert_routing_job(
+    pool: &SqlitePool,
+    holder: &str,
+    requested_by: Option<&str>,
+) -> Result<i64, sqlx::Error> {
+    let _timer = metrics::db_timer("insert_routing_job");
+    let result = sqlx::query("INSERT INTO routing_jobs (holder, requested_by) VALUES (?, ?)")
+        .bind(holder)
+        .bind(requested_by)
+        .execute(pool)
+        .await?;
+
+    Ok(result.last_insert_rowid())
+}
+
+/// Store the progress of a running job
+pub async fn update_routing_job_progress(
+    pool: &SqlitePool,
+    id: i64,
+    progress: &str,
+) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("update_routing_job_progress");
+    sqlx::query(
+        r#"
+        UPDATE routing_jobs
+        SET progress = ?, updated_at = datetime('now')
+        WHERE id = ? AND status = 'running'
+        "#,
+    )
+    .bind(progress)
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Store the result of a job and mark it completed
+pub async fn finish_routing_job(
+    pool: &SqlitePool,
+    id: i64,
+    progress: &str,
+) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("finish_routing_job");
+    sqlx::query(
+        r#"
+        UPDATE routing_jobs
+        SET status = 'completed', progress = ?, updated_at = datetime('now'),
+            finished_at = datetime('now')
+        WHERE id = ?
+        "#,
+    )
+    .bind(progress)
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Mark running jobs interrupted whose holder's leases have all expired
+///
+/// A run's heartbeat renews its lease, so such a job died with its process.
+/// Returns the number of jobs marked.
+pub async fn interrupt_running_jobs(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    let _timer = metrics::db_timer("interrupt_running_jobs");
+    let result = sqlx::query(
+        r#"
+        UPDATE routing_jobs
+        SET status = 'interrupted', updated_at = datetime('now'),
+            finished_at = datetime('now')
+        WHERE status = 'running' AND NOT EXISTS (
+            SELECT 1 FROM routing_leases l
+            WHERE l.holder = routing_jobs.holder AND l.expires_at > datetime('now')
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Columns of a routing job; a running job whose holder's lease has expired
+/// is reported interrupted, since the heartbeat renews it while the job runs
+const JOB_COLUMNS: &str = r#"
+    id,
+    CASE WHEN status = 'running' AND NOT EXISTS (
+        SELECT 1 FROM routing_leases l
+        WHERE l.holder = routing_jobs.holder AND l.expires_at > datetime('now')
+    ) THEN 'interrupted' ELSE status END AS status,
+    requested_by, progress, created_at, updated_at, finished_at
+"#;
+
+/// Get a routing job by ID
+pub async fn get_routing_job(pool: &SqlitePool, id: i64) -> Result<Option<JobRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_routing_job");
+    let query = format!("SELECT {} FROM routing_jobs WHERE id = ?", JOB_COLUMNS);
+    sqlx::query_as(&query).bind(id).fetch_optional(pool).await
+}
+
+/// Get the most recent routing jobs, newest first
+pub async fn list_routing_jobs(pool: &SqlitePool, limit: u32) -> Result<Vec<JobRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("list_routing_jobs");
+    let query = format!(
+        "SELECT {} FROM routing_jobs ORDER BY id DESC LIMIT ?",
+        JOB_COLUMNS
+    );
+    sqlx::query_as(&query)
+        .bind(i64::from(limit))
+        .fetch_all(pool)
+        .await
+}
+
+/// Store a new API key
+pub async fn insert_api_key(
+    pool: &SqlitePool,
+    name: &str,
+    key_hash: &str,
+    key_prefix: &str,
+    roles: &str,
+    created_by: Option<&str>,
+) -> Result<i64, sqlx::Error> {
+    let _timer = metrics::db_timer("insert_api_key");
+    let result = sqlx::query(
+        r#"
+        INSERT INTO api_keys (name, key_hash, key_prefix, roles, created_by)
+        VALUES (?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(name)
+    .bind(key_hash)
+    .bind(key_prefix)
+    .bind(roles)
+    .bind(created_by)
+    .execute(pool)
+    .await?;
+
+    Ok(result.last_insert_rowid())
+}
+
+/// Get an API key by the hash of its secret
+pub async fn get_api_key_by_hash(
+    pool: &SqlitePool,
+    key_hash: &str,
+) -> Result<Option<ApiKeyRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_api_key_by_hash");
+    sqlx::query_as(
+        r#"
+        SELECT id, name, key_prefix, roles, created_by, created_at, last_used_at,
+               request_count, revoked_at
+        FROM api_keys
+        WHERE key_hash = ?
+        "#,
+    )
+    .bind(key_hash)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Get all API keys, revoked ones included
+pub async fn list_api_keys(pool: &SqlitePool) -> Result<Vec<ApiKeyRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("list_api_keys");
+    sqlx::query_as(
+        r#"
+        SELECT id, name, key_prefix, roles, created_by, created_at, last_used_at,
+               request_count, revoked_at
+        FROM api_keys
+        ORDER BY id ASC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Revoke an API key; returns false if no active key has this ID
+pub async fn revoke_api_key(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::db_timer("revoke_api_key");
+    let result = sqlx::query(
+        r#"
+        UPDATE api_keys
+        SET revoked_at = datetime('now')
+        WHERE id = ? AND revoked_at IS NULL
+        "#,
+    )
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Count a request made with an API key
+pub async fn record_api_key_use(pool: &SqlitePool, id: i64) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("record_api_key_use");
+    sqlx::query(
+        r#"
+        UPDATE api_keys
+        SET request_count = request_count + 1, last_used_at = datetime('now')
+        WHERE id = ?
+        "#,
+    )
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Store a SIWE nonce valid for `ttl_secs`, returning its expiry
+pub async fn insert_siwe_nonce(
+    pool: &SqlitePool,
+    nonce: &str,
+    ttl_secs: u64,
+) -> Result<String, sqlx::Error> {
+    let _timer = metrics::db_timer("insert_siwe_nonce");
+    sqlx::query_scalar(
+        r#"
+        INSERT INTO siwe_nonces (nonce, expires_at)
+        VALUES (?, datetime('now', '+' || ? || ' seconds'))
+        RETURNING expires_at
+        "#,
+    )
+    .bind(nonce)
+    .bind(ttl_secs as i64)
+    .fetch_one(pool)
+    .await
+}
+
+/// Mark a nonce used; returns false if it is unknown, expired or already used
+pub async fn consume_siwe_nonce(pool: &SqlitePool, nonce: &str) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::db_timer("consume_siwe_nonce");
+    let result = sqlx::query(
+        r#"
+        UPDATE siwe_nonces
+        SET used_at = datetime('now')
+        WHERE nonce = ? AND used_at IS NULL AND expires_at > datetime('now')
+        "#,
+    )
+    .bind(nonce)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Start a session for a wallet, returning its expiry
+pub async fn insert_session(
+    pool: &SqlitePool,
+    token_hash: &str,
+    address: &str,
+    ttl_secs: u64,
+) -> Result<String, sqlx::Error> {
+    let _timer = metrics::db_timer("insert_session");
+    sqlx::query_scalar(
+        r#"
+        INSERT INTO sessions (token_hash, address, expires_at)
+        VALUES (?, ?, datetime('now', '+' || ? || ' seconds'))
+        RETURNING expires_at
+        "#,
+    )
+    .bind(token_hash)
+    .bind(address)
+    .bind(ttl_secs as i64)
+    .fetch_one(pool)
+    .await
+}
+
+/// Get an unexpired session by the hash of its token
+pub async fn get_session(
+    pool: &SqlitePool,
+    token_hash: &str,
+) -> Result<Option<SessionRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_session");
+    sqlx::query_as(
+        r#"
+        SELECT address, expires_at
+        FROM sessions
+        WHERE token_hash = ? AND expires_at > datetime('now')
+        "#,
+    )
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await
+}
+
+/// End a session
+pub async fn delete_session(pool: &SqlitePool, token_hash: &str) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("delete_session");
+    sqlx::query("DELETE FROM sessions WHERE token_hash = ?")
+        .bind(token_hash)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Take a token from a rate limit bucket, refilling it first
+///
+/// A missing bucket starts full. Returns false, leaving the bucket as it
+/// was, when less than one token is available at `now`.
+pub async fn take_rate_limit_token(
+    pool: &SqlitePool,
+    key: &str,
+    capacity: u32,
+    rate: f64,
+    now: f64,
+) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::db_timer("take_rate_limit_token");
+    // One statement, so concurrent requests cannot both take the last token
+    let taken: Option<f64> = sqlx::query_scalar(
+        r#"
+        INSERT INTO rate_limits (key, tokens, updated_at)
+        VALUES (?1, ?2 - 1, ?4)
+        ON CONFLICT(key) DO UPDATE SET
+            tokens = MIN(?2, tokens + MAX(?4 - updated_at, 0) * ?3) - 1,
+            updated_at = ?4
+        WHERE MIN(?2, tokens + MAX(?4 - updated_at, 0) * ?3) >= 1
+        RETURNING tokens
+        "#,
+    )
+    .bind(key)
+    .bind(f64::from(capacity))
+    .bind(rate)
+    .bind(now)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(taken.is_some())
+}
+
+/// Current state of a rate limit bucket
+pub async fn get_rate_limit_bucket(
+    pool: &SqlitePool,
+    key: &str,
+) -> Result<Option<Bucket>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_rate_limit_bucket");
+    let row: Option<(f64, f64)> =
+        sqlx::query_as("SELECT tokens, updated_at FROM rate_limits WHERE key = ?")
+            .bind(key)
+            .fetch_optional(pool)
+            .await?;
+
+    Ok(row.map(|(tokens, updated)| Bucket { tokens, updated }))
+}
+
+/// Claim an idempotency key for a request with hash `request_hash`
+///
+/// Returns false if the key is already held: by a finished request until it
+/// expires after `ttl_secs`, or by one in progress until it is `stale_secs`
+/// old.
+#[allow(clippy::too_many_arguments)]
+pub async fn claim_idempotency_key(
+    pool: &SqlitePool,
+    caller: &str,
+    scope: &str,
+    key: &str,
+    request_hash: &str,
+    ttl_secs: u64,
+    stale_secs: u64,
+) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::db_timer("claim_idempotency_key");
+    sqlx::query("DELETE FROM idempotency_keys WHERE expires_at <= datetime('now')")
+        .execute(pool)
+        .await?;
+
+    let claimed: Option<String> = sqlx::query_scalar(
+        r#"
+        INSERT INTO idempotency_keys (caller, scope, key, request_hash, expires_at)
+        VALUES (?, ?, ?, ?, datetime('now', '+' || ? || ' seconds'))
+        ON CONFLICT(caller, scope, key) DO UPDATE SET
+            request_hash = excluded.request_hash,
+            response_status = NULL,
+            response_body = NULL,
+            created_at = datetime('now'),
+            expires_at = excluded.expires_at
+        WHERE response_status IS NULL
+          AND created_at <= datetime('now', '-' || ? || ' seconds')
+        RETURNING key
+        "#,
+    )
+    .bind(caller)
+    .bind(scope)
+    .bind(key)
+    .bind(request_hash)
+    .bind(ttl_secs as i64)
+    .bind(stale_secs as i64)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(claimed.is_some())
+}
+
+/// Get a claimed idempotency key
+pub async fn get_idempotency_key(
+    pool: &SqlitePool,
+    caller: &str,
+    scope: &str,
+    key: &str,
+) -> Result<Option<IdempotencyRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_idempotency_key");
+    sqlx::query_as(
+        r#"
+        SELECT request_hash, response_status, response_body
+        FROM idempotency_keys
+        WHERE caller = ? AND scope = ? AND key = ?
+        "#,
+    )
+    .bind(caller)
+    .bind(scope)
+    .bind(key)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Store the response of the request holding an idempotency key
+pub async fn complete_idempotency_key(
+    pool: &SqlitePool,
+    caller: &str,
+    scope: &str,
+    key: &str,
+    status: u16,
+    body: &str,
+) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("complete_idempotency_key");
+    sqlx::query(
+        r#"
+        UPDATE idempotency_keys
+        SET response_status = ?, response_body = ?
+        WHERE caller = ? AND scope = ? AND key = ?
+        "#,
+    )
+    .bind(i64::from(status))
+    .bind(body)
+    .bind(caller)
+    .bind(scope)
+    .bind(key)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Forget an idempotency key so the request can be retried under it
+pub async fn release_idempotency_key(
+    pool: &SqlitePool,
+    caller: &str,
+    scope: &str,
+    key: &str,
+) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("release_idempotency_key");
+    sqlx::query("DELETE FROM idempotency_keys WHERE caller = ? AND scope = ? AND key = ?")
+        .bind(caller)
+        .bind(scope)
+        .bind(key)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Register a treasury under a unique name
+pub async fn insert_treasury(
+    pool: &SqlitePool,
+    name: &str,
+    address: &str,
+) -> Result<TreasuryRow, sqlx::Error> {
+    let _timer = metrics::db_timer("insert_treasury");
+    sqlx::query_as(
+        r#"
+        INSERT INTO treasuries (name, address) VALUES (?, ?)
+        RETURNING id, name, address, active, created_at
+        "#,
+    )
+    .bind(name)
+    .bind(address)
+    .fetch_one(pool)
+    .await
+}
+
+/// All registered treasuries, active ones first
+pub async fn list_treasuries(pool: &SqlitePool) -> Result<Vec<TreasuryRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("list_treasuries");
+    sqlx::query_as(
+        "SELECT id, name, address, active, created_at FROM treasuries ORDER BY active DESC, id ASC",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Treasuries routing may send funds to
+pub async fn get_active_treasuries(pool: &SqlitePool) -> Result<Vec<TreasuryRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_active_treasuries");
+    sqlx::query_as(
+        "SELECT id, name, address, active, created_at FROM treasuries WHERE active = 1 ORDER BY id",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Take a treasury out of the registry; returns false if no active treasury
+/// has that name
+pub async fn deactivate_treasury(pool: &SqlitePool, name: &str) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::db_timer("deactivate_treasury");
+    let result = sqlx::query("UPDATE treasuries SET active = 0 WHERE name = ? AND active = 1")
+        .bind(name)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Add a routing rule; `splits` is its JSON split list
+pub async fn insert_routing_rule(
+    pool: &SqlitePool,
+    user_address: Option<&str>,
+    tenant: Option<&str>,
+    asset: Option<&str>,
+    splits: &str,
+) -> Result<RuleRow, sqlx::Error> {
+    let _timer = metrics::db_timer("insert_routing_rule");
+    sqlx::query_as(
+        r#"
+        INSERT INTO routing_rules (user_address, tenant, asset, splits) VALUES (?, ?, ?, ?)
+        RETURNING id, user_address, tenant, asset, splits, created_at
+        "#,
+    )
+    .bind(user_address)
+    .bind(tenant)
+    .bind(asset)
+    .bind(splits)
+    .fetch_one(pool)
+    .await
+}
+
+/// All routing rules by ID
+pub async fn list_routing_rules(pool: &SqlitePool) -> Result<Vec<RuleRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("list_routing_rules");
+    sqlx::query_as(
+        "SELECT id, user_address, tenant, asset, splits, created_at FROM routing_rules ORDER BY id",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Delete a routing rule; returns false if there was none with that ID
+pub async fn delete_routing_rule(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::db_timer("delete_routing_rule");
+    let result = sqlx::query("DELETE FROM routing_rules WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Record the part of a deposit one transferFunds call sent to a treasury
+pub async fn insert_allocation(
+    pool: &SqlitePool,
+    deposit_address: &str,
+    treasury_address: &str,
+    share_bps: u32,
+    amount_wei: &str,
+    tx_hash: &str,
+) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("insert_allocation");
+    sqlx::query(
+        r#"
+        INSERT INTO deposit_allocations
+            (deposit_address, treasury_address, share_bps, amount_wei, tx_hash)
+        VALUES (?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(deposit_address)
+    .bind(treasury_address)
+    .bind(share_bps as i64)
+    .bind(amount_wei)
+    .bind(tx_hash)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Start counting what a proxy forwards after `block`, unless an earlier
+/// deployment attempt already did
+pub async fn start_forwarded_scan(
+    pool: &SqlitePool,
+    deposit_address: &str,
+    block: u64,
+) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("start_forwarded_scan");
+    sqlx::query(
+        r#"
+        INSERT INTO forwarded_funds (deposit_address, scanned_block)
+        VALUES (?, ?)
+        ON CONFLICT(deposit_address) DO NOTHING
+        "#,
+    )
+    .bind(deposit_address)
+    .bind(block as i64)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// ETH a proxy forwarded so far, if its scan was started
+pub async fn get_forwarded(
+    pool: &SqlitePool,
+    deposit_address: &str,
+) -> Result<Option<ForwardedRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_forwarded");
+    sqlx::query_as(
+        r#"
+        SELECT deposit_address, forwarded_wei, scanned_block
+        FROM forwarded_funds
+        WHERE deposit_address = ?
+        "#,
+    )
+    .bind(deposit_address)
+    .fetch_optional(pool)
+    .await
+}
+
+/// ETH every proxy whose scan was started forwarded so far
+pub async fn get_all_forwarded(pool: &SqlitePool) -> Result<Vec<ForwardedRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_all_forwarded");
+    sqlx::query_as(
+        r#"
+        SELECT deposit_address, forwarded_wei, scanned_block
+        FROM forwarded_funds
+        ORDER BY scanned_block ASC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Record the ETH a proxy forwarded through `scanned_block`, unless a later
+/// scan already did
+pub async fn update_forwarded(
+    pool: &SqlitePool,
+    deposit_address: &str,
+    forwarded_wei: &str,
+    scanned_block: u64,
+) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("update_forwarded");
+    sqlx::query(
+        r#"
+        UPDATE forwarded_funds
+        SET forwarded_wei = ?, scanned_block = ?, updated_at = datetime('now')
+        WHERE deposit_address = ? AND scanned_block < ?
+        "#,
+    )
+    .bind(forwarded_wei)
+    .bind(scanned_block as i64)
+    .bind(deposit_address)
+    .bind(scanned_block as i64)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Mark every proxy scanned through `block`; for those not recorded by
+/// [`update_forwarded`], nothing was forwarded in the blocks scanned
+pub async fn advance_forwarded_scans(pool: &SqlitePool, block: u64) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("advance_forwarded_scans");
+    sqlx::query(
+        r#"
+        UPDATE forwarded_funds
+        SET scanned_block = ?, updated_at = datetime('now')
+        WHERE scanned_block < ?
+        "#,
+    )
+    .bind(block as i64)
+    .bind(block as i64)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Treasuries a deposit's funds went to, in order
+pub async fn get_allocations(
+    pool: &SqlitePool,
+    deposit_address: &str,
+) -> Result<Vec<AllocationRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_allocations");
+    sqlx::query_as(
+        r#"
+        SELECT treasury_address, share_bps, amount_wei, tx_hash, created_at
+        FROM deposit_allocations
+        WHERE deposit_address = ?
+        ORDER BY id ASC
+        "#,
+    )
+    .bind(deposit_address)
+    .fetch_all(pool)
+    .await
+}
+
+/// Amounts of every allocation, by deposit address
+pub async fn get_allocated_wei(pool: &SqlitePool) -> Result<Vec<(String, String)>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_allocated_wei");
+    sqlx::query_as("SELECT deposit_address, amount_wei FROM deposit_allocations")
+        .fetch_all(pool)
+        .await
+}
+
+/// A value the backend recorded about itself
+pub async fn get_setting(pool: &SqlitePool, key: &str) -> Result<Option<String>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_setting");
+    sqlx::query_scalar("SELECT value FROM settings WHERE key = ?")
+        .bind(key)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Record a value unless one is recorded already; returns the value now
+/// recorded
+pub async fn insert_setting(
+    pool: &SqlitePool,
+    key: &str,
+    value: &str,
+) -> Result<String, sqlx::Error> {
+    let _timer = metrics::db_timer("insert_setting");
+    sqlx::query("INSERT INTO settings (key, value) VALUES (?, ?) ON CONFLICT(key) DO NOTHING")
+        .bind(key)
+        .bind(value)
+        .execute(pool)
+        .await?;
+    sqlx::query_scalar("SELECT value FROM settings WHERE key = ?")
+        .bind(key)
+        .fetch_one(pool)
+        .await
+}
+
+/// Record a value, replacing any recorded before
+pub async fn set_setting(pool: &SqlitePool, key: &str, value: &str) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("set_setting");
+    sqlx::query(
+        r#"
+        INSERT INTO settings (key, value) VALUES (?, ?)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = datetime('now')
+        "#,
+    )
+    .bind(key)
+    .bind(value)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Invoice terms for a deposit
+pub struct NewInvoice<'a> {
+    pub expected_amount_wei: &'a str,
+    pub asset: &'a str,
+    pub expires_in_secs: Option<u64>,
+    pub order_ref: Option<&'a str>,
+    pub metadata: Option<&'a str>,
+    pub description: Option<&'a str>,
+    /// Latest block at creation, if it could be read
+    pub created_block: Option<u64>,
+}
+
+/// Columns of an invoice, with whether it has expired and whether it was
+/// paid after expiring
+const INVOICE_COLUMNS: &str = r#"
+    deposit_address, expected_amount_wei, asset, expires_at, order_ref, metadata,
+    description, payment_status, received_wei, paid_at, created_at, created_block,
+    (expires_at IS NOT NULL AND expires_at <= datetime('now')) AS expired,
+    (paid_at IS NOT NULL AND expires_at IS NOT NULL AND paid_at > expires_at) AS paid_late
+"#;
+
+/// Attach invoice terms to a deposit
+pub async fn insert_invoice(
+    pool: &SqlitePool,
+    deposit_address: &str,
+    invoice: &NewInvoice<'_>,
+) -> Result<InvoiceRow, sqlx::Error> {
+    let _timer = metrics::db_timer("insert_invoice");
+    let query = format!(
+        r#"
+        INSERT INTO invoices
+            (deposit_address, expected_amount_wei, asset, expires_at, order_ref, metadata,
+             description, created_block)
+        VALUES (?1, ?2, ?3, CASE WHEN ?4 IS NULL THEN NULL
+                                 ELSE datetime('now', '+' || ?4 || ' seconds') END,
+                ?5, ?6, ?7, ?8)
+        RETURNING {}
+        "#,
+        INVOICE_COLUMNS
+    );
+    sqlx::query_as(&query)
+        .bind(deposit_address)
+        .bind(invoice.expected_amount_wei)
+        .bind(invoice.asset)
+        .bind(invoice.expires_in_secs.map(|secs| secs as i64))
+        .bind(invoice.order_ref)
+        .bind(invoice.metadata)
+        .bind(invoice.description)
+        .bind(invoice.created_block.map(|block| block as i64))
+        .fetch_one(pool)
+        .await
+}
+
+/// Invoice terms of a deposit, if it was created as an invoice
+pub async fn get_invoice(
+    pool: &SqlitePool,
+    deposit_address: &str,
+) -> Result<Option<InvoiceRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_invoice");
+    let query = format!(
+        "SELECT {} FROM invoices WHERE deposit_address = ?",
+        INVOICE_COLUMNS
+    );
+    sqlx::query_as(&query)
+        .bind(deposit_address)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Record what an invoice's deposit received, keeping the largest amount
+/// recorded; the first payment sets `paid_at`, to the Unix time `paid_at` or
+/// else now. Returns `None` for a deposit without an invoice
+pub async fn record_invoice_payment(
+    pool: &SqlitePool,
+    deposit_address: &str,
+    received_wei: &str,
+    paid_at: Option<u64>,
+) -> Result<Option<InvoiceRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("record_invoice_payment");
+    // Decimal strings without leading zeros compare by length, then
+    // digit by digit
+    let query = format!(
+        r#"
+        UPDATE invoices
+        SET received_wei = CASE
+                WHEN length(?1) > length(received_wei)
+                  OR (length(?1) = length(received_wei) AND ?1 > received_wei) THEN ?1
+                ELSE received_wei END,
+            paid_at = COALESCE(paid_at, datetime(?2, 'unixepoch'), datetime('now')),
+            updated_at = datetime('now')
+        WHERE deposit_address = ?3
+        RETURNING {}
+        "#,
+        INVOICE_COLUMNS
+    );
+    sqlx::query_as(&query)
+        .bind(received_wei)
+        .bind(paid_at.map(|secs| secs as i64))
+        .bind(deposit_address)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Store an invoice's payment classification
+pub async fn set_payment_status(
+    pool: &SqlitePool,
+    deposit_address: &str,
+    payment_status: &str,
+) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("set_payment_status");
+    sqlx::query(
+        "UPDATE invoices SET payment_status = ?, updated_at = datetime('now') WHERE deposit_address = ?",
+    )
+    .bind(payment_status)
+    .bind(deposit_address)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Unpaid invoices past their expiry that aren't marked expired yet
+pub async fn get_expiring_invoices(pool: &SqlitePool) -> Result<Vec<InvoiceRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_expiring_invoices");
+    let query = format!(
+        r#"
+        SELECT {} FROM invoices
+        WHERE payment_status = 'awaiting_payment' AND received_wei = '0'
+          AND expires_at IS NOT NULL AND expires_at <= datetime('now')
+        "#,
+        INVOICE_COLUMNS
+    );
+    sqlx::query_as(&query).fetch_all(pool).await
+}
+
+/// Mark an invoice expired unless a payment was recorded meanwhile; returns
+/// whether it was
+pub async fn expire_invoice(pool: &SqlitePool, deposit_address: &str) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::db_timer("expire_invoice");
+    let result = sqlx::query(
+        r#"
+        UPDATE invoices
+        SET payment_status = 'expired', updated_at = datetime('now')
+        WHERE deposit_address = ?
+          AND payment_status = 'awaiting_payment' AND received_wei = '0'
+        "#,
+    )
+    .bind(deposit_address)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+const WEBHOOK_COLUMNS: &str = "id, tenant, url, secret, events, active, created_at";
+
+/// Add a webhook subscription; `events` is its comma-separated event filter
+pub async fn insert_webhook_subscription(
+    pool: &SqlitePool,
+    tenant: Option<&str>,
+    url: &str,
+    secret: &str,
+    events: &str,
+) -> Result<WebhookRow, sqlx::Error> {
+    let _timer = metrics::db_timer("insert_webhook_subscription");
+    sqlx::query_as(&format!(
+        r#"
+        INSERT INTO webhook_subscriptions (tenant, url, secret, events) VALUES (?, ?, ?, ?)
+        RETURNING {}
+        "#,
+        WEBHOOK_COLUMNS
+    ))
+    .bind(tenant)
+    .bind(url)
+    .bind(secret)
+    .bind(events)
+    .fetch_one(pool)
+    .await
+}
+
+/// Active subscriptions by ID; with `tenant`, only that tenant's
+pub async fn list_webhook_subscriptions(
+    pool: &SqlitePool,
+    tenant: Option<&str>,
+) -> Result<Vec<WebhookRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("list_webhook_subscriptions");
+    sqlx::query_as(&format!(
+        r#"
+        SELECT {} FROM webhook_subscriptions
+        WHERE active = 1 AND (?1 IS NULL OR tenant = ?1)
+        ORDER BY id
+        "#,
+        WEBHOOK_COLUMNS
+    ))
+    .bind(tenant)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_webhook_subscription(
+    pool: &SqlitePool,
+    id: i64,
+) -> Result<Option<WebhookRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_webhook_subscription");
+    sqlx::query_as(&format!(
+        "SELECT {} FROM webhook_subscriptions WHERE id = ?",
+        WEBHOOK_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Active subscriptions receiving events of a deposit created by `tenant`:
+/// the tenant's own and those without a tenant
+pub async fn get_webhook_subscribers(
+    pool: &SqlitePool,
+    tenant: Option<&str>,
+) -> Result<Vec<WebhookRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_webhook_subscribers");
+    sqlx::query_as(&format!(
+        r#"
+        SELECT {} FROM webhook_subscriptions
+        WHERE active = 1 AND (tenant IS NULL OR tenant = ?)
+        ORDER BY id
+        "#,
+        WEBHOOK_COLUMNS
+    ))
+    .bind(tenant)
+    .fetch_all(pool)
+    .await
+}
+
+/// Stop a subscription; its deliveries are kept for the log but no longer
+/// sent. Returns false if no active subscription has that ID.
+pub async fn deactivate_webhook_subscription(
+    pool: &SqlitePool,
+    id: i64,
+) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::db_timer("deactivate_webhook_subscription");
+    let result =
+        sqlx::query("UPDATE webhook_subscriptions SET active = 0 WHERE id = ? AND active = 1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Queue an event for a subscription in the outbox, due now
+pub async fn insert_webhook_delivery(
+    pool: &SqlitePool,
+    subscription_id: i64,
+    event: &str,
+    deposit_address: &str,
+    payload: &str,
+) -> Result<i64, sqlx::Error> {
+    let _timer = metrics::db_timer("insert_webhook_delivery");
+    let result = sqlx::query(
+        r#"
+        INSERT INTO webhook_deliveries (subscription_id, event, deposit_address, payload)
+        VALUES (?, ?, ?, ?)
+        "#,
+    )
+    .bind(subscription_id)
+    .bind(event)
+    .bind(deposit_address)
+    .bind(payload)
+    .execute(pool)
+    .await?;
+
+    Ok(result.last_insert_rowid())
+}
+
+const DELIVERY_COLUMNS: &str = r#"
+    id, subscription_id, event, deposit_address, payload, status, attempts, next_attempt_at,
+    last_status_code, last_error, delivered_at, created_at
+"#;
+
+/// Claim the oldest pending delivery that is due and newer than `after_id`
+///
+/// A claimed delivery isn't due again for `claim_secs`, so concurrent
+/// dispatchers don't send it twice and one that dies mid-attempt is retried
+/// after that. Deliveries of inactive subscriptions are left alone.
+pub async fn claim_webhook_delivery(
+    pool: &SqlitePool,
+    after_id: i64,
+    claim_secs: u64,
+) -> Result<Option<DeliveryRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("claim_webhook_delivery");
+    sqlx::query_as(&format!(
+        r#"
+        UPDATE webhook_deliveries
+        SET next_attempt_at = datetime('now', '+' || ?1 || ' seconds')
+        WHERE id = (
+            SELECT d.id FROM webhook_deliveries d
+            JOIN webhook_subscriptions s ON s.id = d.subscription_id
+            WHERE d.status = 'pending' AND d.next_attempt_at <= datetime('now')
+              AND s.active = 1 AND d.id > ?2
+            ORDER BY d.id
+            LIMIT 1
+        )
+        RETURNING {}
+        "#,
+        DELIVERY_COLUMNS
+    ))
+    .bind(claim_secs as i64)
+    .bind(after_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Log an attempt at a delivery and move it to `status`
+///
+/// With `retry_in_secs`, a pending delivery is due again then.
+pub async fn record_webhook_attempt(
+    pool: &SqlitePool,
+    delivery_id: i64,
+    attempt: &WebhookAttempt<'_>,
+    status: &str,
+    retry_in_secs: Option<u64>,
+) -> Result<(), sqlx::Error> {
+    let _timer = metrics::db_timer("record_webhook_attempt");
+    sqlx::query(
+        r#"
+        INSERT INTO webhook_attempts (delivery_id, status_code, error, duration_ms)
+        VALUES (?, ?, ?, ?)
+        "#,
+    )
+    .bind(delivery_id)
+    .bind(attempt.status_code.map(i64::from))
+    .bind(attempt.error)
+    .bind(attempt.duration_ms as i64)
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE webhook_deliveries
+        SET status = ?1, attempts = attempts + 1, last_status_code = ?2, last_error = ?3,
+            next_attempt_at = CASE WHEN ?4 IS NULL THEN next_attempt_at
+                                   ELSE datetime('now', '+' || ?4 || ' seconds') END,
+            delivered_at = CASE WHEN ?1 = 'delivered' THEN datetime('now') ELSE delivered_at END,
+            updated_at = datetime('now')
+        WHERE id = ?5
+        "#,
+    )
+    .bind(status)
+    .bind(attempt.status_code.map(i64::from))
+    .bind(attempt.error)
+    .bind(retry_in_secs.map(|secs| secs as i64))
+    .bind(delivery_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Outcome of one attempt at a delivery
+#[derive(Debug)]
+pub struct WebhookAttempt<'a> {
+    /// HTTP status of the receiver's response, if it answered
+    pub status_code: Option<u16>,
+    pub error: Option<&'a str>,
+    pub duration_ms: u64,
+}
+
+/// A subscription's deliveries, newest first
+pub async fn list_webhook_deliveries(
+    pool: &SqlitePool,
+    subscription_id: i64,
+    limit: u32,
+) -> Result<Vec<DeliveryRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("list_webhook_deliveries");
+    sqlx::query_as(&format!(
+        r#"
+        SELECT {} FROM webhook_deliveries
+        WHERE subscription_id = ?
+        ORDER BY id DESC
+        LIMIT ?
+        "#,
+        DELIVERY_COLUMNS
+    ))
+    .bind(subscription_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_webhook_delivery(
+    pool: &SqlitePool,
+    id: i64,
+) -> Result<Option<DeliveryRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("get_webhook_delivery");
+    sqlx::query_as(&format!(
+        "SELECT {} FROM webhook_deliveries WHERE id = ?",
+        DELIVERY_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Attempts at a delivery, oldest first
+pub async fn list_webhook_attempts(
+    pool: &SqlitePool,
+    delivery_id: i64,
+) -> Result<Vec<AttemptRow>, sqlx::Error> {
+    let _timer = metrics::db_timer("list_webhook_attempts");
+    sqlx::query_as(
+        r#"
+        SELECT status_code, error, duration_ms, attempted_at
+        FROM webhook_attempts
+        WHERE delivery_id = ?
+        ORDER BY id
+        "#,
+    )
+    .bind(delivery_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Send a delivery again with its original payload, due now
+///
+/// Resets its attempts whatever its status; returns false if there is no
+/// such delivery.
+pub async fn replay_webhook_delivery(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::db_timer("replay_webhook_delivery");
+    let result = sqlx::query(
+        r#"
+        UPDATE webhook_deliveries
+        SET status = 'pending', attempts = 0, next_attempt_at = datetime('now'),
+            updated_at = datetime('now')
+        WHERE id = ?
+        "#,
+    )
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Fresh in-memory database with migrations applied
+#[cfg(test)]
+pub async fn test_pool() -> SqlitePool {
+    // A single connection keeps every query on the same in-memory database
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    run_migrations(&pool).await.unwrap();
+    pool
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct DepositRow {
+    pub id: i64,
+    pub user_address: String,
+    pub salt: String,
+    pub deposit_address: String,
+    pub nonce: i64,
+    pub status: String,
+    pub created_at: String,
+    #[allow(dead_code)]
+    pub updated_at: String,
+    /// Failed routing attempts since the deposit was created or requeued
+    pub attempts: i64,
+    pub last_error: Option<String>,
+    pub next_retry_at: Option<String>,
+    /// API key that created the deposit, for routing rules
+    pub tenant: Option<String>,
+}
+
+/// A deposit a routing run would consider; see [`get_routing_candidates`]
+#[derive(Debug, sqlx::FromRow)]
+pub struct CandidateRow {
+    #[sqlx(flatten)]
+    pub deposit: DepositRow,
+    /// Run holding a live claim on the deposit
+    pub claimed_by: Option<String>,
+    pub claimed_until: Option<String>,
+    /// False while the deposit waits for `next_retry_at`
+    pub retry_due: bool,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct TreasuryRow {
+    pub id: i64,
+    pub name: String,
+    pub address: String,
+    pub active: bool,
+    pub created_at: String,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct RuleRow {
+    pub id: i64,
+    pub user_address: Option<String>,
+    pub tenant: Option<String>,
+    pub asset: Option<String>,
+    /// JSON array of {"treasury": <name>, "share_bps": <n>}
+    pub splits: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct AllocationRow {
+    pub treasury_address: String,
+    pub share_bps: i64,
+    pub amount_wei: String,
+    pub tx_hash: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct InvoiceRow {
+    pub deposit_address: String,
+    pub expected_amount_wei: String,
+    pub asset: String,
+    pub expires_at: Option<String>,
+    pub order_ref: Option<String>,
+    /// Caller-supplied JSON
+    pub metadata: Option<String>,
+    pub description: Option<String>,
+    /// Last stored classification, see invoice::PaymentStatus
+    pub payment_status: String,
+    pub received_wei: String,
+    /// Time of the block the first payment landed in, or when routing first
+    /// saw it if that block can't be found
+    pub paid_at: Option<String>,
+    pub created_at: String,
+    /// Latest block when the invoice was created
+    pub created_block: Option<i64>,
+    /// Past `expires_at` now
+    pub expired: bool,
+    /// Paid after `expires_at`
+    pub paid_late: bool,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct ForwardedRow {
+    pub deposit_address: String,
+    pub forwarded_wei: String,
+    /// Last block counted
+    pub scanned_block: i64,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct StrandedRow {
+    pub deposit_address: String,
+    pub user_address: String,
+    pub balance_wei: String,
+    pub detected_at: String,
+    pub last_checked_at: String,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct ApiKeyRow {
+    pub id: i64,
+    pub name: String,
+    pub key_prefix: String,
+    pub roles: String,
+    pub created_by: Option<String>,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+    pub request_count: i64,
+    pub revoked_at: Option<String>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct SessionRow {
+    pub address: String,
+    pub expires_at: String,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct IdempotencyRow {
+    pub request_hash: String,
+    pub response_status: Option<i64>,
+    pub response_body: Option<String>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct JobRow {
+    pub id: i64,
+    pub status: String,
+    pub requested_by: Option<String>,
+    pub progress: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub finished_at: Option<String>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct WebhookRow {
+    pub id: i64,
+    pub tenant: Option<String>,
+    pub url: String,
+    /// Key payloads are signed with
+    pub secret: String,
+    /// Comma-separated event filter
+    pub events: String,
+    pub active: bool,
+    pub created_at: String,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct DeliveryRow {
+    pub id: i64,
+    pub subscription_id: i64,
+    pub event: String,
+    pub deposit_address: String,
+    /// JSON body sent to the subscriber
+    pub payload: String,
+    /// `pending`, `delivered` or `failed`
+    pub status: String,
+    pub attempts: i64,
+    pub next_attempt_at: String,
+    pub last_status_code: Option<i64>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct AttemptRow {
+    pub status_code: Option<i64>,
+    pub error: Option<String>,
+    pub duration_ms: i64,
+    pub attempted_at: String,
 }
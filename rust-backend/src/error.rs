@@ -1,12 +1,17 @@
 //! Error types and handling
 
+use std::time::Duration;
+
 use axum::{
-    http::StatusCode,
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 
-use crate::models::ErrorResponse;
+use crate::{
+    idempotency::IdempotencyError, lease::LeaseError, models::ErrorResponse, request_id,
+    routing::RouteDepositError, siwe::SiweError, treasury::TreasuryError,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum AppError {
@@ -19,11 +24,38 @@ pub enum AppError {
     #[error("Configuration error: {0}")]
     Config(#[from] crate::config::ConfigError),
 
+    #[error("Invalid request: {0}")]
+    BadRequest(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
     #[error("Not found: {0}")]
     NotFound(String),
 
+    /// The request conflicts with the resource's current state
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Rate limited: {message}")]
+    RateLimited {
+        message: String,
+        retry_after: Duration,
+    },
+
+    #[error(transparent)]
+    Siwe(#[from] crate::siwe::SiweError),
+
+    #[error(transparent)]
+    Idempotency(#[from] IdempotencyError),
+
+    #[error(transparent)]
+    Lease(#[from] LeaseError),
+
     #[error("Internal error: {0}")]
-    #[allow(dead_code)]
     Internal(String),
 }
 
@@ -43,7 +75,45 @@ impl IntoResponse for AppError {
                 "CONFIG_ERROR",
                 e.to_string(),
             ),
+            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, "INVALID_REQUEST", msg.clone()),
+            AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, "UNAUTHORIZED", msg.clone()),
+            AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, "FORBIDDEN", msg.clone()),
             AppError::NotFound(msg) => (StatusCode::NOT_FOUND, "NOT_FOUND", msg.clone()),
+            AppError::Conflict(msg) => (StatusCode::CONFLICT, "CONFLICT", msg.clone()),
+            AppError::RateLimited { message, .. } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "RATE_LIMITED",
+                message.clone(),
+            ),
+            AppError::Siwe(e @ SiweError::Parse(_)) => (
+                StatusCode::BAD_REQUEST,
+                "INVALID_SIWE_MESSAGE",
+                e.to_string(),
+            ),
+            AppError::Siwe(e) => (StatusCode::UNAUTHORIZED, "SIWE_REJECTED", e.to_string()),
+            AppError::Idempotency(e) => match e {
+                IdempotencyError::InvalidKey => (
+                    StatusCode::BAD_REQUEST,
+                    "INVALID_IDEMPOTENCY_KEY",
+                    e.to_string(),
+                ),
+                IdempotencyError::InProgress => {
+                    (StatusCode::CONFLICT, "REQUEST_IN_PROGRESS", e.to_string())
+                }
+                IdempotencyError::Mismatch => (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "IDEMPOTENCY_KEY_REUSED",
+                    e.to_string(),
+                ),
+            },
+            AppError::Lease(e @ LeaseError::Busy(_)) => {
+                (StatusCode::CONFLICT, "ROUTING_IN_PROGRESS", e.to_string())
+            }
+            AppError::Lease(e @ LeaseError::Database(_)) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "DATABASE_ERROR",
+                e.to_string(),
+            ),
             AppError::Internal(msg) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "INTERNAL_ERROR",
@@ -54,8 +124,45 @@ impl IntoResponse for AppError {
         let body = Json(ErrorResponse {
             error: message,
             code: code.to_string(),
+            request_id: request_id::current(),
+            details: match &self {
+                AppError::Lease(LeaseError::Busy(lease)) => serde_json::to_value(lease).ok(),
+                _ => None,
+            },
         });
 
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        if let AppError::RateLimited { retry_after, .. } = self {
+            // Whole seconds, rounded up so a retry is never early
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(secs.max(1)));
+        }
+        response
+    }
+}
+
+impl From<RouteDepositError> for AppError {
+    fn from(e: RouteDepositError) -> Self {
+        match e {
+            RouteDepositError::Lease(e) => AppError::Lease(e),
+            RouteDepositError::Database(e) => AppError::Database(e),
+            RouteDepositError::NotFound(_) => AppError::NotFound(e.to_string()),
+            RouteDepositError::InvalidState { .. } | RouteDepositError::Claimed(_) => {
+                AppError::Conflict(e.to_string())
+            }
+        }
+    }
+}
+
+impl From<TreasuryError> for AppError {
+    fn from(e: TreasuryError) -> Self {
+        match e {
+            TreasuryError::Database(e) => AppError::Database(e),
+            TreasuryError::UnknownTreasury(_) | TreasuryError::InvalidSplit(_) => {
+                AppError::BadRequest(e.to_string())
+            }
+        }
     }
 }
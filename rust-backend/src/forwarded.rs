@@ -0,0 +1,311 @@
+//! Funds deployed proxies forwarded to the FundRouter
+//!
+//! A deployed proxy forwards all ETH it receives to the FundRouter, which
+//! emits `EthReceived` with the proxy as the sender. Counting is started for
+//! a deposit just before its proxy is deployed (see [`start`]). A scan then
+//! reads the router's events for every counted proxy at once, from the
+//! oldest block not counted yet and over at most [`MAX_SCAN_BLOCKS`] blocks,
+//! so its RPC cost doesn't grow with the number of deposits.
+//!
+//! What a deposit forwarded and wasn't paid out to a treasury yet is its
+//! credit. Transfers pay credit out of the router's shared balance, so
+//! [`check_router_covers`] refuses to go on if the router holds less than
+//! the credit of all deposits together.
+
+use std::collections::HashMap;
+
+use alloy::primitives::{Address, U256};
+use sqlx::SqlitePool;
+
+use crate::{chain::ChainClient, db, rpc::parse_address};
+
+/// Most blocks one scan reads the router's events for; a scan further
+/// behind catches up over several runs
+pub const MAX_SCAN_BLOCKS: u64 = 2_000;
+
+/// What a deposit's proxy forwarded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Forwarded {
+    pub forwarded: U256,
+    /// Part of `forwarded` not paid out to a treasury yet
+    pub credit: U256,
+}
+
+/// What every counted proxy forwarded through `to_block`
+#[derive(Debug, Default)]
+pub struct ForwardedScan {
+    deposits: HashMap<String, Forwarded>,
+    /// Deposits that forwarded something in the blocks scanned
+    changed: Vec<String>,
+    /// Last block counted, if any block was scanned
+    to_block: Option<u64>,
+}
+
+impl ForwardedScan {
+    /// What the proxy of `addr` forwarded, if it is counted
+    pub fn get(&self, addr: &str) -> Option<Forwarded> {
+        self.deposits.get(addr).copied()
+    }
+
+    /// Deposits with credit left to route
+    pub fn with_credit(&self) -> Vec<&str> {
+        let mut addrs: Vec<&str> = self
+            .deposits
+            .iter()
+            .filter(|(_, f)| f.credit > U256::ZERO)
+            .map(|(addr, _)| addr.as_str())
+            .collect();
+        addrs.sort_unstable();
+        addrs
+    }
+}
+
+/// Count what the given proxies forward from the latest block on, before
+/// their deployment is sent
+///
+/// Starting before the deployment means a deployment mined despite an error
+/// isn't missed. Counting started by an earlier attempt is kept.
+pub async fn start(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    addrs: &[&str],
+) -> Result<(), String> {
+    let block = chain
+        .block_number()
+        .await
+        .map_err(|e| format!("Block number unavailable: {}", e))?;
+    for addr in addrs {
+        db::start_forwarded_scan(pool, addr, block)
+            .await
+            .map_err(db_error)?;
+    }
+    Ok(())
+}
+
+/// Count what every proxy forwarded up to the latest block, or
+/// [`MAX_SCAN_BLOCKS`] past the oldest block counted, without recording
+/// anything
+pub async fn scan(pool: &SqlitePool, chain: &dyn ChainClient) -> Result<ForwardedScan, String> {
+    let rows = db::get_all_forwarded(pool).await.map_err(db_error)?;
+    let paid = allocated(pool).await?;
+    let mut scan = ForwardedScan::default();
+    let Some(oldest) = rows.iter().map(|row| block_of(row.scanned_block)).min() else {
+        return Ok(scan);
+    };
+
+    let latest = chain
+        .block_number()
+        .await
+        .map_err(|e| format!("Block number unavailable: {}", e))?;
+    let from_block = oldest + 1;
+    let to_block = latest.min(oldest.saturating_add(MAX_SCAN_BLOCKS));
+    let receipts = if from_block <= to_block {
+        scan.to_block = Some(to_block);
+        chain
+            .router_receipts(from_block, to_block)
+            .await
+            .map_err(|e| format!("Forwarding scan failed: {}", e))?
+    } else {
+        vec![]
+    };
+
+    let mut new: HashMap<Address, Vec<(u64, U256)>> = HashMap::new();
+    for receipt in receipts {
+        new.entry(receipt.from)
+            .or_default()
+            .push((receipt.block, receipt.wei));
+    }
+
+    for row in rows {
+        let mut forwarded: U256 = row.forwarded_wei.parse().unwrap_or_default();
+        let counted_through = block_of(row.scanned_block);
+        let received = parse_address(&row.deposit_address)
+            .ok()
+            .and_then(|proxy| new.get(&proxy))
+            .into_iter()
+            .flatten()
+            .filter(|(block, _)| *block > counted_through);
+        let mut changed = false;
+        for (_, wei) in received {
+            forwarded = forwarded.saturating_add(*wei);
+            changed = true;
+        }
+        if changed {
+            scan.changed.push(row.deposit_address.clone());
+        }
+
+        let credit =
+            forwarded.saturating_sub(paid.get(&row.deposit_address).copied().unwrap_or_default());
+        scan.deposits
+            .insert(row.deposit_address, Forwarded { forwarded, credit });
+    }
+    Ok(scan)
+}
+
+/// Store what a scan counted, so the next one starts after it
+pub async fn record(pool: &SqlitePool, scan: &ForwardedScan) -> Result<(), String> {
+    let Some(to_block) = scan.to_block else {
+        return Ok(());
+    };
+    for addr in &scan.changed {
+        let forwarded = scan.deposits[addr].forwarded;
+        db::update_forwarded(pool, addr, &forwarded.to_string(), to_block)
+            .await
+            .map_err(db_error)?;
+    }
+    db::advance_forwarded_scans(pool, to_block)
+        .await
+        .map_err(db_error)
+}
+
+/// Check that the router holds at least the credit of all deposits together
+///
+/// A deposit is paid out of the router's shared balance. If the router holds
+/// less than it owes, some forwarded funds were counted twice and paying a
+/// deposit would spend what another deposit's proxy forwarded.
+pub async fn check_router_covers(pool: &SqlitePool, chain: &dyn ChainClient) -> Result<(), String> {
+    let paid = allocated(pool).await?;
+    let owed = db::get_all_forwarded(pool)
+        .await
+        .map_err(db_error)?
+        .iter()
+        .map(|row| {
+            let forwarded: U256 = row.forwarded_wei.parse().unwrap_or_default();
+            forwarded.saturating_sub(paid.get(&row.deposit_address).copied().unwrap_or_default())
+        })
+        .fold(U256::ZERO, |acc, credit| acc.saturating_add(credit));
+
+    let balance = chain
+        .get_balance(chain.router_address())
+        .await
+        .map_err(|e| format!("Router balance unavailable: {}", e))?;
+    if balance < owed {
+        return Err(format!(
+            "Router holds {} wei but deposits are owed {} wei of forwarded funds",
+            balance, owed
+        ));
+    }
+    Ok(())
+}
+
+/// What was paid out to treasuries, by deposit address
+async fn allocated(pool: &SqlitePool) -> Result<HashMap<String, U256>, String> {
+    let mut paid: HashMap<String, U256> = HashMap::new();
+    for (addr, amount_wei) in db::get_allocated_wei(pool).await.map_err(db_error)? {
+        let amount: U256 = amount_wei.parse().unwrap_or_default();
+        let total = paid.entry(addr).or_default();
+        *total = total.saturating_add(amount);
+    }
+    Ok(paid)
+}
+
+fn block_of(scanned_block: i64) -> u64 {
+    u64::try_from(scanned_block).unwrap_or(0)
+}
+
+fn db_error(e: sqlx::Error) -> String {
+    format!("Database error: {}", e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chain::fake::{self, FakeChain},
+        create2::format_address,
+    };
+
+    async fn counted(pool: &SqlitePool, chain: &FakeChain, byte: u8) -> (Address, String) {
+        let proxy = Address::repeat_byte(byte);
+        let addr = format_address(&proxy.0 .0);
+        start(pool, chain, &[&addr]).await.unwrap();
+        chain.set_code(proxy);
+        (proxy, addr)
+    }
+
+    #[tokio::test]
+    async fn test_one_scan_counts_every_proxy() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        let (first, first_addr) = counted(&pool, &chain, 0x01).await;
+        let (second, second_addr) = counted(&pool, &chain, 0x02).await;
+        chain.send(first, 10);
+        chain.send(second, 20);
+        chain.send(first, 5);
+
+        let scan = scan(&pool, &chain).await.unwrap();
+        record(&pool, &scan).await.unwrap();
+
+        assert_eq!(chain.receipt_scans().len(), 1);
+        assert_eq!(scan.get(&first_addr).unwrap().forwarded, U256::from(15));
+        assert_eq!(scan.get(&second_addr).unwrap().credit, U256::from(20));
+        assert_eq!(
+            scan.with_credit(),
+            [first_addr.as_str(), second_addr.as_str()]
+        );
+
+        // The next scan only reads blocks not counted yet
+        chain.send(second, 1);
+        let again = super::scan(&pool, &chain).await.unwrap();
+        assert_eq!(again.get(&first_addr).unwrap().forwarded, U256::from(15));
+        assert_eq!(again.get(&second_addr).unwrap().forwarded, U256::from(21));
+        let scans = chain.receipt_scans();
+        assert_eq!(scans[1].0, scans[0].1 + 1);
+    }
+
+    #[tokio::test]
+    async fn test_scan_is_capped() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        let (proxy, addr) = counted(&pool, &chain, 0x01).await;
+        chain.mine(MAX_SCAN_BLOCKS + 10);
+        chain.send(proxy, 3);
+
+        let first = scan(&pool, &chain).await.unwrap();
+        record(&pool, &first).await.unwrap();
+        assert_eq!(first.get(&addr).unwrap().forwarded, U256::ZERO);
+
+        let second = scan(&pool, &chain).await.unwrap();
+        assert_eq!(second.get(&addr).unwrap().forwarded, U256::from(3));
+        let scans = chain.receipt_scans();
+        assert_eq!(scans[0].1 - scans[0].0 + 1, MAX_SCAN_BLOCKS);
+    }
+
+    #[tokio::test]
+    async fn test_credit_excludes_allocations() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        let (proxy, addr) = counted(&pool, &chain, 0x01).await;
+        chain.send(proxy, 10);
+        db::insert_allocation(&pool, &addr, "0xtreasury", 10_000, "4", "0x01")
+            .await
+            .unwrap();
+
+        let scan = scan(&pool, &chain).await.unwrap();
+        record(&pool, &scan).await.unwrap();
+
+        assert_eq!(
+            scan.get(&addr),
+            Some(Forwarded {
+                forwarded: U256::from(10),
+                credit: U256::from(6),
+            })
+        );
+        check_router_covers(&pool, &chain).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_router_short_of_credit_is_refused() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        let (proxy, _) = counted(&pool, &chain, 0x01).await;
+        chain.send(proxy, 10);
+        let scan = scan(&pool, &chain).await.unwrap();
+        record(&pool, &scan).await.unwrap();
+
+        chain.fund(fake::ROUTER, 4);
+
+        let error = check_router_covers(&pool, &chain).await.unwrap_err();
+        assert!(error.contains("owed 10 wei"), "{}", error);
+    }
+}
@@ -0,0 +1,279 @@
+//! Component health checks
+//!
+//! Each check reports `ok`, `degraded` or `down`. Readiness fails only on
+//! `down` (the database or RPC endpoint is unusable); `degraded` flags things
+//! an operator should look at while the server keeps serving.
+
+use std::time::Instant;
+
+use sqlx::SqlitePool;
+
+use crate::{
+    chain::ChainClient,
+    config::Config,
+    db,
+    models::{ComponentCheck, ComponentStatus},
+};
+
+/// Run all component checks
+pub async fn components(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    config: &Config,
+) -> Vec<ComponentCheck> {
+    vec![
+        database(pool).await,
+        rpc(chain).await,
+        signer_balance(chain, config).await,
+        backlog(pool, config).await,
+        routing(pool, config).await,
+    ]
+}
+
+/// Worst status across components
+pub fn overall(components: &[ComponentCheck]) -> ComponentStatus {
+    components
+        .iter()
+        .map(|c| c.status)
+        .max()
+        .unwrap_or(ComponentStatus::Ok)
+}
+
+fn check(name: &'static str, status: ComponentStatus, detail: impl Into<String>) -> ComponentCheck {
+    ComponentCheck {
+        name,
+        status,
+        detail: detail.into(),
+        latency_ms: None,
+    }
+}
+
+/// DB ping and migration version
+async fn database(pool: &SqlitePool) -> ComponentCheck {
+    let started = Instant::now();
+    let result = async {
+        db::ping(pool).await?;
+        db::schema_version(pool).await
+    }
+    .await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let mut check = match result {
+        Ok(version) if version == db::SCHEMA_VERSION => check(
+            "database",
+            ComponentStatus::Ok,
+            format!("schema version {}", version),
+        ),
+        Ok(version) => check(
+            "database",
+            ComponentStatus::Down,
+            format!(
+                "schema version {}, expected {}",
+                version,
+                db::SCHEMA_VERSION
+            ),
+        ),
+        Err(e) => check("database", ComponentStatus::Down, e.to_string()),
+    };
+    check.latency_ms = Some(latency_ms);
+    check
+}
+
+/// RPC latency and block height
+async fn rpc(chain: &dyn ChainClient) -> ComponentCheck {
+    let started = Instant::now();
+    let result = chain.block_number().await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let mut check = match result {
+        Ok(block) => check("rpc", ComponentStatus::Ok, format!("block {}", block)),
+        Err(e) => check("rpc", ComponentStatus::Down, e.to_string()),
+    };
+    check.latency_ms = Some(latency_ms);
+    check
+}
+
+/// Signer balance against the configured threshold
+async fn signer_balance(chain: &dyn ChainClient, config: &Config) -> ComponentCheck {
+    match chain.get_balance(chain.signer_address()).await {
+        Ok(balance) if balance >= config.min_signer_balance_wei => check(
+            "signer_balance",
+            ComponentStatus::Ok,
+            format!("{} wei", balance),
+        ),
+        Ok(balance) => check(
+            "signer_balance",
+            ComponentStatus::Degraded,
+            format!(
+                "{} wei, below {} wei",
+                balance, config.min_signer_balance_wei
+            ),
+        ),
+        Err(e) => check("signer_balance", ComponentStatus::Degraded, e.to_string()),
+    }
+}
+
+/// Pending and funded deposits waiting for a routing run, and dead-lettered
+/// ones waiting for an operator
+async fn backlog(pool: &SqlitePool, config: &Config) -> ComponentCheck {
+    let counts = match db::count_deposits_by_status(pool).await {
+        Ok(counts) => counts,
+        Err(e) => return check("backlog", ComponentStatus::Degraded, e.to_string()),
+    };
+    let count = |status: &str| {
+        counts
+            .iter()
+            .find(|(s, _)| s == status)
+            .map_or(0, |(_, n)| *n)
+    };
+    let (pending, funded, dead) = (count("pending"), count("funded"), count("dead_letter"));
+
+    check(
+        "backlog",
+        if (pending + funded) as u64 > config.max_deposit_backlog || dead > 0 {
+            ComponentStatus::Degraded
+        } else {
+            ComponentStatus::Ok
+        },
+        format!(
+            "{} pending, {} funded, {} dead-lettered",
+            pending, funded, dead
+        ),
+    )
+}
+
+/// Time since the last routing run that finished without errors
+async fn routing(pool: &SqlitePool, config: &Config) -> ComponentCheck {
+    let age = match db::seconds_since_last_clean_run(pool).await {
+        Ok(age) => age,
+        Err(e) => return check("routing", ComponentStatus::Degraded, e.to_string()),
+    };
+
+    let stale = match (age, config.max_routing_age_secs) {
+        (Some(age), Some(max)) => age as u64 > max,
+        (None, Some(_)) => true,
+        (_, None) => false,
+    };
+
+    check(
+        "routing",
+        if stale {
+            ComponentStatus::Degraded
+        } else {
+            ComponentStatus::Ok
+        },
+        match age {
+            Some(age) => format!("last clean run {}s ago", age),
+            None => "no clean routing run yet".to_string(),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chain::fake::FakeChain, config::PartialConfig, signer::Secret};
+
+    fn config() -> Config {
+        PartialConfig {
+            rpc_url: Some("http://localhost:8545".to_string()),
+            deployer_address: Some("0x01".to_string()),
+            router_address: Some("0x02".to_string()),
+            treasury_address: Some("0x03".to_string()),
+            init_code_hash: Some("0x04".to_string()),
+            private_key: Some(Secret::new("0x05")),
+            min_signer_balance_wei: Some("1000".to_string()),
+            max_deposit_backlog: Some(1),
+            ..PartialConfig::default()
+        }
+        .resolve()
+        .unwrap()
+    }
+
+    fn status_of(components: &[ComponentCheck], name: &str) -> ComponentStatus {
+        components.iter().find(|c| c.name == name).unwrap().status
+    }
+
+    #[tokio::test]
+    async fn test_healthy_components() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        chain.fund(chain.signer_address(), 1000);
+
+        let components = components(&pool, &chain, &config()).await;
+
+        assert_eq!(
+            overall(&components),
+            ComponentStatus::Ok,
+            "{:?}",
+            components
+        );
+        assert_eq!(components.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_low_balance_and_backlog_degrade() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        chain.fund(chain.signer_address(), 999);
+        for (i, address) in ["0xaa", "0xbb"].iter().enumerate() {
+            db::insert_deposit(&pool, "0xuser", "0x00", address, i as u64, None)
+                .await
+                .unwrap();
+        }
+
+        let components = components(&pool, &chain, &config()).await;
+
+        assert_eq!(overall(&components), ComponentStatus::Degraded);
+        assert_eq!(
+            status_of(&components, "signer_balance"),
+            ComponentStatus::Degraded
+        );
+        assert_eq!(status_of(&components, "backlog"), ComponentStatus::Degraded);
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter_degrades_backlog() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        chain.fund(chain.signer_address(), 1000);
+        db::insert_deposit(&pool, "0xuser", "0x00", "0xaa", 0, None)
+            .await
+            .unwrap();
+        db::record_deposit_failure(&pool, "0xaa", "dead_letter", "Deploy failed", None)
+            .await
+            .unwrap();
+
+        let components = components(&pool, &chain, &config()).await;
+
+        assert_eq!(status_of(&components, "backlog"), ComponentStatus::Degraded);
+    }
+
+    #[tokio::test]
+    async fn test_stale_routing_degrades() {
+        let pool = db::test_pool().await;
+        let mut config = config();
+        config.max_routing_age_secs = Some(60);
+
+        let never_ran = routing(&pool, &config).await;
+        db::insert_routing_run(&pool, 1, 1, 0, 0).await.unwrap();
+        let fresh = routing(&pool, &config).await;
+
+        assert_eq!(never_ran.status, ComponentStatus::Degraded);
+        assert_eq!(fresh.status, ComponentStatus::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_dependencies_are_down() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        chain.go_offline();
+        pool.close().await;
+
+        let components = components(&pool, &chain, &config()).await;
+
+        assert_eq!(overall(&components), ComponentStatus::Down);
+        assert_eq!(status_of(&components, "database"), ComponentStatus::Down);
+        assert_eq!(status_of(&components, "rpc"), ComponentStatus::Down);
+    }
+}
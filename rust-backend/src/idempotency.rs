@@ -0,0 +1,316 @@
+//! Idempotency keys for `POST /deposit` and `POST /router`
+//!
+//! A client that times out and retries would otherwise burn another nonce or
+//! start an overlapping routing run. When a request carries an
+//! `Idempotency-Key` header, the first response is stored under that key
+//! (per caller and endpoint) together with a hash of the request:
+//!
+//! - a retry with the same body replays the stored response,
+//! - a different body under the same key is rejected,
+//! - a retry while the first request is still running gets 409.
+//!
+//! Server errors, conflicts and rate limited responses are not stored, so
+//! those can be retried under the same key. Stored responses expire after
+//! `IDEMPOTENCY_TTL_SECS`.
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header::CONTENT_TYPE, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    auth::{ApiKey, Session},
+    db::{self, IdempotencyRow},
+    error::AppError,
+    AppState,
+};
+
+pub static IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// Set on responses replayed from a stored result
+pub static IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// Longest accepted key; UUIDs and similar tokens fit easily
+const MAX_KEY_LEN: usize = 255;
+
+/// Largest request body that is hashed; the guarded endpoints take tiny bodies
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// An in-progress request older than this is assumed to have died with its
+/// process, and the key may be claimed again
+const STALE_AFTER_SECS: u64 = 600;
+
+#[derive(Debug, thiserror::Error)]
+pub enum IdempotencyError {
+    #[error("Idempotency-Key must be 1-{MAX_KEY_LEN} visible ASCII characters")]
+    InvalidKey,
+    #[error("A request with this Idempotency-Key is still in progress")]
+    InProgress,
+    #[error("Idempotency-Key was already used with a different request")]
+    Mismatch,
+}
+
+/// Middleware making the wrapped routes idempotent under `Idempotency-Key`
+///
+/// Must run inside the auth middleware, which identifies the caller.
+pub async fn idempotent(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let Some(key) = request.headers().get(&IDEMPOTENCY_KEY) else {
+        return Ok(next.run(request).await);
+    };
+    let key = key
+        .to_str()
+        .ok()
+        .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LEN)
+        .ok_or(IdempotencyError::InvalidKey)?
+        .to_string();
+
+    let caller = caller(&request);
+    let scope = format!("{} {}", request.method(), request.uri().path());
+
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body, MAX_BODY_BYTES)
+        .await
+        .map_err(|e| AppError::BadRequest(format!("Unreadable request body: {}", e)))?;
+    let hash = request_hash(&scope, &body);
+
+    let claimed = db::claim_idempotency_key(
+        &state.db,
+        &caller,
+        &scope,
+        &key,
+        &hash,
+        state.config.idempotency_ttl_secs,
+        STALE_AFTER_SECS,
+    )
+    .await?;
+
+    if !claimed {
+        let row = db::get_idempotency_key(&state.db, &caller, &scope, &key)
+            .await?
+            .ok_or(IdempotencyError::InProgress)?;
+        return replay(row, &hash);
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    let status = response.status();
+
+    if status.is_server_error()
+        || matches!(status, StatusCode::CONFLICT | StatusCode::TOO_MANY_REQUESTS)
+    {
+        db::release_idempotency_key(&state.db, &caller, &scope, &key).await?;
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to buffer response: {}", e)))?;
+    db::complete_idempotency_key(
+        &state.db,
+        &caller,
+        &scope,
+        &key,
+        status.as_u16(),
+        &String::from_utf8_lossy(&body),
+    )
+    .await?;
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// Who the key belongs to; keys of different callers never collide
+fn caller(request: &Request) -> String {
+    let extensions = request.extensions();
+    if let Some(key) = extensions.get::<ApiKey>() {
+        format!("key:{}", key.id)
+    } else if let Some(session) = extensions.get::<Session>() {
+        format!("wallet:{}", session.address)
+    } else {
+        "anonymous".to_string()
+    }
+}
+
+fn request_hash(scope: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(scope.as_bytes());
+    hasher.update([0]);
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+/// Response for a key that was claimed before
+fn replay(row: IdempotencyRow, hash: &str) -> Result<Response, AppError> {
+    if row.request_hash != hash {
+        return Err(IdempotencyError::Mismatch.into());
+    }
+    let (Some(status), Some(body)) = (row.response_status, row.response_body) else {
+        return Err(IdempotencyError::InProgress.into());
+    };
+
+    let status = StatusCode::from_u16(status as u16)
+        .map_err(|e| AppError::Internal(format!("Stored response status: {}", e)))?;
+    Ok((
+        status,
+        [
+            (CONTENT_TYPE, HeaderValue::from_static("application/json")),
+            (
+                IDEMPOTENT_REPLAYED.clone(),
+                HeaderValue::from_static("true"),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chain::fake::FakeChain, test_state};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    async fn app() -> (AppState, axum::Router) {
+        let state = test_state(FakeChain::default()).await;
+        let mut config = (*state.config).clone();
+        config.require_api_keys = false;
+        let state = AppState {
+            config: Arc::new(config),
+            ..state
+        };
+        (state.clone(), crate::app(state))
+    }
+
+    fn deposit(key: &str, user: u8) -> Request {
+        Request::post("/deposit")
+            .header("content-type", "application/json")
+            .header(&IDEMPOTENCY_KEY, key)
+            .body(Body::from(
+                json!({ "user": format!("{:#x}", alloy::primitives::Address::repeat_byte(user)) })
+                    .to_string(),
+            ))
+            .unwrap()
+    }
+
+    async fn send(
+        app: &axum::Router,
+        request: Request,
+    ) -> (StatusCode, Option<HeaderValue>, Value) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let replayed = response.headers().get(&IDEMPOTENT_REPLAYED).cloned();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, replayed, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_retry_replays_the_first_response() {
+        let (state, app) = app().await;
+
+        let (status, replayed, first) = send(&app, deposit("retry-1", 0x11)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(replayed, None);
+
+        let (status, replayed, second) = send(&app, deposit("retry-1", 0x11)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(replayed.unwrap(), "true");
+        assert_eq!(first, second);
+
+        // Only one nonce was used
+        assert_eq!(db::get_all_deposits(&state.db).await.unwrap().len(), 1);
+
+        // A new key creates a new deposit
+        let (_, _, third) = send(&app, deposit("retry-2", 0x11)).await;
+        assert_ne!(first["deposit_address"], third["deposit_address"]);
+    }
+
+    #[tokio::test]
+    async fn test_different_request_under_same_key_is_rejected() {
+        let (_, app) = app().await;
+
+        send(&app, deposit("reused", 0x11)).await;
+        let (status, _, body) = send(&app, deposit("reused", 0x22)).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "IDEMPOTENCY_KEY_REUSED");
+    }
+
+    #[tokio::test]
+    async fn test_request_in_progress_conflicts() {
+        let (state, app) = app().await;
+        let hash = request_hash(
+            "POST /deposit",
+            json!({ "user": format!("{:#x}", alloy::primitives::Address::repeat_byte(0x11)) })
+                .to_string()
+                .as_bytes(),
+        );
+        assert!(db::claim_idempotency_key(
+            &state.db,
+            "anonymous",
+            "POST /deposit",
+            "busy",
+            &hash,
+            60,
+            STALE_AFTER_SECS
+        )
+        .await
+        .unwrap());
+
+        let (status, _, body) = send(&app, deposit("busy", 0x11)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "REQUEST_IN_PROGRESS");
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_replayed_until_released() {
+        let (state, app) = app().await;
+        let bad = || {
+            Request::post("/deposit")
+                .header("content-type", "application/json")
+                .header(&IDEMPOTENCY_KEY, "bad-user")
+                .body(Body::from(json!({ "user": "nope" }).to_string()))
+                .unwrap()
+        };
+
+        let (status, _, _) = send(&app, bad()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, replayed, _) = send(&app, bad()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(replayed.unwrap(), "true");
+
+        db::release_idempotency_key(&state.db, "anonymous", "POST /deposit", "bad-user")
+            .await
+            .unwrap();
+        let (_, replayed, _) = send(&app, bad()).await;
+        assert_eq!(replayed, None);
+    }
+
+    #[tokio::test]
+    async fn test_expired_key_can_be_claimed_again() {
+        let (state, _) = app().await;
+        let claim = |ttl| {
+            db::claim_idempotency_key(&state.db, "anonymous", "POST /router", "k", "h", ttl, 600)
+        };
+
+        assert!(claim(0).await.unwrap());
+        assert!(claim(60).await.unwrap());
+        assert!(!claim(60).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_key_is_rejected() {
+        let (_, app) = app().await;
+        let (status, _, body) = send(&app, deposit(&"k".repeat(MAX_KEY_LEN + 1), 0x11)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "INVALID_IDEMPOTENCY_KEY");
+    }
+}
@@ -0,0 +1,435 @@
+//! Invoices: deposits created for an expected payment
+//!
+//! A deposit created with an expected amount carries invoice terms. Whenever
+//! routing sees a balance on the deposit address it records what arrived and
+//! classifies the payment against the terms:
+//!
+//! - `awaiting_payment`: nothing received yet, not expired
+//! - `expired`: nothing received before the expiry
+//! - `late`: funds first seen after the expiry, whatever the amount
+//! - `exact`, `underpaid`, `overpaid`: the amount received against the one
+//!   expected
+//!
+//! What arrived is the deposit address's balance plus what its proxy
+//! forwarded, and the amount recorded never goes down. A payment is timed by
+//! the block it landed in, found by bisecting the address's balance from the
+//! block the invoice was created in, so a payment made just before the
+//! expiry is on time even if no routing run saw it in time. Without an
+//! archive node old blocks may be unavailable; the payment is then timed by
+//! when routing first saw it. Invoices are only expired after checking the
+//! deposit address for a payment.
+
+use std::cmp::Ordering;
+
+use alloy::primitives::{Address, U256};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::{
+    chain::ChainClient,
+    db::{self, InvoiceRow},
+    rpc::{parse_address, RpcError},
+};
+
+/// Longest accepted metadata, serialized
+pub const MAX_METADATA_BYTES: usize = 4096;
+
+/// Longest accepted description and order reference
+pub const MAX_DESCRIPTION_CHARS: usize = 1000;
+pub const MAX_ORDER_REF_CHARS: usize = 255;
+
+/// How an invoice was paid
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentStatus {
+    AwaitingPayment,
+    Exact,
+    Underpaid,
+    Overpaid,
+    Late,
+    Expired,
+}
+
+impl PaymentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentStatus::AwaitingPayment => "awaiting_payment",
+            PaymentStatus::Exact => "exact",
+            PaymentStatus::Underpaid => "underpaid",
+            PaymentStatus::Overpaid => "overpaid",
+            PaymentStatus::Late => "late",
+            PaymentStatus::Expired => "expired",
+        }
+    }
+}
+
+/// Classify an invoice's payment as of now
+pub fn classify(row: &InvoiceRow) -> PaymentStatus {
+    let expected: U256 = row.expected_amount_wei.parse().unwrap_or_default();
+    let received: U256 = row.received_wei.parse().unwrap_or_default();
+
+    if received == U256::ZERO {
+        return if row.expired {
+            PaymentStatus::Expired
+        } else {
+            PaymentStatus::AwaitingPayment
+        };
+    }
+    if row.paid_late {
+        return PaymentStatus::Late;
+    }
+    match received.cmp(&expected) {
+        Ordering::Equal => PaymentStatus::Exact,
+        Ordering::Less => PaymentStatus::Underpaid,
+        Ordering::Greater => PaymentStatus::Overpaid,
+    }
+}
+
+/// Record what a deposit received, its balance plus what its proxy
+/// forwarded, and reclassify its invoice; `None` for a deposit without one
+pub async fn record_payment(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    deposit_address: &str,
+    balance: U256,
+) -> Result<Option<PaymentStatus>, sqlx::Error> {
+    let Some(invoice) = db::get_invoice(pool, deposit_address).await? else {
+        return Ok(None);
+    };
+    // Only the first payment is timed
+    let paid_at = match invoice.paid_at {
+        Some(_) => None,
+        None => payment_time(chain, deposit_address, invoice.created_block).await,
+    };
+    let Some(row) =
+        db::record_invoice_payment(pool, deposit_address, &balance.to_string(), paid_at).await?
+    else {
+        return Ok(None);
+    };
+
+    let status = classify(&row);
+    if status.as_str() != row.payment_status {
+        db::set_payment_status(pool, deposit_address, status.as_str()).await?;
+        tracing::info!(
+            deposit = %deposit_address,
+            payment_status = status.as_str(),
+            received_wei = %balance,
+            expected_wei = %row.expected_amount_wei,
+            "Classified invoice payment"
+        );
+    }
+    Ok(Some(status))
+}
+
+/// Expire unpaid invoices past their expiry, first checking each deposit
+/// address: a payment no routing run saw yet is recorded instead. Returns
+/// how many expired
+///
+/// Routing only deploys a proxy once its address holds a balance, so an
+/// unpaid invoice's payment is still on the address.
+pub async fn expire_unpaid(pool: &SqlitePool, chain: &dyn ChainClient) -> Result<u64, sqlx::Error> {
+    let mut expired = 0;
+    for invoice in db::get_expiring_invoices(pool).await? {
+        let address = &invoice.deposit_address;
+        let balance = match parse_address(address) {
+            Ok(proxy) => chain.get_balance(proxy).await,
+            Err(e) => Err(e),
+        };
+        match balance {
+            Ok(balance) if balance > U256::ZERO => {
+                record_payment(pool, chain, address, balance).await?;
+            }
+            Ok(_) => {
+                if db::expire_invoice(pool, address).await? {
+                    expired += 1;
+                }
+            }
+            Err(e) => tracing::warn!(
+                deposit = %address,
+                "Not expiring the invoice, balance check failed: {}",
+                e
+            ),
+        }
+    }
+    Ok(expired)
+}
+
+/// Unix time of the block a deposit address was first funded in, if it can
+/// be found
+async fn payment_time(
+    chain: &dyn ChainClient,
+    deposit_address: &str,
+    created_block: Option<i64>,
+) -> Option<u64> {
+    let from_block = created_block.and_then(|block| u64::try_from(block).ok());
+    let found = match parse_address(deposit_address) {
+        Ok(address) => funded_in(chain, address, from_block.unwrap_or(0)).await,
+        Err(e) => Err(e),
+    };
+    let block = match found {
+        Ok(Some(block)) => block,
+        // Only forwarded funds: the address itself never held any
+        Ok(None) => return None,
+        Err(e) => {
+            tracing::warn!(
+                deposit = %deposit_address,
+                "Payment block not found, timing the payment by now: {}",
+                e
+            );
+            return None;
+        }
+    };
+    match chain.block_timestamp(block).await {
+        Ok(timestamp) => Some(timestamp),
+        Err(e) => {
+            tracing::warn!(
+                deposit = %deposit_address,
+                "Timestamp of block {} unavailable, timing the payment by now: {}",
+                block,
+                e
+            );
+            None
+        }
+    }
+}
+
+/// First block from `from_block` on in which `address` holds a balance,
+/// found by bisection
+///
+/// ETH that reaches an address before its proxy is deployed stays there, so
+/// the balance never drops back to zero once funded.
+async fn funded_in(
+    chain: &dyn ChainClient,
+    address: Address,
+    from_block: u64,
+) -> Result<Option<u64>, RpcError> {
+    let mut high = chain.block_number().await?;
+    if high < from_block || chain.balance_at(address, high).await? == U256::ZERO {
+        return Ok(None);
+    }
+    if chain.balance_at(address, from_block).await? > U256::ZERO {
+        return Ok(Some(from_block));
+    }
+    // Unfunded at `low`, funded at `high`
+    let mut low = from_block;
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if chain.balance_at(address, mid).await? > U256::ZERO {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+    Ok(Some(high))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chain::fake::FakeChain, create2::format_address};
+
+    fn deposit(byte: u8) -> (Address, String) {
+        let address = Address::repeat_byte(byte);
+        (address, format_address(&address.0 .0))
+    }
+
+    fn terms(expires_in_secs: Option<u64>) -> db::NewInvoice<'static> {
+        db::NewInvoice {
+            expected_amount_wei: "1000",
+            asset: "ETH",
+            expires_in_secs,
+            order_ref: None,
+            metadata: None,
+            description: None,
+            created_block: None,
+        }
+    }
+
+    async fn expire_now(pool: &SqlitePool, deposit_address: &str) {
+        sqlx::query(
+            "UPDATE invoices SET expires_at = datetime('now', '-1 hour') WHERE deposit_address = ?",
+        )
+        .bind(deposit_address)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    fn invoice(expected: &str, received: &str, expired: bool, paid_late: bool) -> InvoiceRow {
+        InvoiceRow {
+            deposit_address: "0xaa".to_string(),
+            expected_amount_wei: expected.to_string(),
+            asset: "ETH".to_string(),
+            expires_at: None,
+            order_ref: None,
+            metadata: None,
+            description: None,
+            payment_status: "awaiting_payment".to_string(),
+            received_wei: received.to_string(),
+            paid_at: None,
+            created_block: None,
+            created_at: String::new(),
+            expired,
+            paid_late,
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        let cases = [
+            (
+                invoice("100", "0", false, false),
+                PaymentStatus::AwaitingPayment,
+            ),
+            (invoice("100", "0", true, false), PaymentStatus::Expired),
+            (invoice("100", "100", false, false), PaymentStatus::Exact),
+            (invoice("100", "99", false, false), PaymentStatus::Underpaid),
+            (invoice("100", "101", false, false), PaymentStatus::Overpaid),
+            // Paid in time: expiring afterwards doesn't change the outcome
+            (invoice("100", "100", true, false), PaymentStatus::Exact),
+            (invoice("100", "100", true, true), PaymentStatus::Late),
+        ];
+        for (row, expected) in cases {
+            assert_eq!(classify(&row), expected, "{:?}", row);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_payments_are_recorded_and_classified() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        let (_, addr) = deposit(0xaa);
+        let terms = db::NewInvoice {
+            order_ref: Some("order-1"),
+            metadata: Some(r#"{"sku":"tee"}"#),
+            ..terms(Some(3600))
+        };
+        db::insert_invoice(&pool, &addr, &terms).await.unwrap();
+
+        assert_eq!(
+            record_payment(&pool, &chain, &addr, U256::from(400))
+                .await
+                .unwrap(),
+            Some(PaymentStatus::Underpaid)
+        );
+        // A top-up before routing completes the payment
+        assert_eq!(
+            record_payment(&pool, &chain, &addr, U256::from(1000))
+                .await
+                .unwrap(),
+            Some(PaymentStatus::Exact)
+        );
+        let row = db::get_invoice(&pool, &addr).await.unwrap().unwrap();
+        assert_eq!(row.payment_status, "exact");
+        assert!(row.paid_at.is_some());
+
+        // A smaller amount seen later doesn't lower what was received
+        assert_eq!(
+            record_payment(&pool, &chain, &addr, U256::from(50))
+                .await
+                .unwrap(),
+            Some(PaymentStatus::Exact)
+        );
+        let row = db::get_invoice(&pool, &addr).await.unwrap().unwrap();
+        assert_eq!(row.received_wei, "1000");
+
+        // Deposits without an invoice are left alone
+        let (_, other) = deposit(0xbb);
+        assert_eq!(
+            record_payment(&pool, &chain, &other, U256::from(1))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unpaid_invoices_expire() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        let (address, addr) = deposit(0xaa);
+        let (_, other) = deposit(0xbb);
+        db::insert_invoice(&pool, &addr, &terms(Some(3600)))
+            .await
+            .unwrap();
+        db::insert_invoice(&pool, &other, &terms(None))
+            .await
+            .unwrap();
+        expire_now(&pool, &addr).await;
+
+        assert_eq!(expire_unpaid(&pool, &chain).await.unwrap(), 1);
+        let expired = db::get_invoice(&pool, &addr).await.unwrap().unwrap();
+        assert_eq!(expired.payment_status, "expired");
+        assert_eq!(classify(&expired), PaymentStatus::Expired);
+
+        // Funds arriving afterwards are late
+        chain.send(address, 1000);
+        assert_eq!(
+            record_payment(&pool, &chain, &addr, U256::from(1000))
+                .await
+                .unwrap(),
+            Some(PaymentStatus::Late)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_payment_is_checked_before_expiring() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        let (address, addr) = deposit(0xaa);
+        db::insert_invoice(&pool, &addr, &terms(Some(3600)))
+            .await
+            .unwrap();
+        // Paid in time, but no routing run saw it before the expiry
+        chain.send(address, 1000);
+        chain.set_block_timestamp(1, 1_700_000_000);
+        sqlx::query("UPDATE invoices SET expires_at = '2023-11-15 00:00:00'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(expire_unpaid(&pool, &chain).await.unwrap(), 0);
+        let row = db::get_invoice(&pool, &addr).await.unwrap().unwrap();
+        assert_eq!(row.payment_status, "exact");
+        assert_eq!(row.received_wei, "1000");
+        assert!(!row.paid_late);
+    }
+
+    #[tokio::test]
+    async fn test_payment_is_timed_by_its_block() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        let (address, addr) = deposit(0xaa);
+        chain.mine(10);
+        db::insert_invoice(
+            &pool,
+            &addr,
+            &db::NewInvoice {
+                created_block: Some(5),
+                ..terms(Some(3600))
+            },
+        )
+        .await
+        .unwrap();
+        chain.mine(20);
+        chain.send(address, 600);
+        chain.set_block_timestamp(31, 1_700_000_000);
+        chain.mine(40);
+        chain.send(address, 400);
+
+        record_payment(&pool, &chain, &addr, U256::from(1000))
+            .await
+            .unwrap();
+        let row = db::get_invoice(&pool, &addr).await.unwrap().unwrap();
+        assert_eq!(row.paid_at.as_deref(), Some("2023-11-14 22:13:20"));
+
+        // Seen after the expiry, but paid before it
+        sqlx::query("UPDATE invoices SET expires_at = '2023-11-15 00:00:00'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let row = db::get_invoice(&pool, &addr).await.unwrap().unwrap();
+        assert!(!row.paid_late);
+        assert_eq!(classify(&row), PaymentStatus::Exact);
+    }
+}
@@ -0,0 +1,206 @@
+//! Routing jobs: routing runs recorded in `routing_jobs`
+//!
+//! A run waits for transaction receipts and can take minutes, longer than
+//! proxies keep a request open. `POST /router` therefore starts a run under
+//! the routing lease, records a job and runs it in the background;
+//! `GET /jobs/:id` reports its progress. `radhat route` records its runs the
+//! same way, so `GET /jobs` lists every run.
+//!
+//! The run writes its progress to the job row after each step, so results
+//! survive a restart. A job left running by a process that died is reported
+//! `interrupted` once its holder's lease has expired.
+
+use std::sync::Arc;
+
+use sqlx::SqlitePool;
+use tracing::Instrument;
+
+use crate::{
+    chain::ChainClient,
+    db::{self, JobRow},
+    lease::{LeaseError, LeaseGuard, RoutingLease},
+    models::{JobStatus, RouteResponse, RoutingJobInfo},
+    routing::{self, RetryPolicy},
+};
+
+/// Take the routing lease and record a job for the run
+///
+/// Hand the guard to [`run`] to execute the job.
+pub async fn start(
+    pool: &SqlitePool,
+    lease: &RoutingLease,
+    requested_by: Option<&str>,
+) -> Result<(RoutingJobInfo, LeaseGuard), LeaseError> {
+    let guard = lease.try_acquire(pool).await?;
+    match create(pool, &guard, requested_by).await {
+        Ok(job) => Ok((job, guard)),
+        Err(e) => {
+            guard.release().await;
+            Err(e.into())
+        }
+    }
+}
+
+async fn create(
+    pool: &SqlitePool,
+    guard: &LeaseGuard,
+    requested_by: Option<&str>,
+) -> Result<RoutingJobInfo, sqlx::Error> {
+    // Jobs of runs whose process died
+    let interrupted = db::interrupt_running_jobs(pool).await?;
+    if interrupted > 0 {
+        tracing::warn!("Marked {} abandoned routing jobs interrupted", interrupted);
+    }
+
+    let id = db::insert_routing_job(pool, guard.holder(), requested_by).await?;
+    tracing::info!(job_id = id, "Started routing job");
+    get(pool, id).await?.ok_or(sqlx::Error::RowNotFound)
+}
+
+/// Run a started job to completion, then release the lease
+pub async fn run(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    guard: LeaseGuard,
+    retry: RetryPolicy,
+    id: i64,
+) -> RouteResponse {
+    let response = routing::route_deposits(pool, chain, &guard, retry, Some(id)).await;
+    let result = db::finish_routing_job(pool, id, &to_json(&response)).await;
+    if let Err(e) = result {
+        tracing::error!(job_id = id, "Failed to record routing job result: {}", e);
+    }
+    guard.release().await;
+    response
+}
+
+/// Start a job and run it in the background
+pub async fn spawn(
+    pool: SqlitePool,
+    chain: Arc<dyn ChainClient>,
+    lease: &RoutingLease,
+    retry: RetryPolicy,
+    requested_by: Option<&str>,
+) -> Result<RoutingJobInfo, LeaseError> {
+    let (job, guard) = start(&pool, lease, requested_by).await?;
+    let id = job.id;
+    tokio::spawn(
+        async move {
+            run(&pool, chain.as_ref(), guard, retry, id).await;
+        }
+        .instrument(tracing::info_span!("routing_job", job_id = id)),
+    );
+    Ok(job)
+}
+
+/// Store a running job's progress; failures only cost progress reporting
+pub async fn report_progress(pool: &SqlitePool, id: i64, response: &RouteResponse) {
+    if let Err(e) = db::update_routing_job_progress(pool, id, &to_json(response)).await {
+        tracing::warn!(job_id = id, "Failed to record routing job progress: {}", e);
+    }
+}
+
+/// Get a job with its progress and per-deposit results
+pub async fn get(pool: &SqlitePool, id: i64) -> Result<Option<RoutingJobInfo>, sqlx::Error> {
+    Ok(db::get_routing_job(pool, id)
+        .await?
+        .map(|row| row_to_info(row, true)))
+}
+
+/// Most recent jobs first, without per-deposit results
+pub async fn list(pool: &SqlitePool, limit: u32) -> Result<Vec<RoutingJobInfo>, sqlx::Error> {
+    Ok(db::list_routing_jobs(pool, limit)
+        .await?
+        .into_iter()
+        .map(|row| row_to_info(row, false))
+        .collect())
+}
+
+fn row_to_info(row: JobRow, with_result: bool) -> RoutingJobInfo {
+    // A job that has not reported progress yet stores `{}`
+    let progress: RouteResponse = serde_json::from_str(&row.progress).unwrap_or_else(|e| {
+        tracing::warn!(job_id = row.id, "Unreadable routing job progress: {}", e);
+        RouteResponse::default()
+    });
+
+    RoutingJobInfo {
+        id: row.id,
+        status: JobStatus::parse(&row.status).unwrap_or(JobStatus::Interrupted),
+        requested_by: row.requested_by,
+        checked: progress.checked,
+        funded: progress.funded,
+        deployed: progress.deployed,
+        routed: progress.routed,
+        stranded: progress.stranded,
+        error_count: progress.errors.len(),
+        created_at: row.created_at,
+        updated_at: row.updated_at,
+        finished_at: row.finished_at,
+        result: with_result.then_some(progress),
+    }
+}
+
+fn to_json(response: &RouteResponse) -> String {
+    // Plain strings and numbers always serialize
+    serde_json::to_string(response).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::fake::FakeChain;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_job_records_result() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        let lease = RoutingLease::new(Duration::from_secs(60));
+
+        let (job, guard) = start(&pool, &lease, Some("ops")).await.unwrap();
+        assert_eq!(job.status, JobStatus::Running);
+        assert_eq!(job.requested_by.as_deref(), Some("ops"));
+
+        let retry = RetryPolicy::from_config(&crate::config::test_config());
+        run(&pool, &chain, guard, retry, job.id).await;
+
+        let job = get(&pool, job.id).await.unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Completed);
+        assert!(job.finished_at.is_some());
+        assert!(job.result.is_some());
+
+        let listed = list(&pool, 10).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].result.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_job_of_dead_process_is_interrupted() {
+        let pool = db::test_pool().await;
+        let crashed = RoutingLease::new(Duration::ZERO);
+        let (job, guard) = start(&pool, &crashed, None).await.unwrap();
+        // Dropped without running, as if the process died mid-run
+        drop(guard);
+
+        let next = RoutingLease::new(Duration::from_secs(60));
+        let (_, guard) = start(&pool, &next, None).await.unwrap();
+
+        let job = get(&pool, job.id).await.unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Interrupted);
+        guard.release().await;
+    }
+
+    #[tokio::test]
+    async fn test_busy_lease_records_no_job() {
+        let pool = db::test_pool().await;
+        let lease = RoutingLease::new(Duration::from_secs(60));
+        let (_, guard) = start(&pool, &lease, None).await.unwrap();
+
+        assert!(matches!(
+            start(&pool, &lease, None).await,
+            Err(LeaseError::Busy(_))
+        ));
+        assert_eq!(list(&pool, 10).await.unwrap().len(), 1);
+        guard.release().await;
+    }
+}
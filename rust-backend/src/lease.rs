@@ -0,0 +1,281 @@
+//! Leases for routing runs
+//!
+//! Two overlapping runs would fetch the same deposits and both call
+//! `deployMultiple` with the same salts; the second reverts and marks the
+//! deposits `failed`. A run therefore claims the deposits it works on row by
+//! row (see `db::claim_deposits`), so runs of several workers, e.g. replicas
+//! and the CLI, share the load without touching the same deposit. A run
+//! holds:
+//!
+//! - an in-process mutex, so one server never starts two runs, and
+//! - a `routing_leases` row of its own, which shows the run is alive.
+//!
+//! Every transaction is sent by the same signer, so workers sending at once
+//! would race for its nonce, and a transfer made while another worker checks
+//! the router's balance would look like a shortfall. Deployments and
+//! transfers are therefore sent under the shared transactions lease (see
+//! [`LeaseGuard::transactions`]), held only while sending.
+//!
+//! Rows name their holder and expire unless renewed, so a crashed process
+//! cannot block transactions for longer than the lease TTL. While a run is
+//! active a heartbeat renews its leases and deposit claims.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+use tokio::{
+    sync::{Mutex, OwnedMutexGuard},
+    task::JoinHandle,
+};
+
+use crate::db;
+
+/// Name of the lease row held while sending transactions
+const TRANSACTIONS: &str = "transactions";
+
+/// How often a run waiting for the transactions lease tries again
+const TRANSACTIONS_POLL: Duration = Duration::from_millis(500);
+
+/// The active run, as reported to a caller that could not start one
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, sqlx::FromRow)]
+pub struct LeaseInfo {
+    pub holder: String,
+    pub acquired_at: String,
+    pub expires_at: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LeaseError {
+    #[error("A routing run is already in progress (held by {})", .0.holder)]
+    Busy(LeaseInfo),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+/// Routing lease of this process
+pub struct RoutingLease {
+    local: Arc<Mutex<()>>,
+    holder: String,
+    ttl: Duration,
+}
+
+impl RoutingLease {
+    /// A lease with a fresh holder ID, expiring `ttl` after each renewal
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            local: Arc::default(),
+            holder: format!("{}-{}", std::process::id(), uuid::Uuid::new_v4()),
+            ttl,
+        }
+    }
+
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    /// Start a run, or report the run of this process that is active
+    pub async fn try_acquire(&self, pool: &SqlitePool) -> Result<LeaseGuard, LeaseError> {
+        let local = match self.local.clone().try_lock_owned() {
+            Ok(local) => local,
+            Err(_) => return Err(self.busy(pool).await),
+        };
+
+        // The holder ID is this process's own, so only it renews the row
+        let run = run_lease(&self.holder);
+        if !db::acquire_lease(pool, &run, &self.holder, self.ttl.as_secs()).await? {
+            return Err(self.busy(pool).await);
+        }
+        tracing::debug!(holder = %self.holder, "Acquired routing lease");
+
+        Ok(LeaseGuard {
+            heartbeat: tokio::spawn(heartbeat(pool.clone(), self.holder.clone(), self.ttl)),
+            pool: pool.clone(),
+            holder: self.holder.clone(),
+            ttl: self.ttl,
+            _local: local,
+        })
+    }
+
+    async fn busy(&self, pool: &SqlitePool) -> LeaseError {
+        match db::get_lease(pool, &run_lease(&self.holder)).await {
+            Ok(Some(lease)) => LeaseError::Busy(lease),
+            // This process holds the mutex and is between taking and
+            // releasing the row
+            Ok(None) => LeaseError::Busy(LeaseInfo {
+                holder: self.holder.clone(),
+                acquired_at: String::new(),
+                expires_at: String::new(),
+            }),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// Held routing lease; call [`LeaseGuard::release`] when the run is done
+///
+/// Dropping the guard stops the heartbeat, so an unreleased lease expires
+/// after its TTL.
+pub struct LeaseGuard {
+    heartbeat: JoinHandle<()>,
+    pool: SqlitePool,
+    holder: String,
+    ttl: Duration,
+    _local: OwnedMutexGuard<()>,
+}
+
+impl LeaseGuard {
+    /// Holder ID, used to claim deposits
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    /// How long claims last without a heartbeat
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Wait for the transactions lease, for at most the lease TTL, or report
+    /// the run holding it
+    ///
+    /// A holder that died gives it up after the TTL, so waiting longer only
+    /// means another run is still sending.
+    pub async fn transactions(&self) -> Result<TransactionsGuard<'_>, LeaseError> {
+        let deadline = Instant::now() + self.ttl;
+        loop {
+            if db::acquire_lease(&self.pool, TRANSACTIONS, &self.holder, self.ttl.as_secs()).await?
+            {
+                return Ok(TransactionsGuard { lease: self });
+            }
+            if Instant::now() >= deadline {
+                // Freed since the attempt above: try again
+                if let Some(active) = db::get_lease(&self.pool, TRANSACTIONS).await? {
+                    return Err(LeaseError::Busy(active));
+                }
+            }
+            tokio::time::sleep(TRANSACTIONS_POLL).await;
+        }
+    }
+
+    /// Give up the leases and the deposits still claimed under them
+    pub async fn release(self) {
+        self.heartbeat.abort();
+        if let Err(e) = db::release_deposit_claims(&self.pool, &self.holder).await {
+            tracing::warn!("Failed to release deposit claims: {}", e);
+        }
+        for name in [TRANSACTIONS.to_string(), run_lease(&self.holder)] {
+            if let Err(e) = db::release_lease(&self.pool, &name, &self.holder).await {
+                tracing::warn!("Failed to release routing lease {}: {}", name, e);
+            }
+        }
+    }
+}
+
+/// Held transactions lease; call [`TransactionsGuard::release`] once the
+/// transactions are sent
+///
+/// An unreleased lease is given up with the run's lease.
+pub struct TransactionsGuard<'a> {
+    lease: &'a LeaseGuard,
+}
+
+impl TransactionsGuard<'_> {
+    /// Give up the transactions lease
+    pub async fn release(self) {
+        let lease = self.lease;
+        if let Err(e) = db::release_lease(&lease.pool, TRANSACTIONS, &lease.holder).await {
+            tracing::warn!("Failed to release transactions lease: {}", e);
+        }
+    }
+}
+
+impl Drop for LeaseGuard {
+    fn drop(&mut self) {
+        self.heartbeat.abort();
+    }
+}
+
+/// Name of the lease row of a run
+fn run_lease(holder: &str) -> String {
+    format!("run:{}", holder)
+}
+
+/// Keep the leases and deposit claims alive until aborted
+async fn heartbeat(pool: SqlitePool, holder: String, ttl: Duration) {
+    let mut interval = tokio::time::interval((ttl / 3).max(Duration::from_secs(1)));
+    // The first tick completes immediately, right after acquiring
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        match db::renew_lease(&pool, &run_lease(&holder), &holder, ttl.as_secs()).await {
+            Ok(true) => {}
+            Ok(false) => tracing::warn!("Routing lease expired before the run finished"),
+            Err(e) => tracing::warn!("Failed to renew routing lease: {}", e),
+        }
+        // Only renewed while this run holds it
+        if let Err(e) = db::renew_lease(&pool, TRANSACTIONS, &holder, ttl.as_secs()).await {
+            tracing::warn!("Failed to renew transactions lease: {}", e);
+        }
+        if let Err(e) = db::extend_deposit_claims(&pool, &holder, ttl.as_secs()).await {
+            tracing::warn!("Failed to extend deposit claims: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_second_run_in_process_is_refused() {
+        let pool = db::test_pool().await;
+        let lease = RoutingLease::new(Duration::from_secs(60));
+
+        let guard = lease.try_acquire(&pool).await.unwrap();
+        match lease.try_acquire(&pool).await {
+            Err(LeaseError::Busy(info)) => assert_eq!(info.holder, lease.holder()),
+            other => panic!("expected busy, got {:?}", other.map(|_| ())),
+        }
+
+        guard.release().await;
+        assert!(lease.try_acquire(&pool).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_other_replica_runs_but_waits_to_send() {
+        let pool = db::test_pool().await;
+        let ours = RoutingLease::new(Duration::from_secs(60));
+        let theirs = RoutingLease::new(Duration::from_secs(1));
+
+        let our_run = ours.try_acquire(&pool).await.unwrap();
+        let their_run = theirs.try_acquire(&pool).await.unwrap();
+
+        let sending = our_run.transactions().await.unwrap();
+        assert!(matches!(
+            their_run.transactions().await,
+            Err(LeaseError::Busy(info)) if info.holder == ours.holder()
+        ));
+
+        sending.release().await;
+        assert!(their_run.transactions().await.is_ok());
+        their_run.release().await;
+        our_run.release().await;
+    }
+
+    #[tokio::test]
+    async fn test_expired_transactions_lease_is_taken_over() {
+        let pool = db::test_pool().await;
+        let crashed = RoutingLease::new(Duration::ZERO);
+        let guard = crashed.try_acquire(&pool).await.unwrap();
+        // Neither is released, as if the process died
+        let _sending = guard.transactions().await.unwrap();
+        drop(guard);
+
+        let next = RoutingLease::new(Duration::from_secs(60));
+        let guard = next.try_acquire(&pool).await.unwrap();
+        assert!(guard.transactions().await.is_ok());
+    }
+}
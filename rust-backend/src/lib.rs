@@ -0,0 +1,229 @@
+//! RADHAT backend library
+//!
+//! The HTTP server in `main.rs` is a thin wrapper around [`app`], which keeps
+//! the router buildable from integration tests and other binaries.
+
+use axum::{
+    http::{header, HeaderValue},
+    middleware,
+    routing::{delete, get, post},
+    Router,
+};
+use std::sync::Arc;
+use tower_http::{
+    cors::{AllowOrigin, Any, CorsLayer},
+    trace::TraceLayer,
+};
+
+pub mod auth;
+pub mod chain;
+pub mod checks;
+pub mod config;
+pub mod create2;
+pub mod db;
+pub mod error;
+pub mod forwarded;
+pub mod health;
+pub mod idempotency;
+pub mod invoice;
+pub mod jobs;
+pub mod lease;
+pub mod metrics;
+pub mod models;
+pub mod plan;
+pub mod rate_limit;
+pub mod request_id;
+pub mod routes;
+pub mod routing;
+pub mod rpc;
+pub mod signer;
+pub mod siwe;
+pub mod stranded;
+pub mod telemetry;
+pub mod treasury;
+pub mod webhooks;
+
+use auth::Role;
+use chain::ChainClient;
+use checks::CheckReport;
+use config::Config;
+use lease::RoutingLease;
+use rate_limit::RateLimiter;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub db: sqlx::SqlitePool,
+    pub config: Arc<Config>,
+    pub chain: Arc<dyn ChainClient>,
+    /// Outcome of the checks run at startup
+    pub startup_checks: Arc<CheckReport>,
+    /// Token buckets for deposit creation
+    pub limiter: Arc<RateLimiter>,
+    /// Keeps this server to one routing run at a time
+    pub routing_lease: Arc<RoutingLease>,
+}
+
+/// Build the axum router with all routes and middleware
+///
+/// Routes are grouped by the API key role they require; health checks and
+/// the sign-in endpoints are public.
+pub fn app(state: AppState) -> Router {
+    let public = Router::new()
+        .route("/health", get(routes::health::health_check))
+        .route("/health/live", get(routes::health::live))
+        .route("/health/ready", get(routes::health::ready))
+        .route("/metrics", get(routes::metrics::get_metrics));
+
+    let siwe = Router::new()
+        .route("/auth/nonce", get(routes::siwe::nonce))
+        .route("/auth/verify", post(routes::siwe::verify))
+        .route("/auth/session", get(routes::siwe::get_session))
+        .route("/auth/logout", post(routes::siwe::logout));
+
+    // Retries carrying an Idempotency-Key replay the first response; runs
+    // inside the auth layers added below
+    let idempotent = middleware::from_fn_with_state(state.clone(), idempotency::idempotent);
+
+    // Wallet sessions are accepted here, scoped to the wallet by the handlers
+    let user_read = Router::new()
+        .route("/deposits", get(routes::deposit::list_deposits))
+        .route("/deposits/:address", get(routes::deposit::get_deposit));
+
+    let user_create = Router::new()
+        .route("/deposit", post(routes::deposit::create_deposit))
+        .route_layer(idempotent.clone());
+
+    let read = Router::new()
+        .route("/stranded", get(routes::stranded::get_stranded))
+        .route("/jobs", get(routes::jobs::list_jobs))
+        .route("/jobs/:id", get(routes::jobs::get_job));
+
+    let route = Router::new()
+        .route(
+            "/router",
+            post(routes::router::route_deposits).route_layer(idempotent),
+        )
+        .route("/router/plan", post(routes::router::plan))
+        .route(
+            "/deposits/:address/route",
+            post(routes::deposit::route_deposit),
+        )
+        .route("/stranded/scan", post(routes::stranded::scan_stranded));
+
+    // Keys without the admin role manage subscriptions to their own deposits
+    let webhooks = Router::new()
+        .route(
+            "/webhooks",
+            get(routes::webhooks::list_webhooks).post(routes::webhooks::create_webhook),
+        )
+        .route("/webhooks/:id", delete(routes::webhooks::delete_webhook))
+        .route(
+            "/webhooks/:id/deliveries",
+            get(routes::webhooks::list_deliveries),
+        )
+        .route(
+            "/webhooks/deliveries/:id",
+            get(routes::webhooks::get_delivery),
+        )
+        .route(
+            "/webhooks/deliveries/:id/replay",
+            post(routes::webhooks::replay_delivery),
+        );
+
+    let admin = Router::new()
+        .route(
+            "/admin/keys",
+            get(routes::admin::list_keys).post(routes::admin::create_key),
+        )
+        .route("/admin/keys/:id", delete(routes::admin::revoke_key))
+        .route(
+            "/admin/dead-letter",
+            get(routes::dead_letter::list_dead_letter),
+        )
+        .route(
+            "/admin/deposits/:address/requeue",
+            post(routes::dead_letter::requeue),
+        )
+        .route(
+            "/admin/treasuries",
+            get(routes::treasuries::list_treasuries).post(routes::treasuries::create_treasury),
+        )
+        .route(
+            "/admin/treasuries/:name",
+            delete(routes::treasuries::deactivate_treasury),
+        )
+        .route(
+            "/admin/routing-rules",
+            get(routes::treasuries::list_rules).post(routes::treasuries::create_rule),
+        )
+        .route(
+            "/admin/routing-rules/:id",
+            delete(routes::treasuries::delete_rule),
+        );
+
+    let guarded = |router: Router<AppState>, role: Role| {
+        router.route_layer(middleware::from_fn_with_state(
+            (state.clone(), role),
+            auth::require,
+        ))
+    };
+    let user_scoped = |router: Router<AppState>, role: Role| {
+        router.route_layer(middleware::from_fn_with_state(
+            (state.clone(), role),
+            auth::require_user,
+        ))
+    };
+
+    Router::new()
+        .merge(public)
+        .merge(siwe)
+        .merge(user_scoped(user_read, Role::Read))
+        .merge(user_scoped(user_create, Role::CreateDeposit))
+        .merge(guarded(read, Role::Read))
+        .merge(guarded(route, Role::Route))
+        .merge(guarded(webhooks, Role::CreateDeposit))
+        .merge(guarded(admin, Role::Admin))
+        .route_layer(middleware::from_fn(metrics::track_http))
+        .layer(cors(&state.config.cors_origins))
+        .layer(TraceLayer::new_for_http().make_span_with(request_id::make_span))
+        .layer(middleware::from_fn(request_id::propagate))
+        .with_state(state)
+}
+
+/// CORS for the configured origins; `*` allows any origin
+fn cors(origins: &[String]) -> CorsLayer {
+    let allow_origin = if origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(origins.iter().filter_map(|origin| {
+            HeaderValue::from_str(origin)
+                .map_err(|_| tracing::warn!("Ignoring invalid CORS origin {:?}", origin))
+                .ok()
+        }))
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(Any)
+        .allow_headers(Any)
+        .expose_headers([
+            request_id::X_REQUEST_ID.clone(),
+            idempotency::IDEMPOTENT_REPLAYED.clone(),
+            header::RETRY_AFTER,
+        ])
+}
+
+/// State backed by an in-memory database and `chain`, for handler tests
+#[cfg(test)]
+pub async fn test_state(chain: chain::fake::FakeChain) -> AppState {
+    let db = db::test_pool().await;
+    let config = config::test_config();
+    AppState {
+        limiter: Arc::new(RateLimiter::new(config.rate_limit_store, &db)),
+        routing_lease: Arc::new(RoutingLease::new(config.routing_lease_ttl())),
+        db,
+        config: Arc::new(config),
+        chain: Arc::new(chain),
+        startup_checks: Arc::new(CheckReport::new(checks::CheckMode::Lenient, vec![])),
+    }
+}
@@ -1,85 +1,123 @@
-use axum::{routing::get, routing::post, Router};
-use sqlx::sqlite::SqlitePoolOptions;
-use std::sync::Arc;
-use tower_http::cors::{Any, CorsLayer};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-mod config;
-mod create2;
-mod db;
-mod error;
-mod models;
-mod routes;
-mod rpc;
-
-use config::Config;
-
-#[derive(Clone)]
-pub struct AppState {
-    pub db: sqlx::SqlitePool,
-    pub config: Arc<Config>,
+use clap::Parser;
+use std::{net::SocketAddr, sync::Arc};
+
+use radhat_backend::{
+    app,
+    chain::ChainClient,
+    checks::{self, CheckMode},
+    config::{Config, ConfigArgs},
+    db,
+    lease::RoutingLease,
+    metrics,
+    rate_limit::RateLimiter,
+    rpc::RpcClient,
+    signer, telemetry,
+    webhooks::{self, DeliveryPolicy},
+    AppState,
+};
+
+/// RADHAT deposit proxy backend
+#[derive(Parser)]
+#[command(name = "radhat-backend", version)]
+struct Args {
+    #[command(flatten)]
+    config: ConfigArgs,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Load .env file
     dotenvy::dotenv().ok();
-
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "radhat_backend=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let args = Args::parse();
 
     // Load configuration
-    let config = Config::from_env()?;
+    let config = Config::load(&args.config)?;
+
+    // Initialize tracing, exporting spans if OTLP_ENDPOINT is set; the guard
+    // flushes pending spans on shutdown
+    let _telemetry = telemetry::init(&config)?;
+
+    // Record metrics for /metrics
+    metrics::install()?;
+
     tracing::info!("Loaded configuration");
-    tracing::info!("  Deployer: {}", config.deployer_address);
-    tracing::info!("  Router: {}", config.router_address);
-    tracing::info!("  Treasury: {}", config.treasury_address);
+    if let Some(network) = &config.network {
+        tracing::info!("  Network: {}", network);
+    }
+    tracing::info!("  RPC: {}", telemetry::redact_url(&config.rpc_url));
+    tracing::info!(
+        "  Deployer: {}",
+        telemetry::redact_address(&config.deployer_address)
+    );
+    tracing::info!(
+        "  Router: {}",
+        telemetry::redact_address(&config.router_address)
+    );
+    tracing::info!(
+        "  Treasury: {}",
+        telemetry::redact_address(&config.treasury_address)
+    );
     tracing::info!("  Init Code Hash: {}", config.init_code_hash);
 
     // Create database pool
-    let db = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect(&config.database_url)
-        .await?;
+    let db = db::connect(&config.database_url).await?;
 
     // Run migrations
     db::run_migrations(&db).await?;
     tracing::info!("Database migrations complete");
 
+    if !config.require_api_keys {
+        tracing::warn!("REQUIRE_API_KEYS=false: every endpoint is public");
+    } else if db::list_api_keys(&db).await?.is_empty() {
+        tracing::warn!(
+            "No API keys exist; create one with `radhat keys create --name ops --role admin`"
+        );
+    }
+
+    // Create RPC client shared by all handlers
+    let chain = RpcClient::from_config(&config).await?;
+
+    // Deposit addresses are derived from the signer, so refuse another one
+    signer::check_recorded(&db, chain.signer_address()).await?;
+
+    // Verify the configuration against the chain before serving
+    let startup_checks = checks::startup_checks(&db, &config, &chain).await;
+    if !startup_checks.ok && startup_checks.mode == CheckMode::Strict {
+        return Err(format!(
+            "Startup checks failed: {}",
+            startup_checks.failures().join("; ")
+        )
+        .into());
+    }
+
+    // Send queued webhook deliveries, including those left over from a
+    // previous run
+    webhooks::spawn(db.clone(), DeliveryPolicy::from_config(&config));
+
     // Create app state
     let state = AppState {
+        limiter: Arc::new(RateLimiter::new(config.rate_limit_store, &db)),
+        routing_lease: Arc::new(RoutingLease::new(config.routing_lease_ttl())),
         db,
         config: Arc::new(config.clone()),
+        chain: Arc::new(chain),
+        startup_checks: Arc::new(startup_checks),
     };
 
     // Build router
-    let app = Router::new()
-        .route("/health", get(routes::health::health_check))
-        .route("/deposit", post(routes::deposit::create_deposit))
-        .route("/deposits", get(routes::deposit::list_deposits))
-        .route("/deposits/{address}", get(routes::deposit::get_deposit))
-        .route("/router", post(routes::router::route_deposits))
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Any)
-                .allow_methods(Any)
-                .allow_headers(Any),
-        )
-        .layer(tower_http::trace::TraceLayer::new_for_http())
-        .with_state(state);
+    let app = app(state);
 
     // Start server
     let addr = format!("{}:{}", config.host, config.port);
     tracing::info!("Starting server on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    // Peer addresses feed the per-IP rate limit
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
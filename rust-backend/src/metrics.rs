@@ -0,0 +1,263 @@
+//! Prometheus metrics
+//!
+//! Code records through the `metrics` macros; [`install`] sets up the
+//! Prometheus recorder that `/metrics` renders. Without a recorder (tests,
+//! the CLI) recording is a no-op.
+//!
+//! Gauges derived from the database or chain (deposits per status, stranded
+//! funds, signer balance) are refreshed on every scrape by [`refresh`].
+
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use alloy::primitives::U256;
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram};
+use metrics_exporter_prometheus::{BuildError, Matcher, PrometheusBuilder, PrometheusHandle};
+use sqlx::SqlitePool;
+
+use crate::{chain::ChainClient, db, rpc::RpcError, stranded};
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Latency buckets in seconds, from a local SQLite query to a mined transaction
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Install the global Prometheus recorder (once per process)
+pub fn install() -> Result<(), BuildError> {
+    if HANDLE.get().is_some() {
+        return Ok(());
+    }
+
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), LATENCY_BUCKETS)?
+        .install_recorder()?;
+    let _ = HANDLE.set(handle);
+    describe();
+    Ok(())
+}
+
+fn describe() {
+    describe_counter!(
+        "radhat_deposits_created_total",
+        "Deposit addresses handed out"
+    );
+    describe_gauge!("radhat_deposits", "Deposits per status");
+    describe_counter!(
+        "radhat_rate_limited_total",
+        "Deposit requests rejected by rate limits, by limit"
+    );
+    describe_counter!("radhat_routing_runs_total", "Routing passes by outcome");
+    describe_histogram!(
+        "radhat_routing_run_duration_seconds",
+        "Duration of a routing pass"
+    );
+    describe_counter!(
+        "radhat_deploy_transactions_total",
+        "deployMultiple transactions by outcome"
+    );
+    describe_counter!(
+        "radhat_transfer_transactions_total",
+        "transferFunds transactions by outcome"
+    );
+    describe_counter!(
+        "radhat_routed_gwei_total",
+        "Gwei paid out to treasuries by successful transfers"
+    );
+    describe_counter!(
+        "radhat_webhook_deliveries_total",
+        "Webhook delivery attempts by resulting delivery status"
+    );
+    describe_gauge!(
+        "radhat_signer_balance_wei",
+        "Balance of the signing account"
+    );
+    describe_gauge!("radhat_stranded_wei", "Wei stuck on deployed proxies");
+    describe_gauge!(
+        "radhat_stranded_proxies",
+        "Deployed proxies holding stuck funds"
+    );
+    describe_histogram!(
+        "radhat_rpc_request_duration_seconds",
+        "Latency of chain calls by method"
+    );
+    describe_histogram!(
+        "radhat_db_query_duration_seconds",
+        "Latency of database queries by query"
+    );
+    describe_histogram!(
+        "radhat_http_request_duration_seconds",
+        "Latency of HTTP requests by route"
+    );
+}
+
+/// Render all metrics in the Prometheus text format
+pub fn render() -> String {
+    HANDLE.get().map(|h| h.render()).unwrap_or_default()
+}
+
+/// Records the time until it is dropped into a histogram
+pub struct Timer {
+    name: &'static str,
+    label: (&'static str, &'static str),
+    started: Instant,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        histogram!(self.name, self.label.0 => self.label.1)
+            .record(self.started.elapsed().as_secs_f64());
+    }
+}
+
+/// Time a chain call, labelled with its JSON-RPC method or contract function
+pub fn rpc_timer(method: &'static str) -> Timer {
+    Timer {
+        name: "radhat_rpc_request_duration_seconds",
+        label: ("method", method),
+        started: Instant::now(),
+    }
+}
+
+/// Time a database query
+pub fn db_timer(query: &'static str) -> Timer {
+    Timer {
+        name: "radhat_db_query_duration_seconds",
+        label: ("query", query),
+        started: Instant::now(),
+    }
+}
+
+pub fn deposit_created() {
+    counter!("radhat_deposits_created_total").increment(1);
+}
+
+/// Count a rejected deposit request: "ip", "key", "user" or "pending"
+pub fn rate_limited(limit: &'static str) {
+    counter!("radhat_rate_limited_total", "limit" => limit).increment(1);
+}
+
+pub fn routing_run(clean: bool, duration: Duration) {
+    let outcome = if clean { "clean" } else { "errors" };
+    counter!("radhat_routing_runs_total", "outcome" => outcome).increment(1);
+    histogram!("radhat_routing_run_duration_seconds").record(duration.as_secs_f64());
+}
+
+/// Count a deployMultiple outcome, `Err` labelled by error kind
+pub fn deploy_outcome<T>(result: &Result<T, RpcError>) {
+    let outcome = result.as_ref().map_or_else(RpcError::kind, |_| "success");
+    counter!("radhat_deploy_transactions_total", "outcome" => outcome).increment(1);
+}
+
+/// Count a transferFunds outcome: "success", "stranded" or an error kind
+pub fn transfer_outcome(outcome: &'static str) {
+    counter!("radhat_transfer_transactions_total", "outcome" => outcome).increment(1);
+}
+
+/// Count a webhook delivery attempt: "delivered", "pending" (to be
+/// retried) or "failed"
+pub fn webhook_delivery(status: &'static str) {
+    counter!("radhat_webhook_deliveries_total", "status" => status).increment(1);
+}
+
+/// Count what a deposit's transfers paid out, in whole gwei
+pub fn wei_routed(wei: U256) {
+    // Counters are u64, which wei amounts overflow past ~18 ETH; gwei lasts
+    // for ~18 billion ETH
+    let gwei = wei / U256::from(1_000_000_000u64);
+    counter!("radhat_routed_gwei_total").increment(u64::try_from(gwei).unwrap_or(u64::MAX));
+}
+
+/// Refresh gauges that mirror database and chain state
+pub async fn refresh(pool: &SqlitePool, chain: &dyn ChainClient) {
+    match db::count_deposits_by_status(pool).await {
+        Ok(counts) => {
+            for (status, count) in counts {
+                gauge!("radhat_deposits", "status" => status).set(count as f64);
+            }
+        }
+        Err(e) => tracing::warn!("Failed to count deposits for metrics: {}", e),
+    }
+
+    match stranded::report(pool).await {
+        Ok(report) => {
+            let total = report.total_wei.parse::<U256>().unwrap_or_default();
+            gauge!("radhat_stranded_wei").set(f64::from(total));
+            gauge!("radhat_stranded_proxies").set(report.count as f64);
+        }
+        Err(e) => tracing::warn!("Failed to read stranded funds for metrics: {}", e),
+    }
+
+    match chain.get_balance(chain.signer_address()).await {
+        Ok(balance) => gauge!("radhat_signer_balance_wei").set(f64::from(balance)),
+        Err(e) => tracing::warn!("Failed to read signer balance for metrics: {}", e),
+    }
+}
+
+/// Middleware recording request latency per matched route
+///
+/// Installed with `route_layer` so the matched route template (e.g.
+/// `/deposits/:address`) is known and label cardinality stays bounded.
+pub async fn track_http(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    histogram!(
+        "radhat_http_request_duration_seconds",
+        "method" => method,
+        "route" => route,
+        "status" => response.status().as_u16().to_string()
+    )
+    .record(started.elapsed().as_secs_f64());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::fake::FakeChain;
+
+    #[tokio::test]
+    async fn test_render_includes_recorded_metrics() {
+        install().unwrap();
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        chain.fund(chain.signer_address(), 42);
+        db::insert_deposit(&pool, "0xuser", "0x00", "0xaa", 0, None)
+            .await
+            .unwrap();
+
+        refresh(&pool, &chain).await;
+        deploy_outcome::<()>(&Err(RpcError::TransactionFailed("reverted".to_string())));
+        drop(rpc_timer("eth_getBalance"));
+
+        let output = render();
+        assert!(
+            output.contains(r#"radhat_deposits{status="pending"} 1"#),
+            "{}",
+            output
+        );
+        assert!(output.contains("radhat_signer_balance_wei 42"));
+        assert!(output.contains("radhat_stranded_wei 0"));
+        assert!(
+            output.contains(r#"radhat_deploy_transactions_total{outcome="transaction_failed"}"#)
+        );
+        assert!(output
+            .contains(r#"radhat_rpc_request_duration_seconds_bucket{method="eth_getBalance""#));
+    }
+}
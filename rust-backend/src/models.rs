@@ -2,11 +2,39 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    auth::Role, checks::CheckReport, invoice::PaymentStatus, treasury::SplitSpec,
+    webhooks::WebhookEvent,
+};
+
 /// POST /deposit request
 #[derive(Debug, Deserialize)]
 pub struct CreateDepositRequest {
     /// User's Ethereum address (0x prefixed)
     pub user: String,
+    /// Amount to invoice in wei; makes the deposit an invoice
+    pub expected_amount_wei: Option<String>,
+    /// Asset the invoice is paid in; only `ETH` is supported
+    pub asset: Option<String>,
+    /// Seconds until the invoice expires
+    pub expires_in_secs: Option<u64>,
+    /// Caller's order reference, e.g. a checkout ID
+    pub order_ref: Option<String>,
+    /// Free-form JSON stored with the invoice
+    pub metadata: Option<serde_json::Value>,
+    pub description: Option<String>,
+}
+
+impl CreateDepositRequest {
+    /// Whether any invoice field is set
+    pub fn has_invoice_fields(&self) -> bool {
+        self.expected_amount_wei.is_some()
+            || self.asset.is_some()
+            || self.expires_in_secs.is_some()
+            || self.order_ref.is_some()
+            || self.metadata.is_some()
+            || self.description.is_some()
+    }
 }
 
 /// POST /deposit response
@@ -18,8 +46,42 @@ pub struct CreateDepositResponse {
     pub salt: String,
     /// The nonce used for this user
     pub nonce: u64,
+    /// DeterministicProxyDeployer, init code hash and signer the address
+    /// derives from, so clients can recompute it
+    pub deployer_address: String,
+    pub init_code_hash: String,
+    pub signer_address: String,
     /// Helpful note for the user
     pub note: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invoice: Option<InvoiceInfo>,
+}
+
+/// Invoice terms of a deposit and how it was paid
+#[derive(Debug, Serialize)]
+pub struct InvoiceInfo {
+    pub expected_amount_wei: String,
+    pub asset: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order_ref: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub payment_status: PaymentStatus,
+    /// Balance last seen on the deposit address before routing
+    pub received_wei: String,
+    /// When routing first saw funds on the deposit address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paid_at: Option<String>,
+    /// Amount missing, when underpaid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shortfall_wei: Option<String>,
+    /// Amount paid in excess, when overpaid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excess_wei: Option<String>,
 }
 
 /// Deposit record for listing
@@ -32,6 +94,33 @@ pub struct DepositInfo {
     pub nonce: u64,
     pub status: String,
     pub created_at: String,
+    /// Failed routing attempts since the deposit was created or requeued
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// When a routing run may try the deposit again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_retry_at: Option<String>,
+    /// API key the deposit was created with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Where the routed funds went (GET /deposits/:address only)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allocations: Vec<AllocationInfo>,
+    /// Invoice terms and payment assessment (GET /deposits/:address only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invoice: Option<InvoiceInfo>,
+}
+
+/// Amount of a routed deposit one treasury received
+#[derive(Debug, Serialize)]
+pub struct AllocationInfo {
+    pub treasury_address: String,
+    /// Share of the deposit in basis points (10000 = 100%)
+    pub share_bps: u32,
+    pub amount_wei: String,
+    pub tx_hash: String,
+    pub created_at: String,
 }
 
 /// GET /deposits response
@@ -47,6 +136,47 @@ pub struct HealthResponse {
     pub status: String,
     pub version: String,
     pub deployer_address: String,
+    pub init_code_hash: String,
+    /// Account that sends deployMultiple; deposit addresses depend on it
+    pub signer_address: String,
+    pub startup_checks: CheckReport,
+    pub components: Vec<ComponentCheck>,
+}
+
+/// GET /health/live response
+#[derive(Debug, Serialize)]
+pub struct LiveResponse {
+    pub status: String,
+    pub version: String,
+}
+
+/// Health of a single component, ordered from best to worst
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentStatus {
+    Ok,
+    Degraded,
+    Down,
+}
+
+impl ComponentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ComponentStatus::Ok => "ok",
+            ComponentStatus::Degraded => "degraded",
+            ComponentStatus::Down => "down",
+        }
+    }
+}
+
+/// Result of one component check
+#[derive(Debug, Serialize)]
+pub struct ComponentCheck {
+    pub name: &'static str,
+    pub status: ComponentStatus,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
 }
 
 /// Error response
@@ -54,10 +184,17 @@ pub struct HealthResponse {
 pub struct ErrorResponse {
     pub error: String,
     pub code: String,
+    /// Same as the `X-Request-Id` response header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Structured context, e.g. the routing run that is already active
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
 }
 
-/// POST /router response
-#[derive(Debug, Serialize)]
+/// Outcome of a routing run, stored as a routing job's result
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RouteResponse {
     /// Number of pending deposits checked
     pub checked: usize,
@@ -67,6 +204,8 @@ pub struct RouteResponse {
     pub deployed: usize,
     /// Number of proxies with funds routed to treasury
     pub routed: usize,
+    /// Number of proxies holding ETH sent before they were deployed
+    pub stranded: usize,
     /// Transaction hash for deployMultiple (if any)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub deploy_tx_hash: Option<String>,
@@ -74,12 +213,506 @@ pub struct RouteResponse {
     pub route_tx_hashes: Vec<RouteTransactionInfo>,
     /// Any errors encountered during routing
     pub errors: Vec<String>,
+    /// What happened to each deposit the run claimed
+    pub deposits: Vec<DepositResult>,
 }
 
 /// Info about a routing transaction
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct RouteTransactionInfo {
     pub proxy_address: String,
     pub tx_hash: String,
     pub amount_wei: String,
+    #[serde(default)]
+    pub treasury_address: String,
+}
+
+/// Where one deposit ended up in a routing run
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DepositResult {
+    pub deposit_address: String,
+    /// Deposit status after the run's last step for it
+    pub status: String,
+    /// Last transferFunds transaction, once routed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount_wei: Option<String>,
+    /// Invoice payment classification, for invoices
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payment_status: Option<PaymentStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// POST /deposits/:address/route request; the body may be omitted
+#[derive(Debug, Default, Deserialize)]
+pub struct RouteDepositRequest {
+    /// Treasury to route everything to instead of following the routing
+    /// rules; must be on the treasury allowlist or in the registry
+    pub treasury: Option<String>,
+}
+
+/// POST /deposits/:address/route response
+#[derive(Debug, Serialize)]
+pub struct RouteDepositResponse {
+    pub deposit_address: String,
+    /// Status after the steps below
+    pub status: String,
+    /// Treasury requested instead of the routing rules
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub treasury_address: Option<String>,
+    /// `balance_check`, `deploy` and one `transfer` per treasury, as far as
+    /// they got
+    pub steps: Vec<RouteStep>,
+}
+
+/// One step of routing a single deposit
+#[derive(Debug, Serialize)]
+pub struct RouteStep {
+    pub step: &'static str,
+    pub outcome: StepOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount_wei: Option<String>,
+    /// Recipient of a transfer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub treasury_address: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepOutcome {
+    Done,
+    /// Not needed, e.g. deploying a proxy that is already deployed
+    Skipped,
+    Failed,
+}
+
+/// Lifecycle of a routing job
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// The run is in progress
+    Running,
+    /// The run finished; per-deposit failures are in the result's `errors`
+    Completed,
+    /// The process running the job died before it finished
+    Interrupted,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Running => "running",
+            JobStatus::Completed => "completed",
+            JobStatus::Interrupted => "interrupted",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "running" => Some(JobStatus::Running),
+            "completed" => Some(JobStatus::Completed),
+            "interrupted" => Some(JobStatus::Interrupted),
+            _ => None,
+        }
+    }
+}
+
+/// A routing job: POST /router response and GET /jobs/:id
+#[derive(Debug, Serialize)]
+pub struct RoutingJobInfo {
+    pub id: i64,
+    pub status: JobStatus,
+    /// API key that started the job, or `cli` for `radhat route`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requested_by: Option<String>,
+    pub checked: usize,
+    pub funded: usize,
+    pub deployed: usize,
+    pub routed: usize,
+    pub stranded: usize,
+    pub error_count: usize,
+    pub created_at: String,
+    pub updated_at: String,
+    pub finished_at: Option<String>,
+    /// Progress so far, with per-deposit results and errors; omitted from
+    /// GET /jobs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<RouteResponse>,
+}
+
+/// GET /jobs query
+#[derive(Debug, Deserialize)]
+pub struct ListJobsQuery {
+    /// Most recent jobs to return (default 50, at most 500)
+    pub limit: Option<u32>,
+}
+
+/// GET /jobs response
+#[derive(Debug, Serialize)]
+pub struct ListJobsResponse {
+    pub jobs: Vec<RoutingJobInfo>,
+    pub total: usize,
+}
+
+/// POST /router/plan response: what a routing run would do right now
+#[derive(Debug, Default, Serialize)]
+pub struct RoutePlan {
+    /// Deposits a run would claim and check
+    pub checked: usize,
+    pub funded: usize,
+    pub deployed: usize,
+    pub routed: usize,
+    pub deposits: Vec<PlannedDeposit>,
+    /// Transactions in the order a run would send them
+    pub transactions: Vec<PlannedTransaction>,
+    /// Deposits a run would leave alone, with the reason
+    pub excluded: Vec<ExcludedDeposit>,
+    /// Deposits holding ETH no transfer can move, with the reason
+    pub stranded: Vec<StrandedDeposit>,
+    pub total_gas: u64,
+    /// Omitted if the gas price could not be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_price_wei: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_fee_wei: Option<String>,
+    /// Sum of the forwarded funds the transfers would move
+    pub value_to_treasury_wei: String,
+    pub treasury_address: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+/// A deposit a routing run would act on
+#[derive(Debug, Serialize)]
+pub struct PlannedDeposit {
+    pub deposit_address: String,
+    /// Current status
+    pub status: String,
+    /// Steps the run would take: `fund`, `deploy` and `transfer`
+    pub steps: Vec<&'static str>,
+    /// ETH on the deposit address itself
+    pub balance_wei: String,
+    /// ETH its proxy forwarded to the router that wasn't routed yet
+    pub forwarded_wei: String,
+}
+
+/// A transaction a routing run would send
+#[derive(Debug, Serialize)]
+pub struct PlannedTransaction {
+    /// `deployMultiple` or `transferFunds`
+    pub call: &'static str,
+    /// Contract the call goes to
+    pub to: String,
+    pub deposits: Vec<String>,
+    pub gas: u64,
+    /// False when eth_estimateGas failed; `error` says why
+    pub simulated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_wei: Option<String>,
+    /// Value the call would move to the treasury
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_wei: Option<String>,
+    /// Treasury a transfer would pay
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub treasury: Option<String>,
+    /// Why the simulation says the call would fail
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A deposit a routing run would skip
+#[derive(Debug, Serialize)]
+pub struct ExcludedDeposit {
+    pub deposit_address: String,
+    pub status: String,
+    pub reason: String,
+}
+
+/// A deposit whose ETH a routing run would leave stranded
+#[derive(Debug, Serialize)]
+pub struct StrandedDeposit {
+    pub deposit_address: String,
+    pub status: String,
+    pub balance_wei: String,
+    pub reason: String,
+}
+
+/// A treasury in the registry
+#[derive(Debug, Serialize)]
+pub struct TreasuryInfo {
+    pub id: i64,
+    pub name: String,
+    pub address: String,
+    pub active: bool,
+    pub created_at: String,
+}
+
+/// POST /admin/treasuries request
+#[derive(Debug, Deserialize)]
+pub struct CreateTreasuryRequest {
+    /// Name routing rules refer to the treasury by, e.g. `ops`
+    pub name: String,
+    pub address: String,
+}
+
+/// GET /admin/treasuries response
+#[derive(Debug, Serialize)]
+pub struct ListTreasuriesResponse {
+    pub treasuries: Vec<TreasuryInfo>,
+    pub total: usize,
+}
+
+/// A routing rule
+#[derive(Debug, Serialize)]
+pub struct RoutingRuleInfo {
+    pub id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asset: Option<String>,
+    pub splits: Vec<SplitSpec>,
+    pub created_at: String,
+}
+
+/// POST /admin/routing-rules request; unset match fields match any deposit
+#[derive(Debug, Deserialize)]
+pub struct CreateRoutingRuleRequest {
+    /// Depositing user's address
+    pub user_address: Option<String>,
+    /// Name of the API key the deposit was created with
+    pub tenant: Option<String>,
+    /// Asset symbol; only `ETH` is routed today
+    pub asset: Option<String>,
+    /// Registered treasuries and their shares, adding up to 10000 bps
+    pub splits: Vec<SplitSpec>,
+}
+
+/// GET /admin/routing-rules response
+#[derive(Debug, Serialize)]
+pub struct ListRoutingRulesResponse {
+    pub rules: Vec<RoutingRuleInfo>,
+    pub total: usize,
+}
+
+/// A deployed proxy holding funds that no call can move
+#[derive(Debug, Serialize)]
+pub struct StrandedProxyInfo {
+    pub deposit_address: String,
+    pub user_address: String,
+    pub balance_wei: String,
+    pub detected_at: String,
+    pub last_checked_at: String,
+}
+
+/// GET /stranded response
+#[derive(Debug, Serialize)]
+pub struct StrandedReport {
+    pub proxies: Vec<StrandedProxyInfo>,
+    /// Number of proxies with stranded funds
+    pub count: usize,
+    /// Sum of all stranded balances in wei
+    pub total_wei: String,
+    /// Errors encountered while re-checking balances (scan only)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+/// POST /admin/keys request
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    /// Label for the key, e.g. the service using it
+    pub name: String,
+    pub roles: Vec<Role>,
+}
+
+/// POST /admin/keys response
+#[derive(Debug, Serialize)]
+pub struct CreateApiKeyResponse {
+    pub id: i64,
+    pub name: String,
+    pub roles: Vec<Role>,
+    /// The key itself; it is not stored and cannot be shown again
+    pub key: String,
+}
+
+/// An API key without its secret
+#[derive(Debug, Serialize)]
+pub struct ApiKeyInfo {
+    pub id: i64,
+    pub name: String,
+    /// First characters of the key, to tell keys apart
+    pub key_prefix: String,
+    pub roles: Vec<Role>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+    pub request_count: i64,
+    pub revoked_at: Option<String>,
+}
+
+/// GET /admin/keys response
+#[derive(Debug, Serialize)]
+pub struct ListApiKeysResponse {
+    pub keys: Vec<ApiKeyInfo>,
+    pub total: usize,
+}
+
+/// GET /auth/nonce response
+#[derive(Debug, Serialize)]
+pub struct NonceResponse {
+    /// Put this in the SIWE message's `Nonce` field
+    pub nonce: String,
+    pub expires_at: String,
+}
+
+/// POST /auth/verify request
+#[derive(Debug, Deserialize)]
+pub struct SiweVerifyRequest {
+    /// The EIP-4361 message exactly as signed
+    pub message: String,
+    /// 65-byte `personal_sign` signature, hex encoded
+    pub signature: String,
+}
+
+/// POST /auth/verify response
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    /// Send as `Authorization: Bearer <token>`
+    pub token: String,
+    pub address: String,
+    pub expires_at: String,
+}
+
+/// GET /auth/session response
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub address: String,
+    pub expires_at: String,
+}
+
+/// A webhook subscription without its secret
+#[derive(Debug, Serialize)]
+pub struct WebhookInfo {
+    pub id: i64,
+    /// API key whose deposits the subscription follows; every deposit if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub created_at: String,
+}
+
+/// POST /webhooks request
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookRequest {
+    /// http(s) URL events are POSTed to
+    pub url: String,
+    /// Events to send; every event if unset
+    pub events: Option<Vec<WebhookEvent>>,
+    /// API key whose deposits to follow (admin only; other keys always
+    /// follow their own)
+    pub tenant: Option<String>,
+}
+
+/// POST /webhooks response
+#[derive(Debug, Serialize)]
+pub struct CreateWebhookResponse {
+    #[serde(flatten)]
+    pub webhook: WebhookInfo,
+    /// Key requests are signed with; it is not shown again
+    pub secret: String,
+}
+
+/// GET /webhooks response
+#[derive(Debug, Serialize)]
+pub struct ListWebhooksResponse {
+    pub webhooks: Vec<WebhookInfo>,
+    pub total: usize,
+}
+
+/// One event sent, or to be sent, to a subscription
+#[derive(Debug, Serialize)]
+pub struct WebhookDeliveryInfo {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: WebhookEvent,
+    pub deposit_address: String,
+    /// `pending`, `delivered` or `failed`
+    pub status: String,
+    pub attempts: i64,
+    /// When a pending delivery is next attempted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_attempt_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_status_code: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivered_at: Option<String>,
+    pub created_at: String,
+    /// The request body (GET /webhooks/deliveries/:id only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<serde_json::Value>,
+    /// Every attempt, oldest first (GET /webhooks/deliveries/:id only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<Vec<WebhookAttemptInfo>>,
+}
+
+/// One attempt at a delivery
+#[derive(Debug, Serialize)]
+pub struct WebhookAttemptInfo {
+    /// Receiver's HTTP status, if it answered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: i64,
+    pub attempted_at: String,
+}
+
+/// GET /webhooks/:id/deliveries query
+#[derive(Debug, Deserialize)]
+pub struct ListWebhookDeliveriesQuery {
+    /// Most deliveries to return, newest first
+    pub limit: Option<u32>,
+}
+
+/// GET /webhooks/:id/deliveries response
+#[derive(Debug, Serialize)]
+pub struct ListWebhookDeliveriesResponse {
+    pub deliveries: Vec<WebhookDeliveryInfo>,
+    pub total: usize,
+}
+
+/// Body of a webhook request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    /// Event ID, the same in every subscriber's delivery and in replays
+    pub id: String,
+    pub event: WebhookEvent,
+    pub created_at: String,
+    pub deposit: WebhookDeposit,
+}
+
+/// The deposit an event is about, as of the event
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookDeposit {
+    pub deposit_address: String,
+    pub user: String,
+    pub nonce: i64,
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Why the deposit failed (`failed` events)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
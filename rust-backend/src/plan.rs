@@ -0,0 +1,672 @@
+//! Routing plans: what a routing run would do, without sending anything
+//!
+//! `POST /router/plan` selects deposits the way
+//! [`crate::routing::route_deposits`] does and simulates the transactions the
+//! run would send with `eth_estimateGas`, so a run can be reviewed before it
+//! moves funds on mainnet. Nothing is claimed, written or sent, so a plan
+//! doesn't need the routing lease; deposits claimed by an active run are
+//! reported as excluded.
+
+use alloy::primitives::U256;
+use sqlx::SqlitePool;
+
+use crate::{
+    chain::ChainClient,
+    config::Config,
+    db::{self, CandidateRow},
+    forwarded,
+    models::{ExcludedDeposit, PlannedDeposit, PlannedTransaction, RoutePlan, StrandedDeposit},
+    routing,
+    rpc::{parse_address, parse_salt},
+    treasury::{self, RoutingRules, Split},
+};
+
+/// Statuses a plan reports on: the ones a run claims, plus the ones that need
+/// a requeue before a run will pick them up again. Deposits in
+/// [`routing::CREDIT_STATUSES`] are only reported while they have credit.
+const PLAN_STATUSES: &[&str] = &[
+    "pending",
+    "funded",
+    "deployed",
+    "routed",
+    "stranded",
+    "dead_letter",
+    "failed",
+];
+
+/// A deposit whose proxy forwarded funds the run would transfer
+struct Transfer {
+    address: String,
+    credit: U256,
+    /// Treasuries the funds would be split across, or why there are none
+    splits: Result<Vec<Split>, String>,
+}
+
+/// Plan one routing pass over the current deposits
+///
+/// Mirrors the steps of [`crate::routing::route_deposits`]: pending deposits
+/// with a balance would be funded, funded deposits without a proxy deployed
+/// in one `deployMultiple`, and what each deployed proxy forwarded to the
+/// router and wasn't routed yet paid out by one `transferFunds` per treasury
+/// of its routing rule, including routed deposits whose proxies forwarded more
+/// since. ETH on a deposit address arrived before its proxy existed and can't
+/// be moved, so it is reported as stranded next to any credit the deposit has
+/// rather than planned as a transfer.
+#[tracing::instrument(name = "routing_plan", skip_all)]
+pub async fn plan_routing(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    config: &Config,
+) -> RoutePlan {
+    let mut plan = RoutePlan {
+        treasury_address: config.treasury_address.clone(),
+        ..Default::default()
+    };
+
+    let candidates = match db::get_routing_candidates(pool, PLAN_STATUSES).await {
+        Ok(candidates) => candidates,
+        Err(e) => {
+            tracing::error!("Failed to fetch deposits: {}", e);
+            plan.errors.push(format!("Database error: {}", e));
+            plan.value_to_treasury_wei = U256::ZERO.to_string();
+            return plan;
+        }
+    };
+
+    // What every proxy forwarded, as the run's scan would count it
+    let scan = forwarded::scan(pool, chain).await;
+    if let Err(e) = &scan {
+        plan.errors.push(e.clone());
+    }
+    let with_credit = scan
+        .as_ref()
+        .map(|scan| scan.with_credit())
+        .unwrap_or_default();
+
+    // Deposits the run would claim, in claim order
+    let mut claimed = vec![];
+    for candidate in candidates {
+        // A run only takes these back for what their proxies forwarded since
+        let status = candidate.deposit.status.as_str();
+        if routing::CREDIT_STATUSES.contains(&status)
+            && !with_credit.contains(&candidate.deposit.deposit_address.as_str())
+        {
+            continue;
+        }
+        match exclusion(&candidate) {
+            Some(reason) => exclude(&mut plan, &candidate.deposit, reason),
+            None => claimed.push(candidate.deposit),
+        }
+    }
+    plan.checked = claimed.len();
+
+    let rules = RoutingRules::load(pool)
+        .await
+        .map_err(|e| format!("Routing rules unavailable: {}", e));
+
+    let mut deployments = vec![];
+    let mut salts = vec![];
+    let mut transfers = vec![];
+    for deposit in &claimed {
+        let proxy = match parse_address(&deposit.deposit_address) {
+            Ok(proxy) => proxy,
+            Err(e) => {
+                exclude(&mut plan, deposit, format!("Invalid address: {}", e));
+                continue;
+            }
+        };
+
+        let balance = match chain.get_balance(proxy).await {
+            Ok(balance) => Some(balance),
+            // A run skips pending deposits it can't check
+            Err(e) if deposit.status == "pending" => {
+                exclude(&mut plan, deposit, format!("Balance check failed: {}", e));
+                continue;
+            }
+            Err(e) => {
+                plan.errors.push(format!(
+                    "Balance check failed for {}: {}",
+                    deposit.deposit_address, e
+                ));
+                None
+            }
+        };
+
+        let mut steps = vec![];
+        if deposit.status == "pending" {
+            if balance == Some(U256::ZERO) {
+                exclude(&mut plan, deposit, "No balance yet".to_string());
+                continue;
+            }
+            steps.push("fund");
+        }
+
+        // A run records proxies that already have code instead of deploying
+        // them again
+        let was_deployed = deposit.status == "deployed"
+            || routing::CREDIT_STATUSES.contains(&deposit.status.as_str());
+        let deployed = was_deployed
+            || chain
+                .get_code(proxy)
+                .await
+                .is_ok_and(|code| !code.is_empty());
+        if !was_deployed {
+            plan.funded += 1;
+            if !deployed {
+                match parse_salt(&deposit.salt) {
+                    Ok(salt) => salts.push(salt),
+                    Err(e) => {
+                        exclude(&mut plan, deposit, format!("Invalid salt: {}", e));
+                        continue;
+                    }
+                }
+                steps.push("deploy");
+                deployments.push(deposit.deposit_address.clone());
+            }
+        }
+
+        // The proxy forwards only what it receives once deployed
+        if let Some(balance) = balance.filter(|b| *b > U256::ZERO) {
+            plan.stranded.push(StrandedDeposit {
+                deposit_address: deposit.deposit_address.clone(),
+                status: deposit.status.clone(),
+                balance_wei: balance.to_string(),
+                reason: "Sent before the proxy was deployed, which can't move it".to_string(),
+            });
+        }
+
+        // A proxy this run deploys hasn't forwarded anything yet
+        let credit = if deployed {
+            scan.as_ref().ok().map(|scan| {
+                scan.get(&deposit.deposit_address)
+                    .map_or(U256::ZERO, |counted| counted.credit)
+            })
+        } else {
+            Some(U256::ZERO)
+        };
+        if let Some(credit) = credit.filter(|c| *c > U256::ZERO) {
+            steps.push("transfer");
+            let splits = rules.as_ref().map_err(Clone::clone).and_then(|rules| {
+                rules
+                    .splits_for(deposit, chain.treasury_address())
+                    .map_err(|e| e.to_string())
+            });
+            transfers.push(Transfer {
+                address: deposit.deposit_address.clone(),
+                credit,
+                splits,
+            });
+        }
+
+        plan.deposits.push(PlannedDeposit {
+            deposit_address: deposit.deposit_address.clone(),
+            status: deposit.status.clone(),
+            steps,
+            balance_wei: balance.map_or_else(|| "unknown".to_string(), |b| b.to_string()),
+            forwarded_wei: credit.map_or_else(|| "unknown".to_string(), |c| c.to_string()),
+        });
+    }
+
+    let gas_price = match chain.gas_price().await {
+        Ok(price) => {
+            plan.gas_price_wei = Some(price.to_string());
+            Some(price)
+        }
+        Err(e) => {
+            plan.errors.push(format!("Gas price unavailable: {}", e));
+            None
+        }
+    };
+
+    if !deployments.is_empty() {
+        let deploy = simulate_deploy(chain, config, salts, &deployments).await;
+        if let Some(error) = &deploy.error {
+            plan.errors.push(format!("Deploy would fail: {}", error));
+        } else {
+            plan.deployed = deployments.len();
+        }
+        plan.transactions.push(deploy);
+    }
+
+    // Transfers pay out of the router's balance, so they don't depend on the
+    // deployment going through
+    let mut value = U256::ZERO;
+    for transfer in &transfers {
+        let splits = match &transfer.splits {
+            Ok(splits) => splits,
+            Err(error) => {
+                plan.errors
+                    .push(format!("No treasury for {}: {}", transfer.address, error));
+                continue;
+            }
+        };
+        let amounts = treasury::split_amounts(transfer.credit, splits);
+        let mut fails = false;
+        for (split, amount) in splits.iter().zip(amounts) {
+            if amount == U256::ZERO {
+                continue;
+            }
+            let planned = simulate_transfer(chain, config, transfer, split, amount).await;
+            if let Some(error) = &planned.error {
+                plan.errors.push(format!(
+                    "Transfer would fail for {}: {}",
+                    transfer.address, error
+                ));
+                fails = true;
+            }
+            plan.transactions.push(planned);
+        }
+        if !fails {
+            plan.routed += 1;
+            value = value.saturating_add(transfer.credit);
+        }
+    }
+
+    plan.total_gas = plan.transactions.iter().map(|tx| tx.gas).sum();
+    if let Some(price) = gas_price {
+        for tx in &mut plan.transactions {
+            tx.fee_wei = Some(fee(tx.gas, price).to_string());
+        }
+        plan.total_fee_wei = Some(fee(plan.total_gas, price).to_string());
+    }
+    plan.value_to_treasury_wei = value.to_string();
+
+    tracing::info!(
+        "Planned routing: checked={}, funded={}, deployed={}, routed={}, stranded={}, excluded={}",
+        plan.checked,
+        plan.funded,
+        plan.deployed,
+        plan.routed,
+        plan.stranded.len(),
+        plan.excluded.len()
+    );
+
+    plan
+}
+
+/// Why a run would not claim the deposit
+fn exclusion(candidate: &CandidateRow) -> Option<String> {
+    let deposit = &candidate.deposit;
+    let last_error = deposit.last_error.as_deref().unwrap_or("unknown error");
+    match deposit.status.as_str() {
+        "dead_letter" => Some(format!(
+            "Dead-lettered after {} attempts ({}); requeue to retry",
+            deposit.attempts, last_error
+        )),
+        "failed" => Some(format!(
+            "Deployment failed permanently ({}); requeue to retry",
+            last_error
+        )),
+        _ => match (&candidate.claimed_by, &candidate.claimed_until) {
+            (Some(holder), Some(until)) => {
+                Some(format!("Claimed by routing run {} until {}", holder, until))
+            }
+            _ if !candidate.retry_due => Some(format!(
+                "Waiting to retry at {} after {} failed attempts",
+                deposit.next_retry_at.as_deref().unwrap_or("unknown"),
+                deposit.attempts
+            )),
+            _ => None,
+        },
+    }
+}
+
+fn exclude(plan: &mut RoutePlan, deposit: &db::DepositRow, reason: String) {
+    plan.excluded.push(ExcludedDeposit {
+        deposit_address: deposit.deposit_address.clone(),
+        status: deposit.status.clone(),
+        reason,
+    });
+}
+
+async fn simulate_deploy(
+    chain: &dyn ChainClient,
+    config: &Config,
+    salts: Vec<alloy::primitives::FixedBytes<32>>,
+    deployments: &[String],
+) -> PlannedTransaction {
+    let estimate = chain.estimate_deploy_gas(salts).await;
+    PlannedTransaction {
+        call: "deployMultiple",
+        to: config.deployer_address.clone(),
+        deposits: deployments.to_vec(),
+        gas: *estimate.as_ref().unwrap_or(&0),
+        simulated: estimate.is_ok(),
+        fee_wei: None,
+        value_wei: None,
+        treasury: None,
+        error: estimate.err().map(|e| e.to_string()),
+    }
+}
+
+async fn simulate_transfer(
+    chain: &dyn ChainClient,
+    config: &Config,
+    transfer: &Transfer,
+    split: &Split,
+    amount: U256,
+) -> PlannedTransaction {
+    let estimate = chain.estimate_transfer_gas(split.treasury, amount).await;
+    PlannedTransaction {
+        call: "transferFunds",
+        to: config.router_address.clone(),
+        deposits: vec![transfer.address.clone()],
+        gas: *estimate.as_ref().unwrap_or(&0),
+        simulated: estimate.is_ok(),
+        fee_wei: None,
+        value_wei: Some(amount.to_string()),
+        treasury: Some(format!("{:#x}", split.treasury)),
+        error: estimate.err().map(|e| e.to_string()),
+    }
+}
+
+fn fee(gas: u64, gas_price: u128) -> U256 {
+    U256::from(gas).saturating_mul(U256::from(gas_price))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chain::fake::{self, FakeChain},
+        create2::{compute_deposit_address, format_address, format_bytes32},
+        lease::RoutingLease,
+        routing::{self, RetryPolicy},
+    };
+    use alloy::primitives::Address;
+    use std::time::Duration;
+
+    /// Insert a deposit with the given status and return its address
+    async fn seed(pool: &SqlitePool, nonce: u64, status: &str) -> Address {
+        let user = [0x42u8; 20];
+        let (addr, salt) = compute_deposit_address(
+            &fake::DEPLOYER.0 .0,
+            &fake::INIT_CODE_HASH.0,
+            &fake::SIGNER.0 .0,
+            &user,
+            nonce,
+        );
+        let deposit_address = format_address(&addr);
+        db::insert_deposit(
+            pool,
+            &format_address(&user),
+            &format_bytes32(&salt),
+            &deposit_address,
+            nonce,
+            None,
+        )
+        .await
+        .unwrap();
+        db::update_deposit_status(pool, &deposit_address, status)
+            .await
+            .unwrap();
+        Address::from(addr)
+    }
+
+    /// Insert a deposit whose proxy is deployed and forwarded `wei` since
+    async fn seed_forwarding(
+        pool: &SqlitePool,
+        chain: &FakeChain,
+        nonce: u64,
+        wei: u64,
+    ) -> Address {
+        let addr = seed(pool, nonce, "deployed").await;
+        chain.set_code(addr);
+        let block = chain.block_number().await.unwrap();
+        db::start_forwarded_scan(pool, &format_address(&addr.0 .0), block)
+            .await
+            .unwrap();
+        chain.send(addr, wei);
+        addr
+    }
+
+    fn reason_for(plan: &RoutePlan, address: Address) -> &str {
+        let address = format_address(&address.0 .0);
+        plan.excluded
+            .iter()
+            .find(|d| d.deposit_address == address)
+            .map(|d| d.reason.as_str())
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn test_plan_matches_run_without_sending() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        let config = crate::config::test_config();
+
+        let pending = seed(&pool, 0, "pending").await;
+        chain.fund(pending, 100);
+        seed(&pool, 1, "funded").await;
+        let empty = seed(&pool, 2, "pending").await;
+        let forwarding = seed_forwarding(&pool, &chain, 3, 70).await;
+        chain.fund(forwarding, 5);
+
+        let plan = plan_routing(&pool, &chain, &config).await;
+        assert!(plan.errors.is_empty(), "{:?}", plan.errors);
+        assert_eq!(
+            (plan.checked, plan.funded, plan.deployed, plan.routed),
+            (4, 2, 2, 1)
+        );
+        assert_eq!(plan.deposits[0].steps, ["fund", "deploy"]);
+        assert_eq!(plan.deposits[1].steps, ["deploy"]);
+        assert_eq!(plan.deposits[2].steps, ["transfer"]);
+        assert_eq!(plan.deposits[2].forwarded_wei, "70");
+        assert_eq!(reason_for(&plan, empty), "No balance yet");
+        // ETH that reached the addresses before their proxies isn't counted
+        let stranded: Vec<_> = plan
+            .stranded
+            .iter()
+            .map(|d| (d.deposit_address.clone(), d.balance_wei.as_str()))
+            .collect();
+        assert_eq!(
+            stranded,
+            [
+                (format_address(&pending.0 .0), "100"),
+                (format_address(&forwarding.0 .0), "5"),
+            ]
+        );
+        assert_eq!(plan.value_to_treasury_wei, "70");
+
+        // One batched deployment, then one simulated transfer from the router
+        let calls: Vec<_> = plan.transactions.iter().map(|tx| tx.call).collect();
+        assert_eq!(calls, ["deployMultiple", "transferFunds"]);
+        assert_eq!(plan.transactions[0].deposits.len(), 2);
+        assert_eq!(
+            plan.transactions[0].gas,
+            fake::TX_GAS + 2 * fake::DEPLOY_GAS_PER_PROXY
+        );
+        assert_eq!(plan.transactions[1].to, config.router_address);
+        assert!(plan.transactions[1].simulated);
+        let total_gas = 2 * fake::TX_GAS + 2 * fake::DEPLOY_GAS_PER_PROXY;
+        assert_eq!(plan.total_gas, total_gas);
+        assert_eq!(
+            plan.total_fee_wei,
+            Some((total_gas as u128 * fake::GAS_PRICE).to_string())
+        );
+
+        // Nothing was sent or written
+        assert_eq!(chain.deploy_calls(), 0);
+        assert!(chain.transfers().is_empty());
+        let row = db::get_deposit_by_address(&pool, &format_address(&pending.0 .0))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(row.status, "pending");
+        let scan = db::get_forwarded(&pool, &format_address(&forwarding.0 .0))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(scan.forwarded_wei, "0");
+
+        let lease = RoutingLease::new(Duration::from_secs(60));
+        let retry = RetryPolicy::from_config(&config);
+        let run = routing::route_exclusive(&pool, &chain, &lease, retry, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            (run.checked, run.funded, run.deployed, run.routed),
+            (plan.checked, plan.funded, plan.deployed, plan.routed)
+        );
+        assert_eq!(run.stranded, plan.stranded.len());
+        assert_eq!(
+            chain.treasury_balance().to_string(),
+            plan.value_to_treasury_wei
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stuck_balance_and_credit_are_both_shown() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        let config = crate::config::test_config();
+
+        // Routed before, stuck ETH from before its deployment, forwarded more since
+        let routed = seed_forwarding(&pool, &chain, 0, 40).await;
+        chain.fund(routed, 9);
+        db::update_deposit_status(&pool, &format_address(&routed.0 .0), "routed")
+            .await
+            .unwrap();
+        // Routed with nothing forwarded since; a run leaves it alone
+        let done = seed_forwarding(&pool, &chain, 1, 0).await;
+        chain.fund(done, 3);
+        db::update_deposit_status(&pool, &format_address(&done.0 .0), "routed")
+            .await
+            .unwrap();
+
+        let plan = plan_routing(&pool, &chain, &config).await;
+        assert!(plan.errors.is_empty(), "{:?}", plan.errors);
+        assert_eq!((plan.checked, plan.funded, plan.routed), (1, 0, 1));
+        let deposit = &plan.deposits[0];
+        assert_eq!(deposit.deposit_address, format_address(&routed.0 .0));
+        assert_eq!(deposit.steps, ["transfer"]);
+        assert_eq!(deposit.balance_wei, "9");
+        assert_eq!(deposit.forwarded_wei, "40");
+        assert_eq!(plan.stranded.len(), 1);
+        assert_eq!(plan.stranded[0].balance_wei, "9");
+        assert_eq!(plan.value_to_treasury_wei, "40");
+
+        let lease = RoutingLease::new(Duration::from_secs(60));
+        let retry = RetryPolicy::from_config(&config);
+        let run = routing::route_exclusive(&pool, &chain, &lease, retry, None)
+            .await
+            .unwrap();
+        assert_eq!((run.checked, run.routed), (plan.checked, plan.routed));
+        assert_eq!(run.stranded, plan.stranded.len());
+        assert_eq!(chain.treasury_balance(), U256::from(40));
+    }
+
+    #[tokio::test]
+    async fn test_plan_reports_exclusions() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        let config = crate::config::test_config();
+
+        let dead = seed(&pool, 0, "funded").await;
+        db::record_deposit_failure(
+            &pool,
+            &format_address(&dead.0 .0),
+            "dead_letter",
+            "nonce too low",
+            None,
+        )
+        .await
+        .unwrap();
+        let waiting = seed(&pool, 1, "funded").await;
+        db::record_deposit_failure(
+            &pool,
+            &format_address(&waiting.0 .0),
+            "funded",
+            "timeout",
+            Some(600),
+        )
+        .await
+        .unwrap();
+        let claimed = seed(&pool, 2, "funded").await;
+        db::claim_deposits(&pool, &["funded"], "other-run", 60)
+            .await
+            .unwrap();
+        let unreadable = seed(&pool, 3, "pending").await;
+        chain.fail_balance(unreadable);
+
+        let plan = plan_routing(&pool, &chain, &config).await;
+        assert_eq!(plan.checked, 1);
+        assert!(plan.transactions.is_empty());
+        assert!(
+            reason_for(&plan, dead).starts_with("Dead-lettered after 1 attempts (nonce too low)")
+        );
+        assert!(reason_for(&plan, waiting).starts_with("Waiting to retry at"));
+        assert!(reason_for(&plan, claimed).starts_with("Claimed by routing run other-run"));
+        assert!(reason_for(&plan, unreadable).starts_with("Balance check failed"));
+    }
+
+    #[tokio::test]
+    async fn test_failing_deploy_leaves_router_transfers_planned() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        seed(&pool, 0, "funded").await;
+        seed_forwarding(&pool, &chain, 1, 30).await;
+        chain.fail_deploy("execution reverted");
+
+        let plan = plan_routing(&pool, &chain, &crate::config::test_config()).await;
+        assert_eq!((plan.deployed, plan.routed), (0, 1));
+        assert_eq!(plan.transactions.len(), 2);
+        assert!(!plan.transactions[0].simulated);
+        assert!(plan.transactions[1].simulated);
+        assert_eq!(plan.value_to_treasury_wei, "30");
+        assert_eq!(plan.errors.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reverting_transfer_is_not_counted() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        seed_forwarding(&pool, &chain, 0, 30).await;
+        chain.fail_transfer(fake::TREASURY);
+
+        let plan = plan_routing(&pool, &chain, &crate::config::test_config()).await;
+        assert_eq!(plan.routed, 0);
+        assert!(!plan.transactions[0].simulated);
+        assert!(plan.transactions[0].error.is_some());
+        assert_eq!(plan.value_to_treasury_wei, "0");
+        assert_eq!(plan.errors.len(), 1, "{:?}", plan.errors);
+    }
+
+    #[tokio::test]
+    async fn test_plan_splits_transfers_per_rule() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+        db::insert_treasury(&pool, "ops", &format!("{:#x}", Address::repeat_byte(0x0a)))
+            .await
+            .unwrap();
+        db::insert_treasury(&pool, "fees", &format!("{:#x}", Address::repeat_byte(0x0f)))
+            .await
+            .unwrap();
+        let splits = r#"[{"treasury":"ops","share_bps":9500},{"treasury":"fees","share_bps":500}]"#;
+        db::insert_routing_rule(&pool, None, None, None, splits)
+            .await
+            .unwrap();
+        seed_forwarding(&pool, &chain, 0, 1_000).await;
+
+        let plan = plan_routing(&pool, &chain, &crate::config::test_config()).await;
+        assert_eq!(plan.routed, 1);
+        let transfers: Vec<_> = plan
+            .transactions
+            .iter()
+            .map(|tx| {
+                (
+                    tx.value_wei.as_deref().unwrap(),
+                    tx.treasury.clone().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            transfers,
+            [
+                ("950", format!("{:#x}", Address::repeat_byte(0x0a))),
+                ("50", format!("{:#x}", Address::repeat_byte(0x0f))),
+            ]
+        );
+        assert_eq!(plan.value_to_treasury_wei, "1000");
+    }
+}
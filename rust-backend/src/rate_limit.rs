@@ -0,0 +1,623 @@
+//! Rate limiting for deposit creation
+//!
+//! Every `POST /deposit` writes permanent rows, so callers are throttled by
+//! token buckets per client IP, per API key and per `user` address, and a
+//! user may only hold a limited number of recent `pending` deposits at once.
+//! Pending deposits older than the window no longer count, so addresses
+//! that were never funded don't lock a user out.
+//!
+//! Buckets live in memory by default. With `RATE_LIMIT_STORE=database` they
+//! are kept in the `rate_limits` table instead, so replicas sharing the
+//! database also share the limits.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::{
+    auth::ApiKey,
+    db::{self, PendingCap},
+    error::AppError,
+    metrics, AppState,
+};
+
+/// In-memory buckets kept before idle ones are dropped
+const MAX_MEMORY_BUCKETS: usize = 10_000;
+
+/// Pending deposits only close when funds arrive, so there is no exact time
+/// to retry after; this is a polite hint
+const PENDING_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Bucket size and the period over which it refills completely
+///
+/// Written as `<requests>/<period>`, e.g. `10/min`; `off` disables the limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Quota {
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl Quota {
+    pub const OFF: Quota = Quota {
+        capacity: 0,
+        period: Duration::ZERO,
+    };
+
+    pub const fn per_minute(capacity: u32) -> Self {
+        Self {
+            capacity,
+            period: Duration::from_secs(60),
+        }
+    }
+
+    pub fn is_off(&self) -> bool {
+        self.capacity == 0
+    }
+
+    /// Tokens added per second
+    fn rate(&self) -> f64 {
+        f64::from(self.capacity) / self.period.as_secs_f64()
+    }
+}
+
+impl FromStr for Quota {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if matches!(s, "off" | "0") {
+            return Ok(Quota::OFF);
+        }
+        let invalid = || format!("'{}' is not <requests>/<s|min|hour|day> or off", s);
+        let (capacity, period) = s.split_once('/').ok_or_else(invalid)?;
+        let capacity: u32 = capacity.trim().parse().map_err(|_| invalid())?;
+        let period = match period.trim() {
+            "s" | "sec" | "second" => 1,
+            "m" | "min" | "minute" => 60,
+            "h" | "hour" => 3_600,
+            "d" | "day" => 86_400,
+            _ => return Err(invalid()),
+        };
+        if capacity == 0 {
+            return Ok(Quota::OFF);
+        }
+        Ok(Quota {
+            capacity,
+            period: Duration::from_secs(period),
+        })
+    }
+}
+
+impl TryFrom<String> for Quota {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Where bucket state is kept
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitStore {
+    /// Per process; fine for a single replica
+    #[default]
+    Memory,
+    /// The `rate_limits` table, shared by every replica using the database
+    Database,
+}
+
+impl FromStr for RateLimitStore {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(RateLimitStore::Memory),
+            "database" => Ok(RateLimitStore::Database),
+            other => Err(format!("unknown rate limit store '{}'", other)),
+        }
+    }
+}
+
+/// Tokens left in a bucket as of `updated` (Unix seconds)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bucket {
+    pub tokens: f64,
+    pub updated: f64,
+}
+
+impl Bucket {
+    /// Tokens available at `now`
+    fn refilled(&self, quota: Quota, now: f64) -> f64 {
+        let elapsed = (now - self.updated).max(0.0);
+        (self.tokens + elapsed * quota.rate()).min(f64::from(quota.capacity))
+    }
+
+    /// Take a token, or return how long until one is available
+    fn take(&mut self, quota: Quota, now: f64) -> Result<(), Duration> {
+        let tokens = self.refilled(quota, now);
+        self.updated = now;
+        if tokens >= 1.0 {
+            self.tokens = tokens - 1.0;
+            Ok(())
+        } else {
+            self.tokens = tokens;
+            Err(wait_for_token(tokens, quota))
+        }
+    }
+}
+
+fn wait_for_token(tokens: f64, quota: Quota) -> Duration {
+    Duration::from_secs_f64(((1.0 - tokens) / quota.rate()).max(0.0))
+}
+
+/// Token buckets keyed by what is being limited, e.g. `ip:203.0.113.7`
+///
+/// In memory each bucket keeps the quota it was last taken from, since
+/// IP, key and user buckets refill at different rates.
+pub enum RateLimiter {
+    Memory(Mutex<HashMap<String, (Bucket, Quota)>>),
+    Database(SqlitePool),
+}
+
+impl RateLimiter {
+    pub fn new(store: RateLimitStore, pool: &SqlitePool) -> Self {
+        match store {
+            RateLimitStore::Memory => RateLimiter::Memory(Mutex::default()),
+            RateLimitStore::Database => RateLimiter::Database(pool.clone()),
+        }
+    }
+
+    /// Take a token from the `key` bucket, or fail with how long to wait
+    pub async fn take(&self, key: &str, quota: Quota) -> Result<Result<(), Duration>, AppError> {
+        self.take_at(key, quota, unix_now()).await
+    }
+
+    async fn take_at(
+        &self,
+        key: &str,
+        quota: Quota,
+        now: f64,
+    ) -> Result<Result<(), Duration>, AppError> {
+        if quota.is_off() {
+            return Ok(Ok(()));
+        }
+        match self {
+            RateLimiter::Memory(buckets) => {
+                let mut buckets = buckets.lock().expect("rate limit buckets poisoned");
+                if buckets.len() >= MAX_MEMORY_BUCKETS && !buckets.contains_key(key) {
+                    evict(&mut buckets, now);
+                }
+                let (bucket, bucket_quota) = buckets.entry(key.to_string()).or_insert((
+                    Bucket {
+                        tokens: f64::from(quota.capacity),
+                        updated: now,
+                    },
+                    quota,
+                ));
+                *bucket_quota = quota;
+                Ok(bucket.take(quota, now))
+            }
+            RateLimiter::Database(pool) => {
+                if db::take_rate_limit_token(pool, key, quota.capacity, quota.rate(), now).await? {
+                    return Ok(Ok(()));
+                }
+                let tokens = db::get_rate_limit_bucket(pool, key)
+                    .await?
+                    .map_or(0.0, |bucket| bucket.refilled(quota, now));
+                Ok(Err(wait_for_token(tokens, quota)))
+            }
+        }
+    }
+}
+
+/// Make room in a full in-memory store
+///
+/// Buckets that have refilled at their own quota's rate hold no state worth
+/// keeping. If callers spent tokens under more keys than fit, e.g. made-up
+/// `user` addresses, the least recently used half goes as well, so the map
+/// stays bounded.
+fn evict(buckets: &mut HashMap<String, (Bucket, Quota)>, now: f64) {
+    buckets.retain(|_, (bucket, quota)| bucket.refilled(*quota, now) < f64::from(quota.capacity));
+    if buckets.len() >= MAX_MEMORY_BUCKETS {
+        let mut updated: Vec<f64> = buckets.values().map(|(bucket, _)| bucket.updated).collect();
+        updated.sort_by(f64::total_cmp);
+        let cutoff = updated[updated.len() / 2];
+        buckets.retain(|_, (bucket, _)| bucket.updated > cutoff);
+    }
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Address the request came from
+///
+/// With `TRUST_FORWARDED_FOR` the last `X-Forwarded-For` entry is used, which
+/// is the one added by the proxy in front of this server; otherwise the
+/// socket's peer address. `None` when neither is known (e.g. in tests).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let forwarded = state
+            .config
+            .trust_forwarded_for
+            .then(|| forwarded_for(&parts.headers))
+            .flatten();
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        Ok(ClientIp(forwarded.or(peer)))
+    }
+}
+
+fn forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .next_back()
+        .and_then(|ip| ip.trim().parse().ok())
+}
+
+/// Check the per-IP and per-key limits of a deposit request
+pub async fn check_caller(
+    state: &AppState,
+    ip: ClientIp,
+    api_key: Option<&ApiKey>,
+) -> Result<(), AppError> {
+    let config = &state.config;
+    if let ClientIp(Some(ip)) = ip {
+        enforce(state, "ip", &ip.to_string(), config.rate_limit_per_ip).await?;
+    }
+    if let Some(key) = api_key {
+        enforce(state, "key", &key.id.to_string(), config.rate_limit_per_key).await?;
+    }
+    Ok(())
+}
+
+/// Check the per-user limit of a deposit request
+pub async fn check_user(state: &AppState, user: &str) -> Result<(), AppError> {
+    enforce(state, "user", user, state.config.rate_limit_per_user).await
+}
+
+/// Insert a new deposit for `user`, refused while they hold the maximum of
+/// recent pending deposits
+///
+/// Counting and inserting happen in one statement, so concurrent requests
+/// can't go over the cap together.
+pub async fn insert_deposit(
+    state: &AppState,
+    user: &str,
+    salt: &str,
+    deposit_address: &str,
+    nonce: u64,
+    tenant: Option<&str>,
+) -> Result<(), AppError> {
+    let config = &state.config;
+    if config.max_pending_deposits == 0 {
+        db::insert_deposit(&state.db, user, salt, deposit_address, nonce, tenant).await?;
+        return Ok(());
+    }
+    let cap = PendingCap {
+        max: config.max_pending_deposits,
+        window_secs: config.pending_deposit_window_secs,
+    };
+    let inserted =
+        db::insert_deposit_capped(&state.db, user, salt, deposit_address, nonce, tenant, cap)
+            .await?;
+    if inserted.is_none() {
+        metrics::rate_limited("pending");
+        return Err(AppError::RateLimited {
+            message: format!(
+                "{} already has {} recent pending deposits; fund or wait for those first",
+                user, cap.max
+            ),
+            retry_after: PENDING_RETRY_AFTER,
+        });
+    }
+    Ok(())
+}
+
+async fn enforce(
+    state: &AppState,
+    scope: &'static str,
+    id: &str,
+    quota: Quota,
+) -> Result<(), AppError> {
+    let key = format!("{}:{}", scope, id);
+    state
+        .limiter
+        .take(&key, quota)
+        .await?
+        .map_err(|retry_after| {
+            metrics::rate_limited(scope);
+            AppError::RateLimited {
+                message: format!("Too many deposit requests per {}", scope),
+                retry_after,
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chain::fake::FakeChain, test_state};
+    use axum::{body::Body, extract::Request, http::StatusCode};
+    use serde_json::json;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_parse_quota() {
+        assert_eq!("10/min".parse(), Ok(Quota::per_minute(10)));
+        assert_eq!(
+            "2/s".parse(),
+            Ok(Quota {
+                capacity: 2,
+                period: Duration::from_secs(1)
+            })
+        );
+        assert_eq!("off".parse(), Ok(Quota::OFF));
+        assert_eq!("0/hour".parse(), Ok(Quota::OFF));
+        assert!("10".parse::<Quota>().is_err());
+        assert!("10/fortnight".parse::<Quota>().is_err());
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let quota = Quota::per_minute(2);
+        let mut bucket = Bucket {
+            tokens: 2.0,
+            updated: 0.0,
+        };
+
+        assert!(bucket.take(quota, 0.0).is_ok());
+        assert!(bucket.take(quota, 0.0).is_ok());
+        assert_eq!(bucket.take(quota, 0.0), Err(Duration::from_secs(30)));
+        // Half a token after 15 s
+        assert_eq!(bucket.take(quota, 15.0), Err(Duration::from_secs(15)));
+        assert!(bucket.take(quota, 30.0).is_ok());
+        // Never more than the capacity
+        assert_eq!(bucket.refilled(quota, 10_000.0), 2.0);
+    }
+
+    async fn exhaust(limiter: &RateLimiter) {
+        let quota = Quota::per_minute(3);
+        for _ in 0..3 {
+            assert_eq!(limiter.take_at("ip:a", quota, 100.0).await.unwrap(), Ok(()));
+        }
+        let wait = limiter.take_at("ip:a", quota, 100.0).await.unwrap();
+        assert_eq!(wait, Err(Duration::from_secs(20)));
+        // Other keys have their own bucket
+        assert_eq!(limiter.take_at("ip:b", quota, 100.0).await.unwrap(), Ok(()));
+        assert_eq!(limiter.take_at("ip:a", quota, 120.0).await.unwrap(), Ok(()));
+        assert!(limiter
+            .take_at("ip:a", Quota::OFF, 120.0)
+            .await
+            .unwrap()
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_memory_limiter() {
+        let pool = db::test_pool().await;
+        exhaust(&RateLimiter::new(RateLimitStore::Memory, &pool)).await;
+    }
+
+    #[tokio::test]
+    async fn test_database_limiter() {
+        let pool = db::test_pool().await;
+        exhaust(&RateLimiter::new(RateLimitStore::Database, &pool)).await;
+    }
+
+    fn memory_buckets(limiter: &RateLimiter) -> usize {
+        match limiter {
+            RateLimiter::Memory(buckets) => buckets.lock().unwrap().len(),
+            RateLimiter::Database(_) => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_limiter_stays_bounded() {
+        let pool = db::test_pool().await;
+        let limiter = RateLimiter::new(RateLimitStore::Memory, &pool);
+        let quota = Quota::per_minute(5);
+
+        // Spent buckets under more keys than the store holds
+        for i in 0..MAX_MEMORY_BUCKETS + 10 {
+            let key = format!("user:{}", i);
+            let now = i as f64 / 1_000.0;
+            assert!(limiter.take_at(&key, quota, now).await.unwrap().is_ok());
+        }
+        let size = memory_buckets(&limiter);
+        assert!(size < MAX_MEMORY_BUCKETS, "{} buckets", size);
+        // The most recent callers keep their state
+        let last = format!("user:{}", MAX_MEMORY_BUCKETS + 9);
+        for _ in 0..4 {
+            assert!(limiter.take_at(&last, quota, 11.0).await.unwrap().is_ok());
+        }
+        assert!(limiter.take_at(&last, quota, 11.0).await.unwrap().is_err());
+
+        // Once the buckets have refilled they are all dropped
+        let limiter = RateLimiter::new(RateLimitStore::Memory, &pool);
+        for i in 0..MAX_MEMORY_BUCKETS {
+            let key = format!("user:{}", i);
+            assert!(limiter.take_at(&key, quota, 0.0).await.unwrap().is_ok());
+        }
+        assert_eq!(memory_buckets(&limiter), MAX_MEMORY_BUCKETS);
+        assert!(limiter
+            .take_at("user:new", quota, 60.0)
+            .await
+            .unwrap()
+            .is_ok());
+        assert_eq!(memory_buckets(&limiter), 1);
+    }
+
+    #[tokio::test]
+    async fn test_eviction_uses_each_buckets_quota() {
+        let pool = db::test_pool().await;
+        let limiter = RateLimiter::new(RateLimitStore::Memory, &pool);
+        let fast = Quota::per_minute(60);
+        let slow = Quota {
+            capacity: 2,
+            period: Duration::from_secs(86_400),
+        };
+
+        assert!(limiter.take_at("user:a", slow, 0.0).await.unwrap().is_ok());
+        assert!(limiter.take_at("user:a", slow, 0.0).await.unwrap().is_ok());
+        for i in 1..MAX_MEMORY_BUCKETS {
+            let key = format!("ip:{}", i);
+            assert!(limiter.take_at(&key, fast, 0.0).await.unwrap().is_ok());
+        }
+        assert_eq!(memory_buckets(&limiter), MAX_MEMORY_BUCKETS);
+
+        // A minute later the IP buckets have refilled but the user's hasn't
+        assert!(limiter.take_at("ip:new", fast, 60.0).await.unwrap().is_ok());
+        assert_eq!(memory_buckets(&limiter), 2);
+        assert!(limiter
+            .take_at("user:a", slow, 60.0)
+            .await
+            .unwrap()
+            .is_err());
+    }
+
+    #[test]
+    fn test_forwarded_for_uses_last_hop() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "10.0.0.1, 203.0.113.7".parse().unwrap());
+        assert_eq!(forwarded_for(&headers), "203.0.113.7".parse().ok());
+
+        headers.insert("x-forwarded-for", "garbage".parse().unwrap());
+        assert_eq!(forwarded_for(&headers), None);
+    }
+
+    fn deposit_request(user: &str, ip: &str) -> Request {
+        Request::post("/deposit")
+            .header("content-type", "application/json")
+            .header("x-forwarded-for", ip)
+            .body(Body::from(json!({ "user": user }).to_string()))
+            .unwrap()
+    }
+
+    async fn app_with(
+        configure: impl FnOnce(&mut crate::config::Config),
+    ) -> (axum::Router, SqlitePool) {
+        let mut state = test_state(FakeChain::default()).await;
+        let mut config = (*state.config).clone();
+        config.require_api_keys = false;
+        config.trust_forwarded_for = true;
+        configure(&mut config);
+        state.config = Arc::new(config);
+        let pool = state.db.clone();
+        (crate::app(state), pool)
+    }
+
+    #[tokio::test]
+    async fn test_per_ip_limit_returns_429_with_retry_after() {
+        let (app, _) = app_with(|config| {
+            config.rate_limit_per_ip = Quota::per_minute(2);
+        })
+        .await;
+        let user = format!("{:#x}", alloy::primitives::Address::repeat_byte(0x11));
+
+        for _ in 0..2 {
+            let response = app
+                .clone()
+                .oneshot(deposit_request(&user, "203.0.113.7"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = app
+            .clone()
+            .oneshot(deposit_request(&user, "203.0.113.7"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "30");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "RATE_LIMITED");
+
+        // Another client is unaffected
+        let response = app
+            .oneshot(deposit_request(&user, "198.51.100.1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_pending_deposit_cap() {
+        let (app, pool) = app_with(|config| {
+            config.max_pending_deposits = 2;
+        })
+        .await;
+        let user = format!("{:#x}", alloy::primitives::Address::repeat_byte(0x22));
+        let other = format!("{:#x}", alloy::primitives::Address::repeat_byte(0x33));
+
+        for _ in 0..2 {
+            let response = app
+                .clone()
+                .oneshot(deposit_request(&user, "203.0.113.7"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = app
+            .clone()
+            .oneshot(deposit_request(&user, "203.0.113.7"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key("retry-after"));
+
+        let response = app
+            .clone()
+            .oneshot(deposit_request(&other, "203.0.113.7"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Pending deposits past the window no longer count
+        sqlx::query("UPDATE deposits SET created_at = datetime('now', '-2 days')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let response = app
+            .oneshot(deposit_request(&user, "203.0.113.7"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
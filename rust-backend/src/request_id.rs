@@ -0,0 +1,144 @@
+//! Request IDs
+//!
+//! Every request gets an ID, taken from an incoming `X-Request-Id` header or
+//! generated. The ID is echoed in the response header and in error bodies,
+//! and recorded on the request span so every log line and exported span of
+//! the request can be correlated.
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::Span;
+
+use crate::telemetry;
+
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client-supplied ID that is accepted
+const MAX_LEN: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+/// ID of a request, stored in the request extensions
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Use the client's ID if it is a reasonable token, otherwise generate one
+    fn from_header(value: Option<&HeaderValue>) -> Self {
+        match value.and_then(|v| v.to_str().ok()) {
+            Some(id) if is_valid(id) => Self(id.to_string()),
+            _ => Self(uuid::Uuid::new_v4().to_string()),
+        }
+    }
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+/// ID of the request being handled, if called from within one
+pub fn current() -> Option<String> {
+    CURRENT.try_with(|id| id.0.clone()).ok()
+}
+
+/// Middleware assigning the request ID
+///
+/// Must wrap the `TraceLayer` so [`make_span`] can read the ID.
+pub async fn propagate(mut request: Request, next: Next) -> Response {
+    let id = RequestId::from_header(request.headers().get(&X_REQUEST_ID));
+    request.extensions_mut().insert(id.clone());
+
+    let header = HeaderValue::from_str(&id.0).expect("request IDs are visible ASCII");
+    let mut response = CURRENT.scope(id, next.run(request)).await;
+    response.headers_mut().insert(X_REQUEST_ID.clone(), header);
+    response
+}
+
+/// Span for a request, carrying its ID
+pub fn make_span(request: &Request) -> Span {
+    let id = request
+        .extensions()
+        .get::<RequestId>()
+        .map(|id| id.0.as_str())
+        .unwrap_or_default();
+
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %telemetry::redact_path(request.uri().path()),
+        version = ?request.version(),
+        request_id = %id,
+        api_key = tracing::field::Empty,
+        wallet = tracing::field::Empty,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AppError;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn router() -> Router {
+        Router::new()
+            .route(
+                "/missing",
+                get(|| async { Err::<(), _>(AppError::NotFound("nothing here".to_string())) }),
+            )
+            .layer(tower_http::trace::TraceLayer::new_for_http().make_span_with(make_span))
+            .layer(middleware::from_fn(propagate))
+    }
+
+    #[tokio::test]
+    async fn test_id_is_echoed_in_header_and_error_body() {
+        let request = Request::get("/missing")
+            .header(&X_REQUEST_ID, "client-42")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = router().oneshot(request).await.unwrap();
+
+        assert_eq!(response.headers()[&X_REQUEST_ID], "client-42");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["request_id"], "client-42");
+    }
+
+    #[test]
+    fn test_client_id_is_kept() {
+        let value = HeaderValue::from_static("abc-123");
+        assert_eq!(RequestId::from_header(Some(&value)).0, "abc-123");
+    }
+
+    #[test]
+    fn test_invalid_client_id_is_replaced() {
+        let value = HeaderValue::from_static("has spaces\tand tabs");
+        let long = HeaderValue::from_str(&"a".repeat(MAX_LEN + 1)).unwrap();
+
+        for value in [Some(&value), Some(&long), None] {
+            let id = RequestId::from_header(value);
+            assert!(uuid::Uuid::parse_str(&id.0).is_ok(), "{}", id.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_current_is_scoped() {
+        assert_eq!(current(), None);
+        let id = RequestId("req-1".to_string());
+        let inside = CURRENT.scope(id, async { current() }).await;
+        assert_eq!(inside.as_deref(), Some("req-1"));
+    }
+}
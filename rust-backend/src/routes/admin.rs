@@ -0,0 +1,93 @@
+//! API key management endpoints (admin role)
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::{
+    auth::{self, ApiKey},
+    db::{self, ApiKeyRow},
+    error::AppError,
+    models::{ApiKeyInfo, CreateApiKeyRequest, CreateApiKeyResponse, ListApiKeysResponse},
+    AppState,
+};
+
+/// GET /admin/keys
+///
+/// List API keys with their usage; secrets are never returned
+pub async fn list_keys(
+    State(state): State<AppState>,
+) -> Result<Json<ListApiKeysResponse>, AppError> {
+    let keys: Vec<ApiKeyInfo> = db::list_api_keys(&state.db)
+        .await?
+        .into_iter()
+        .map(row_to_info)
+        .collect();
+
+    Ok(Json(ListApiKeysResponse {
+        total: keys.len(),
+        keys,
+    }))
+}
+
+/// POST /admin/keys
+///
+/// Create a key; the response is the only time the key is shown
+pub async fn create_key(
+    State(state): State<AppState>,
+    caller: Option<ApiKey>,
+    Json(req): Json<CreateApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreateApiKeyResponse>), AppError> {
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("name must not be empty".to_string()));
+    }
+    if req.roles.is_empty() {
+        return Err(AppError::BadRequest(
+            "at least one role is required".to_string(),
+        ));
+    }
+
+    let created_by = caller.as_ref().map(|key| key.name.as_str());
+    let (id, key) = auth::create_key(&state.db, name, &req.roles, created_by).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateApiKeyResponse {
+            id,
+            name: name.to_string(),
+            roles: req.roles,
+            key,
+        }),
+    ))
+}
+
+/// DELETE /admin/keys/:id
+///
+/// Revoke a key; it stays listed with its usage
+pub async fn revoke_key(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    if !db::revoke_api_key(&state.db, id).await? {
+        return Err(AppError::NotFound(format!("Active API key {}", id)));
+    }
+    tracing::info!(key_id = id, "Revoked API key");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn row_to_info(row: ApiKeyRow) -> ApiKeyInfo {
+    ApiKeyInfo {
+        id: row.id,
+        name: row.name,
+        key_prefix: row.key_prefix,
+        roles: auth::parse_roles(&row.roles),
+        created_by: row.created_by,
+        created_at: row.created_at,
+        last_used_at: row.last_used_at,
+        request_count: row.request_count,
+        revoked_at: row.revoked_at,
+    }
+}
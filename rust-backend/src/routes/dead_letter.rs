@@ -0,0 +1,115 @@
+//! Dead-letter endpoints (admin role)
+//!
+//! Deposits whose deployment failed terminally (`failed`) or ran out of
+//! retries (`dead_letter`) are not picked up by routing runs until an
+//! operator requeues them.
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+
+use crate::{
+    db,
+    error::AppError,
+    models::{DepositInfo, ListDepositsResponse},
+    routes::deposit::row_to_info,
+    AppState,
+};
+
+/// GET /admin/dead-letter
+///
+/// List dead-lettered and failed deposits with their attempts and last error
+pub async fn list_dead_letter(
+    State(state): State<AppState>,
+) -> Result<Json<ListDepositsResponse>, AppError> {
+    let rows = db::get_deposits_by_statuses(&state.db, &["dead_letter", "failed"]).await?;
+    let total = rows.len();
+
+    let deposits = rows.into_iter().map(row_to_info).collect();
+
+    Ok(Json(ListDepositsResponse { deposits, total }))
+}
+
+/// POST /admin/deposits/:address/requeue
+///
+/// Reset a dead-lettered or failed deposit's attempts and queue it for the
+/// next routing run
+pub async fn requeue(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+) -> Result<Json<DepositInfo>, AppError> {
+    let address = address.to_lowercase();
+    if !db::requeue_deposit(&state.db, &address).await? {
+        return Err(AppError::NotFound(format!(
+            "Dead-lettered or failed deposit {}",
+            address
+        )));
+    }
+    tracing::info!(deposit = %address, "Requeued deposit");
+
+    let row = db::get_deposit_by_address(&state.db, &address)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Deposit {} not found", address)))?;
+    Ok(Json(row_to_info(row)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chain::fake::FakeChain, test_state};
+    use axum::{body::Body, extract::Request, http::StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_dead_lettered_deposit_is_requeued() {
+        let state = test_state(FakeChain::default()).await;
+        let mut config = (*state.config).clone();
+        config.require_api_keys = false;
+        let state = AppState {
+            config: std::sync::Arc::new(config),
+            ..state
+        };
+        let app = crate::app(state.clone());
+
+        db::insert_deposit(&state.db, "0xuser", "0x00", "0xaa", 0, None)
+            .await
+            .unwrap();
+        db::record_deposit_failure(&state.db, "0xaa", "dead_letter", "Deploy failed", None)
+            .await
+            .unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/admin/dead-letter")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["total"], 1);
+        assert_eq!(body["deposits"][0]["attempts"], 1);
+
+        let requeue = || {
+            Request::post("/admin/deposits/0xAA/requeue")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = app.clone().oneshot(requeue()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let row = db::get_deposit_by_address(&state.db, "0xaa")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((row.status.as_str(), row.attempts), ("funded", 0));
+
+        // Already back in line
+        let response = app.oneshot(requeue()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
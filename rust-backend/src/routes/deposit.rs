@@ -1,15 +1,31 @@
 //! Deposit address generation endpoint
+//!
+//! Wallet sessions (SIWE) may only act for their own address; API key
+//! callers may act for any user.
 
+use alloy::primitives::{Address, U256};
 use axum::{
+    body::Bytes,
     extract::{Path, State},
     Json,
 };
 
 use crate::{
+    auth::{ApiKey, Session},
     create2::{compute_deposit_address, format_address, format_bytes32, parse_address},
-    db::{self, DepositRow},
+    db::{self, DepositRow, InvoiceRow},
     error::AppError,
-    models::{CreateDepositRequest, CreateDepositResponse, DepositInfo, ListDepositsResponse},
+    invoice::{self, PaymentStatus},
+    metrics,
+    models::{
+        AllocationInfo, CreateDepositRequest, CreateDepositResponse, DepositInfo, InvoiceInfo,
+        ListDepositsResponse, RouteDepositRequest, RouteDepositResponse,
+    },
+    rate_limit::{self, ClientIp},
+    routing::{self, RetryPolicy},
+    telemetry,
+    treasury::{self, NATIVE_ASSET},
+    webhooks::{self, WebhookEvent},
     AppState,
 };
 
@@ -18,32 +34,74 @@ use crate::{
 /// Generate next deterministic deposit address for a user
 pub async fn create_deposit(
     State(state): State<AppState>,
+    client_ip: ClientIp,
+    api_key: Option<ApiKey>,
+    session: Option<Session>,
     Json(req): Json<CreateDepositRequest>,
 ) -> Result<Json<CreateDepositResponse>, AppError> {
+    // Throttle the caller before anything else, malformed requests included
+    rate_limit::check_caller(&state, client_ip, api_key.as_ref()).await?;
+
     // Validate and parse user address
     let user_address_str = req.user.to_lowercase();
     let user_bytes =
         parse_address(&user_address_str).map_err(|_| AppError::InvalidAddress(req.user.clone()))?;
 
-    // Get deployer and init code hash
+    if let Some(session) = &session {
+        if session.address != user_address_str {
+            return Err(AppError::Forbidden(
+                "Signed-in wallets can only create deposits for themselves".to_string(),
+            ));
+        }
+    }
+
+    rate_limit::check_user(&state, &user_address_str).await?;
+
+    // Check the invoice terms before a nonce is used up
+    let metadata = req.metadata.as_ref().map(|m| m.to_string());
+    let mut invoice_terms = invoice_terms(&req, metadata.as_deref())?;
+
+    // Get deployer, init code hash and the signer that will deploy the proxy
     let deployer = state.config.deployer_bytes()?;
     let init_code_hash = state.config.init_code_hash_bytes()?;
+    let signer = state.chain.signer_address().0 .0;
 
     // Get next nonce for this user
     let nonce = db::get_and_increment_nonce(&state.db, &user_address_str).await?;
 
     // Compute deposit address
     let (deposit_bytes, salt_bytes) =
-        compute_deposit_address(&deployer, &init_code_hash, &user_bytes, nonce);
+        compute_deposit_address(&deployer, &init_code_hash, &signer, &user_bytes, nonce);
 
     let deposit_address = format_address(&deposit_bytes);
     let salt = format_bytes32(&salt_bytes);
 
-    // Store in database
-    db::insert_deposit(&state.db, &user_address_str, &salt, &deposit_address, nonce).await?;
+    // Store in database unless the user is at the pending deposit cap; the
+    // creating API key is the deposit's tenant for routing rules
+    let tenant = api_key.as_ref().map(|key| key.name.as_str());
+    rate_limit::insert_deposit(
+        &state,
+        &user_address_str,
+        &salt,
+        &deposit_address,
+        nonce,
+        tenant,
+    )
+    .await?;
+    let invoice = match &mut invoice_terms {
+        Some(terms) => {
+            // Where looking for the payment starts; without it the search
+            // starts from the first block
+            terms.created_block = state.chain.block_number().await.ok();
+            Some(db::insert_invoice(&state.db, &deposit_address, terms).await?)
+        }
+        None => None,
+    };
+    metrics::deposit_created();
+    webhooks::notify(&state.db, &deposit_address, WebhookEvent::Created).await;
 
     tracing::info!(
-        user = %user_address_str,
+        user = %telemetry::redact_address(&user_address_str),
         nonce = nonce,
         deposit = %deposit_address,
         "Created new deposit address"
@@ -53,17 +111,99 @@ pub async fn create_deposit(
         deposit_address,
         salt,
         nonce,
+        deployer_address: format_address(&deployer),
+        init_code_hash: format_bytes32(&init_code_hash),
+        signer_address: format_address(&signer),
         note: "Send Sepolia ETH to this address. Funds will be routed to treasury.".to_string(),
+        invoice: invoice.map(invoice_to_info),
+    }))
+}
+
+/// Invoice terms of a deposit request, if it sets any
+fn invoice_terms<'a>(
+    req: &'a CreateDepositRequest,
+    metadata: Option<&'a str>,
+) -> Result<Option<db::NewInvoice<'a>>, AppError> {
+    if !req.has_invoice_fields() {
+        return Ok(None);
+    }
+    let Some(expected) = req.expected_amount_wei.as_deref() else {
+        return Err(AppError::BadRequest(
+            "invoice fields require expected_amount_wei".to_string(),
+        ));
+    };
+    match expected.parse::<U256>() {
+        Ok(amount) if amount > U256::ZERO => {}
+        _ => {
+            return Err(AppError::BadRequest(
+                "expected_amount_wei must be a positive integer in wei".to_string(),
+            ))
+        }
+    }
+    let asset = match req.asset.as_deref() {
+        None => NATIVE_ASSET,
+        Some(asset) if asset.eq_ignore_ascii_case(NATIVE_ASSET) => NATIVE_ASSET,
+        Some(asset) => {
+            return Err(AppError::BadRequest(format!(
+                "Unsupported asset {}; only ETH is routed",
+                asset
+            )))
+        }
+    };
+    if req.expires_in_secs == Some(0) {
+        return Err(AppError::BadRequest(
+            "expires_in_secs must be positive".to_string(),
+        ));
+    }
+    if req
+        .order_ref
+        .as_ref()
+        .is_some_and(|r| r.chars().count() > invoice::MAX_ORDER_REF_CHARS)
+    {
+        return Err(AppError::BadRequest(format!(
+            "order_ref is longer than {} characters",
+            invoice::MAX_ORDER_REF_CHARS
+        )));
+    }
+    if req
+        .description
+        .as_ref()
+        .is_some_and(|d| d.chars().count() > invoice::MAX_DESCRIPTION_CHARS)
+    {
+        return Err(AppError::BadRequest(format!(
+            "description is longer than {} characters",
+            invoice::MAX_DESCRIPTION_CHARS
+        )));
+    }
+    if metadata.is_some_and(|m| m.len() > invoice::MAX_METADATA_BYTES) {
+        return Err(AppError::BadRequest(format!(
+            "metadata is larger than {} bytes",
+            invoice::MAX_METADATA_BYTES
+        )));
+    }
+
+    Ok(Some(db::NewInvoice {
+        expected_amount_wei: expected,
+        asset,
+        expires_in_secs: req.expires_in_secs,
+        order_ref: req.order_ref.as_deref(),
+        metadata,
+        description: req.description.as_deref(),
+        created_block: None,
     }))
 }
 
 /// GET /deposits
 ///
-/// List all deposit addresses
+/// List all deposit addresses, or the signed-in wallet's
 pub async fn list_deposits(
     State(state): State<AppState>,
+    session: Option<Session>,
 ) -> Result<Json<ListDepositsResponse>, AppError> {
-    let rows = db::get_all_deposits(&state.db).await?;
+    let rows = match &session {
+        Some(session) => db::get_deposits_by_user(&state.db, &session.address).await?,
+        None => db::get_all_deposits(&state.db).await?,
+    };
     let total = rows.len();
 
     let deposits = rows.into_iter().map(row_to_info).collect();
@@ -73,21 +213,102 @@ pub async fn list_deposits(
 
 /// GET /deposits/:address
 ///
-/// Get a specific deposit by address
+/// Get a specific deposit by address, with its payment assessment if it is
+/// an invoice and where its funds went once routed
 pub async fn get_deposit(
     State(state): State<AppState>,
+    session: Option<Session>,
     Path(address): Path<String>,
 ) -> Result<Json<DepositInfo>, AppError> {
     let address = address.to_lowercase();
 
+    // Other wallets' deposits look the same as missing ones
     let row = db::get_deposit_by_address(&state.db, &address)
         .await?
+        .filter(|row| {
+            session
+                .as_ref()
+                .is_none_or(|s| s.address == row.user_address)
+        })
         .ok_or_else(|| AppError::NotFound(format!("Deposit {} not found", address)))?;
 
-    Ok(Json(row_to_info(row)))
+    let allocations = db::get_allocations(&state.db, &address).await?;
+    let invoice = db::get_invoice(&state.db, &address).await?;
+    let mut info = row_to_info(row);
+    info.invoice = invoice.map(invoice_to_info);
+    info.allocations = allocations
+        .into_iter()
+        .map(|a| AllocationInfo {
+            treasury_address: a.treasury_address,
+            share_bps: a.share_bps as u32,
+            amount_wei: a.amount_wei,
+            tx_hash: a.tx_hash,
+            created_at: a.created_at,
+        })
+        .collect();
+
+    Ok(Json(info))
 }
 
-fn row_to_info(row: DepositRow) -> DepositInfo {
+/// POST /deposits/:address/route
+///
+/// Route one deposit now instead of waiting for the next routing run; see
+/// [`routing::route_single`]. Funds follow the routing rules unless the body
+/// names a treasury to send everything to, which must be on the allowlist or
+/// an active treasury in the registry. Returns 409 while a routing run is
+/// active or if the deposit's status doesn't allow routing.
+pub async fn route_deposit(
+    State(state): State<AppState>,
+    caller: Option<ApiKey>,
+    Path(address): Path<String>,
+    body: Bytes,
+) -> Result<Json<RouteDepositResponse>, AppError> {
+    let req: RouteDepositRequest = if body.is_empty() {
+        RouteDepositRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|e| AppError::BadRequest(e.to_string()))?
+    };
+
+    let treasury = match req.treasury {
+        Some(treasury) => {
+            let bytes = parse_address(&treasury.to_lowercase())
+                .map_err(|_| AppError::InvalidAddress(treasury.clone()))?;
+            let allowed = state.config.is_allowed_treasury(&bytes)
+                || treasury::is_registered(&state.db, Address::from(bytes)).await?;
+            if !allowed {
+                return Err(AppError::Forbidden(format!(
+                    "Treasury {} is not on the treasury allowlist",
+                    treasury
+                )));
+            }
+            Some(Address::from(bytes))
+        }
+        None => None,
+    };
+
+    let address = address.to_lowercase();
+    tracing::info!(
+        deposit = %address,
+        treasury = ?treasury,
+        requested_by = caller.as_ref().map(|key| key.name.as_str()),
+        "Routing single deposit"
+    );
+
+    let response = routing::route_single(
+        &state.db,
+        state.chain.as_ref(),
+        &state.routing_lease,
+        RetryPolicy::from_config(&state.config),
+        &address,
+        treasury,
+    )
+    .await?;
+
+    Ok(Json(response))
+}
+
+/// Convert a database row to its API representation
+pub fn row_to_info(row: DepositRow) -> DepositInfo {
     DepositInfo {
         id: row.id,
         user_address: row.user_address,
@@ -96,5 +317,266 @@ fn row_to_info(row: DepositRow) -> DepositInfo {
         nonce: row.nonce as u64,
         status: row.status,
         created_at: row.created_at,
+        attempts: row.attempts as u32,
+        last_error: row.last_error,
+        next_retry_at: row.next_retry_at,
+        tenant: row.tenant,
+        allocations: vec![],
+        invoice: None,
+    }
+}
+
+/// Invoice terms with the payment classified as of now
+fn invoice_to_info(row: InvoiceRow) -> InvoiceInfo {
+    let payment_status = invoice::classify(&row);
+    let expected: U256 = row.expected_amount_wei.parse().unwrap_or_default();
+    let received: U256 = row.received_wei.parse().unwrap_or_default();
+    let (shortfall_wei, excess_wei) = match payment_status {
+        PaymentStatus::Underpaid => (Some((expected - received).to_string()), None),
+        PaymentStatus::Overpaid => (None, Some((received - expected).to_string())),
+        _ => (None, None),
+    };
+
+    InvoiceInfo {
+        expected_amount_wei: row.expected_amount_wei,
+        asset: row.asset,
+        expires_at: row.expires_at,
+        order_ref: row.order_ref,
+        metadata: row
+            .metadata
+            .and_then(|metadata| serde_json::from_str(&metadata).ok()),
+        description: row.description,
+        payment_status,
+        received_wei: row.received_wei,
+        paid_at: row.paid_at,
+        shortfall_wei,
+        excess_wei,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chain::fake::FakeChain, test_state};
+    use axum::{
+        body::Body,
+        extract::Request,
+        http::{header::CONTENT_TYPE, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    const ALLOWED: &str = "0x7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f";
+
+    async fn route(state: &AppState, address: &str, body: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::post(format!("/deposits/{}/route", address))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = crate::app(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_route_deposit_only_to_allowlisted_treasuries() {
+        let chain = FakeChain::new();
+        let deposit = Address::repeat_byte(0x42);
+        // A deployed proxy forwards what it receives to the router
+        chain.set_code(deposit);
+        chain.send(deposit, 25);
+        let state = test_state(chain).await;
+        let mut config = (*state.config).clone();
+        config.require_api_keys = false;
+        config.treasury_allowlist = vec![ALLOWED.to_string()];
+        let state = AppState {
+            config: std::sync::Arc::new(config),
+            ..state
+        };
+
+        let address = format!("{:#x}", deposit);
+        db::insert_deposit(&state.db, "0x01", "0x02", &address, 0, None)
+            .await
+            .unwrap();
+        db::update_deposit_status(&state.db, &address, "deployed")
+            .await
+            .unwrap();
+        db::start_forwarded_scan(&state.db, &address, 0)
+            .await
+            .unwrap();
+
+        let other = format!(r#"{{"treasury": "{:#x}"}}"#, Address::repeat_byte(0x66));
+        let (status, body) = route(&state, &address, &other).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+
+        let (status, body) = route(
+            &state,
+            &address,
+            &format!(r#"{{"treasury": "{}"}}"#, ALLOWED),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["status"], "routed");
+        assert_eq!(body["treasury_address"], ALLOWED);
+        assert_eq!(body["steps"][2]["outcome"], "done");
+
+        // Routed deposits can't be routed again
+        let (status, body) = route(&state, &address, "").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "CONFLICT");
+
+        let request = Request::get(format!("/deposits/{}", address))
+            .body(Body::empty())
+            .unwrap();
+        let response = crate::app(state.clone()).oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(info["allocations"][0]["treasury_address"], ALLOWED);
+        assert_eq!(info["allocations"][0]["amount_wei"], "25");
+    }
+
+    #[tokio::test]
+    async fn test_route_deposit_to_registered_treasury() {
+        let chain = FakeChain::new();
+        let deposit = Address::repeat_byte(0x43);
+        chain.set_code(deposit);
+        chain.send(deposit, 5);
+        let state = test_state(chain).await;
+        let mut config = (*state.config).clone();
+        config.require_api_keys = false;
+        let state = AppState {
+            config: std::sync::Arc::new(config),
+            ..state
+        };
+
+        let address = format!("{:#x}", deposit);
+        db::insert_deposit(&state.db, "0x01", "0x02", &address, 0, None)
+            .await
+            .unwrap();
+        db::update_deposit_status(&state.db, &address, "deployed")
+            .await
+            .unwrap();
+        db::start_forwarded_scan(&state.db, &address, 0)
+            .await
+            .unwrap();
+        let registered = format!("{:#x}", Address::repeat_byte(0x0a));
+        db::insert_treasury(&state.db, "ops", &registered)
+            .await
+            .unwrap();
+
+        let body = format!(r#"{{"treasury": "{}"}}"#, registered);
+        let (status, body) = route(&state, &address, &body).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["steps"][2]["treasury_address"], registered);
+    }
+
+    async fn send(state: &AppState, request: Request) -> (StatusCode, serde_json::Value) {
+        let response = crate::app(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn create(body: serde_json::Value) -> Request {
+        Request::post("/deposit")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_created_address_can_be_recomputed() {
+        let state = test_state(FakeChain::new()).await;
+        let mut config = (*state.config).clone();
+        config.require_api_keys = false;
+        let state = AppState {
+            config: std::sync::Arc::new(config),
+            ..state
+        };
+        let user = format!("{:#x}", Address::repeat_byte(0x42));
+
+        let (status, created) = send(&state, create(serde_json::json!({ "user": user }))).await;
+        assert_eq!(status, StatusCode::OK, "{}", created);
+        assert_eq!(
+            created["signer_address"],
+            format!("{:#x}", crate::chain::fake::SIGNER)
+        );
+        let field = |name: &str| created[name].as_str().unwrap().to_string();
+        let (expected, _) = compute_deposit_address(
+            &parse_address(&field("deployer_address")).unwrap(),
+            &crate::create2::parse_bytes32(&field("init_code_hash")).unwrap(),
+            &parse_address(&field("signer_address")).unwrap(),
+            &parse_address(&user).unwrap(),
+            created["nonce"].as_u64().unwrap(),
+        );
+        assert_eq!(field("deposit_address"), format_address(&expected));
+    }
+
+    #[tokio::test]
+    async fn test_invoice_payment_is_assessed() {
+        let chain = std::sync::Arc::new(FakeChain::new());
+        let state = test_state(FakeChain::new()).await;
+        let mut config = (*state.config).clone();
+        config.require_api_keys = false;
+        let state = AppState {
+            config: std::sync::Arc::new(config),
+            chain: chain.clone(),
+            ..state
+        };
+        let user = format!("{:#x}", Address::repeat_byte(0x42));
+
+        let (status, _) = send(
+            &state,
+            create(
+                serde_json::json!({ "user": user, "asset": "USDC", "expected_amount_wei": "1" }),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(
+            &state,
+            create(serde_json::json!({ "user": user, "order_ref": "A-1" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, created) = send(
+            &state,
+            create(serde_json::json!({
+                "user": user,
+                "expected_amount_wei": "1000",
+                "expires_in_secs": 900,
+                "order_ref": "A-1",
+                "metadata": { "cart": [1, 2] },
+                "description": "Two tees",
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", created);
+        assert_eq!(created["invoice"]["payment_status"], "awaiting_payment");
+        let address = created["deposit_address"].as_str().unwrap().to_string();
+
+        chain.fund(address.parse().unwrap(), 600);
+        let (status, body) = route(&state, &address, "").await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let request = Request::get(format!("/deposits/{}", address))
+            .body(Body::empty())
+            .unwrap();
+        let (status, info) = send(&state, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let invoice = &info["invoice"];
+        assert_eq!(invoice["payment_status"], "underpaid");
+        assert_eq!(invoice["received_wei"], "600");
+        assert_eq!(invoice["shortfall_wei"], "400");
+        assert_eq!(invoice["order_ref"], "A-1");
+        assert_eq!(invoice["metadata"]["cart"][1], 2);
+        assert!(invoice["paid_at"].is_string());
     }
 }
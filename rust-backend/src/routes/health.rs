@@ -1,16 +1,60 @@
-//! Health check endpoint
+//! Health check endpoints
 
-use axum::{extract::State, Json};
+use axum::{extract::State, http::StatusCode, Json};
 
-use crate::{models::HealthResponse, AppState};
+use crate::{
+    health,
+    models::{ComponentStatus, HealthResponse, LiveResponse},
+    AppState,
+};
 
 /// GET /health
 ///
-/// Returns server health status for Railway/monitoring
+/// Detailed status report with startup checks and component checks. Always
+/// returns 200; use `/health/ready` for a status code to act on.
 pub async fn health_check(State(state): State<AppState>) -> Json<HealthResponse> {
-    Json(HealthResponse {
+    Json(report(&state).await)
+}
+
+/// GET /health/live
+///
+/// The process is up and serving requests
+pub async fn live() -> Json<LiveResponse> {
+    Json(LiveResponse {
         status: "ok".to_string(),
         version: env!("CARGO_PKG_VERSION").to_string(),
-        deployer_address: state.config.deployer_address.clone(),
     })
 }
+
+/// GET /health/ready
+///
+/// Same report as `/health`, with 503 when the database or RPC endpoint is down
+pub async fn ready(State(state): State<AppState>) -> (StatusCode, Json<HealthResponse>) {
+    let report = report(&state).await;
+    let code = if report.status == ComponentStatus::Down.as_str() {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    (code, Json(report))
+}
+
+async fn report(state: &AppState) -> HealthResponse {
+    let components = health::components(&state.db, state.chain.as_ref(), &state.config).await;
+
+    // A failed startup check leaves the server at least degraded
+    let mut status = health::overall(&components);
+    if !state.startup_checks.ok {
+        status = status.max(ComponentStatus::Degraded);
+    }
+
+    HealthResponse {
+        status: status.as_str().to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        deployer_address: state.config.deployer_address.clone(),
+        init_code_hash: state.config.init_code_hash.clone(),
+        signer_address: format!("{:#x}", state.chain.signer_address()),
+        startup_checks: state.startup_checks.as_ref().clone(),
+        components,
+    }
+}
@@ -0,0 +1,48 @@
+//! Routing job status endpoints
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+
+use crate::{
+    error::AppError,
+    jobs,
+    models::{ListJobsQuery, ListJobsResponse, RoutingJobInfo},
+    AppState,
+};
+
+/// Jobs returned by GET /jobs without a `limit`
+const DEFAULT_LIMIT: u32 = 50;
+
+/// Most jobs GET /jobs returns at once
+const MAX_LIMIT: u32 = 500;
+
+/// GET /jobs
+///
+/// List the most recent routing jobs with their counts, newest first
+pub async fn list_jobs(
+    State(state): State<AppState>,
+    Query(query): Query<ListJobsQuery>,
+) -> Result<Json<ListJobsResponse>, AppError> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let jobs = jobs::list(&state.db, limit).await?;
+
+    Ok(Json(ListJobsResponse {
+        total: jobs.len(),
+        jobs,
+    }))
+}
+
+/// GET /jobs/:id
+///
+/// Get a routing job with its progress, per-deposit results and errors
+pub async fn get_job(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<RoutingJobInfo>, AppError> {
+    jobs::get(&state.db, id)
+        .await?
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("Routing job {}", id)))
+}
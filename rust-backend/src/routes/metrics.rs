@@ -0,0 +1,76 @@
+//! Prometheus scrape endpoint
+//!
+//! Served outside the API key layers, like the health checks, so a scraper
+//! needs no API key. With `METRICS_TOKEN` set it must send that token as
+//! `Authorization: Bearer <token>`.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap},
+    response::IntoResponse,
+};
+
+use crate::{auth, error::AppError, metrics, AppState};
+
+/// GET /metrics
+///
+/// Refreshes the database and chain gauges, then renders every metric in the
+/// Prometheus text format
+pub async fn get_metrics(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some(token) = &state.config.metrics_token {
+        // Compared as hashes, so timing reveals nothing about the token
+        let presented = auth::presented_token(&headers).map(auth::hash_token);
+        if presented != Some(auth::hash_token(token.expose())) {
+            return Err(AppError::Unauthorized("Metrics token required".to_string()));
+        }
+    }
+    metrics::refresh(&state.db, state.chain.as_ref()).await;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chain::fake::FakeChain, signer::Secret, test_state};
+    use axum::{body::Body, extract::Request, http::StatusCode, Router};
+    use tower::ServiceExt;
+
+    async fn app(metrics_token: Option<&str>) -> Router {
+        let state = test_state(FakeChain::default()).await;
+        let mut config = (*state.config).clone();
+        config.metrics_token = metrics_token.map(Secret::new);
+        crate::app(AppState {
+            config: std::sync::Arc::new(config),
+            ..state
+        })
+    }
+
+    async fn scrape(app: &Router, token: Option<&str>) -> StatusCode {
+        let mut request = Request::get("/metrics");
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let request = request.body(Body::empty()).unwrap();
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_scrape_needs_no_api_key() {
+        let app = app(None).await;
+        assert_eq!(scrape(&app, None).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_scrape_token_is_required_when_set() {
+        let app = app(Some("scrape-secret")).await;
+        assert_eq!(scrape(&app, None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(scrape(&app, Some("wrong")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(scrape(&app, Some("scrape-secret")).await, StatusCode::OK);
+    }
+}
@@ -1,3 +1,11 @@
+pub mod admin;
+pub mod dead_letter;
 pub mod deposit;
 pub mod health;
+pub mod jobs;
+pub mod metrics;
 pub mod router;
+pub mod siwe;
+pub mod stranded;
+pub mod treasuries;
+pub mod webhooks;
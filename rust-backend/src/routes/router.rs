@@ -1,263 +1,137 @@
-//! POST /router - Route funded deposits to treasury
+//! POST /router - Start a routing job
+//! POST /router/plan - Preview a routing run
 
-use alloy::primitives::U256;
-use axum::{extract::State, Json};
+use axum::{extract::State, http::StatusCode, Json};
 
 use crate::{
-    db,
+    auth::ApiKey,
     error::AppError,
-    models::{RouteResponse, RouteTransactionInfo},
-    rpc::{parse_address, parse_salt, RpcClient},
+    jobs,
+    models::{RoutePlan, RoutingJobInfo},
+    plan::plan_routing,
+    routing::RetryPolicy,
     AppState,
 };
 
 /// POST /router
 ///
-/// Processes pending and funded deposits:
-/// 1. Fetch all 'pending' and 'funded' deposits from DB
-/// 2. Check balances on-chain for pending deposits
-/// 3. Update funded deposits (balance > 0) to 'funded' status
-/// 4. Deploy proxies for funded deposits using deployMultiple()
-/// 5. Call transferFunds() on each deployed proxy
-/// 6. Update status to 'routed' on success
+/// Starts a routing job and returns it with 202 right away; the job checks
+/// balances, deploys proxies for funded deposits and routes their funds to
+/// the treasury in the background. Follow it with `GET /jobs/:id`.
+/// See [`crate::routing::route_deposits`] for the individual steps.
+///
+/// Only one run is active at a time; while one is, this returns 409 with the
+/// active run's holder and lease expiry instead of starting another.
 pub async fn route_deposits(
     State(state): State<AppState>,
-) -> Result<Json<RouteResponse>, AppError> {
-    tracing::info!("Starting deposit routing process");
-
-    let mut response = RouteResponse {
-        checked: 0,
-        funded: 0,
-        deployed: 0,
-        routed: 0,
-        deploy_tx_hash: None,
-        route_tx_hashes: vec![],
-        errors: vec![],
-    };
-
-    // Initialize RPC client
-    let rpc = match RpcClient::from_config(&state.config).await {
-        Ok(client) => client,
-        Err(e) => {
-            tracing::error!("Failed to initialize RPC client: {}", e);
-            response
-                .errors
-                .push(format!("RPC initialization failed: {}", e));
-            return Ok(Json(response));
-        }
-    };
-
-    // Fetch pending and funded deposits
-    let deposits = match db::get_deposits_by_statuses(&state.db, &["pending", "funded"]).await {
-        Ok(deps) => deps,
-        Err(e) => {
-            tracing::error!("Failed to fetch deposits: {}", e);
-            response.errors.push(format!("Database error: {}", e));
-            return Ok(Json(response));
-        }
-    };
-
-    if deposits.is_empty() {
-        tracing::info!("No pending or funded deposits to process");
-        return Ok(Json(response));
-    }
-
-    response.checked = deposits.len();
-    tracing::info!("Found {} deposits to check", deposits.len());
+    caller: Option<ApiKey>,
+) -> Result<(StatusCode, Json<RoutingJobInfo>), AppError> {
+    let requested_by = caller.as_ref().map(|key| key.name.as_str());
+    let job = jobs::spawn(
+        state.db.clone(),
+        state.chain.clone(),
+        &state.routing_lease,
+        RetryPolicy::from_config(&state.config),
+        requested_by,
+    )
+    .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
 
-    // Separate pending (need balance check) and already funded
-    let pending_deposits: Vec<_> = deposits.iter().filter(|d| d.status == "pending").collect();
-    let funded_deposits: Vec<_> = deposits.iter().filter(|d| d.status == "funded").collect();
+/// POST /router/plan
+///
+/// Reports what `POST /router` would do right now without sending anything:
+/// the deposits it would fund, deploy and route, its transactions with
+/// simulated gas and fees, the value moving to the treasury and the deposits
+/// it would skip. See [`crate::plan::plan_routing`].
+pub async fn plan(State(state): State<AppState>) -> Json<RoutePlan> {
+    Json(plan_routing(&state.db, state.chain.as_ref(), &state.config).await)
+}
 
-    // Check balances for pending deposits
-    let mut balances: Vec<(String, U256)> = vec![];
-    for deposit in &pending_deposits {
-        match parse_address(&deposit.deposit_address) {
-            Ok(addr) => match rpc.get_balance(addr).await {
-                Ok(balance) => {
-                    balances.push((deposit.deposit_address.clone(), balance));
-                    if balance > U256::ZERO {
-                        tracing::info!(
-                            "Deposit {} has balance: {} wei",
-                            deposit.deposit_address,
-                            balance
-                        );
-                    }
-                }
-                Err(e) => {
-                    tracing::error!(
-                        "Failed to get balance for {}: {}",
-                        deposit.deposit_address,
-                        e
-                    );
-                    response.errors.push(format!(
-                        "Balance check failed for {}: {}",
-                        deposit.deposit_address, e
-                    ));
-                }
-            },
-            Err(e) => {
-                tracing::error!("Invalid address {}: {}", deposit.deposit_address, e);
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chain::fake::FakeChain, test_state};
+    use axum::{body::Body, extract::Request, Router};
+    use tower::ServiceExt;
+
+    async fn app() -> (Router, AppState) {
+        let state = test_state(FakeChain::default()).await;
+        let mut config = (*state.config).clone();
+        config.require_api_keys = false;
+        let state = AppState {
+            config: std::sync::Arc::new(config),
+            ..state
+        };
+        (crate::app(state.clone()), state)
     }
 
-    // Update status to 'funded' for deposits with balance > 0
-    let mut newly_funded = vec![];
-    for (addr, balance) in &balances {
-        if *balance > U256::ZERO {
-            if let Err(e) = db::update_deposit_status(&state.db, addr, "funded").await {
-                tracing::error!("Failed to update status for {}: {}", addr, e);
-                response
-                    .errors
-                    .push(format!("DB update failed for {}: {}", addr, e));
-            } else {
-                newly_funded.push(addr.clone());
-            }
-        }
+    async fn call(app: &Router, request: Request) -> (StatusCode, serde_json::Value) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
     }
 
-    response.funded = newly_funded.len() + funded_deposits.len();
-    tracing::info!(
-        "{} newly funded, {} previously funded",
-        newly_funded.len(),
-        funded_deposits.len()
-    );
-
-    // Collect all funded deposits for deployment
-    let mut deposits_to_deploy: Vec<&db::DepositRow> = vec![];
-
-    // Add previously funded deposits
-    deposits_to_deploy.extend(funded_deposits.iter().copied());
-
-    // Add newly funded deposits (find them in pending_deposits)
-    for addr in &newly_funded {
-        if let Some(dep) = pending_deposits.iter().find(|d| &d.deposit_address == addr) {
-            deposits_to_deploy.push(*dep);
-        }
+    fn route() -> Request {
+        Request::post("/router").body(Body::empty()).unwrap()
     }
 
-    if deposits_to_deploy.is_empty() {
-        tracing::info!("No funded deposits to deploy");
-        return Ok(Json(response));
-    }
+    #[tokio::test]
+    async fn test_active_run_is_reported_instead_of_overlapping() {
+        let (app, state) = app().await;
 
-    // Parse salts for deployment
-    let mut salts_and_deposits = vec![];
-    for deposit in &deposits_to_deploy {
-        match parse_salt(&deposit.salt) {
-            Ok(salt) => {
-                salts_and_deposits.push((
-                    salt,
-                    deposit.deposit_address.clone(),
-                    deposit.salt.clone(),
-                ));
-            }
-            Err(e) => {
-                tracing::error!("Invalid salt for {}: {}", deposit.deposit_address, e);
-                response.errors.push(format!(
-                    "Invalid salt for {}: {}",
-                    deposit.deposit_address, e
-                ));
-            }
-        }
-    }
+        let guard = state.routing_lease.try_acquire(&state.db).await.unwrap();
+        let (status, body) = call(&app, route()).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "ROUTING_IN_PROGRESS");
+        assert_eq!(body["details"]["holder"], state.routing_lease.holder());
 
-    if salts_and_deposits.is_empty() {
-        tracing::info!("No valid salts to deploy");
-        return Ok(Json(response));
+        guard.release().await;
+        let (status, _) = call(&app, route()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
     }
 
-    let salts: Vec<_> = salts_and_deposits.iter().map(|(s, _, _)| *s).collect();
-    tracing::info!("Deploying {} proxies", salts.len());
+    #[tokio::test]
+    async fn test_job_is_started_and_reported() {
+        let (app, _) = app().await;
 
-    // Deploy all proxies in one transaction
-    match rpc.deploy_multiple(salts).await {
-        Ok(tx_hash) => {
-            response.deploy_tx_hash = Some(format!("{:#x}", tx_hash));
-            response.deployed = salts_and_deposits.len();
-            tracing::info!(
-                "Deployed {} proxies, tx: {:#x}",
-                salts_and_deposits.len(),
-                tx_hash
-            );
+        let (status, job) = call(&app, route()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(job["status"], "running");
+        let uri = format!("/jobs/{}", job["id"]);
 
-            // Update status to 'deployed'
-            for (_, addr, _) in &salts_and_deposits {
-                if let Err(e) = db::update_deposit_status(&state.db, addr, "deployed").await {
-                    tracing::error!("Failed to update status to deployed for {}: {}", addr, e);
-                }
-            }
-        }
-        Err(e) => {
-            tracing::error!("deployMultiple failed: {}", e);
-            response.errors.push(format!("Deploy failed: {}", e));
-            // Mark as failed
-            for (_, addr, _) in &salts_and_deposits {
-                let _ = db::update_deposit_status(&state.db, addr, "failed").await;
+        let job = loop {
+            let (status, job) = call(&app, Request::get(&uri).body(Body::empty()).unwrap()).await;
+            assert_eq!(status, StatusCode::OK);
+            if job["status"] != "running" {
+                break job;
             }
-            return Ok(Json(response));
-        }
-    }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert_eq!(job["status"], "completed");
+        assert_eq!(job["result"]["errors"], serde_json::json!([]));
 
-    // Now route funds from each deployed proxy to treasury
-    for (_, addr, _) in &salts_and_deposits {
-        match parse_address(addr) {
-            Ok(proxy_addr) => {
-                // Get current balance before transfer
-                let balance = rpc.get_balance(proxy_addr).await.unwrap_or(U256::ZERO);
+        let (_, list) = call(&app, Request::get("/jobs").body(Body::empty()).unwrap()).await;
+        assert_eq!(list["total"], 1);
 
-                if balance == U256::ZERO {
-                    tracing::warn!("Proxy {} has zero balance, skipping transfer", addr);
-                    continue;
-                }
-
-                match rpc.transfer_funds(proxy_addr).await {
-                    Ok(tx_hash) => {
-                        response.route_tx_hashes.push(RouteTransactionInfo {
-                            proxy_address: addr.clone(),
-                            tx_hash: format!("{:#x}", tx_hash),
-                            amount_wei: balance.to_string(),
-                        });
-                        response.routed += 1;
-
-                        // Update status to 'routed'
-                        if let Err(e) = db::update_deposit_status(&state.db, addr, "routed").await {
-                            tracing::error!(
-                                "Failed to update status to routed for {}: {}",
-                                addr,
-                                e
-                            );
-                        }
-
-                        tracing::info!(
-                            "Routed {} wei from {} to treasury, tx: {:#x}",
-                            balance,
-                            addr,
-                            tx_hash
-                        );
-                    }
-                    Err(e) => {
-                        tracing::error!("transferFunds failed for {}: {}", addr, e);
-                        response
-                            .errors
-                            .push(format!("Transfer failed for {}: {}", addr, e));
-                    }
-                }
-            }
-            Err(e) => {
-                tracing::error!("Invalid proxy address {}: {}", addr, e);
-            }
-        }
+        let (status, _) = call(&app, Request::get("/jobs/999").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
     }
 
-    tracing::info!(
-        "Routing complete: checked={}, funded={}, deployed={}, routed={}",
-        response.checked,
-        response.funded,
-        response.deployed,
-        response.routed
-    );
-
-    Ok(Json(response))
+    #[tokio::test]
+    async fn test_plan_runs_alongside_an_active_run() {
+        let (app, state) = app().await;
+
+        let guard = state.routing_lease.try_acquire(&state.db).await.unwrap();
+        let request = Request::post("/router/plan").body(Body::empty()).unwrap();
+        let (status, plan) = call(&app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(plan["checked"], 0);
+        assert_eq!(plan["value_to_treasury_wei"], "0");
+        guard.release().await;
+    }
 }
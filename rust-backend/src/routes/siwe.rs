@@ -0,0 +1,267 @@
+//! Sign-In with Ethereum endpoints
+//!
+//! The client fetches a nonce, has the wallet sign an EIP-4361 message
+//! containing it and exchanges message and signature for a session token.
+
+use axum::{extract::State, http::HeaderMap, http::StatusCode, Json};
+use time::OffsetDateTime;
+
+use crate::{
+    auth, db,
+    error::AppError,
+    models::{NonceResponse, SessionInfo, SessionResponse, SiweVerifyRequest},
+    siwe::{SiweError, SiweMessage},
+    telemetry, AppState,
+};
+
+/// How long a nonce can be used to sign in
+const NONCE_TTL_SECS: u64 = 600;
+
+fn siwe_domain(state: &AppState) -> Result<&str, AppError> {
+    state.config.siwe_domain.as_deref().ok_or_else(|| {
+        AppError::NotFound("Sign-In with Ethereum is disabled (SIWE_DOMAIN unset)".to_string())
+    })
+}
+
+/// GET /auth/nonce
+///
+/// Issue a single-use nonce for a SIWE message
+pub async fn nonce(State(state): State<AppState>) -> Result<Json<NonceResponse>, AppError> {
+    siwe_domain(&state)?;
+
+    let nonce = auth::random_token("");
+    let expires_at = db::insert_siwe_nonce(&state.db, &nonce, NONCE_TTL_SECS).await?;
+
+    Ok(Json(NonceResponse { nonce, expires_at }))
+}
+
+/// POST /auth/verify
+///
+/// Check a signed SIWE message and start a session for its wallet
+pub async fn verify(
+    State(state): State<AppState>,
+    Json(req): Json<SiweVerifyRequest>,
+) -> Result<Json<SessionResponse>, AppError> {
+    let domain = siwe_domain(&state)?;
+    let message: SiweMessage = req.message.parse()?;
+
+    let chain_id = match state.config.chain_id {
+        Some(chain_id) => chain_id,
+        None => state
+            .chain
+            .chain_id()
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to read chain ID: {}", e)))?,
+    };
+    message.verify(
+        &req.message,
+        &req.signature,
+        domain,
+        chain_id,
+        OffsetDateTime::now_utc(),
+    )?;
+
+    // Only after the signature checks out, so garbage can't burn nonces
+    if !db::consume_siwe_nonce(&state.db, &message.nonce).await? {
+        return Err(SiweError::Nonce.into());
+    }
+
+    let token = auth::random_token(auth::SESSION_PREFIX);
+    let address = format!("{:#x}", message.address);
+    let expires_at = db::insert_session(
+        &state.db,
+        &auth::hash_token(&token),
+        &address,
+        state.config.session_ttl_secs,
+    )
+    .await?;
+
+    tracing::info!(
+        wallet = %telemetry::redact_address(&address),
+        "Wallet signed in"
+    );
+
+    Ok(Json(SessionResponse {
+        token,
+        address,
+        expires_at,
+    }))
+}
+
+/// GET /auth/session
+///
+/// The wallet the presented session token belongs to
+pub async fn get_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<SessionInfo>, AppError> {
+    let session = auth::session(&state.db, &headers).await?;
+    Ok(Json(SessionInfo {
+        address: session.address,
+        expires_at: session.expires_at,
+    }))
+}
+
+/// POST /auth/logout
+///
+/// End the presented session; ending an unknown session is not an error
+pub async fn logout(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<StatusCode, AppError> {
+    let token = auth::presented_token(&headers)
+        .filter(|token| token.starts_with(auth::SESSION_PREFIX))
+        .ok_or_else(|| AppError::Unauthorized("Sign in required".to_string()))?;
+    db::delete_session(&state.db, &auth::hash_token(token)).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app, chain::fake::FakeChain, siwe::tests::message, test_state};
+    use alloy::signers::{local::PrivateKeySigner, SignerSync};
+    use axum::{body::Body, extract::Request, Router};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    async fn call(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json");
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let request = request
+            .body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    async fn siwe_app() -> (Router, AppState) {
+        let mut state = test_state(FakeChain::new()).await;
+        let mut config = (*state.config).clone();
+        config.siwe_domain = Some("app.radhat.xyz".to_string());
+        state.config = Arc::new(config);
+        (app(state.clone()), state)
+    }
+
+    async fn sign_in(app: &Router, wallet: &PrivateKeySigner) -> (StatusCode, Value) {
+        let (_, nonce) = call(app, "GET", "/auth/nonce", None, None).await;
+        let raw = message(wallet.address(), nonce["nonce"].as_str().unwrap(), 31337);
+        let signature = wallet.sign_message_sync(raw.as_bytes()).unwrap();
+        call(
+            app,
+            "POST",
+            "/auth/verify",
+            None,
+            Some(json!({ "message": raw, "signature": signature.to_string() })),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_session_is_scoped_to_the_wallet() {
+        let (app, state) = siwe_app().await;
+        let wallet = PrivateKeySigner::random();
+        let own = format!("{:#x}", wallet.address());
+        let other = format!("{:#x}", alloy::primitives::Address::repeat_byte(0x42));
+        db::insert_deposit(&state.db, &other, "0x00", "0xotherdeposit", 0, None)
+            .await
+            .unwrap();
+
+        let (status, session) = sign_in(&app, &wallet).await;
+        assert_eq!(status, StatusCode::OK, "{}", session);
+        assert_eq!(session["address"], own.as_str());
+        let token = session["token"].as_str().unwrap();
+
+        let (status, _) = call(
+            &app,
+            "POST",
+            "/deposit",
+            Some(token),
+            Some(json!({ "user": own })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = call(
+            &app,
+            "POST",
+            "/deposit",
+            Some(token),
+            Some(json!({ "user": other })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (_, list) = call(&app, "GET", "/deposits", Some(token), None).await;
+        assert_eq!(list["total"], 1);
+        assert_eq!(list["deposits"][0]["user_address"], own.as_str());
+        let (status, _) = call(&app, "GET", "/deposits/0xotherdeposit", Some(token), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Sessions don't reach key-only routes
+        let (status, _) = call(&app, "GET", "/stranded", Some(token), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = call(&app, "POST", "/auth/logout", Some(token), None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = call(&app, "GET", "/auth/session", Some(token), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_nonce_cannot_be_reused() {
+        let (app, _) = siwe_app().await;
+        let wallet = PrivateKeySigner::random();
+        let (_, nonce) = call(&app, "GET", "/auth/nonce", None, None).await;
+        let raw = message(wallet.address(), nonce["nonce"].as_str().unwrap(), 31337);
+        let signature = wallet.sign_message_sync(raw.as_bytes()).unwrap();
+        let body = json!({ "message": raw, "signature": signature.to_string() });
+
+        let (first, _) = call(&app, "POST", "/auth/verify", None, Some(body.clone())).await;
+        let (second, error) = call(&app, "POST", "/auth/verify", None, Some(body)).await;
+
+        assert_eq!(first, StatusCode::OK);
+        assert_eq!(second, StatusCode::UNAUTHORIZED);
+        assert_eq!(error["code"], "SIWE_REJECTED");
+    }
+
+    #[tokio::test]
+    async fn test_unissued_nonce_and_wrong_chain_are_rejected() {
+        let (app, _) = siwe_app().await;
+        let wallet = PrivateKeySigner::random();
+
+        for raw in [
+            message(wallet.address(), "madeupnonce1", 31337),
+            message(wallet.address(), "madeupnonce1", 1),
+        ] {
+            let signature = wallet.sign_message_sync(raw.as_bytes()).unwrap();
+            let (status, _) = call(
+                &app,
+                "POST",
+                "/auth/verify",
+                None,
+                Some(json!({ "message": raw, "signature": signature.to_string() })),
+            )
+            .await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! Stranded funds reporting endpoints
+
+use axum::{extract::State, Json};
+
+use crate::{error::AppError, models::StrandedReport, stranded, AppState};
+
+/// GET /stranded
+///
+/// Report funds stuck on deployed proxies, per proxy and overall
+pub async fn get_stranded(State(state): State<AppState>) -> Result<Json<StrandedReport>, AppError> {
+    Ok(Json(stranded::report(&state.db).await?))
+}
+
+/// POST /stranded/scan
+///
+/// Re-check the balance of every deployed proxy, then report stranded funds
+pub async fn scan_stranded(
+    State(state): State<AppState>,
+) -> Result<Json<StrandedReport>, AppError> {
+    let errors = stranded::scan(&state.db, state.chain.as_ref()).await?;
+
+    let mut report = stranded::report(&state.db).await?;
+    report.errors = errors;
+
+    Ok(Json(report))
+}
@@ -0,0 +1,283 @@
+//! Treasury registry and routing rule endpoints (admin role)
+//!
+//! Routing rules split a deposit's funds across registered treasuries; see
+//! [`crate::treasury`] for how a rule is picked and how amounts are rounded.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::{
+    create2::{format_address, parse_address},
+    db::{self, RuleRow, TreasuryRow},
+    error::AppError,
+    models::{
+        CreateRoutingRuleRequest, CreateTreasuryRequest, ListRoutingRulesResponse,
+        ListTreasuriesResponse, RoutingRuleInfo, TreasuryInfo,
+    },
+    treasury::{self, SplitSpec, TreasuryError},
+    AppState,
+};
+
+/// GET /admin/treasuries
+///
+/// List registered treasuries, active ones first
+pub async fn list_treasuries(
+    State(state): State<AppState>,
+) -> Result<Json<ListTreasuriesResponse>, AppError> {
+    let treasuries: Vec<TreasuryInfo> = db::list_treasuries(&state.db)
+        .await?
+        .into_iter()
+        .map(treasury_info)
+        .collect();
+
+    Ok(Json(ListTreasuriesResponse {
+        total: treasuries.len(),
+        treasuries,
+    }))
+}
+
+/// POST /admin/treasuries
+///
+/// Register a treasury under a name routing rules can refer to
+pub async fn create_treasury(
+    State(state): State<AppState>,
+    Json(req): Json<CreateTreasuryRequest>,
+) -> Result<(StatusCode, Json<TreasuryInfo>), AppError> {
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("name must not be empty".to_string()));
+    }
+    let address = parse_address(&req.address.to_lowercase())
+        .map_err(|_| AppError::InvalidAddress(req.address.clone()))?;
+
+    let row = db::insert_treasury(&state.db, name, &format_address(&address))
+        .await
+        .map_err(|e| match e.as_database_error() {
+            Some(db_error) if db_error.is_unique_violation() => AppError::Conflict(format!(
+                "A treasury named {} or with address {} is already registered",
+                name, req.address
+            )),
+            _ => AppError::Database(e),
+        })?;
+    tracing::info!(treasury = %row.name, address = %row.address, "Registered treasury");
+
+    Ok((StatusCode::CREATED, Json(treasury_info(row))))
+}
+
+/// DELETE /admin/treasuries/:name
+///
+/// Deactivate a treasury; refused while a routing rule still sends funds to it
+pub async fn deactivate_treasury(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let rules = db::list_routing_rules(&state.db).await?;
+    if let Some(rule) = rules
+        .iter()
+        .find(|rule| treasury::rule_treasuries(rule).contains(&name))
+    {
+        return Err(AppError::Conflict(format!(
+            "Routing rule {} still sends funds to {}",
+            rule.id, name
+        )));
+    }
+
+    if !db::deactivate_treasury(&state.db, &name).await? {
+        return Err(AppError::NotFound(format!("Active treasury {}", name)));
+    }
+    tracing::info!(treasury = %name, "Deactivated treasury");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /admin/routing-rules
+pub async fn list_rules(
+    State(state): State<AppState>,
+) -> Result<Json<ListRoutingRulesResponse>, AppError> {
+    let rules: Vec<RoutingRuleInfo> = db::list_routing_rules(&state.db)
+        .await?
+        .into_iter()
+        .map(rule_info)
+        .collect();
+
+    Ok(Json(ListRoutingRulesResponse {
+        total: rules.len(),
+        rules,
+    }))
+}
+
+/// POST /admin/routing-rules
+///
+/// Add a rule; its shares must add up to 10000 bps and name active
+/// treasuries
+pub async fn create_rule(
+    State(state): State<AppState>,
+    Json(req): Json<CreateRoutingRuleRequest>,
+) -> Result<(StatusCode, Json<RoutingRuleInfo>), AppError> {
+    treasury::validate_splits(&req.splits)?;
+    let active = db::get_active_treasuries(&state.db).await?;
+    if let Some(unknown) = req
+        .splits
+        .iter()
+        .find(|split| !active.iter().any(|t| t.name == split.treasury))
+    {
+        return Err(TreasuryError::UnknownTreasury(unknown.treasury.clone()).into());
+    }
+
+    let user_address = match &req.user_address {
+        Some(user) => Some(format_address(
+            &parse_address(&user.to_lowercase())
+                .map_err(|_| AppError::InvalidAddress(user.clone()))?,
+        )),
+        None => None,
+    };
+    let asset = req.asset.as_deref().map(str::to_uppercase);
+    let splits =
+        serde_json::to_string(&req.splits).map_err(|e| AppError::Internal(e.to_string()))?;
+
+    let row = db::insert_routing_rule(
+        &state.db,
+        user_address.as_deref(),
+        req.tenant.as_deref(),
+        asset.as_deref(),
+        &splits,
+    )
+    .await?;
+    tracing::info!(rule_id = row.id, "Added routing rule");
+
+    Ok((StatusCode::CREATED, Json(rule_info(row))))
+}
+
+/// DELETE /admin/routing-rules/:id
+pub async fn delete_rule(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    if !db::delete_routing_rule(&state.db, id).await? {
+        return Err(AppError::NotFound(format!("Routing rule {}", id)));
+    }
+    tracing::info!(rule_id = id, "Deleted routing rule");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn treasury_info(row: TreasuryRow) -> TreasuryInfo {
+    TreasuryInfo {
+        id: row.id,
+        name: row.name,
+        address: row.address,
+        active: row.active,
+        created_at: row.created_at,
+    }
+}
+
+fn rule_info(row: RuleRow) -> RoutingRuleInfo {
+    RoutingRuleInfo {
+        id: row.id,
+        splits: serde_json::from_str::<Vec<SplitSpec>>(&row.splits).unwrap_or_default(),
+        user_address: row.user_address,
+        tenant: row.tenant,
+        asset: row.asset,
+        created_at: row.created_at,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chain::fake::FakeChain, test_state};
+    use axum::{
+        body::Body,
+        extract::Request,
+        http::{header::CONTENT_TYPE, Method},
+    };
+    use tower::ServiceExt;
+
+    async fn send(state: &AppState, method: Method, uri: &str, body: &str) -> StatusCode {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        crate::app(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_rules_only_split_across_active_treasuries() {
+        let state = test_state(FakeChain::default()).await;
+        let mut config = (*state.config).clone();
+        config.require_api_keys = false;
+        let state = AppState {
+            config: std::sync::Arc::new(config),
+            ..state
+        };
+
+        let ops = format!(
+            r#"{{"name": "ops", "address": "{}"}}"#,
+            "0x".to_string() + &"0a".repeat(20)
+        );
+        let fees = format!(
+            r#"{{"name": "fees", "address": "{}"}}"#,
+            "0x".to_string() + &"0f".repeat(20)
+        );
+        assert_eq!(
+            send(&state, Method::POST, "/admin/treasuries", &ops).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            send(&state, Method::POST, "/admin/treasuries", &fees).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            send(&state, Method::POST, "/admin/treasuries", &ops).await,
+            StatusCode::CONFLICT
+        );
+
+        let rule = |splits: &str| format!(r#"{{"tenant": "shop", "splits": {}}}"#, splits);
+        let short = rule(r#"[{"treasury": "ops", "share_bps": 9000}]"#);
+        assert_eq!(
+            send(&state, Method::POST, "/admin/routing-rules", &short).await,
+            StatusCode::BAD_REQUEST
+        );
+        let unknown = rule(
+            r#"[{"treasury": "ops", "share_bps": 9500}, {"treasury": "tax", "share_bps": 500}]"#,
+        );
+        assert_eq!(
+            send(&state, Method::POST, "/admin/routing-rules", &unknown).await,
+            StatusCode::BAD_REQUEST
+        );
+        let split = rule(
+            r#"[{"treasury": "ops", "share_bps": 9500}, {"treasury": "fees", "share_bps": 500}]"#,
+        );
+        assert_eq!(
+            send(&state, Method::POST, "/admin/routing-rules", &split).await,
+            StatusCode::CREATED
+        );
+
+        // The rule holds on to its treasuries
+        assert_eq!(
+            send(&state, Method::DELETE, "/admin/treasuries/fees", "").await,
+            StatusCode::CONFLICT
+        );
+        let id = db::list_routing_rules(&state.db).await.unwrap()[0].id;
+        let uri = format!("/admin/routing-rules/{}", id);
+        assert_eq!(
+            send(&state, Method::DELETE, &uri, "").await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            send(&state, Method::DELETE, "/admin/treasuries/fees", "").await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            send(&state, Method::DELETE, "/admin/treasuries/fees", "").await,
+            StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -0,0 +1,441 @@
+//! Webhook subscription endpoints (create_deposit role)
+//!
+//! API keys manage subscriptions to their own deposits' events; admins (and
+//! every caller while API keys are off) manage all of them. See
+//! [`crate::webhooks`] for the events, signatures and retries.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::{
+    auth::{self, ApiKey, Role},
+    db::{self, DeliveryRow, WebhookRow},
+    error::AppError,
+    models::{
+        CreateWebhookRequest, CreateWebhookResponse, ListWebhookDeliveriesQuery,
+        ListWebhookDeliveriesResponse, ListWebhooksResponse, WebhookAttemptInfo,
+        WebhookDeliveryInfo, WebhookInfo,
+    },
+    telemetry,
+    webhooks::{self, WebhookEvent},
+    AppState,
+};
+
+/// Deliveries returned by GET /webhooks/:id/deliveries without a `limit`
+const DEFAULT_LIMIT: u32 = 50;
+
+/// Most deliveries GET /webhooks/:id/deliveries returns at once
+const MAX_LIMIT: u32 = 500;
+
+/// Prefix of subscription secrets
+const SECRET_PREFIX: &str = "whsec_";
+
+/// GET /webhooks
+///
+/// List the caller's active subscriptions
+pub async fn list_webhooks(
+    State(state): State<AppState>,
+    api_key: Option<ApiKey>,
+) -> Result<Json<ListWebhooksResponse>, AppError> {
+    let webhooks: Vec<WebhookInfo> =
+        db::list_webhook_subscriptions(&state.db, caller_tenant(&api_key))
+            .await?
+            .into_iter()
+            .map(webhook_info)
+            .collect();
+
+    Ok(Json(ListWebhooksResponse {
+        total: webhooks.len(),
+        webhooks,
+    }))
+}
+
+/// POST /webhooks
+///
+/// Subscribe a URL to deposit events; the response carries the signing
+/// secret, which is not shown again
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    api_key: Option<ApiKey>,
+    Json(req): Json<CreateWebhookRequest>,
+) -> Result<(StatusCode, Json<CreateWebhookResponse>), AppError> {
+    let url = req.url.trim();
+    webhooks::check_target(url, state.config.webhook_allow_private_targets)
+        .await
+        .map_err(AppError::BadRequest)?;
+
+    let tenant = match (caller_tenant(&api_key), req.tenant) {
+        (Some(own), Some(requested)) if requested != own => {
+            return Err(AppError::Forbidden(
+                "API keys can only subscribe to their own deposits".to_string(),
+            ))
+        }
+        (Some(own), _) => Some(own.to_string()),
+        (None, requested) => requested,
+    };
+
+    let events: Vec<WebhookEvent> = match req.events {
+        Some(events) if events.is_empty() => {
+            return Err(AppError::BadRequest(
+                "events must name at least one event".to_string(),
+            ))
+        }
+        Some(events) => WebhookEvent::ALL
+            .into_iter()
+            .filter(|e| events.contains(e))
+            .collect(),
+        None => WebhookEvent::ALL.to_vec(),
+    };
+
+    let secret = auth::random_token(SECRET_PREFIX);
+    let row = db::insert_webhook_subscription(
+        &state.db,
+        tenant.as_deref(),
+        url,
+        &secret,
+        &webhooks::format_events(&events),
+    )
+    .await?;
+    tracing::info!(
+        webhook_id = row.id,
+        url = %telemetry::redact_url(&row.url),
+        events = %row.events,
+        "Created webhook subscription"
+    );
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateWebhookResponse {
+            webhook: webhook_info(row),
+            secret,
+        }),
+    ))
+}
+
+/// DELETE /webhooks/:id
+///
+/// Stop a subscription; its delivery log is kept
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    api_key: Option<ApiKey>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    subscription(&state, &api_key, id).await?;
+    if !db::deactivate_webhook_subscription(&state.db, id).await? {
+        return Err(AppError::NotFound(format!("Webhook {}", id)));
+    }
+    tracing::info!(webhook_id = id, "Deactivated webhook subscription");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /webhooks/:id/deliveries
+///
+/// A subscription's deliveries with their status and last error, newest
+/// first
+pub async fn list_deliveries(
+    State(state): State<AppState>,
+    api_key: Option<ApiKey>,
+    Path(id): Path<i64>,
+    Query(query): Query<ListWebhookDeliveriesQuery>,
+) -> Result<Json<ListWebhookDeliveriesResponse>, AppError> {
+    subscription(&state, &api_key, id).await?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let deliveries: Vec<WebhookDeliveryInfo> = db::list_webhook_deliveries(&state.db, id, limit)
+        .await?
+        .into_iter()
+        .map(delivery_info)
+        .collect();
+
+    Ok(Json(ListWebhookDeliveriesResponse {
+        total: deliveries.len(),
+        deliveries,
+    }))
+}
+
+/// GET /webhooks/deliveries/:id
+///
+/// A delivery with its payload and the log of every attempt
+pub async fn get_delivery(
+    State(state): State<AppState>,
+    api_key: Option<ApiKey>,
+    Path(id): Path<i64>,
+) -> Result<Json<WebhookDeliveryInfo>, AppError> {
+    let row = delivery(&state, &api_key, id).await?;
+    let log = db::list_webhook_attempts(&state.db, id)
+        .await?
+        .into_iter()
+        .map(|attempt| WebhookAttemptInfo {
+            status_code: attempt.status_code,
+            error: attempt.error,
+            duration_ms: attempt.duration_ms,
+            attempted_at: attempt.attempted_at,
+        })
+        .collect();
+    let payload = serde_json::from_str(&row.payload).ok();
+
+    let mut info = delivery_info(row);
+    info.payload = payload;
+    info.log = Some(log);
+    Ok(Json(info))
+}
+
+/// POST /webhooks/deliveries/:id/replay
+///
+/// Send a delivery again, whatever its status, with its original payload
+/// and a fresh signature; its attempts start over
+pub async fn replay_delivery(
+    State(state): State<AppState>,
+    api_key: Option<ApiKey>,
+    Path(id): Path<i64>,
+) -> Result<Json<WebhookDeliveryInfo>, AppError> {
+    delivery(&state, &api_key, id).await?;
+    if !db::replay_webhook_delivery(&state.db, id).await? {
+        return Err(AppError::NotFound(format!("Webhook delivery {}", id)));
+    }
+    tracing::info!(delivery_id = id, "Queued webhook delivery for replay");
+
+    let row = db::get_webhook_delivery(&state.db, id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Webhook delivery {}", id)))?;
+    Ok(Json(delivery_info(row)))
+}
+
+/// Tenant the caller's subscriptions are limited to: its own for keys
+/// without the admin role, none for admins and while API keys are off
+fn caller_tenant(api_key: &Option<ApiKey>) -> Option<&str> {
+    api_key
+        .as_ref()
+        .filter(|key| !key.has(Role::Admin))
+        .map(|key| key.name.as_str())
+}
+
+/// A subscription the caller may manage, active or not; others are not
+/// found
+async fn subscription(
+    state: &AppState,
+    api_key: &Option<ApiKey>,
+    id: i64,
+) -> Result<WebhookRow, AppError> {
+    let tenant = caller_tenant(api_key);
+    db::get_webhook_subscription(&state.db, id)
+        .await?
+        .filter(|row| tenant.is_none_or(|t| row.tenant.as_deref() == Some(t)))
+        .ok_or_else(|| AppError::NotFound(format!("Webhook {}", id)))
+}
+
+/// A delivery of a subscription the caller may see, active or not
+async fn delivery(
+    state: &AppState,
+    api_key: &Option<ApiKey>,
+    id: i64,
+) -> Result<DeliveryRow, AppError> {
+    let not_found = || AppError::NotFound(format!("Webhook delivery {}", id));
+    let row = db::get_webhook_delivery(&state.db, id)
+        .await?
+        .ok_or_else(not_found)?;
+    if let Some(tenant) = caller_tenant(api_key) {
+        let sub = db::get_webhook_subscription(&state.db, row.subscription_id).await?;
+        if sub.and_then(|sub| sub.tenant).as_deref() != Some(tenant) {
+            return Err(not_found());
+        }
+    }
+    Ok(row)
+}
+
+fn webhook_info(row: WebhookRow) -> WebhookInfo {
+    WebhookInfo {
+        id: row.id,
+        tenant: row.tenant,
+        url: row.url,
+        events: webhooks::parse_events(&row.events),
+        created_at: row.created_at,
+    }
+}
+
+fn delivery_info(row: DeliveryRow) -> WebhookDeliveryInfo {
+    WebhookDeliveryInfo {
+        id: row.id,
+        webhook_id: row.subscription_id,
+        // Only known events are ever queued
+        event: WebhookEvent::parse(&row.event).unwrap_or(WebhookEvent::Created),
+        deposit_address: row.deposit_address,
+        next_attempt_at: (row.status == "pending").then_some(row.next_attempt_at),
+        status: row.status,
+        attempts: row.attempts,
+        last_status_code: row.last_status_code,
+        last_error: row.last_error,
+        delivered_at: row.delivered_at,
+        created_at: row.created_at,
+        payload: None,
+        log: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chain::fake::FakeChain, test_state};
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request},
+        Router,
+    };
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        key: &str,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(AUTHORIZATION, format!("Bearer {}", key))
+            .header("content-type", "application/json");
+        let body = body.map_or_else(Body::empty, |b| Body::from(b.to_string()));
+        let response = app
+            .clone()
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&bytes).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn test_keys_manage_their_own_webhooks() {
+        let state = test_state(FakeChain::new()).await;
+        let (_, shop) = auth::create_key(&state.db, "shop", &[Role::CreateDeposit], None)
+            .await
+            .unwrap();
+        let (_, other) = auth::create_key(&state.db, "other", &[Role::CreateDeposit], None)
+            .await
+            .unwrap();
+        let (_, admin) = auth::create_key(&state.db, "ops", &[Role::Admin], None)
+            .await
+            .unwrap();
+        let app = crate::app(state.clone());
+
+        let subscription = serde_json::json!({
+            "url": "https://orders.example.com/hooks",
+            "events": ["funded", "routed"],
+        });
+        let (status, created) =
+            send(&app, "POST", "/webhooks", &shop, Some(subscription.clone())).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created["tenant"], "shop");
+        assert_eq!(created["events"], serde_json::json!(["funded", "routed"]));
+        assert!(created["secret"].as_str().unwrap().starts_with("whsec_"));
+        let id = created["id"].as_i64().unwrap();
+
+        // Keys can't subscribe to other tenants' deposits
+        let mut foreign = subscription.clone();
+        foreign["tenant"] = "other".into();
+        let (status, _) = send(&app, "POST", "/webhooks", &shop, Some(foreign)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let mut ftp = subscription;
+        ftp["url"] = "ftp://orders.example.com".into();
+        let (status, _) = send(&app, "POST", "/webhooks", &shop, Some(ftp)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        // Nor point the server at its own network
+        for target in ["http://127.0.0.1:8080/", "http://169.254.169.254/latest"] {
+            let request = serde_json::json!({ "url": target });
+            let (status, body) = send(&app, "POST", "/webhooks", &shop, Some(request)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", target);
+            assert!(body["error"]
+                .as_str()
+                .unwrap()
+                .contains("not a public address"));
+        }
+
+        // Each key sees its own subscriptions, admins see all; secrets are
+        // never listed
+        let (_, list) = send(&app, "GET", "/webhooks", &shop, None).await;
+        assert_eq!(list["total"], 1);
+        assert!(list["webhooks"][0].get("secret").is_none());
+        let (_, list) = send(&app, "GET", "/webhooks", &other, None).await;
+        assert_eq!(list["total"], 0);
+        let (_, list) = send(&app, "GET", "/webhooks", &admin, None).await;
+        assert_eq!(list["total"], 1);
+
+        let uri = format!("/webhooks/{}", id);
+        let (status, _) = send(&app, "DELETE", &uri, &other, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&app, "DELETE", &uri, &shop, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, list) = send(&app, "GET", "/webhooks", &shop, None).await;
+        assert_eq!(list["total"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_delivery_log_and_replay() {
+        let mut state = test_state(FakeChain::new()).await;
+        // The subscription points at loopback
+        let mut config = (*state.config).clone();
+        config.webhook_allow_private_targets = true;
+        state.config = std::sync::Arc::new(config);
+        let (_, shop) = auth::create_key(&state.db, "shop", &[Role::CreateDeposit], None)
+            .await
+            .unwrap();
+        let (_, other) = auth::create_key(&state.db, "other", &[Role::CreateDeposit], None)
+            .await
+            .unwrap();
+        let app = crate::app(state.clone());
+
+        let subscription = serde_json::json!({ "url": "http://127.0.0.1:9/hook" });
+        let (_, created) = send(&app, "POST", "/webhooks", &shop, Some(subscription)).await;
+        let id = created["id"].as_i64().unwrap();
+
+        // Creating a deposit queues a `created` event for the key's webhook
+        let user = format!("{:#x}", alloy::primitives::Address::repeat_byte(0x11));
+        let deposit = serde_json::json!({ "user": user });
+        let (status, _) = send(&app, "POST", "/deposit", &shop, Some(deposit)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, list) = send(
+            &app,
+            "GET",
+            &format!("/webhooks/{}/deliveries", id),
+            &shop,
+            None,
+        )
+        .await;
+        assert_eq!(list["total"], 1);
+        assert_eq!(list["deliveries"][0]["event"], "created");
+        assert_eq!(list["deliveries"][0]["status"], "pending");
+        let delivery_id = list["deliveries"][0]["id"].as_i64().unwrap();
+
+        // Simulate a delivery that ran out of attempts
+        let attempt = db::WebhookAttempt {
+            status_code: Some(500),
+            error: Some("HTTP 500 Internal Server Error: "),
+            duration_ms: 12,
+        };
+        db::record_webhook_attempt(&state.db, delivery_id, &attempt, "failed", None)
+            .await
+            .unwrap();
+
+        let uri = format!("/webhooks/deliveries/{}", delivery_id);
+        let (status, _) = send(&app, "GET", &uri, &other, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, delivery) = send(&app, "GET", &uri, &shop, None).await;
+        assert_eq!(delivery["status"], "failed");
+        assert_eq!(delivery["payload"]["event"], "created");
+        assert_eq!(delivery["log"][0]["status_code"], 500);
+
+        let replay = format!("{}/replay", uri);
+        let (status, _) = send(&app, "POST", &replay, &other, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, replayed) = send(&app, "POST", &replay, &shop, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(replayed["status"], "pending");
+        assert_eq!(replayed["attempts"], 0);
+    }
+}
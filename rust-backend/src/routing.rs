@@ -0,0 +1,2275 @@
+//! Deposit routing: move funded deposits through deployment to the treasury
+//!
+//! The routing run is independent of HTTP and of the concrete RPC client so it
+//! can be driven by the `/router` handler or by tests against a fake chain.
+//! Each server runs one routing pass at a time; passes of several workers
+//! share the load through deposit claims and send their transactions one
+//! worker at a time (see [`crate::lease`]). Runs are recorded as routing jobs
+//! (see [`crate::jobs`]).
+
+use std::time::{Duration, Instant};
+
+use alloy::primitives::{Address, FixedBytes, U256};
+use sqlx::SqlitePool;
+use tracing::Instrument;
+
+use crate::{
+    chain::ChainClient,
+    config::Config,
+    db,
+    forwarded::{self, ForwardedScan},
+    invoice, jobs,
+    lease::{LeaseError, LeaseGuard, RoutingLease},
+    metrics,
+    models::{
+        DepositResult, RouteDepositResponse, RouteResponse, RouteStep, RouteTransactionInfo,
+        StepOutcome,
+    },
+    rpc::{parse_address, parse_salt},
+    stranded,
+    treasury::{self, RoutingRules, Split},
+    webhooks::{self, WebhookEvent},
+};
+
+/// Statuses a single deposit can be routed from
+const SINGLE_ROUTE_STATUSES: &[&str] = &["pending", "funded", "deployed", "stranded"];
+
+/// Statuses a routing run claims
+pub(crate) const RUN_STATUSES: &[&str] = &["pending", "funded", "deployed"];
+
+/// Statuses a routing run claims a deposit in only while its proxy forwarded
+/// funds that weren't routed yet; no deposit is moved to `stranded` anymore,
+/// earlier versions did
+pub(crate) const CREDIT_STATUSES: &[&str] = &["routed", "stranded"];
+
+/// Longest wait between two attempts at a deposit
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(6 * 3600);
+
+/// How deposits whose deployment failed are retried
+///
+/// Retriable failures (see [`crate::rpc::RpcError::is_retriable`]) put the
+/// deposit back to `funded` with exponential backoff until `max_attempts`,
+/// after which it is dead-lettered. Terminal failures mark it `failed`. Both
+/// can be requeued through `POST /admin/deposits/:address/requeue`.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubles with each further attempt
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_attempts: config.retry_max_attempts,
+            base_delay: Duration::from_secs(config.retry_base_delay_secs),
+        }
+    }
+
+    /// Status for a deposit whose `attempt`th attempt failed, and when to
+    /// try again
+    pub fn after_failure(&self, attempt: u32, retriable: bool) -> (&'static str, Option<Duration>) {
+        if !retriable {
+            ("failed", None)
+        } else if attempt >= self.max_attempts {
+            ("dead_letter", None)
+        } else {
+            ("funded", Some(self.delay(attempt)))
+        }
+    }
+
+    /// Backoff after the `attempt`th failed attempt
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay
+            .checked_mul(factor)
+            .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+    }
+}
+
+/// Run one routing pass as a job and wait for it, unless another run holds
+/// the routing lease
+pub async fn route_exclusive(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    lease: &RoutingLease,
+    retry: RetryPolicy,
+    requested_by: Option<&str>,
+) -> Result<RouteResponse, LeaseError> {
+    let (job, guard) = jobs::start(pool, lease, requested_by).await?;
+    Ok(jobs::run(pool, chain, guard, retry, job.id).await)
+}
+
+/// Why a single deposit could not be routed
+#[derive(Debug, thiserror::Error)]
+pub enum RouteDepositError {
+    #[error(transparent)]
+    Lease(#[from] LeaseError),
+    #[error("Deposit {0} not found")]
+    NotFound(String),
+    #[error(
+        "Deposit {address} is {status}; only pending, funded, deployed and stranded deposits \
+         can be routed (requeue dead-lettered and failed ones first)"
+    )]
+    InvalidState { address: String, status: String },
+    #[error("Deposit {0} is claimed by another routing run")]
+    Claimed(String),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// Route one deposit now: check its balance, deploy its proxy if needed and
+/// transfer what it forwarded to `treasury`, or per the routing rules
+/// without one
+///
+/// Takes the routing lease like a routing run, so a server never runs both
+/// at once, and claims the deposit. Follows the status flow: a pending deposit without a
+/// balance stays pending, dead-lettered and failed ones must be requeued
+/// first and routed ones are refused. A stranded deposit is routed for what
+/// its proxy forwarded since it was deployed. Unlike a run, it doesn't wait
+/// for a scheduled retry; a failed deployment counts as an attempt under
+/// `retry` all the same.
+#[tracing::instrument(name = "route_deposit", skip_all, fields(deposit = %address))]
+pub async fn route_single(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    lease: &RoutingLease,
+    retry: RetryPolicy,
+    address: &str,
+    treasury: Option<Address>,
+) -> Result<RouteDepositResponse, RouteDepositError> {
+    let guard = lease.try_acquire(pool).await?;
+    let result = route_claimed(pool, chain, &guard, retry, address, treasury).await;
+    guard.release().await;
+    result
+}
+
+async fn route_claimed(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    lease: &LeaseGuard,
+    retry: RetryPolicy,
+    address: &str,
+    treasury: Option<Address>,
+) -> Result<RouteDepositResponse, RouteDepositError> {
+    let claimed = db::claim_deposit(
+        pool,
+        address,
+        SINGLE_ROUTE_STATUSES,
+        lease.holder(),
+        lease.ttl().as_secs(),
+    )
+    .await?;
+    let Some(deposit) = claimed else {
+        return Err(match db::get_deposit_by_address(pool, address).await? {
+            None => RouteDepositError::NotFound(address.to_string()),
+            Some(row) if SINGLE_ROUTE_STATUSES.contains(&row.status.as_str()) => {
+                RouteDepositError::Claimed(address.to_string())
+            }
+            Some(row) => RouteDepositError::InvalidState {
+                address: address.to_string(),
+                status: row.status,
+            },
+        });
+    };
+
+    let steps = route_steps(pool, chain, lease, retry, &deposit, treasury).await;
+    let status = db::get_deposit_by_address(pool, address)
+        .await?
+        .map_or(deposit.status, |row| row.status);
+
+    Ok(RouteDepositResponse {
+        deposit_address: address.to_string(),
+        status,
+        treasury_address: treasury.map(|treasury| format!("{:#x}", treasury)),
+        steps,
+    })
+}
+
+/// Balance check, deployment and transfer of one claimed deposit, stopping
+/// at the first step that fails
+async fn route_steps(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    lease: &LeaseGuard,
+    retry: RetryPolicy,
+    deposit: &db::DepositRow,
+    treasury: Option<Address>,
+) -> Vec<RouteStep> {
+    let addr = deposit.deposit_address.as_str();
+    let mut steps = vec![];
+
+    let proxy_addr = match parse_address(addr) {
+        Ok(proxy_addr) => proxy_addr,
+        Err(e) => {
+            steps.push(step(
+                "balance_check",
+                StepOutcome::Failed,
+                Some(e.to_string()),
+            ));
+            return steps;
+        }
+    };
+
+    let balance = match chain
+        .get_balance(proxy_addr)
+        .instrument(tracing::info_span!("balance_check", deposit = %addr))
+        .await
+    {
+        Ok(balance) => balance,
+        Err(e) => {
+            let error = format!("Balance check failed: {}", e);
+            steps.push(step("balance_check", StepOutcome::Failed, Some(error)));
+            return steps;
+        }
+    };
+    let mut check = step("balance_check", StepOutcome::Done, None);
+    check.amount_wei = Some(balance.to_string());
+    // Before deployment the balance is all the deposit received; afterwards
+    // the transfer records it with what the proxy forwarded
+    if balance > U256::ZERO && matches!(deposit.status.as_str(), "pending" | "funded") {
+        if let Err(e) = invoice::record_payment(pool, chain, addr, balance).await {
+            tracing::error!("Failed to record payment for {}: {}", addr, e);
+        }
+    }
+    if deposit.status == "pending" {
+        if balance == U256::ZERO {
+            check.detail = Some("No balance yet; the deposit stays pending".to_string());
+            steps.push(check);
+            return steps;
+        }
+        if let Err(e) = db::update_deposit_status(pool, addr, "funded").await {
+            tracing::error!("Failed to update status for {}: {}", addr, e);
+            check.outcome = StepOutcome::Failed;
+            check.detail = Some(format!("DB update failed: {}", e));
+            steps.push(check);
+            return steps;
+        }
+        webhooks::notify(pool, addr, WebhookEvent::Funded).await;
+    }
+    steps.push(check);
+
+    if deposit.status == "deployed" || deposit.status == "stranded" {
+        steps.push(step(
+            "deploy",
+            StepOutcome::Skipped,
+            Some("Proxy already deployed".to_string()),
+        ));
+    } else {
+        let sending = match lease.transactions().await {
+            Ok(sending) => sending,
+            Err(e) => {
+                steps.push(step("deploy", StepOutcome::Failed, Some(not_sent(e))));
+                return steps;
+            }
+        };
+        let deploy = deploy_one(pool, chain, retry, deposit, proxy_addr).await;
+        sending.release().await;
+        let failed = deploy.outcome == StepOutcome::Failed;
+        steps.push(deploy);
+        if failed {
+            return steps;
+        }
+    }
+
+    let splits = match treasury {
+        Some(treasury) => Split::whole(treasury),
+        None => match rule_splits(pool, chain, deposit).await {
+            Ok(splits) => splits,
+            Err(error) => {
+                steps.push(step("transfer", StepOutcome::Failed, Some(error)));
+                return steps;
+            }
+        },
+    };
+
+    let scan = scan_forwarded(pool, chain).await;
+    let mut response = RouteResponse::default();
+    let sending = match lease.transactions().await {
+        Ok(sending) => sending,
+        Err(e) => {
+            steps.push(step("transfer", StepOutcome::Failed, Some(not_sent(e))));
+            return steps;
+        }
+    };
+    transfer_one(pool, chain, addr, proxy_addr, &splits, &scan, &mut response).await;
+    sending.release().await;
+    // An invoice deposit reports its payment even when nothing was routed
+    let result = response.deposits.pop();
+    if response.route_tx_hashes.is_empty() && result.as_ref().map_or(true, |r| r.error.is_none()) {
+        let mut detail = "Nothing forwarded to the router yet".to_string();
+        if response.stranded > 0 {
+            detail
+                .push_str("; the balance on the address arrived before the proxy and is stranded");
+        }
+        steps.push(step("transfer", StepOutcome::Skipped, Some(detail)));
+        return steps;
+    }
+    let Some(result) = result else {
+        return steps;
+    };
+    for leg in response.route_tx_hashes {
+        let mut transfer = step("transfer", StepOutcome::Done, None);
+        transfer.tx_hash = Some(leg.tx_hash);
+        transfer.amount_wei = Some(leg.amount_wei);
+        transfer.treasury_address = Some(leg.treasury_address);
+        steps.push(transfer);
+    }
+    if result.status != "routed" {
+        let mut transfer = step("transfer", StepOutcome::Failed, result.error);
+        transfer.tx_hash = result.tx_hash;
+        transfer.amount_wei = result.amount_wei;
+        steps.push(transfer);
+    }
+    steps
+}
+
+/// Deploy the proxy of one funded deposit, unless it already has code; call
+/// while holding the transactions lease
+async fn deploy_one(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    retry: RetryPolicy,
+    deposit: &db::DepositRow,
+    proxy_addr: Address,
+) -> RouteStep {
+    let addr = deposit.deposit_address.as_str();
+    if let Err(e) = forwarded::start(pool, chain, &[addr]).await {
+        return step("deploy", StepOutcome::Failed, Some(e));
+    }
+    if chain
+        .get_code(proxy_addr)
+        .await
+        .is_ok_and(|code| !code.is_empty())
+    {
+        // A run died between deploying the proxy and recording it
+        mark_deployed(pool, addr).await;
+        return step(
+            "deploy",
+            StepOutcome::Skipped,
+            Some("Proxy already has code".to_string()),
+        );
+    }
+    let salt = match parse_salt(&deposit.salt) {
+        Ok(salt) => salt,
+        Err(e) => {
+            let error = format!("Invalid salt: {}", e);
+            return step("deploy", StepOutcome::Failed, Some(error));
+        }
+    };
+
+    let span = tracing::info_span!("deploy", proxies = 1, deposits = %addr);
+    let deployment = chain.deploy_multiple(vec![salt]).instrument(span).await;
+    metrics::deploy_outcome(&deployment);
+    match deployment {
+        Ok(tx_hash) => {
+            tracing::info!("Deployed proxy {}, tx: {:#x}", addr, tx_hash);
+            let mut deploy = match check_deployed(chain, addr, proxy_addr).await {
+                Ok(()) => {
+                    mark_deployed(pool, addr).await;
+                    step("deploy", StepOutcome::Done, None)
+                }
+                Err((error, retriable)) => {
+                    record_deploy_failure(pool, retry, addr, deposit.attempts, retriable, &error)
+                        .await;
+                    step("deploy", StepOutcome::Failed, Some(error))
+                }
+            };
+            deploy.tx_hash = Some(format!("{:#x}", tx_hash));
+            deploy
+        }
+        Err(e) => {
+            tracing::error!("deployMultiple failed for {}: {}", addr, e);
+            let error = format!("Deploy failed: {}", e);
+            record_deploy_failure(
+                pool,
+                retry,
+                addr,
+                deposit.attempts,
+                e.is_retriable(),
+                &error,
+            )
+            .await;
+            step("deploy", StepOutcome::Failed, Some(error))
+        }
+    }
+}
+
+/// Why transactions weren't sent without the transactions lease
+fn not_sent(e: LeaseError) -> String {
+    match e {
+        LeaseError::Busy(active) => format!(
+            "Another routing run is sending transactions (held by {} until {}); try again later",
+            active.holder, active.expires_at
+        ),
+        LeaseError::Database(e) => format!("Database error: {}", e),
+    }
+}
+
+/// Where a deposit's funds go under the routing rules
+async fn rule_splits(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    deposit: &db::DepositRow,
+) -> Result<Vec<Split>, String> {
+    let rules = RoutingRules::load(pool)
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+    rules
+        .splits_for(deposit, chain.treasury_address())
+        .map_err(|e| e.to_string())
+}
+
+fn step(name: &'static str, outcome: StepOutcome, detail: Option<String>) -> RouteStep {
+    RouteStep {
+        step: name,
+        outcome,
+        detail,
+        tx_hash: None,
+        amount_wei: None,
+        treasury_address: None,
+    }
+}
+
+/// Run one routing pass over all pending, funded and deployed deposits
+///
+/// 1. Claim all unclaimed 'pending', 'funded' and 'deployed' deposits for
+///    this run, and routed ones whose proxies forwarded more since
+/// 2. Check balances on-chain for pending deposits
+/// 3. Update funded deposits (balance > 0) to 'funded' status
+/// 4. Deploy proxies for funded deposits using deployMultiple(), skipping
+///    proxies that already have code (a failed deployment is retried in a
+///    later run, see [`RetryPolicy`])
+/// 5. For each deployed proxy, take the ETH it forwarded to the FundRouter
+///    (counted for all proxies at the start of the run, see
+///    [`crate::forwarded`]) and call the router's transferFunds() to pay what
+///    wasn't routed yet, once per treasury its routing rule splits the funds
+///    across (see [`crate::treasury`])
+/// 6. Update status to 'routed' on success
+/// 7. Record what a proxy holds as stranded: it arrived before the
+///    deployment. The deposit keeps its status.
+///
+/// Failures are collected in `errors` rather than aborting the whole run. Every
+/// run is recorded in `routing_runs` for health reporting. With a `job`, its
+/// progress is stored after each step.
+#[tracing::instrument(
+    name = "routing_run",
+    skip_all,
+    fields(checked = tracing::field::Empty, routed = tracing::field::Empty)
+)]
+pub async fn route_deposits(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    lease: &LeaseGuard,
+    retry: RetryPolicy,
+    job: Option<i64>,
+) -> RouteResponse {
+    let started = Instant::now();
+    let response = run_pass(pool, chain, lease, retry, job).await;
+    metrics::routing_run(response.errors.is_empty(), started.elapsed());
+
+    let span = tracing::Span::current();
+    span.record("checked", response.checked);
+    span.record("routed", response.routed);
+
+    if let Err(e) = db::insert_routing_run(
+        pool,
+        response.checked,
+        response.routed,
+        response.stranded,
+        response.errors.len(),
+    )
+    .await
+    {
+        tracing::warn!("Failed to record routing run: {}", e);
+    }
+
+    response
+}
+
+async fn run_pass(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    lease: &LeaseGuard,
+    retry: RetryPolicy,
+    job: Option<i64>,
+) -> RouteResponse {
+    tracing::info!("Starting deposit routing process");
+
+    let mut response = RouteResponse::default();
+
+    match invoice::expire_unpaid(pool, chain).await {
+        Ok(0) => {}
+        Ok(expired) => tracing::info!("{} unpaid invoices expired", expired),
+        Err(e) => tracing::warn!("Failed to expire invoices: {}", e),
+    }
+
+    // One scan of what every proxy forwarded, for all deposits of the run
+    let scan = scan_forwarded(pool, chain).await;
+    if let Err(e) = &scan {
+        tracing::error!("{}", e);
+        response.errors.push(e.clone());
+    }
+
+    // Claim pending and funded deposits; rows claimed by another live run
+    // are left to it
+    let mut deposits =
+        match db::claim_deposits(pool, RUN_STATUSES, lease.holder(), lease.ttl().as_secs()).await {
+            Ok(deps) => deps,
+            Err(e) => {
+                tracing::error!("Failed to fetch deposits: {}", e);
+                response.errors.push(format!("Database error: {}", e));
+                return response;
+            }
+        };
+
+    // Routed deposits whose proxies forwarded more since
+    if let Ok(scan) = &scan {
+        for addr in scan.with_credit() {
+            if deposits.iter().any(|d| d.deposit_address == addr) {
+                continue;
+            }
+            match db::claim_deposit(
+                pool,
+                addr,
+                CREDIT_STATUSES,
+                lease.holder(),
+                lease.ttl().as_secs(),
+            )
+            .await
+            {
+                Ok(Some(deposit)) => deposits.push(deposit),
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::error!("Failed to claim {}: {}", addr, e);
+                    response.errors.push(format!("Database error: {}", e));
+                }
+            }
+        }
+    }
+
+    if deposits.is_empty() {
+        tracing::info!("No pending, funded or deployed deposits to process");
+        return response;
+    }
+
+    response.checked = deposits.len();
+    for deposit in &deposits {
+        result_of(&mut response, &deposit.deposit_address).status = deposit.status.clone();
+    }
+    tracing::info!("Found {} deposits to check", deposits.len());
+    progress(pool, job, &response).await;
+
+    // Separate pending (need balance check) and already funded
+    let pending_deposits: Vec<_> = deposits.iter().filter(|d| d.status == "pending").collect();
+    let funded_deposits: Vec<_> = deposits.iter().filter(|d| d.status == "funded").collect();
+
+    // Check balances for pending deposits
+    let mut balances: Vec<(String, U256)> = vec![];
+    for deposit in &pending_deposits {
+        match parse_address(&deposit.deposit_address) {
+            Ok(addr) => match chain
+                .get_balance(addr)
+                .instrument(tracing::info_span!(
+                    "balance_check",
+                    deposit = %deposit.deposit_address
+                ))
+                .await
+            {
+                Ok(balance) => {
+                    balances.push((deposit.deposit_address.clone(), balance));
+                    if balance > U256::ZERO {
+                        tracing::info!(
+                            "Deposit {} has balance: {} wei",
+                            deposit.deposit_address,
+                            balance
+                        );
+                        record_payment(
+                            pool,
+                            chain,
+                            &deposit.deposit_address,
+                            balance,
+                            &mut response,
+                        )
+                        .await;
+                    }
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "Failed to get balance for {}: {}",
+                        deposit.deposit_address,
+                        e
+                    );
+                    fail(
+                        &mut response,
+                        &deposit.deposit_address,
+                        format!(
+                            "Balance check failed for {}: {}",
+                            deposit.deposit_address, e
+                        ),
+                    );
+                }
+            },
+            Err(e) => {
+                tracing::error!("Invalid address {}: {}", deposit.deposit_address, e);
+            }
+        }
+    }
+
+    // Update status to 'funded' for deposits with balance > 0
+    let mut newly_funded = vec![];
+    for (addr, balance) in &balances {
+        if *balance > U256::ZERO {
+            if let Err(e) = db::update_deposit_status(pool, addr, "funded").await {
+                tracing::error!("Failed to update status for {}: {}", addr, e);
+                fail(
+                    &mut response,
+                    addr,
+                    format!("DB update failed for {}: {}", addr, e),
+                );
+            } else {
+                result_of(&mut response, addr).status = "funded".to_string();
+                newly_funded.push(addr.clone());
+                webhooks::notify(pool, addr, WebhookEvent::Funded).await;
+            }
+        }
+    }
+
+    response.funded = newly_funded.len() + funded_deposits.len();
+    tracing::info!(
+        "{} newly funded, {} previously funded",
+        newly_funded.len(),
+        funded_deposits.len()
+    );
+    progress(pool, job, &response).await;
+
+    // Collect all funded deposits for deployment
+    let mut deposits_to_deploy: Vec<&db::DepositRow> = vec![];
+
+    // Add previously funded deposits
+    deposits_to_deploy.extend(funded_deposits.iter().copied());
+
+    // Add newly funded deposits (find them in pending_deposits)
+    for addr in &newly_funded {
+        if let Some(dep) = pending_deposits.iter().find(|d| &d.deposit_address == addr) {
+            deposits_to_deploy.push(*dep);
+        }
+    }
+
+    // Proxies deployed earlier, waiting for what they forward to be routed
+    let mut deployed: Vec<String> = deposits
+        .iter()
+        .filter(|d| d.status == "deployed" || CREDIT_STATUSES.contains(&d.status.as_str()))
+        .map(|d| d.deposit_address.clone())
+        .collect();
+    deployed.extend(
+        deploy_funded(
+            pool,
+            chain,
+            lease,
+            retry,
+            &deposits_to_deploy,
+            &mut response,
+        )
+        .await,
+    );
+    if deployed.is_empty() {
+        return response;
+    }
+    progress(pool, job, &response).await;
+
+    // Now route funds from each deployed proxy to its treasuries
+    let rules = match RoutingRules::load(pool).await {
+        Ok(rules) => rules,
+        Err(e) => {
+            tracing::error!("Failed to load routing rules: {}", e);
+            response.errors.push(format!("Database error: {}", e));
+            return response;
+        }
+    };
+    for addr in &deployed {
+        let Some(deposit) = deposits.iter().find(|d| &d.deposit_address == addr) else {
+            continue;
+        };
+        let splits = match rules.splits_for(deposit, chain.treasury_address()) {
+            Ok(splits) => splits,
+            Err(e) => {
+                tracing::error!("No treasury for {}: {}", addr, e);
+                fail(
+                    &mut response,
+                    addr,
+                    format!("No treasury for {}: {}", addr, e),
+                );
+                continue;
+            }
+        };
+        match parse_address(addr) {
+            Ok(proxy_addr) => {
+                // Taken per deposit, so other workers' transfers go in between
+                let sending = match lease.transactions().await {
+                    Ok(sending) => sending,
+                    Err(e) => {
+                        let error = not_sent(e);
+                        tracing::error!("Stopping transfers: {}", error);
+                        fail(&mut response, addr, error);
+                        break;
+                    }
+                };
+                let go_on =
+                    transfer_one(pool, chain, addr, proxy_addr, &splits, &scan, &mut response)
+                        .await;
+                sending.release().await;
+                progress(pool, job, &response).await;
+                if !go_on {
+                    break;
+                }
+            }
+            Err(e) => {
+                tracing::error!("Invalid proxy address {}: {}", addr, e);
+            }
+        }
+    }
+
+    tracing::info!(
+        "Routing complete: checked={}, funded={}, deployed={}, routed={}, stranded={}",
+        response.checked,
+        response.funded,
+        response.deployed,
+        response.routed,
+        response.stranded
+    );
+
+    response
+}
+
+/// Deploy the proxies of funded deposits in one deployMultiple() and return
+/// the deposits whose proxies are deployed
+///
+/// Proxies that already have code are recorded instead of redeployed. A
+/// failed deployment is retried in a later run, see [`RetryPolicy`]. The
+/// batch is sent under the transactions lease; without it the deposits stay
+/// funded for a later run.
+async fn deploy_funded(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    lease: &LeaseGuard,
+    retry: RetryPolicy,
+    deposits_to_deploy: &[&db::DepositRow],
+    response: &mut RouteResponse,
+) -> Vec<String> {
+    if deposits_to_deploy.is_empty() {
+        tracing::info!("No funded deposits to deploy");
+        return vec![];
+    }
+
+    // Parse salts for deployment
+    let mut salts_and_deposits = vec![];
+    for deposit in deposits_to_deploy {
+        match parse_salt(&deposit.salt) {
+            Ok(salt) => {
+                salts_and_deposits.push((salt, deposit.deposit_address.clone(), deposit.attempts));
+            }
+            Err(e) => {
+                tracing::error!("Invalid salt for {}: {}", deposit.deposit_address, e);
+                fail(
+                    response,
+                    &deposit.deposit_address,
+                    format!("Invalid salt for {}: {}", deposit.deposit_address, e),
+                );
+            }
+        }
+    }
+
+    if salts_and_deposits.is_empty() {
+        tracing::info!("No valid salts to deploy");
+        return vec![];
+    }
+
+    let sending = match lease.transactions().await {
+        Ok(sending) => sending,
+        Err(e) => {
+            let error = not_sent(e);
+            tracing::error!("Not deploying: {}", error);
+            response.errors.push(error);
+            return vec![];
+        }
+    };
+    let deployed = deploy_batch(pool, chain, retry, salts_and_deposits, response).await;
+    sending.release().await;
+    deployed
+}
+
+/// Deploy the proxies of `salts_and_deposits` (salt, address, attempts so
+/// far) in one deployMultiple(); call while holding the transactions lease
+async fn deploy_batch(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    retry: RetryPolicy,
+    salts_and_deposits: Vec<(FixedBytes<32>, String, i64)>,
+    response: &mut RouteResponse,
+) -> Vec<String> {
+    let addrs: Vec<&str> = salts_and_deposits
+        .iter()
+        .map(|(_, addr, _)| addr.as_str())
+        .collect();
+    if let Err(e) = forwarded::start(pool, chain, &addrs).await {
+        tracing::error!("{}", e);
+        response.errors.push(e);
+        return vec![];
+    }
+
+    // A deployment reported as failed may still have been mined, e.g. after a
+    // receipt timeout. CREATE2 to an occupied address reverts the whole batch,
+    // so proxies that already have code are recorded instead of redeployed.
+    let mut deployed = vec![];
+    let mut to_deploy = vec![];
+    for (salt, addr, attempts) in salts_and_deposits {
+        let has_code = match parse_address(&addr) {
+            Ok(proxy_addr) => chain
+                .get_code(proxy_addr)
+                .await
+                .is_ok_and(|code| !code.is_empty()),
+            Err(_) => false,
+        };
+        if has_code {
+            tracing::info!("Proxy {} already has code, skipping its deployment", addr);
+            result_of(response, &addr).status = "deployed".to_string();
+            mark_deployed(pool, &addr).await;
+            deployed.push(addr);
+        } else {
+            to_deploy.push((salt, addr, attempts));
+        }
+    }
+
+    if !to_deploy.is_empty() {
+        let salts: Vec<_> = to_deploy.iter().map(|(s, _, _)| *s).collect();
+        tracing::info!("Deploying {} proxies", salts.len());
+
+        // Deploy all proxies in one transaction
+        let span = tracing::info_span!(
+            "deploy",
+            proxies = salts.len(),
+            deposits = %to_deploy
+                .iter()
+                .map(|(_, addr, _)| addr.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+            tx_hash = tracing::field::Empty
+        );
+        let deployment = chain.deploy_multiple(salts).instrument(span.clone()).await;
+        if let Ok(tx_hash) = &deployment {
+            span.record("tx_hash", tracing::field::display(tx_hash));
+        }
+        metrics::deploy_outcome(&deployment);
+        match deployment {
+            Ok(tx_hash) => {
+                response.deploy_tx_hash = Some(format!("{:#x}", tx_hash));
+                tracing::info!("Deployed {} proxies, tx: {:#x}", to_deploy.len(), tx_hash);
+
+                // Update status to 'deployed' where the proxy landed
+                for (_, addr, attempts) in to_deploy {
+                    let proxy_addr = match parse_address(&addr) {
+                        Ok(proxy_addr) => proxy_addr,
+                        Err(e) => {
+                            fail(response, &addr, format!("Invalid address {}: {}", addr, e));
+                            continue;
+                        }
+                    };
+                    if let Err((error, retriable)) = check_deployed(chain, &addr, proxy_addr).await
+                    {
+                        tracing::error!("{}", error);
+                        response.errors.push(error.clone());
+                        let status =
+                            record_deploy_failure(pool, retry, &addr, attempts, retriable, &error)
+                                .await;
+                        let result = result_of(response, &addr);
+                        result.status = status.to_string();
+                        result.error = Some(error);
+                        continue;
+                    }
+                    response.deployed += 1;
+                    result_of(response, &addr).status = "deployed".to_string();
+                    mark_deployed(pool, &addr).await;
+                    deployed.push(addr);
+                }
+            }
+            Err(e) => {
+                tracing::error!("deployMultiple failed: {}", e);
+                let error = format!("Deploy failed: {}", e);
+                response.errors.push(error.clone());
+                // Schedule a retry, dead-letter or mark as failed
+                for (_, addr, attempts) in &to_deploy {
+                    let status = record_deploy_failure(
+                        pool,
+                        retry,
+                        addr,
+                        *attempts,
+                        e.is_retriable(),
+                        &error,
+                    )
+                    .await;
+                    let result = result_of(response, addr);
+                    result.status = status.to_string();
+                    result.error = Some(error.clone());
+                }
+            }
+        }
+    }
+    deployed
+}
+
+/// Schedule a retry for a deposit whose deployment failed, or dead-letter or
+/// fail it, per `retry`; returns its new status
+async fn record_deploy_failure(
+    pool: &SqlitePool,
+    retry: RetryPolicy,
+    addr: &str,
+    attempts: i64,
+    retriable: bool,
+    error: &str,
+) -> &'static str {
+    let attempt = u32::try_from(attempts + 1).unwrap_or(u32::MAX);
+    let (status, retry_in) = retry.after_failure(attempt, retriable);
+    match retry_in {
+        Some(delay) => tracing::warn!(
+            "Retrying {} in {}s (attempt {} of {})",
+            addr,
+            delay.as_secs(),
+            attempt,
+            retry.max_attempts
+        ),
+        None if status == "dead_letter" => {
+            tracing::error!("Dead-lettered {} after {} attempts", addr, attempt)
+        }
+        None => {}
+    }
+
+    let recorded = db::record_deposit_failure(
+        pool,
+        addr,
+        status,
+        error,
+        retry_in.map(|delay| delay.as_secs()),
+    )
+    .await;
+    match recorded {
+        Ok(()) if retry_in.is_none() => {
+            webhooks::notify(pool, addr, WebhookEvent::Failed).await;
+        }
+        Ok(()) => {}
+        Err(e) => tracing::error!("Failed to record failure for {}: {}", addr, e),
+    }
+    status
+}
+
+/// Count and record what every proxy forwarded since the last scan
+async fn scan_forwarded(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+) -> Result<ForwardedScan, String> {
+    let scan = forwarded::scan(pool, chain).await?;
+    forwarded::record(pool, &scan).await?;
+    Ok(scan)
+}
+
+/// Check that a mined deployment put a proxy at the deposit address, with
+/// whether a failure is worth retrying
+///
+/// `deployMultiple` derives the address from the signer sending it, so a
+/// deposit address derived from another deployer, init code hash or signer
+/// stays empty.
+async fn check_deployed(
+    chain: &dyn ChainClient,
+    addr: &str,
+    proxy_addr: Address,
+) -> Result<(), (String, bool)> {
+    match chain.get_code(proxy_addr).await {
+        Ok(code) if !code.is_empty() => Ok(()),
+        Ok(_) => Err((
+            format!(
+                "No proxy code at {} after deployMultiple; the address doesn't match the deployer, init code hash and signer",
+                addr
+            ),
+            false,
+        )),
+        Err(e) => Err((format!("Code check failed for {}: {}", addr, e), true)),
+    }
+}
+
+/// Record a confirmed proxy deployment
+async fn mark_deployed(pool: &SqlitePool, addr: &str) {
+    match db::update_deposit_status(pool, addr, "deployed").await {
+        Ok(()) => webhooks::notify(pool, addr, WebhookEvent::Confirmed).await,
+        Err(e) => tracing::error!("Failed to update status to deployed for {}: {}", addr, e),
+    }
+}
+
+/// Store a job's progress so far
+async fn progress(pool: &SqlitePool, job: Option<i64>, response: &RouteResponse) {
+    if let Some(id) = job {
+        jobs::report_progress(pool, id, response).await;
+    }
+}
+
+/// Per-deposit result for `addr`, added on first use
+fn result_of<'a>(response: &'a mut RouteResponse, addr: &str) -> &'a mut DepositResult {
+    let index = match response
+        .deposits
+        .iter()
+        .position(|d| d.deposit_address == addr)
+    {
+        Some(index) => index,
+        None => {
+            response.deposits.push(DepositResult {
+                deposit_address: addr.to_string(),
+                status: String::new(),
+                tx_hash: None,
+                amount_wei: None,
+                payment_status: None,
+                error: None,
+            });
+            response.deposits.len() - 1
+        }
+    };
+    &mut response.deposits[index]
+}
+
+/// Record an error against a single deposit
+fn fail(response: &mut RouteResponse, addr: &str, error: String) {
+    result_of(response, addr).error = Some(error.clone());
+    response.errors.push(error);
+}
+
+/// Pay what one deployed proxy forwarded to the FundRouter to the
+/// treasuries of `splits`, one router transferFunds call each, and record the
+/// outcome
+///
+/// ETH that reached the address before the proxy was deployed stays on the
+/// proxy for good. It is recorded as stranded and the deposit keeps its
+/// status, so what the proxy forwards later is still routed. Stops at the first
+/// transfer that fails; the deposit keeps its status and the transfers
+/// already made are recorded as allocations, so a later attempt only pays
+/// the rest.
+///
+/// Before each transfer the router's balance must cover the credit of all
+/// deposits (see [`forwarded::check_router_covers`]); otherwise nothing is
+/// transferred and `false` is returned to stop the run's other transfers.
+#[tracing::instrument(
+    name = "transfer",
+    skip_all,
+    fields(deposit = %addr, tx_hash = tracing::field::Empty)
+)]
+async fn transfer_one(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    addr: &str,
+    proxy_addr: Address,
+    splits: &[Split],
+    scan: &Result<ForwardedScan, String>,
+    response: &mut RouteResponse,
+) -> bool {
+    // Without a reading nothing is recorded as stranded; `stranded::scan`
+    // checks the proxy later
+    let stuck = match chain.get_balance(proxy_addr).await {
+        Ok(balance) => balance,
+        Err(e) => {
+            tracing::warn!("Failed to check balance of {}: {}", addr, e);
+            fail(
+                response,
+                addr,
+                format!("Balance check failed for {}: {}", addr, e),
+            );
+            return true;
+        }
+    };
+    let counted = match scan {
+        Ok(scan) => scan.get(addr),
+        Err(e) => {
+            fail(
+                response,
+                addr,
+                format!("Forwarded funds unknown for {}: {}", addr, e),
+            );
+            return true;
+        }
+    };
+    // Not counted yet: deployed in this run, or before counting began
+    let counted = match counted {
+        Some(counted) => counted,
+        None => match forwarded::start(pool, chain, &[addr]).await {
+            Ok(()) => Default::default(),
+            Err(e) => {
+                fail(
+                    response,
+                    addr,
+                    format!("Forwarded funds unknown for {}: {}", addr, e),
+                );
+                return true;
+            }
+        },
+    };
+    let (forwarded, credit) = (counted.forwarded, counted.credit);
+    if stuck > U256::ZERO || credit > U256::ZERO {
+        record_payment(pool, chain, addr, stuck.saturating_add(forwarded), response).await;
+    }
+
+    if stuck > U256::ZERO {
+        metrics::transfer_outcome("stranded");
+        tracing::warn!(
+            "Funds stranded on {}: {} wei arrived before the proxy was deployed",
+            addr,
+            stuck
+        );
+        response.stranded += 1;
+        if let Err(e) = stranded::mark_stranded(pool, addr, stuck).await {
+            tracing::error!("Failed to record stranded funds for {}: {}", addr, e);
+        }
+    }
+    if credit == U256::ZERO {
+        tracing::info!("Nothing forwarded by {} to route yet", addr);
+        return true;
+    }
+
+    let amounts = treasury::split_amounts(credit, splits);
+    let mut legs = 0;
+    let mut last_tx_hash = None;
+    for (split, amount) in splits.iter().zip(amounts) {
+        if amount == U256::ZERO {
+            continue;
+        }
+        if let Err(e) = forwarded::check_router_covers(pool, chain).await {
+            tracing::error!("Stopping transfers at {}: {}", addr, e);
+            fail(
+                response,
+                addr,
+                format!("Transfers stopped at {}: {}", addr, e),
+            );
+            return false;
+        }
+        match chain.transfer_from_router(split.treasury, amount).await {
+            Ok(tx_hash) => {
+                // Recorded right away, so the next check counts it as paid
+                record_allocations(pool, addr, &[(*split, amount, tx_hash)], response).await;
+                legs += 1;
+                last_tx_hash = Some(tx_hash);
+            }
+            Err(e) => {
+                metrics::transfer_outcome(e.kind());
+                tracing::error!(
+                    "transferFunds to {} failed for {}: {}",
+                    split.treasury,
+                    addr,
+                    e
+                );
+                fail(
+                    response,
+                    addr,
+                    format!("Transfer failed for {}: {}", addr, e),
+                );
+                return true;
+            }
+        }
+    }
+    let Some(tx_hash) = last_tx_hash else {
+        return true;
+    };
+    tracing::Span::current().record("tx_hash", tracing::field::display(tx_hash));
+
+    response.routed += 1;
+    let result = result_of(response, addr);
+    result.status = "routed".to_string();
+    result.tx_hash = Some(format!("{:#x}", tx_hash));
+    result.amount_wei = Some(credit.to_string());
+    metrics::transfer_outcome("success");
+    metrics::wei_routed(credit);
+
+    // Update status to 'routed'
+    match db::update_deposit_status(pool, addr, "routed").await {
+        Ok(()) => webhooks::notify(pool, addr, WebhookEvent::Routed).await,
+        Err(e) => tracing::error!("Failed to update status to routed for {}: {}", addr, e),
+    }
+
+    tracing::info!(
+        "Routed {} wei forwarded by {} to {} treasuries, last tx: {:#x}",
+        credit,
+        addr,
+        legs,
+        tx_hash
+    );
+    true
+}
+
+/// Classify the payment of an invoice deposit that received `balance`
+async fn record_payment(
+    pool: &SqlitePool,
+    chain: &dyn ChainClient,
+    addr: &str,
+    balance: U256,
+    response: &mut RouteResponse,
+) {
+    match invoice::record_payment(pool, chain, addr, balance).await {
+        Ok(Some(status)) => result_of(response, addr).payment_status = Some(status),
+        Ok(None) => {}
+        Err(e) => tracing::error!("Failed to record payment for {}: {}", addr, e),
+    }
+}
+
+/// Record which treasury got how much of a deposit, one allocation per
+/// transfer made
+async fn record_allocations(
+    pool: &SqlitePool,
+    addr: &str,
+    legs: &[(Split, U256, FixedBytes<32>)],
+    response: &mut RouteResponse,
+) {
+    for (split, amount, tx_hash) in legs {
+        let treasury_address = format!("{:#x}", split.treasury);
+        let tx_hash = format!("{:#x}", tx_hash);
+        let amount_wei = amount.to_string();
+        if let Err(e) = db::insert_allocation(
+            pool,
+            addr,
+            &treasury_address,
+            split.share_bps,
+            &amount_wei,
+            &tx_hash,
+        )
+        .await
+        {
+            tracing::error!("Failed to record allocation for {}: {}", addr, e);
+        }
+        response.route_tx_hashes.push(RouteTransactionInfo {
+            proxy_address: addr.to_string(),
+            tx_hash,
+            amount_wei,
+            treasury_address,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chain::fake::{self, FakeChain},
+        create2::{compute_deposit_address, format_address, format_bytes32},
+    };
+    use alloy::primitives::Address;
+    use db::test_pool;
+    use std::time::Duration;
+
+    fn retry() -> RetryPolicy {
+        RetryPolicy::from_config(&crate::config::test_config())
+    }
+
+    /// One exclusive routing pass
+    async fn run(pool: &SqlitePool, chain: &FakeChain) -> RouteResponse {
+        let lease = RoutingLease::new(Duration::from_secs(60));
+        route_exclusive(pool, chain, &lease, retry(), None)
+            .await
+            .unwrap()
+    }
+
+    /// Insert a deposit with the given status and return its address
+    async fn seed(pool: &SqlitePool, nonce: u64, status: &str) -> Address {
+        let user = [0x42u8; 20];
+        let (addr, salt) = compute_deposit_address(
+            &fake::DEPLOYER.0 .0,
+            &fake::INIT_CODE_HASH.0,
+            &fake::SIGNER.0 .0,
+            &user,
+            nonce,
+        );
+        let deposit_address = format_address(&addr);
+        db::insert_deposit(
+            pool,
+            &format_address(&user),
+            &format_bytes32(&salt),
+            &deposit_address,
+            nonce,
+            None,
+        )
+        .await
+        .unwrap();
+        db::update_deposit_status(pool, &deposit_address, status)
+            .await
+            .unwrap();
+        Address::from(addr)
+    }
+
+    /// Insert a deposit whose proxy is already deployed and return its
+    /// address; what it forwards from now on is counted
+    async fn seed_deployed(pool: &SqlitePool, chain: &FakeChain, nonce: u64) -> Address {
+        let addr = seed(pool, nonce, "deployed").await;
+        chain.set_code(addr);
+        let block = chain.block_number().await.unwrap();
+        db::start_forwarded_scan(pool, &format_address(&addr.0 .0), block)
+            .await
+            .unwrap();
+        addr
+    }
+
+    async fn row_of(pool: &SqlitePool, address: Address) -> db::DepositRow {
+        db::get_deposit_by_address(pool, &format_address(&address.0 .0))
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    async fn status_of(pool: &SqlitePool, address: Address) -> String {
+        row_of(pool, address).await.status
+    }
+
+    #[derive(Clone, Copy)]
+    enum Chain {
+        /// ETH reached the address before its proxy was deployed
+        Prefunded(u64),
+        /// The deployed proxy forwarded this much to the router
+        Forwarded(u64),
+        /// Deposit has no balance
+        Empty,
+        /// Balance reads fail
+        BalanceFails,
+        /// The deployed proxy forwarded this much but transferFunds reverts
+        TransferReverts(u64),
+    }
+
+    struct Case {
+        name: &'static str,
+        deposits: &'static [(&'static str, Chain)],
+        deploy_reverts: bool,
+        expect_counts: (usize, usize, usize, usize, usize),
+        expect_statuses: &'static [&'static str],
+        expect_errors: usize,
+        expect_treasury: u64,
+    }
+
+    const CASES: &[Case] = &[
+        Case {
+            name: "nothing to do",
+            deposits: &[],
+            deploy_reverts: false,
+            expect_counts: (0, 0, 0, 0, 0),
+            expect_statuses: &[],
+            expect_errors: 0,
+            expect_treasury: 0,
+        },
+        Case {
+            name: "unfunded deposits stay pending",
+            deposits: &[("pending", Chain::Empty), ("pending", Chain::Empty)],
+            deploy_reverts: false,
+            expect_counts: (2, 0, 0, 0, 0),
+            expect_statuses: &["pending", "pending"],
+            expect_errors: 0,
+            expect_treasury: 0,
+        },
+        Case {
+            name: "forwarded funds are routed",
+            deposits: &[
+                ("deployed", Chain::Forwarded(100)),
+                ("deployed", Chain::Forwarded(50)),
+                ("pending", Chain::Empty),
+            ],
+            deploy_reverts: false,
+            expect_counts: (3, 0, 0, 2, 0),
+            expect_statuses: &["routed", "routed", "pending"],
+            expect_errors: 0,
+            expect_treasury: 150,
+        },
+        Case {
+            name: "funds sent before deployment are stranded",
+            deposits: &[
+                ("pending", Chain::Prefunded(100)),
+                ("funded", Chain::Prefunded(50)),
+                ("pending", Chain::Empty),
+            ],
+            deploy_reverts: false,
+            expect_counts: (3, 2, 2, 0, 2),
+            expect_statuses: &["deployed", "deployed", "pending"],
+            expect_errors: 0,
+            expect_treasury: 0,
+        },
+        Case {
+            name: "deployed proxies wait for forwarded funds",
+            deposits: &[("funded", Chain::Empty), ("deployed", Chain::Empty)],
+            deploy_reverts: false,
+            expect_counts: (2, 1, 1, 0, 0),
+            expect_statuses: &["deployed", "deployed"],
+            expect_errors: 0,
+            expect_treasury: 0,
+        },
+        Case {
+            name: "balance check failure only skips that deposit",
+            deposits: &[
+                ("pending", Chain::BalanceFails),
+                ("deployed", Chain::Forwarded(10)),
+            ],
+            deploy_reverts: false,
+            expect_counts: (2, 0, 0, 1, 0),
+            expect_statuses: &["pending", "routed"],
+            expect_errors: 1,
+            expect_treasury: 10,
+        },
+        Case {
+            name: "deploy revert fails the whole batch",
+            deposits: &[
+                ("pending", Chain::Prefunded(10)),
+                ("funded", Chain::Prefunded(20)),
+            ],
+            deploy_reverts: true,
+            expect_counts: (2, 2, 0, 0, 0),
+            expect_statuses: &["failed", "failed"],
+            expect_errors: 1,
+            expect_treasury: 0,
+        },
+        Case {
+            name: "transfer revert leaves deposit deployed",
+            deposits: &[("deployed", Chain::TransferReverts(10))],
+            deploy_reverts: false,
+            expect_counts: (1, 0, 0, 0, 0),
+            expect_statuses: &["deployed"],
+            expect_errors: 1,
+            expect_treasury: 0,
+        },
+    ];
+
+    #[tokio::test]
+    async fn test_route_deposits_cases() {
+        for case in CASES {
+            let pool = test_pool().await;
+            let chain = FakeChain::new();
+            if case.deploy_reverts {
+                chain.fail_deploy("Transaction reverted");
+            }
+
+            let mut addresses = vec![];
+            for (nonce, (status, setup)) in case.deposits.iter().enumerate() {
+                let addr = if *status == "deployed" {
+                    seed_deployed(&pool, &chain, nonce as u64).await
+                } else {
+                    seed(&pool, nonce as u64, status).await
+                };
+                match *setup {
+                    Chain::Prefunded(wei) => chain.fund(addr, wei),
+                    Chain::Forwarded(wei) => chain.send(addr, wei),
+                    Chain::Empty => {}
+                    Chain::BalanceFails => chain.fail_balance(addr),
+                    Chain::TransferReverts(wei) => {
+                        chain.send(addr, wei);
+                        chain.fail_transfer(fake::TREASURY);
+                    }
+                }
+                addresses.push(addr);
+            }
+
+            let response = run(&pool, &chain).await;
+
+            assert_eq!(
+                (
+                    response.checked,
+                    response.funded,
+                    response.deployed,
+                    response.routed,
+                    response.stranded
+                ),
+                case.expect_counts,
+                "{}: counts",
+                case.name
+            );
+            assert_eq!(
+                response.errors.len(),
+                case.expect_errors,
+                "{}: errors {:?}",
+                case.name,
+                response.errors
+            );
+            assert_eq!(
+                chain.treasury_balance(),
+                U256::from(case.expect_treasury),
+                "{}: treasury balance",
+                case.name
+            );
+            for (addr, expected) in addresses.iter().zip(case.expect_statuses) {
+                assert_eq!(
+                    status_of(&pool, *addr).await,
+                    *expected,
+                    "{}: status of {}",
+                    case.name,
+                    addr
+                );
+                let result = response
+                    .deposits
+                    .iter()
+                    .find(|d| d.deposit_address == format_address(&addr.0 .0))
+                    .unwrap();
+                assert_eq!(
+                    result.status, *expected,
+                    "{}: result of {}",
+                    case.name, addr
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_funds_forwarded_after_deployment_are_routed() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let addr = seed(&pool, 0, "funded").await;
+
+        // Deployed, with nothing to route yet
+        let first = run(&pool, &chain).await;
+        assert!(first.errors.is_empty(), "{:?}", first.errors);
+        assert_eq!((first.deployed, first.routed), (1, 0));
+        assert_eq!(status_of(&pool, addr).await, "deployed");
+
+        // The proxy forwards what it receives; the run pays it from the router
+        chain.send(addr, 10);
+        assert_eq!(chain.balance_of(fake::ROUTER), U256::from(10));
+        let second = run(&pool, &chain).await;
+        assert_eq!(second.routed, 1);
+        assert_eq!(status_of(&pool, addr).await, "routed");
+        assert_eq!(chain.transfers(), vec![(fake::TREASURY, U256::from(10))]);
+        assert_eq!(chain.balance_of(fake::ROUTER), U256::ZERO);
+        assert_eq!(chain.treasury_balance(), U256::from(10));
+
+        // Routed deposits are left alone
+        let third = run(&pool, &chain).await;
+        assert_eq!(third.checked, 0);
+        assert_eq!(chain.deploy_calls(), 1);
+
+        // ...until their proxies forward more
+        chain.send(addr, 4);
+        let fourth = run(&pool, &chain).await;
+        assert_eq!((fourth.checked, fourth.routed), (1, 1));
+        assert_eq!(status_of(&pool, addr).await, "routed");
+        assert_eq!(chain.treasury_balance(), U256::from(14));
+    }
+
+    #[tokio::test]
+    async fn test_each_deposit_is_paid_what_its_proxy_forwarded() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let first = seed_deployed(&pool, &chain, 0).await;
+        let second = seed_deployed(&pool, &chain, 1).await;
+        chain.send(first, 10);
+        chain.send(second, 20);
+        chain.send(first, 5);
+
+        let response = route_one(&pool, &chain, first).await.unwrap();
+        assert_eq!(response.status, "routed");
+        assert_eq!(response.steps[2].amount_wei.as_deref(), Some("15"));
+        // The rest of the router's balance belongs to the other deposit
+        assert_eq!(chain.balance_of(fake::ROUTER), U256::from(20));
+        assert_eq!(status_of(&pool, second).await, "deployed");
+    }
+
+    #[tokio::test]
+    async fn test_failed_transfer_is_retried_for_the_rest() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let (ops, fees) = split_rule(&pool).await;
+        chain.fail_transfer(fees);
+        let addr = seed_deployed(&pool, &chain, 0).await;
+        chain.send(addr, 100);
+
+        let response = run(&pool, &chain).await;
+        assert_eq!(response.routed, 0);
+        assert_eq!(status_of(&pool, addr).await, "deployed");
+        assert_eq!(chain.balance_of(ops), U256::from(95));
+
+        // Only the 5 wei not paid out yet are routed, to the explicit treasury
+        let lease = RoutingLease::new(Duration::from_secs(60));
+        let address = format_address(&addr.0 .0);
+        let response = route_single(&pool, &chain, &lease, retry(), &address, Some(ops))
+            .await
+            .unwrap();
+        assert_eq!(response.status, "routed");
+        assert_eq!(chain.balance_of(ops), U256::from(100));
+        assert_eq!(chain.balance_of(fake::ROUTER), U256::ZERO);
+        let allocations = db::get_allocations(&pool, &address).await.unwrap();
+        let amounts: Vec<_> = allocations.iter().map(|a| a.amount_wei.as_str()).collect();
+        assert_eq!(amounts, ["95", "5"]);
+    }
+
+    #[tokio::test]
+    async fn test_stranded_deposit_routes_what_it_forwards_later() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let addr = seed(&pool, 0, "pending").await;
+        chain.fund(addr, 30);
+
+        let response = run(&pool, &chain).await;
+        assert_eq!((response.deployed, response.stranded), (1, 1));
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(status_of(&pool, addr).await, "deployed");
+        assert_eq!(chain.treasury_balance(), U256::ZERO);
+
+        chain.send(addr, 12);
+        let response = run(&pool, &chain).await;
+        assert_eq!((response.routed, response.stranded), (1, 1));
+        assert_eq!(status_of(&pool, addr).await, "routed");
+        assert_eq!(chain.treasury_balance(), U256::from(12));
+        // What arrived before the deployment stays on the proxy, and reported
+        assert_eq!(chain.balance_of(addr), U256::from(30));
+        let stranded = db::get_stranded(&pool).await.unwrap();
+        assert_eq!(stranded.len(), 1);
+        assert_eq!(stranded[0].balance_wei, "30");
+    }
+
+    #[tokio::test]
+    async fn test_failed_balance_check_is_not_stranded() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let addr = seed_deployed(&pool, &chain, 0).await;
+        chain.send(addr, 10);
+        chain.fail_balance(addr);
+
+        let response = run(&pool, &chain).await;
+
+        assert_eq!((response.routed, response.stranded), (0, 0));
+        assert_eq!(response.errors.len(), 1, "{:?}", response.errors);
+        assert_eq!(status_of(&pool, addr).await, "deployed");
+        assert!(db::get_stranded(&pool).await.unwrap().is_empty());
+        assert!(chain.transfers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_router_short_of_credit_stops_transfers() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let first = seed_deployed(&pool, &chain, 0).await;
+        let second = seed_deployed(&pool, &chain, 1).await;
+        chain.send(first, 10);
+        chain.send(second, 20);
+        // The router holds less than what its proxies were counted to forward
+        chain.fund(fake::ROUTER, 25);
+
+        let response = run(&pool, &chain).await;
+
+        assert_eq!(response.routed, 0);
+        assert_eq!(response.errors.len(), 1, "{:?}", response.errors);
+        assert!(response.errors[0].contains("owed 30 wei"));
+        assert!(chain.transfers().is_empty());
+        assert_eq!(status_of(&pool, first).await, "deployed");
+        assert_eq!(status_of(&pool, second).await, "deployed");
+    }
+
+    #[tokio::test]
+    async fn test_database_error_is_reported() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+
+        let lease = RoutingLease::new(Duration::from_secs(60));
+        let guard = lease.try_acquire(&pool).await.unwrap();
+        pool.close().await;
+
+        let response = route_deposits(&pool, &chain, &guard, retry(), None).await;
+
+        assert_eq!(response.checked, 0);
+        assert_eq!(response.errors.len(), 1);
+        assert!(response.errors[0].starts_with("Database error"));
+        assert_eq!(chain.deploy_calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_status_update_failure_skips_deployment() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let addr = seed(&pool, 0, "pending").await;
+        chain.fund(addr, 10);
+
+        // Status updates fail, but the initial select still works
+        sqlx::query(
+            "CREATE TRIGGER block_updates BEFORE UPDATE OF status ON deposits \
+             BEGIN SELECT RAISE(ABORT, 'read only'); END",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let response = run(&pool, &chain).await;
+
+        assert_eq!(response.checked, 1);
+        assert_eq!(response.funded, 0);
+        assert!(response.errors[0].starts_with("DB update failed"));
+        assert_eq!(chain.deploy_calls(), 0);
+        assert_eq!(status_of(&pool, addr).await, "pending");
+    }
+
+    #[tokio::test]
+    async fn test_deposits_claimed_by_another_run_are_skipped() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let theirs = seed(&pool, 0, "funded").await;
+        let ours = seed_deployed(&pool, &chain, 1).await;
+        chain.send(ours, 10);
+
+        // Another worker holds a live claim on the first deposit
+        let claimed = db::claim_deposits(&pool, &["funded"], "other-worker", 60)
+            .await
+            .unwrap();
+        assert_eq!(claimed.len(), 1);
+
+        let response = run(&pool, &chain).await;
+
+        assert_eq!(response.checked, 1);
+        assert_eq!(response.routed, 1);
+        assert_eq!(status_of(&pool, theirs).await, "funded");
+        assert_eq!(status_of(&pool, ours).await, "routed");
+
+        // Its claim expired: the next run picks the deposit up
+        db::extend_deposit_claims(&pool, "other-worker", 0)
+            .await
+            .unwrap();
+        let response = run(&pool, &chain).await;
+        assert_eq!(response.deployed, 1);
+        assert_eq!(status_of(&pool, theirs).await, "deployed");
+    }
+
+    #[tokio::test]
+    async fn test_overlapping_run_is_refused() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let addr = seed_deployed(&pool, &chain, 0).await;
+        chain.send(addr, 10);
+
+        let lease = RoutingLease::new(Duration::from_secs(60));
+        let guard = lease.try_acquire(&pool).await.unwrap();
+
+        assert!(matches!(
+            route_exclusive(&pool, &chain, &lease, retry(), None).await,
+            Err(LeaseError::Busy(_))
+        ));
+        assert!(chain.transfers().is_empty());
+
+        guard.release().await;
+        assert_eq!(run(&pool, &chain).await.routed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_other_workers_run_but_send_one_at_a_time() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let addr = seed(&pool, 0, "funded").await;
+        chain.fund(addr, 10);
+
+        // Another worker's run is sending transactions
+        let other = RoutingLease::new(Duration::from_secs(60));
+        let other_run = other.try_acquire(&pool).await.unwrap();
+        let sending = other_run.transactions().await.unwrap();
+
+        let lease = RoutingLease::new(Duration::from_secs(1));
+        let response = route_exclusive(&pool, &chain, &lease, retry(), None)
+            .await
+            .unwrap();
+        assert_eq!(response.checked, 1);
+        assert!(
+            response
+                .errors
+                .iter()
+                .any(|e| e.contains("sending transactions")),
+            "{:?}",
+            response.errors
+        );
+        assert_eq!(chain.deploy_calls(), 0);
+        assert_eq!(status_of(&pool, addr).await, "funded");
+
+        sending.release().await;
+        assert_eq!(run(&pool, &chain).await.deployed, 1);
+        other_run.release().await;
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_secs(60),
+        };
+        let minutes = |m| Some(Duration::from_secs(m * 60));
+
+        assert_eq!(policy.after_failure(1, true), ("funded", minutes(1)));
+        assert_eq!(policy.after_failure(2, true), ("funded", minutes(2)));
+        assert_eq!(policy.after_failure(3, true), ("funded", minutes(4)));
+        assert_eq!(policy.after_failure(4, true), ("dead_letter", None));
+        assert_eq!(policy.after_failure(1, false), ("failed", None));
+        assert_eq!(policy.delay(40), MAX_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_retriable_deploy_failure_is_retried_then_dead_lettered() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        chain.fail_deploy("receipt request timed out");
+        let addr = seed(&pool, 0, "funded").await;
+        chain.fund(addr, 10);
+        let lease = RoutingLease::new(Duration::from_secs(60));
+        let policy = RetryPolicy {
+            max_attempts: 2,
+            base_delay: Duration::from_secs(3600),
+        };
+
+        let first = route_exclusive(&pool, &chain, &lease, policy, None)
+            .await
+            .unwrap();
+        assert_eq!(first.deposits[0].status, "funded");
+        let row = row_of(&pool, addr).await;
+        assert_eq!((row.status.as_str(), row.attempts), ("funded", 1));
+        assert!(row.last_error.unwrap().contains("timed out"));
+        assert!(row.next_retry_at.is_some());
+
+        // Not due yet
+        let second = route_exclusive(&pool, &chain, &lease, policy, None)
+            .await
+            .unwrap();
+        assert_eq!(second.checked, 0);
+
+        sqlx::query("UPDATE deposits SET next_retry_at = datetime('now')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        route_exclusive(&pool, &chain, &lease, policy, None)
+            .await
+            .unwrap();
+        let row = row_of(&pool, addr).await;
+        assert_eq!((row.status.as_str(), row.attempts), ("dead_letter", 2));
+        assert_eq!(row.next_retry_at, None);
+        assert_eq!(chain.deploy_calls(), 2);
+
+        // Dead letters wait for an operator
+        let fourth = route_exclusive(&pool, &chain, &lease, policy, None)
+            .await
+            .unwrap();
+        assert_eq!(fourth.checked, 0);
+        assert!(db::requeue_deposit(&pool, &row.deposit_address)
+            .await
+            .unwrap());
+        assert_eq!(status_of(&pool, addr).await, "funded");
+    }
+
+    #[tokio::test]
+    async fn test_retry_skips_proxies_deployed_by_a_timed_out_attempt() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        // The earlier deployMultiple timed out waiting for its receipt but
+        // was mined anyway
+        let mined = seed(&pool, 0, "funded").await;
+        chain.set_code(mined);
+        let fresh = seed(&pool, 1, "funded").await;
+
+        let response = run(&pool, &chain).await;
+
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(response.deployed, 1);
+        assert_eq!(chain.deploy_calls(), 1);
+        assert_eq!(status_of(&pool, mined).await, "deployed");
+        assert_eq!(status_of(&pool, fresh).await, "deployed");
+
+        // What the recorded proxy forwards is routed like any other
+        chain.send(mined, 10);
+        let response = run(&pool, &chain).await;
+        assert_eq!(response.routed, 1);
+        assert_eq!(status_of(&pool, mined).await, "routed");
+
+        // With every proxy already there, no deployment is sent at all
+        let retried = seed(&pool, 2, "funded").await;
+        chain.set_code(retried);
+        let response = run(&pool, &chain).await;
+        assert_eq!(response.deployed, 0);
+        assert_eq!(chain.deploy_calls(), 1);
+        assert_eq!(status_of(&pool, retried).await, "deployed");
+    }
+
+    /// Insert a funded deposit derived from another signer, so deployMultiple
+    /// puts its proxy elsewhere
+    async fn seed_stale(pool: &SqlitePool, nonce: u64) -> Address {
+        let user = [0x42u8; 20];
+        let (addr, salt) = compute_deposit_address(
+            &fake::DEPLOYER.0 .0,
+            &fake::INIT_CODE_HASH.0,
+            &[0x77u8; 20],
+            &user,
+            nonce,
+        );
+        let deposit_address = format_address(&addr);
+        db::insert_deposit(
+            pool,
+            &format_address(&user),
+            &format_bytes32(&salt),
+            &deposit_address,
+            nonce,
+            None,
+        )
+        .await
+        .unwrap();
+        db::update_deposit_status(pool, &deposit_address, "funded")
+            .await
+            .unwrap();
+        Address::from(addr)
+    }
+
+    #[tokio::test]
+    async fn test_deployment_elsewhere_is_not_recorded() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let fine = seed(&pool, 0, "funded").await;
+        let stale = seed_stale(&pool, 1).await;
+
+        let response = run(&pool, &chain).await;
+
+        assert_eq!(response.deployed, 1);
+        assert_eq!(status_of(&pool, fine).await, "deployed");
+        assert_eq!(status_of(&pool, stale).await, "failed");
+        assert_eq!(response.errors.len(), 1);
+        assert!(
+            response.errors[0].starts_with("No proxy code at"),
+            "{:?}",
+            response.errors
+        );
+
+        // Routing one on its own fails the same way
+        let stale = seed_stale(&pool, 2).await;
+        let response = route_one(&pool, &chain, stale).await.unwrap();
+        assert_eq!(response.steps[1].outcome, StepOutcome::Failed);
+        let detail = response.steps[1].detail.as_deref().unwrap();
+        assert!(detail.starts_with("No proxy code at"), "{}", detail);
+        assert_eq!(response.status, "failed");
+    }
+
+    /// Route one deposit per the routing rules under a fresh lease
+    async fn route_one(
+        pool: &SqlitePool,
+        chain: &FakeChain,
+        addr: Address,
+    ) -> Result<RouteDepositResponse, RouteDepositError> {
+        let lease = RoutingLease::new(Duration::from_secs(60));
+        let address = format_address(&addr.0 .0);
+        route_single(pool, chain, &lease, retry(), &address, None).await
+    }
+
+    fn outcomes(response: &RouteDepositResponse) -> Vec<(&str, StepOutcome)> {
+        response.steps.iter().map(|s| (s.step, s.outcome)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_single_deposit_is_routed_step_by_step() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let addr = seed(&pool, 0, "pending").await;
+        let other = seed(&pool, 1, "pending").await;
+        chain.fund(addr, 70);
+        chain.fund(other, 5);
+
+        // ETH sent before the deployment can't be moved
+        let response = route_one(&pool, &chain, addr).await.unwrap();
+        assert_eq!(
+            outcomes(&response),
+            [
+                ("balance_check", StepOutcome::Done),
+                ("deploy", StepOutcome::Done),
+                ("transfer", StepOutcome::Skipped),
+            ]
+        );
+        assert_eq!(response.status, "deployed");
+        let detail = response.steps[2].detail.as_deref().unwrap();
+        assert!(detail.contains("stranded"), "{}", detail);
+
+        // ETH sent after it is forwarded and routed
+        chain.send(addr, 30);
+        let response = route_one(&pool, &chain, addr).await.unwrap();
+        assert_eq!(
+            outcomes(&response),
+            [
+                ("balance_check", StepOutcome::Done),
+                ("deploy", StepOutcome::Skipped),
+                ("transfer", StepOutcome::Done),
+            ]
+        );
+        assert_eq!(response.status, "routed");
+        assert_eq!(response.steps[2].amount_wei.as_deref(), Some("30"));
+        assert_eq!(chain.treasury_balance(), U256::from(30));
+
+        // Nothing else was touched
+        assert_eq!(status_of(&pool, other).await, "pending");
+        assert_eq!(chain.deploy_calls(), 1);
+        assert_eq!(chain.transfers(), vec![(fake::TREASURY, U256::from(30))]);
+    }
+
+    #[tokio::test]
+    async fn test_single_deposit_follows_the_status_flow() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+
+        // Unfunded deposits stay pending
+        let empty = seed(&pool, 0, "pending").await;
+        let response = route_one(&pool, &chain, empty).await.unwrap();
+        assert_eq!(outcomes(&response), [("balance_check", StepOutcome::Done)]);
+        assert_eq!(response.status, "pending");
+
+        // A deployed proxy is only transferred from
+        let deployed = seed_deployed(&pool, &chain, 1).await;
+        chain.send(deployed, 9);
+        let response = route_one(&pool, &chain, deployed).await.unwrap();
+        assert_eq!(response.steps[1].outcome, StepOutcome::Skipped);
+        assert_eq!(response.status, "routed");
+        assert_eq!(chain.deploy_calls(), 0);
+
+        for status in ["routed", "dead_letter", "failed"] {
+            let addr = seed(&pool, 2, status).await;
+            assert!(
+                matches!(
+                    route_one(&pool, &chain, addr).await,
+                    Err(RouteDepositError::InvalidState { .. })
+                ),
+                "{}",
+                status
+            );
+            sqlx::query("DELETE FROM deposits WHERE nonce = 2")
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        assert!(matches!(
+            route_one(&pool, &chain, Address::repeat_byte(0x99)).await,
+            Err(RouteDepositError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_single_deposit_respects_the_routing_lock() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let addr = seed(&pool, 0, "funded").await;
+        chain.fund(addr, 10);
+
+        let lease = RoutingLease::new(Duration::from_secs(60));
+        let guard = lease.try_acquire(&pool).await.unwrap();
+        let address = format_address(&addr.0 .0);
+        let result = route_single(
+            &pool,
+            &chain,
+            &lease,
+            retry(),
+            &address,
+            Some(fake::TREASURY),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(RouteDepositError::Lease(LeaseError::Busy(_)))
+        ));
+        guard.release().await;
+
+        // A live claim of a worker that lost its lease holds the deposit too
+        db::claim_deposits(&pool, &["funded"], "other-worker", 60)
+            .await
+            .unwrap();
+        assert!(matches!(
+            route_one(&pool, &chain, addr).await,
+            Err(RouteDepositError::Claimed(_))
+        ));
+        assert_eq!(chain.deploy_calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_single_deposit_to_another_treasury() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let addr = seed_deployed(&pool, &chain, 0).await;
+        chain.send(addr, 40);
+        let treasury = Address::repeat_byte(0x7f);
+
+        let lease = RoutingLease::new(Duration::from_secs(60));
+        let address = format_address(&addr.0 .0);
+        let response = route_single(&pool, &chain, &lease, retry(), &address, Some(treasury))
+            .await
+            .unwrap();
+        assert_eq!(response.treasury_address, Some(format!("{:#x}", treasury)));
+        assert_eq!(chain.balance_of(treasury), U256::from(40));
+        assert_eq!(chain.treasury_balance(), U256::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_single_deposit_deploy_failure_counts_as_attempt() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        chain.fail_deploy("receipt request timed out");
+        let addr = seed(&pool, 0, "funded").await;
+        chain.fund(addr, 10);
+
+        let response = route_one(&pool, &chain, addr).await.unwrap();
+        assert_eq!(
+            outcomes(&response),
+            [
+                ("balance_check", StepOutcome::Done),
+                ("deploy", StepOutcome::Failed),
+            ]
+        );
+        assert_eq!(response.status, "funded");
+        assert_eq!(row_of(&pool, addr).await.attempts, 1);
+    }
+
+    /// Split the seeded user's deposits 95% to `ops`, 5% to `fees`
+    async fn split_rule(pool: &SqlitePool) -> (Address, Address) {
+        let (ops, fees) = (Address::repeat_byte(0x0a), Address::repeat_byte(0x0f));
+        db::insert_treasury(pool, "ops", &format!("{:#x}", ops))
+            .await
+            .unwrap();
+        db::insert_treasury(pool, "fees", &format!("{:#x}", fees))
+            .await
+            .unwrap();
+        let user = format_address(&[0x42u8; 20]);
+        let splits = r#"[{"treasury":"ops","share_bps":9500},{"treasury":"fees","share_bps":500}]"#;
+        db::insert_routing_rule(pool, Some(&user), None, None, splits)
+            .await
+            .unwrap();
+        (ops, fees)
+    }
+
+    #[tokio::test]
+    async fn test_split_rule_sends_one_transfer_per_treasury() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let (ops, fees) = split_rule(&pool).await;
+        let addr = seed_deployed(&pool, &chain, 0).await;
+        chain.send(addr, 999);
+
+        let response = run(&pool, &chain).await;
+        assert_eq!(response.routed, 1);
+        // 95% of 999 rounds down to 949; the fee wallet gets the remainder
+        assert_eq!(
+            chain.transfers(),
+            vec![(ops, U256::from(949)), (fees, U256::from(50))]
+        );
+        assert_eq!(chain.balance_of(ops), U256::from(949));
+        assert_eq!(chain.balance_of(fees), U256::from(50));
+        assert_eq!(chain.balance_of(fake::ROUTER), U256::ZERO);
+
+        let allocations = db::get_allocations(&pool, &format_address(&addr.0 .0))
+            .await
+            .unwrap();
+        let recorded: Vec<_> = allocations
+            .iter()
+            .map(|a| {
+                (
+                    a.treasury_address.clone(),
+                    a.share_bps,
+                    a.amount_wei.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            recorded,
+            [
+                (format!("{:#x}", ops), 9500, "949"),
+                (format!("{:#x}", fees), 500, "50"),
+            ]
+        );
+        assert_eq!(response.route_tx_hashes.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_single_deposit_follows_split_rule_unless_overridden() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let (ops, fees) = split_rule(&pool).await;
+        let split = seed_deployed(&pool, &chain, 0).await;
+        let whole = seed_deployed(&pool, &chain, 1).await;
+        chain.send(split, 100);
+        chain.send(whole, 30);
+
+        let response = route_one(&pool, &chain, split).await.unwrap();
+        let transfers: Vec<_> = response
+            .steps
+            .iter()
+            .filter(|s| s.step == "transfer")
+            .map(|s| {
+                (
+                    s.treasury_address.clone().unwrap(),
+                    s.amount_wei.clone().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            transfers,
+            [
+                (format!("{:#x}", ops), "95".to_string()),
+                (format!("{:#x}", fees), "5".to_string()),
+            ]
+        );
+
+        // An explicit treasury takes everything
+        let lease = RoutingLease::new(Duration::from_secs(60));
+        let address = format_address(&whole.0 .0);
+        route_single(&pool, &chain, &lease, retry(), &address, Some(fees))
+            .await
+            .unwrap();
+        assert_eq!(chain.balance_of(fees), U256::from(35));
+        let allocations = db::get_allocations(&pool, &address).await.unwrap();
+        assert_eq!(allocations.len(), 1);
+        assert_eq!(allocations[0].share_bps, 10_000);
+    }
+
+    #[tokio::test]
+    async fn test_rule_with_inactive_treasury_leaves_deposit_deployed() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        split_rule(&pool).await;
+        db::deactivate_treasury(&pool, "fees").await.unwrap();
+        let addr = seed(&pool, 0, "funded").await;
+        chain.fund(addr, 10);
+
+        let response = run(&pool, &chain).await;
+        assert_eq!(response.routed, 0);
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(status_of(&pool, addr).await, "deployed");
+        assert!(chain.transfers().is_empty());
+    }
+
+    /// Events queued for a subscription, oldest first
+    async fn events(pool: &SqlitePool, subscription_id: i64) -> Vec<String> {
+        let mut deliveries = db::list_webhook_deliveries(pool, subscription_id, 50)
+            .await
+            .unwrap();
+        deliveries.reverse();
+        deliveries.into_iter().map(|d| d.event).collect()
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_events_are_queued_for_webhooks() {
+        let pool = test_pool().await;
+        let chain = FakeChain::new();
+        let sub = db::insert_webhook_subscription(
+            &pool,
+            None,
+            "http://127.0.0.1:9/hook",
+            "whsec_test",
+            &webhooks::format_events(&WebhookEvent::ALL),
+        )
+        .await
+        .unwrap();
+        let routed = seed(&pool, 0, "pending").await;
+        chain.fund(routed, 10);
+        seed(&pool, 1, "pending").await;
+
+        run(&pool, &chain).await;
+        assert_eq!(status_of(&pool, routed).await, "deployed");
+        chain.send(routed, 5);
+        route_one(&pool, &chain, routed).await.unwrap();
+        assert_eq!(status_of(&pool, routed).await, "routed");
+        assert_eq!(
+            events(&pool, sub.id).await,
+            ["funded", "confirmed", "routed"]
+        );
+
+        // A deployment that fails for good is reported once
+        let failed = seed(&pool, 2, "funded").await;
+        chain.fund(failed, 10);
+        chain.fail_deploy("execution reverted");
+        run(&pool, &chain).await;
+        assert_eq!(status_of(&pool, failed).await, "failed");
+        let deliveries = db::list_webhook_deliveries(&pool, sub.id, 1).await.unwrap();
+        assert_eq!(deliveries[0].event, "failed");
+        assert_eq!(deliveries[0].deposit_address, format_address(&failed.0 .0));
+    }
+}
@@ -1,8 +1,8 @@
 //! RPC client for interacting with Ethereum via Alloy
 
 use alloy::{
-    network::{Ethereum, EthereumWallet},
-    primitives::{Address, FixedBytes, U256},
+    network::Ethereum,
+    primitives::{Address, Bytes, FixedBytes, U256},
     providers::{
         fillers::{
             BlobGasFiller, ChainIdFiller, FillProvider, GasFiller, JoinFill, NonceFiller,
@@ -10,20 +10,27 @@ use alloy::{
         },
         Identity, Provider, ProviderBuilder, RootProvider,
     },
-    signers::local::PrivateKeySigner,
+    rpc::types::{BlockTransactionsKind, Filter},
     sol,
+    sol_types::SolEvent,
     transports::http::{Client, Http},
 };
 
-use crate::config::Config;
+use crate::{
+    chain::{ChainClient, RouterReceipt},
+    config::Config,
+    metrics,
+    signer::{Signer, SignerError},
+    telemetry,
+};
 
 /// Errors that can occur during RPC operations
 #[derive(Debug, thiserror::Error)]
 pub enum RpcError {
     #[error("Failed to parse address: {0}")]
     InvalidAddress(String),
-    #[error("Failed to parse private key: {0}")]
-    InvalidPrivateKey(String),
+    #[error("Signer error: {0}")]
+    Signer(#[from] SignerError),
     #[error("RPC transport error: {0}")]
     Transport(String),
     #[error("Contract call failed: {0}")]
@@ -32,6 +39,51 @@ pub enum RpcError {
     TransactionFailed(String),
 }
 
+impl RpcError {
+    /// Short label for metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RpcError::InvalidAddress(_) => "invalid_address",
+            RpcError::Signer(_) => "signer",
+            RpcError::Transport(_) => "transport",
+            RpcError::ContractCall(_) => "contract_call",
+            RpcError::TransactionFailed(_) => "transaction_failed",
+        }
+    }
+
+    /// Whether the same call may succeed later
+    ///
+    /// Transport and signer outages, gas and nonce problems and lost receipts
+    /// are retriable. Reverts (missing permissions, wrong code at the
+    /// deployer, an occupied address) fail the same way every time.
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            RpcError::Transport(_) | RpcError::Signer(_) => true,
+            RpcError::InvalidAddress(_) => false,
+            RpcError::ContractCall(msg) => {
+                let msg = msg.to_lowercase();
+                !msg.contains("revert") && RETRIABLE_SEND_ERRORS.iter().any(|e| msg.contains(e))
+            }
+            // Reverted receipts say so; anything else failed to fetch one
+            RpcError::TransactionFailed(msg) => !msg.to_lowercase().contains("revert"),
+        }
+    }
+}
+
+/// Fragments of node errors for transactions that were not sent but may be
+/// on a later attempt
+const RETRIABLE_SEND_ERRORS: &[&str] = &[
+    "insufficient funds",
+    "gas",
+    "nonce",
+    "underpriced",
+    "timeout",
+    "timed out",
+    "rate limit",
+    "connection",
+    "temporarily unavailable",
+];
+
 // Define the DeterministicProxyDeployer contract interface using Alloy's sol! macro
 sol! {
     #[sol(rpc)]
@@ -39,6 +91,8 @@ sol! {
         function deploy(bytes32 salt) external returns (address proxy);
         function deployMultiple(bytes32[] calldata salts) external returns (address[] memory proxies);
         function computeProxyAddress(bytes32 salt) external view returns (address);
+        function FUND_ROUTER_ADDRESS() external view returns (address);
+        function getInitCodeHash() external view returns (bytes32);
     }
 }
 
@@ -46,7 +100,9 @@ sol! {
 sol! {
     #[sol(rpc)]
     interface IFundRouter {
-        function transferFunds(address payable recipient) external;
+        function transferFunds(uint256 etherAmount, address[] calldata tokens, uint256[] calldata amounts, address payable treasuryAddress) external;
+        function STORAGE() external view returns (address);
+        event EthReceived(address indexed from, uint256 amount);
     }
 }
 
@@ -60,7 +116,7 @@ type WalletProvider = FillProvider<
             Identity,
             JoinFill<GasFiller, JoinFill<BlobGasFiller, JoinFill<NonceFiller, ChainIdFiller>>>,
         >,
-        WalletFiller<EthereumWallet>,
+        WalletFiller<Signer>,
     >,
     RootProvider<Http<Client>>,
     Http<Client>,
@@ -71,8 +127,8 @@ type WalletProvider = FillProvider<
 pub struct RpcClient {
     provider: ReadProvider,
     wallet_provider: WalletProvider,
+    signer_address: Address,
     deployer_address: Address,
-    #[allow(dead_code)]
     router_address: Address,
     treasury_address: Address,
 }
@@ -96,13 +152,10 @@ impl RpcClient {
             .parse()
             .map_err(|_| RpcError::InvalidAddress(config.treasury_address.clone()))?;
 
-        // Parse private key
-        let signer: PrivateKeySigner = config
-            .private_key
-            .parse()
-            .map_err(|e| RpcError::InvalidPrivateKey(format!("{}", e)))?;
-
-        let wallet = EthereumWallet::from(signer);
+        // Unlock the signer once; it is shared by every transaction
+        let signer = Signer::from_config(&config.signer).await?;
+        let signer_address = signer.address();
+        tracing::info!(signer = %telemetry::redact_address(&signer_address.to_string()), backend = signer.kind(), "Signer ready");
 
         // Parse RPC URL
         let rpc_url: url::Url = config
@@ -116,11 +169,12 @@ impl RpcClient {
         // Create wallet provider for signing transactions
         let wallet_provider = ProviderBuilder::new()
             .with_recommended_fillers()
-            .wallet(wallet)
+            .wallet(signer)
             .on_http(rpc_url);
 
         Ok(Self {
             provider,
+            signer_address,
             wallet_provider,
             deployer_address,
             router_address,
@@ -128,14 +182,6 @@ impl RpcClient {
         })
     }
 
-    /// Get the balance of an address
-    pub async fn get_balance(&self, address: Address) -> Result<U256, RpcError> {
-        self.provider
-            .get_balance(address)
-            .await
-            .map_err(|e| RpcError::Transport(e.to_string()))
-    }
-
     /// Check balances for multiple addresses
     #[allow(dead_code)]
     pub async fn get_balances(
@@ -154,9 +200,130 @@ impl RpcClient {
         Ok(results)
     }
 
+    /// Get the deployer contract address
+    #[allow(dead_code)]
+    pub fn deployer_address(&self) -> Address {
+        self.deployer_address
+    }
+}
+
+#[async_trait::async_trait]
+impl ChainClient for RpcClient {
+    fn signer_address(&self) -> Address {
+        self.signer_address
+    }
+
+    fn treasury_address(&self) -> Address {
+        self.treasury_address
+    }
+
+    fn router_address(&self) -> Address {
+        self.router_address
+    }
+
+    /// Get the balance of an address
+    #[tracing::instrument(name = "rpc", skip_all, fields(rpc.method = "eth_getBalance", address = %address))]
+    async fn get_balance(&self, address: Address) -> Result<U256, RpcError> {
+        let _timer = metrics::rpc_timer("eth_getBalance");
+        self.provider
+            .get_balance(address)
+            .await
+            .map_err(|e| RpcError::Transport(e.to_string()))
+    }
+
+    /// Get the balance of an address as of a past block
+    #[tracing::instrument(name = "rpc", skip_all, fields(rpc.method = "eth_getBalance", address = %address, block = block))]
+    async fn balance_at(&self, address: Address, block: u64) -> Result<U256, RpcError> {
+        let _timer = metrics::rpc_timer("eth_getBalance");
+        self.provider
+            .get_balance(address)
+            .number(block)
+            .await
+            .map_err(|e| RpcError::Transport(e.to_string()))
+    }
+
+    /// Get the timestamp of a block
+    #[tracing::instrument(name = "rpc", skip_all, fields(rpc.method = "eth_getBlockByNumber", block = block))]
+    async fn block_timestamp(&self, block: u64) -> Result<u64, RpcError> {
+        let _timer = metrics::rpc_timer("eth_getBlockByNumber");
+        self.provider
+            .get_block_by_number(block.into(), BlockTransactionsKind::Hashes)
+            .await
+            .map_err(|e| RpcError::Transport(e.to_string()))?
+            .map(|block| block.header.timestamp)
+            .ok_or_else(|| RpcError::Transport(format!("Block {} not found", block)))
+    }
+
+    /// Get the deployed code at an address
+    #[tracing::instrument(name = "rpc", skip_all, fields(rpc.method = "eth_getCode", address = %address))]
+    async fn get_code(&self, address: Address) -> Result<Bytes, RpcError> {
+        let _timer = metrics::rpc_timer("eth_getCode");
+        self.provider
+            .get_code_at(address)
+            .await
+            .map_err(|e| RpcError::Transport(e.to_string()))
+    }
+
+    /// Get the chain ID of the RPC endpoint
+    #[tracing::instrument(name = "rpc", skip_all, fields(rpc.method = "eth_chainId"))]
+    async fn chain_id(&self) -> Result<u64, RpcError> {
+        let _timer = metrics::rpc_timer("eth_chainId");
+        self.provider
+            .get_chain_id()
+            .await
+            .map_err(|e| RpcError::Transport(e.to_string()))
+    }
+
+    /// Get the latest block number
+    #[tracing::instrument(name = "rpc", skip_all, fields(rpc.method = "eth_blockNumber"))]
+    async fn block_number(&self) -> Result<u64, RpcError> {
+        let _timer = metrics::rpc_timer("eth_blockNumber");
+        self.provider
+            .get_block_number()
+            .await
+            .map_err(|e| RpcError::Transport(e.to_string()))
+    }
+
+    /// Read DeterministicProxyDeployer.FUND_ROUTER_ADDRESS()
+    #[tracing::instrument(name = "rpc", skip_all, fields(rpc.method = "FUND_ROUTER_ADDRESS", contract = %deployer))]
+    async fn deployer_fund_router(&self, deployer: Address) -> Result<Address, RpcError> {
+        let _timer = metrics::rpc_timer("FUND_ROUTER_ADDRESS");
+        IDeterministicProxyDeployer::new(deployer, &self.provider)
+            .FUND_ROUTER_ADDRESS()
+            .call()
+            .await
+            .map(|r| r._0)
+            .map_err(|e| RpcError::ContractCall(e.to_string()))
+    }
+
+    /// Read DeterministicProxyDeployer.getInitCodeHash()
+    #[tracing::instrument(name = "rpc", skip_all, fields(rpc.method = "getInitCodeHash", contract = %deployer))]
+    async fn deployer_init_code_hash(&self, deployer: Address) -> Result<FixedBytes<32>, RpcError> {
+        let _timer = metrics::rpc_timer("getInitCodeHash");
+        IDeterministicProxyDeployer::new(deployer, &self.provider)
+            .getInitCodeHash()
+            .call()
+            .await
+            .map(|r| r._0)
+            .map_err(|e| RpcError::ContractCall(e.to_string()))
+    }
+
+    /// Read FundRouter.STORAGE()
+    #[tracing::instrument(name = "rpc", skip_all, fields(rpc.method = "STORAGE", contract = %router))]
+    async fn router_storage(&self, router: Address) -> Result<Address, RpcError> {
+        let _timer = metrics::rpc_timer("STORAGE");
+        IFundRouter::new(router, &self.provider)
+            .STORAGE()
+            .call()
+            .await
+            .map(|r| r._0)
+            .map_err(|e| RpcError::ContractCall(e.to_string()))
+    }
+
     /// Deploy multiple proxies using DeterministicProxyDeployer.deployMultiple()
     /// Returns the transaction hash
-    pub async fn deploy_multiple(
+    #[tracing::instrument(name = "rpc", skip_all, fields(rpc.method = "deployMultiple", salts = salts.len(), tx_hash = tracing::field::Empty))]
+    async fn deploy_multiple(
         &self,
         salts: Vec<FixedBytes<32>>,
     ) -> Result<FixedBytes<32>, RpcError> {
@@ -164,6 +331,7 @@ impl RpcClient {
             return Err(RpcError::ContractCall("No salts provided".to_string()));
         }
 
+        let _timer = metrics::rpc_timer("deployMultiple");
         let contract =
             IDeterministicProxyDeployer::new(self.deployer_address, &self.wallet_provider);
 
@@ -175,6 +343,7 @@ impl RpcClient {
             .map_err(|e| RpcError::ContractCall(e.to_string()))?;
 
         let tx_hash = *pending_tx.tx_hash();
+        tracing::Span::current().record("tx_hash", tracing::field::display(tx_hash));
 
         // Wait for the transaction to be mined
         let receipt = pending_tx
@@ -193,12 +362,54 @@ impl RpcClient {
         Ok(tx_hash)
     }
 
-    /// Call transferFunds on a proxy to route funds to treasury
+    /// Read the FundRouter's EthReceived events in one eth_getLogs request
+    #[tracing::instrument(name = "rpc", skip_all, fields(rpc.method = "eth_getLogs", from_block = from_block, to_block = to_block))]
+    async fn router_receipts(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<RouterReceipt>, RpcError> {
+        let _timer = metrics::rpc_timer("eth_getLogs");
+        let filter = Filter::new()
+            .address(self.router_address)
+            .event_signature(IFundRouter::EthReceived::SIGNATURE_HASH)
+            .from_block(from_block)
+            .to_block(to_block);
+        let logs = self
+            .provider
+            .get_logs(&filter)
+            .await
+            .map_err(|e| RpcError::Transport(e.to_string()))?;
+
+        logs.iter()
+            .map(|log| {
+                let block = log.block_number.ok_or_else(|| {
+                    RpcError::Transport("EthReceived log without a block number".to_string())
+                })?;
+                let event = log
+                    .log_decode::<IFundRouter::EthReceived>()
+                    .map_err(|e| RpcError::ContractCall(e.to_string()))?;
+                Ok(RouterReceipt {
+                    from: event.inner.data.from,
+                    block,
+                    wei: event.inner.data.amount,
+                })
+            })
+            .collect()
+    }
+
+    /// Call FundRouter.transferFunds() to pay `amount` wei to `treasury`
     /// Returns the transaction hash
-    pub async fn transfer_funds(&self, proxy_address: Address) -> Result<FixedBytes<32>, RpcError> {
-        let contract = IFundRouter::new(proxy_address, &self.wallet_provider);
+    #[tracing::instrument(name = "rpc", skip_all, fields(rpc.method = "transferFunds", treasury = %treasury, amount = %amount, tx_hash = tracing::field::Empty))]
+    async fn transfer_from_router(
+        &self,
+        treasury: Address,
+        amount: U256,
+    ) -> Result<FixedBytes<32>, RpcError> {
+        let _timer = metrics::rpc_timer("transferFunds");
+        let contract = IFundRouter::new(self.router_address, &self.wallet_provider);
 
-        let call = contract.transferFunds(self.treasury_address);
+        let call = contract.transferFunds(amount, Vec::new(), Vec::new(), treasury);
 
         let pending_tx = call
             .send()
@@ -206,6 +417,7 @@ impl RpcClient {
             .map_err(|e| RpcError::ContractCall(e.to_string()))?;
 
         let tx_hash = *pending_tx.tx_hash();
+        tracing::Span::current().record("tx_hash", tracing::field::display(tx_hash));
 
         // Wait for the transaction to be mined
         let receipt = pending_tx
@@ -220,54 +432,51 @@ impl RpcClient {
         }
 
         tracing::info!(
-            "transferFunds tx confirmed for proxy {:?}: {:?}",
-            proxy_address,
+            "transferFunds tx confirmed: {:?} wei to {:?}, tx: {:?}",
+            amount,
+            treasury,
             tx_hash
         );
 
         Ok(tx_hash)
     }
 
-    /// Batch transfer funds from multiple proxies
-    /// Returns a vector of (proxy_address, tx_hash) for successful transfers
-    #[allow(dead_code)]
-    pub async fn batch_transfer_funds(
-        &self,
-        proxy_addresses: Vec<Address>,
-    ) -> Result<Vec<(Address, FixedBytes<32>)>, RpcError> {
-        let mut results = Vec::new();
-
-        for proxy in proxy_addresses {
-            match self.transfer_funds(proxy).await {
-                Ok(tx_hash) => {
-                    results.push((proxy, tx_hash));
-                }
-                Err(e) => {
-                    tracing::error!("Failed to transfer funds from {:?}: {}", proxy, e);
-                    // Continue with other proxies even if one fails
-                }
-            }
-        }
-
-        Ok(results)
-    }
-
-    /// Get the treasury address
-    #[allow(dead_code)]
-    pub fn treasury_address(&self) -> Address {
-        self.treasury_address
+    /// Estimate deployMultiple() from the signer
+    #[tracing::instrument(name = "rpc", skip_all, fields(rpc.method = "eth_estimateGas", call = "deployMultiple", salts = salts.len()))]
+    async fn estimate_deploy_gas(&self, salts: Vec<FixedBytes<32>>) -> Result<u64, RpcError> {
+        let _timer = metrics::rpc_timer("eth_estimateGas");
+        IDeterministicProxyDeployer::new(self.deployer_address, &self.provider)
+            .deployMultiple(salts)
+            .from(self.signer_address)
+            .estimate_gas()
+            .await
+            .map_err(|e| RpcError::ContractCall(e.to_string()))
     }
 
-    /// Get the deployer contract address
-    #[allow(dead_code)]
-    pub fn deployer_address(&self) -> Address {
-        self.deployer_address
+    /// Estimate FundRouter.transferFunds() from the signer
+    #[tracing::instrument(name = "rpc", skip_all, fields(rpc.method = "eth_estimateGas", call = "transferFunds", treasury = %treasury, amount = %amount))]
+    async fn estimate_transfer_gas(
+        &self,
+        treasury: Address,
+        amount: U256,
+    ) -> Result<u64, RpcError> {
+        let _timer = metrics::rpc_timer("eth_estimateGas");
+        IFundRouter::new(self.router_address, &self.provider)
+            .transferFunds(amount, Vec::new(), Vec::new(), treasury)
+            .from(self.signer_address)
+            .estimate_gas()
+            .await
+            .map_err(|e| RpcError::ContractCall(e.to_string()))
     }
 
-    /// Get the router address
-    #[allow(dead_code)]
-    pub fn router_address(&self) -> Address {
-        self.router_address
+    /// Get the current gas price
+    #[tracing::instrument(name = "rpc", skip_all, fields(rpc.method = "eth_gasPrice"))]
+    async fn gas_price(&self) -> Result<u128, RpcError> {
+        let _timer = metrics::rpc_timer("eth_gasPrice");
+        self.provider
+            .get_gas_price()
+            .await
+            .map_err(|e| RpcError::Transport(e.to_string()))
     }
 }
 
@@ -326,4 +535,35 @@ mod tests {
         // Address is stored lowercase internally
         assert_eq!(format!("{:?}", result).to_lowercase(), addr.to_lowercase());
     }
+
+    #[test]
+    fn test_retriable_errors() {
+        let cases = [
+            (RpcError::Transport("connection refused".into()), true),
+            (
+                RpcError::ContractCall("insufficient funds for gas * price + value".into()),
+                true,
+            ),
+            (RpcError::ContractCall("nonce too low".into()), true),
+            (
+                RpcError::ContractCall(
+                    "execution reverted: Ownable: caller is not the owner".into(),
+                ),
+                false,
+            ),
+            (RpcError::ContractCall("No salts provided".into()), false),
+            (
+                RpcError::TransactionFailed("Transaction reverted".into()),
+                false,
+            ),
+            (
+                RpcError::TransactionFailed("receipt request timed out".into()),
+                true,
+            ),
+            (RpcError::InvalidAddress("0x12".into()), false),
+        ];
+        for (error, retriable) in cases {
+            assert_eq!(error.is_retriable(), retriable, "{}", error);
+        }
+    }
 }
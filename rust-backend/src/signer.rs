@@ -0,0 +1,472 @@
+//! Transaction signer backends
+//!
+//! The backend signs `deployMultiple` and `transferFunds` transactions with one
+//! of three backends:
+//! - an scrypt-encrypted JSON keystore (Web3 Secret Storage), unlocked once at
+//!   startup
+//! - a remote signer speaking the Web3Signer JSON-RPC API (`eth_accounts`,
+//!   `eth_signTransaction`), so the key never enters this process
+//! - a raw hex private key, for development only
+//!
+//! Key material is held in zeroizing buffers and never shows up in `Debug`
+//! output.
+//!
+//! Deposit addresses are derived from the signer, so the first signer the
+//! backend starts with is recorded and another one is refused until it is
+//! adopted on purpose (see [`check_recorded`]).
+
+use std::{fmt, path::PathBuf, sync::Arc};
+
+use alloy::{
+    consensus::{TxEnvelope, TypedTransaction},
+    eips::eip2718::Decodable2718,
+    network::{Ethereum, EthereumWallet, NetworkWallet},
+    primitives::{Address, Bytes},
+    rpc::{
+        client::{ClientBuilder, RpcClient as JsonRpcClient},
+        types::TransactionRequest,
+    },
+    signers::local::PrivateKeySigner,
+};
+use serde::{Deserialize, Deserializer};
+use sqlx::SqlitePool;
+use zeroize::Zeroizing;
+
+use crate::db;
+
+/// Setting holding the signer deposit addresses are derived from
+const RECORDED_SIGNER: &str = "deposit_signer";
+
+/// A secret string that is wiped on drop and redacted from `Debug`
+#[derive(Clone)]
+pub struct Secret(Zeroizing<String>);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(Zeroizing::new(value.into()))
+    }
+
+    /// Access the secret value
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::new)
+    }
+}
+
+/// Which signer backend to use, and how to reach it
+#[derive(Clone, Debug)]
+pub enum SignerConfig {
+    /// Encrypted JSON keystore file and its password
+    Keystore { path: PathBuf, password: Secret },
+    /// Web3Signer-compatible JSON-RPC endpoint; without an address the first
+    /// account it reports is used
+    Remote {
+        url: String,
+        address: Option<String>,
+    },
+    /// Raw hex private key (development only)
+    PrivateKey(Secret),
+}
+
+impl SignerConfig {
+    /// Backend name for logs
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SignerConfig::Keystore { .. } => "keystore",
+            SignerConfig::Remote { .. } => "remote",
+            SignerConfig::PrivateKey(_) => "private_key",
+        }
+    }
+}
+
+/// Errors that can occur while setting up or using a signer
+#[derive(Debug, thiserror::Error)]
+pub enum SignerError {
+    #[error("Failed to unlock keystore {0}: {1}")]
+    Keystore(String, String),
+    #[error("Invalid private key")]
+    InvalidPrivateKey,
+    #[error("Invalid remote signer URL: {0}")]
+    InvalidUrl(String),
+    #[error("Invalid signer address: {0}")]
+    InvalidAddress(String),
+    #[error("Remote signer error: {0}")]
+    Remote(String),
+    #[error("Remote signer does not manage account {0}")]
+    UnknownAccount(Address),
+    #[error(
+        "Signer {configured} differs from {recorded}, which the deposit addresses in the database are derived from; restore its key or adopt the new signer with `radhat adopt-signer`"
+    )]
+    Changed {
+        recorded: String,
+        configured: Address,
+    },
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// An unlocked signer, usable as the wallet of an Alloy provider
+#[derive(Clone)]
+pub struct Signer {
+    address: Address,
+    kind: &'static str,
+    backend: Backend,
+}
+
+#[derive(Clone)]
+enum Backend {
+    Local(EthereumWallet),
+    Remote(Arc<RemoteSigner>),
+}
+
+impl Signer {
+    /// Unlock the configured backend
+    pub async fn from_config(config: &SignerConfig) -> Result<Self, SignerError> {
+        match config {
+            SignerConfig::Keystore { path, password } => {
+                let secret = eth_keystore::decrypt_key(path, password.expose().as_bytes())
+                    .map(Zeroizing::new)
+                    .map_err(|e| {
+                        SignerError::Keystore(path.display().to_string(), e.to_string())
+                    })?;
+                let signer = PrivateKeySigner::from_slice(&secret)
+                    .map_err(|_| SignerError::InvalidPrivateKey)?;
+                Ok(Self::local(signer, config.kind()))
+            }
+            SignerConfig::Remote { url, address } => {
+                let address = address
+                    .as_deref()
+                    .map(|a| {
+                        a.parse()
+                            .map_err(|_| SignerError::InvalidAddress(a.to_string()))
+                    })
+                    .transpose()?;
+                let remote = RemoteSigner::connect(url, address).await?;
+                Ok(Self {
+                    address: remote.address,
+                    kind: config.kind(),
+                    backend: Backend::Remote(Arc::new(remote)),
+                })
+            }
+            SignerConfig::PrivateKey(key) => {
+                let signer: PrivateKeySigner = key
+                    .expose()
+                    .parse()
+                    .map_err(|_| SignerError::InvalidPrivateKey)?;
+                tracing::warn!("Signing with a raw PRIVATE_KEY; use a keystore or remote signer outside development");
+                Ok(Self::local(signer, config.kind()))
+            }
+        }
+    }
+
+    fn local(signer: PrivateKeySigner, kind: &'static str) -> Self {
+        Self {
+            address: signer.address(),
+            kind,
+            backend: Backend::Local(EthereumWallet::from(signer)),
+        }
+    }
+
+    /// Address transactions are sent from
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Backend name for logs
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+}
+
+/// Refuse a signer other than the one recorded in the database, recording
+/// `address` if none is
+///
+/// `deployMultiple` puts a proxy at the address derived from the signer that
+/// sends it, so under another signer every deposit without a proxy yet would
+/// get its proxy deployed elsewhere.
+pub async fn check_recorded(pool: &SqlitePool, address: Address) -> Result<(), SignerError> {
+    let configured = format!("{:#x}", address);
+    let recorded = db::insert_setting(pool, RECORDED_SIGNER, &configured).await?;
+    if recorded != configured {
+        return Err(SignerError::Changed {
+            recorded,
+            configured: address,
+        });
+    }
+    Ok(())
+}
+
+/// Record `address` as the signer deposit addresses are derived from and
+/// return the one recorded before
+pub async fn adopt(pool: &SqlitePool, address: Address) -> Result<Option<String>, sqlx::Error> {
+    let previous = db::get_setting(pool, RECORDED_SIGNER).await?;
+    db::set_setting(pool, RECORDED_SIGNER, &format!("{:#x}", address)).await?;
+    Ok(previous)
+}
+
+impl fmt::Debug for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Signer")
+            .field("kind", &self.kind)
+            .field("address", &self.address)
+            .finish_non_exhaustive()
+    }
+}
+
+impl NetworkWallet<Ethereum> for Signer {
+    fn default_signer_address(&self) -> Address {
+        self.address
+    }
+
+    fn has_signer_for(&self, address: &Address) -> bool {
+        *address == self.address
+    }
+
+    fn signer_addresses(&self) -> impl Iterator<Item = Address> {
+        std::iter::once(self.address)
+    }
+
+    async fn sign_transaction_from(
+        &self,
+        sender: Address,
+        tx: TypedTransaction,
+    ) -> alloy::signers::Result<TxEnvelope> {
+        match &self.backend {
+            Backend::Local(wallet) => {
+                NetworkWallet::<Ethereum>::sign_transaction_from(wallet, sender, tx).await
+            }
+            Backend::Remote(remote) => remote.sign_transaction(sender, tx).await,
+        }
+    }
+}
+
+/// Client for a Web3Signer-style JSON-RPC signing service
+struct RemoteSigner {
+    client: JsonRpcClient<alloy::transports::http::Http<alloy::transports::http::Client>>,
+    address: Address,
+}
+
+impl RemoteSigner {
+    /// Connect and make sure the service manages the signing account
+    async fn connect(url: &str, address: Option<Address>) -> Result<Self, SignerError> {
+        let url: url::Url = url
+            .parse()
+            .map_err(|_| SignerError::InvalidUrl(url.to_string()))?;
+        let client = ClientBuilder::default().http(url);
+
+        let accounts: Vec<Address> = client
+            .request_noparams("eth_accounts")
+            .await
+            .map_err(|e| SignerError::Remote(e.to_string()))?;
+
+        let address = match address {
+            Some(address) if accounts.contains(&address) => address,
+            Some(address) => return Err(SignerError::UnknownAccount(address)),
+            None => *accounts
+                .first()
+                .ok_or_else(|| SignerError::Remote("no accounts available".to_string()))?,
+        };
+
+        Ok(Self { client, address })
+    }
+
+    /// Have the service sign a transaction and check who signed it
+    async fn sign_transaction(
+        &self,
+        sender: Address,
+        tx: TypedTransaction,
+    ) -> alloy::signers::Result<TxEnvelope> {
+        let mut request: TransactionRequest = tx.into();
+        request.from = Some(sender);
+
+        let raw: Bytes = self
+            .client
+            .request("eth_signTransaction", (request,))
+            .await
+            .map_err(alloy::signers::Error::other)?;
+
+        let envelope =
+            TxEnvelope::decode_2718(&mut raw.as_ref()).map_err(alloy::signers::Error::other)?;
+        let signed_by = envelope
+            .recover_signer()
+            .map_err(alloy::signers::Error::other)?;
+        if signed_by != sender {
+            return Err(alloy::signers::Error::other(format!(
+                "remote signer signed with {} instead of {}",
+                signed_by, sender
+            )));
+        }
+
+        Ok(envelope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::{
+        consensus::TxLegacy,
+        eips::eip2718::Encodable2718,
+        primitives::{TxKind, U256},
+    };
+    use axum::{routing::post, Json, Router};
+    use serde_json::{json, Value};
+
+    // Hardhat dev account #0
+    const KEY: &str = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+    fn tx() -> TypedTransaction {
+        TypedTransaction::Legacy(TxLegacy {
+            chain_id: Some(31337),
+            nonce: 0,
+            gas_price: 1_000_000_000,
+            gas_limit: 21_000,
+            to: TxKind::Call(Address::repeat_byte(0x11)),
+            value: U256::from(1u64),
+            input: Bytes::new(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_another_signer_is_refused_until_adopted() {
+        let pool = db::test_pool().await;
+        let first = Address::repeat_byte(0x01);
+        let second = Address::repeat_byte(0x02);
+
+        check_recorded(&pool, first).await.unwrap();
+        check_recorded(&pool, first).await.unwrap();
+        let error = check_recorded(&pool, second).await.unwrap_err();
+        assert!(matches!(error, SignerError::Changed { configured, .. } if configured == second));
+
+        let previous = adopt(&pool, second).await.unwrap();
+        assert_eq!(previous, Some(format!("{:#x}", first)));
+        check_recorded(&pool, second).await.unwrap();
+        assert!(check_recorded(&pool, first).await.is_err());
+    }
+
+    /// Minimal Web3Signer stand-in holding one local key
+    async fn remote_signer(key: &str) -> String {
+        let wallet = EthereumWallet::from(key.parse::<PrivateKeySigner>().unwrap());
+
+        let app = Router::new().route(
+            "/",
+            post(move |Json(req): Json<Value>| {
+                let wallet = wallet.clone();
+                async move {
+                    let result = match req["method"].as_str() {
+                        Some("eth_accounts") => {
+                            json!([NetworkWallet::<Ethereum>::default_signer_address(&wallet)])
+                        }
+                        Some("eth_signTransaction") => {
+                            let request: TransactionRequest =
+                                serde_json::from_value(req["params"][0].clone()).unwrap();
+                            let from = request.from.unwrap();
+                            let tx = request.build_typed_tx().unwrap();
+                            let signed =
+                                NetworkWallet::<Ethereum>::sign_transaction_from(&wallet, from, tx)
+                                    .await
+                                    .unwrap();
+                            json!(Bytes::from(signed.encoded_2718()))
+                        }
+                        _ => Value::Null,
+                    };
+                    Json(json!({ "jsonrpc": "2.0", "id": req["id"], "result": result }))
+                }
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_private_key_signer() {
+        let signer = Signer::from_config(&SignerConfig::PrivateKey(Secret::new(KEY)))
+            .await
+            .unwrap();
+        let expected: PrivateKeySigner = KEY.parse().unwrap();
+        assert_eq!(signer.address(), expected.address());
+        assert!(matches!(
+            Signer::from_config(&SignerConfig::PrivateKey(Secret::new("0x1234"))).await,
+            Err(SignerError::InvalidPrivateKey)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_keystore_signer() {
+        let dir = std::env::temp_dir().join(format!("radhat-keystore-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let key = hex::decode(KEY.trim_start_matches("0x")).unwrap();
+        eth_keystore::encrypt_key(&dir, &mut rand::thread_rng(), key, "hunter2", Some("key"))
+            .unwrap();
+        let path = dir.join("key");
+
+        let unlocked = Signer::from_config(&SignerConfig::Keystore {
+            path: path.clone(),
+            password: Secret::new("hunter2"),
+        })
+        .await;
+        let wrong_password = Signer::from_config(&SignerConfig::Keystore {
+            path,
+            password: Secret::new("hunter3"),
+        })
+        .await;
+        std::fs::remove_dir_all(&dir).ok();
+
+        let expected: PrivateKeySigner = KEY.parse().unwrap();
+        assert_eq!(unlocked.unwrap().address(), expected.address());
+        assert!(matches!(wrong_password, Err(SignerError::Keystore(..))));
+    }
+
+    #[tokio::test]
+    async fn test_remote_signer_signs_transactions() {
+        let url = remote_signer(KEY).await;
+        let signer = Signer::from_config(&SignerConfig::Remote { url, address: None })
+            .await
+            .unwrap();
+
+        let expected: PrivateKeySigner = KEY.parse().unwrap();
+        assert_eq!(signer.address(), expected.address());
+
+        let signed = signer
+            .sign_transaction_from(signer.address(), tx())
+            .await
+            .unwrap();
+        assert_eq!(signed.recover_signer().unwrap(), expected.address());
+    }
+
+    #[tokio::test]
+    async fn test_remote_signer_rejects_unknown_account() {
+        let url = remote_signer(KEY).await;
+        let result = Signer::from_config(&SignerConfig::Remote {
+            url,
+            address: Some(format!("{:#x}", Address::repeat_byte(0x22))),
+        })
+        .await;
+        assert!(matches!(result, Err(SignerError::UnknownAccount(_))));
+    }
+
+    #[tokio::test]
+    async fn test_debug_output_is_redacted() {
+        let config = SignerConfig::PrivateKey(Secret::new(KEY));
+        let signer = Signer::from_config(&config).await.unwrap();
+        let key = KEY.trim_start_matches("0x");
+
+        assert!(!format!("{:?}", config).contains(key));
+        assert!(!format!("{:?}", signer).contains(key));
+        assert!(format!("{:?}", config).contains("[REDACTED]"));
+    }
+}
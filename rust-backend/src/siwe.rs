@@ -0,0 +1,344 @@
+//! Sign-In with Ethereum (EIP-4361)
+//!
+//! Parses the plain-text SIWE message a wallet signed and checks it against
+//! what this server expects: its domain, the chain, the validity window and
+//! an EIP-191 `personal_sign` signature from the address in the message.
+//! Nonce bookkeeping and sessions live in `routes::siwe`.
+//!
+//! Only EOA signatures are accepted; EIP-1271 contract wallets are not.
+
+use std::str::FromStr;
+
+use alloy::primitives::{Address, PrimitiveSignature};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+const PREAMBLE: &str = " wants you to sign in with your Ethereum account:";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SiweError {
+    #[error("Malformed SIWE message: {0}")]
+    Parse(String),
+    #[error("Message is for domain '{found}', expected '{expected}'")]
+    Domain { expected: String, found: String },
+    #[error("Message is for chain {found}, expected {expected}")]
+    ChainId { expected: u64, found: u64 },
+    #[error("Message has expired")]
+    Expired,
+    #[error("Message is not valid yet")]
+    NotYetValid,
+    #[error("Invalid signature: {0}")]
+    Signature(String),
+    #[error("Signature is from {recovered}, not {address}")]
+    WrongSigner {
+        address: Address,
+        recovered: Address,
+    },
+    #[error("Unknown, expired or already used nonce")]
+    Nonce,
+}
+
+/// A parsed EIP-4361 message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SiweMessage {
+    pub domain: String,
+    pub address: Address,
+    pub statement: Option<String>,
+    pub uri: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub nonce: String,
+    pub issued_at: OffsetDateTime,
+    pub expiration_time: Option<OffsetDateTime>,
+    pub not_before: Option<OffsetDateTime>,
+    pub request_id: Option<String>,
+    pub resources: Vec<String>,
+}
+
+fn parse_time(field: &str, value: &str) -> Result<OffsetDateTime, SiweError> {
+    OffsetDateTime::parse(value, &Rfc3339)
+        .map_err(|e| SiweError::Parse(format!("{}: {}", field, e)))
+}
+
+impl FromStr for SiweMessage {
+    type Err = SiweError;
+
+    fn from_str(message: &str) -> Result<Self, Self::Err> {
+        let missing = |what: &str| SiweError::Parse(format!("missing {}", what));
+        let mut lines = message.lines().peekable();
+
+        let domain = lines
+            .next()
+            .and_then(|line| line.strip_suffix(PREAMBLE))
+            .filter(|domain| !domain.is_empty())
+            .ok_or_else(|| missing("preamble"))?
+            .to_string();
+
+        // EIP-4361 requires the EIP-55 checksummed form
+        let address = lines.next().ok_or_else(|| missing("address"))?;
+        let address = Address::parse_checksummed(address, None)
+            .map_err(|e| SiweError::Parse(format!("address: {}", e)))?;
+
+        // Optional statement, surrounded by blank lines
+        while lines.peek() == Some(&"") {
+            lines.next();
+        }
+        let statement = match lines.peek() {
+            Some(line) if !line.starts_with("URI: ") => {
+                let statement = line.to_string();
+                lines.next();
+                Some(statement)
+            }
+            _ => None,
+        };
+        while lines.peek() == Some(&"") {
+            lines.next();
+        }
+
+        let mut field = |name: &str| -> Option<String> {
+            let value = lines.peek()?.strip_prefix(name)?.strip_prefix(": ")?;
+            let value = value.to_string();
+            lines.next();
+            Some(value)
+        };
+
+        let uri = field("URI").ok_or_else(|| missing("URI"))?;
+        let version = field("Version").ok_or_else(|| missing("Version"))?;
+        let chain_id = field("Chain ID")
+            .ok_or_else(|| missing("Chain ID"))?
+            .parse()
+            .map_err(|_| SiweError::Parse("invalid Chain ID".to_string()))?;
+        let nonce = field("Nonce").ok_or_else(|| missing("Nonce"))?;
+        let issued_at = parse_time(
+            "Issued At",
+            &field("Issued At").ok_or_else(|| missing("Issued At"))?,
+        )?;
+        let expiration_time = field("Expiration Time")
+            .map(|t| parse_time("Expiration Time", &t))
+            .transpose()?;
+        let not_before = field("Not Before")
+            .map(|t| parse_time("Not Before", &t))
+            .transpose()?;
+        let request_id = field("Request ID");
+
+        let mut resources = vec![];
+        if lines.peek() == Some(&"Resources:") {
+            lines.next();
+            while let Some(resource) = lines.peek().and_then(|l| l.strip_prefix("- ")) {
+                resources.push(resource.to_string());
+                lines.next();
+            }
+        }
+
+        if let Some(extra) = lines.find(|line| !line.is_empty()) {
+            return Err(SiweError::Parse(format!("unexpected line '{}'", extra)));
+        }
+        if version != "1" {
+            return Err(SiweError::Parse(format!(
+                "unsupported version '{}'",
+                version
+            )));
+        }
+        if nonce.len() < 8 || !nonce.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(SiweError::Parse(
+                "nonce must be at least 8 alphanumeric characters".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            domain,
+            address,
+            statement,
+            uri,
+            version,
+            chain_id,
+            nonce,
+            issued_at,
+            expiration_time,
+            not_before,
+            request_id,
+            resources,
+        })
+    }
+}
+
+impl SiweMessage {
+    /// Check everything except the nonce, which the caller must consume
+    ///
+    /// `raw` is the exact text that was signed.
+    pub fn verify(
+        &self,
+        raw: &str,
+        signature: &str,
+        domain: &str,
+        chain_id: u64,
+        now: OffsetDateTime,
+    ) -> Result<(), SiweError> {
+        if without_scheme(&self.domain) != without_scheme(domain) {
+            return Err(SiweError::Domain {
+                expected: domain.to_string(),
+                found: self.domain.clone(),
+            });
+        }
+        if self.chain_id != chain_id {
+            return Err(SiweError::ChainId {
+                expected: chain_id,
+                found: self.chain_id,
+            });
+        }
+        if self.expiration_time.is_some_and(|t| now >= t) {
+            return Err(SiweError::Expired);
+        }
+        if self.not_before.is_some_and(|t| now < t) {
+            return Err(SiweError::NotYetValid);
+        }
+
+        let signature = PrimitiveSignature::from_str(signature)
+            .map_err(|e| SiweError::Signature(e.to_string()))?;
+        let recovered = signature
+            .recover_address_from_msg(raw)
+            .map_err(|e| SiweError::Signature(e.to_string()))?;
+        if recovered != self.address {
+            return Err(SiweError::WrongSigner {
+                address: self.address,
+                recovered,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn without_scheme(domain: &str) -> &str {
+    domain.split_once("://").map_or(domain, |(_, rest)| rest)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use alloy::signers::{local::PrivateKeySigner, SignerSync};
+    use time::Duration;
+
+    /// A message as wallets' SIWE libraries format it
+    pub fn message(address: Address, nonce: &str, chain_id: u64) -> String {
+        format!(
+            "app.radhat.xyz wants you to sign in with your Ethereum account:\n\
+             {}\n\
+             \n\
+             Sign in to manage your deposit addresses.\n\
+             \n\
+             URI: https://app.radhat.xyz\n\
+             Version: 1\n\
+             Chain ID: {}\n\
+             Nonce: {}\n\
+             Issued At: 2026-01-01T00:00:00Z\n\
+             Expiration Time: 2099-01-01T00:00:00Z",
+            address.to_checksum(None),
+            chain_id,
+            nonce
+        )
+    }
+
+    fn now() -> OffsetDateTime {
+        OffsetDateTime::parse("2026-06-01T00:00:00Z", &Rfc3339).unwrap()
+    }
+
+    #[test]
+    fn test_parse_full_message() {
+        let address = Address::repeat_byte(0xab);
+        let raw = format!(
+            "{}\nNot Before: 2026-01-01T00:00:00Z\nRequest ID: req-1\nResources:\n- ipfs://a\n- https://b",
+            message(address, "abcdef123456", 11155111)
+        );
+
+        let msg: SiweMessage = raw.parse().unwrap();
+
+        assert_eq!(msg.domain, "app.radhat.xyz");
+        assert_eq!(msg.address, address);
+        assert_eq!(
+            msg.statement.as_deref(),
+            Some("Sign in to manage your deposit addresses.")
+        );
+        assert_eq!(msg.chain_id, 11155111);
+        assert_eq!(msg.nonce, "abcdef123456");
+        assert_eq!(msg.request_id.as_deref(), Some("req-1"));
+        assert_eq!(msg.resources, ["ipfs://a", "https://b"]);
+    }
+
+    #[test]
+    fn test_parse_without_statement() {
+        let address = Address::repeat_byte(0xab).to_checksum(None);
+        let raw = format!(
+            "localhost:3000 wants you to sign in with your Ethereum account:\n{}\n\nURI: http://localhost:3000\nVersion: 1\nChain ID: 1\nNonce: 12345678\nIssued At: 2026-01-01T00:00:00.000Z",
+            address
+        );
+
+        let msg: SiweMessage = raw.parse().unwrap();
+        assert_eq!(msg.statement, None);
+        assert_eq!(msg.expiration_time, None);
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_messages() {
+        let good = message(Address::repeat_byte(0xab), "abcdef123456", 1);
+        let cases = [
+            good.replace("Version: 1", "Version: 2"),
+            good.replace("abcdef123456", "short"),
+            good.replace(
+                &Address::repeat_byte(0xab).to_checksum(None),
+                &format!("{:#x}", Address::repeat_byte(0xab)),
+            ),
+            good.replace("Chain ID: 1\n", ""),
+            format!("{}\nUnexpected: field", good),
+        ];
+
+        for raw in cases {
+            assert!(
+                matches!(raw.parse::<SiweMessage>(), Err(SiweError::Parse(_))),
+                "{}",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify_signature_and_constraints() {
+        let wallet = PrivateKeySigner::random();
+        let raw = message(wallet.address(), "abcdef123456", 1);
+        let signature = wallet
+            .sign_message_sync(raw.as_bytes())
+            .unwrap()
+            .to_string();
+        let msg: SiweMessage = raw.parse().unwrap();
+
+        assert!(msg
+            .verify(&raw, &signature, "app.radhat.xyz", 1, now())
+            .is_ok());
+        assert!(msg
+            .verify(&raw, &signature, "https://app.radhat.xyz", 1, now())
+            .is_ok());
+        assert!(matches!(
+            msg.verify(&raw, &signature, "evil.example", 1, now()),
+            Err(SiweError::Domain { .. })
+        ));
+        assert!(matches!(
+            msg.verify(&raw, &signature, "app.radhat.xyz", 5, now()),
+            Err(SiweError::ChainId { .. })
+        ));
+        assert!(matches!(
+            msg.verify(
+                &raw,
+                &signature,
+                "app.radhat.xyz",
+                1,
+                now() + Duration::days(365 * 100)
+            ),
+            Err(SiweError::Expired)
+        ));
+
+        let other = PrivateKeySigner::random();
+        let forged = other.sign_message_sync(raw.as_bytes()).unwrap().to_string();
+        assert!(matches!(
+            msg.verify(&raw, &forged, "app.radhat.xyz", 1, now()),
+            Err(SiweError::WrongSigner { .. })
+        ));
+    }
+}
@@ -0,0 +1,186 @@
+//! Stranded funds analyzer
+//!
+//! The proxy runtime only forwards ETH on `receive`, so ETH that reached a
+//! CREATE2 address *before* the proxy was deployed stays on the proxy after
+//! deployment and no call can move it. Once a proxy is deployed, any ETH sent
+//! to it is forwarded immediately, so a deployed proxy with a non-zero balance
+//! is stranded by definition. Stranded funds are recorded next to the deposit
+//! without changing its status, so what its proxy forwards later is still
+//! routed.
+
+use alloy::primitives::U256;
+use sqlx::SqlitePool;
+
+use crate::{
+    chain::ChainClient,
+    db::{self, StrandedRow},
+    models::{StrandedProxyInfo, StrandedReport},
+    rpc::parse_address,
+};
+
+/// Statuses whose proxies are already deployed on-chain
+pub const DEPLOYED_STATUSES: &[&str] = &["deployed", "routed", "stranded"];
+
+/// Sum the recorded balances of stranded proxies
+pub fn total_wei(rows: &[StrandedRow]) -> U256 {
+    rows.iter()
+        .filter_map(|row| row.balance_wei.parse::<U256>().ok())
+        .fold(U256::ZERO, |acc, balance| acc.saturating_add(balance))
+}
+
+/// Record a stranded balance; the deposit keeps its status
+pub async fn mark_stranded(
+    pool: &SqlitePool,
+    deposit_address: &str,
+    balance: U256,
+) -> Result<(), sqlx::Error> {
+    db::upsert_stranded(pool, deposit_address, &balance.to_string()).await
+}
+
+/// Re-check the balance of every deployed proxy and record any stranded funds
+///
+/// Returns the errors encountered for individual proxies; a failed check or an
+/// address without code leaves the deposit untouched.
+pub async fn scan(pool: &SqlitePool, chain: &dyn ChainClient) -> Result<Vec<String>, sqlx::Error> {
+    let deposits = db::get_deposits_by_statuses(pool, DEPLOYED_STATUSES).await?;
+    let mut errors = vec![];
+
+    for deposit in &deposits {
+        let proxy = match parse_address(&deposit.deposit_address) {
+            Ok(addr) => addr,
+            Err(e) => {
+                errors.push(format!(
+                    "Invalid address {}: {}",
+                    deposit.deposit_address, e
+                ));
+                continue;
+            }
+        };
+
+        match chain.get_code(proxy).await {
+            Ok(code) if code.is_empty() => {
+                errors.push(format!(
+                    "No proxy code at {} despite status '{}'",
+                    deposit.deposit_address, deposit.status
+                ));
+                continue;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                errors.push(format!(
+                    "Code check failed for {}: {}",
+                    deposit.deposit_address, e
+                ));
+                continue;
+            }
+        }
+
+        match chain.get_balance(proxy).await {
+            Ok(balance) if balance > U256::ZERO => {
+                tracing::warn!(
+                    deposit = %deposit.deposit_address,
+                    balance_wei = %balance,
+                    "Funds stranded on deployed proxy"
+                );
+                mark_stranded(pool, &deposit.deposit_address, balance).await?;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                errors.push(format!(
+                    "Balance check failed for {}: {}",
+                    deposit.deposit_address, e
+                ));
+            }
+        }
+    }
+
+    Ok(errors)
+}
+
+/// Build the stranded funds report from the database
+pub async fn report(pool: &SqlitePool) -> Result<StrandedReport, sqlx::Error> {
+    let rows = db::get_stranded(pool).await?;
+    let total = total_wei(&rows);
+
+    tracing::info!(
+        stranded_proxies = rows.len(),
+        stranded_wei = %total,
+        "Stranded funds report"
+    );
+
+    Ok(StrandedReport {
+        count: rows.len(),
+        total_wei: total.to_string(),
+        proxies: rows
+            .into_iter()
+            .map(|row| StrandedProxyInfo {
+                deposit_address: row.deposit_address,
+                user_address: row.user_address,
+                balance_wei: row.balance_wei,
+                detected_at: row.detected_at,
+                last_checked_at: row.last_checked_at,
+            })
+            .collect(),
+        errors: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::fake::FakeChain;
+    use alloy::primitives::Address;
+
+    fn row(balance_wei: &str) -> StrandedRow {
+        StrandedRow {
+            deposit_address: "0x00".to_string(),
+            user_address: "0x00".to_string(),
+            balance_wei: balance_wei.to_string(),
+            detected_at: String::new(),
+            last_checked_at: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scan_records_balances_on_deployed_proxies() {
+        let pool = db::test_pool().await;
+        let chain = FakeChain::new();
+
+        let stuck = Address::repeat_byte(0x01);
+        let drained = Address::repeat_byte(0x02);
+        let missing = Address::repeat_byte(0x03);
+        for (i, addr) in [stuck, drained, missing].iter().enumerate() {
+            let deposit_address = format!("{:#x}", addr);
+            db::insert_deposit(&pool, "0xuser", "0x00", &deposit_address, i as u64, None)
+                .await
+                .unwrap();
+            db::update_deposit_status(&pool, &deposit_address, "routed")
+                .await
+                .unwrap();
+        }
+        chain.set_code(stuck);
+        chain.set_code(drained);
+        chain.fund(stuck, 7);
+        chain.fund(missing, 9);
+
+        let errors = scan(&pool, &chain).await.unwrap();
+        let report = report(&pool).await.unwrap();
+
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("No proxy code"));
+        assert_eq!(report.count, 1);
+        assert_eq!(report.total_wei, "7");
+        assert_eq!(report.proxies[0].deposit_address, format!("{:#x}", stuck));
+        let row = db::get_deposit_by_address(&pool, &format!("{:#x}", stuck))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(row.status, "routed");
+    }
+
+    #[test]
+    fn test_total_wei_sums_balances() {
+        let rows = vec![row("1000000000000000000"), row("5"), row("not a number")];
+        assert_eq!(total_wei(&rows), U256::from(1_000_000_000_000_000_005u64));
+    }
+}
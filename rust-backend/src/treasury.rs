@@ -0,0 +1,341 @@
+//! Treasuries and routing rules
+//!
+//! Funds go to the configured treasury unless a routing rule matches the
+//! deposit. Rules match on the deposit's user, its tenant (the API key that
+//! created it) and the asset, and split the funds across treasuries from the
+//! `treasuries` registry in basis points. Each share becomes its own
+//! `transferFunds` call and is recorded in `deposit_allocations`.
+//!
+//! Rounding: every share is rounded down to the wei and the last treasury of
+//! the split receives the remainder. Its transfer goes last and moves the
+//! proxy's whole remaining balance, so no dust is left on the proxy.
+
+use std::collections::HashMap;
+
+use alloy::primitives::{Address, U256};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::db::{self, DepositRow, RuleRow};
+
+/// Shares of a split add up to this many basis points
+pub const TOTAL_BPS: u32 = 10_000;
+
+/// The only asset routing moves; rules for other assets don't match yet
+pub const NATIVE_ASSET: &str = "ETH";
+
+/// One share of a rule's split, naming a registered treasury
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitSpec {
+    pub treasury: String,
+    pub share_bps: u32,
+}
+
+/// One share of a deposit's funds, resolved to a treasury address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Split {
+    pub treasury: Address,
+    pub share_bps: u32,
+}
+
+impl Split {
+    /// Everything to one treasury
+    pub fn whole(treasury: Address) -> Vec<Split> {
+        vec![Split {
+            treasury,
+            share_bps: TOTAL_BPS,
+        }]
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TreasuryError {
+    #[error("Treasury {0} is not registered or no longer active")]
+    UnknownTreasury(String),
+    #[error("Invalid split: {0}")]
+    InvalidSplit(String),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// Check that shares are positive, name each treasury once and add up to
+/// [`TOTAL_BPS`]
+pub fn validate_splits(splits: &[SplitSpec]) -> Result<(), TreasuryError> {
+    if splits.is_empty() {
+        return Err(TreasuryError::InvalidSplit(
+            "no treasuries given".to_string(),
+        ));
+    }
+    for (i, split) in splits.iter().enumerate() {
+        if split.share_bps == 0 {
+            return Err(TreasuryError::InvalidSplit(format!(
+                "share of {} is zero",
+                split.treasury
+            )));
+        }
+        if splits[..i].iter().any(|s| s.treasury == split.treasury) {
+            return Err(TreasuryError::InvalidSplit(format!(
+                "{} is listed twice",
+                split.treasury
+            )));
+        }
+    }
+    let total: u64 = splits.iter().map(|s| u64::from(s.share_bps)).sum();
+    if total != u64::from(TOTAL_BPS) {
+        return Err(TreasuryError::InvalidSplit(format!(
+            "shares add up to {} bps, not {}",
+            total, TOTAL_BPS
+        )));
+    }
+    Ok(())
+}
+
+/// Amount each treasury of `splits` gets of `balance`: shares rounded down
+/// to the wei, the remainder to the last treasury
+pub fn split_amounts(balance: U256, splits: &[Split]) -> Vec<U256> {
+    let mut amounts: Vec<U256> = splits
+        .iter()
+        .map(|s| balance * U256::from(s.share_bps) / U256::from(TOTAL_BPS))
+        .collect();
+    if let Some(last) = amounts.len().checked_sub(1) {
+        let others = amounts[..last]
+            .iter()
+            .fold(U256::ZERO, |acc, amount| acc + amount);
+        amounts[last] = balance - others;
+    }
+    amounts
+}
+
+/// Routing rules and active treasuries, loaded once per routing run
+pub struct RoutingRules {
+    rules: Vec<RuleRow>,
+    /// Active treasuries by name
+    treasuries: HashMap<String, Address>,
+}
+
+impl RoutingRules {
+    pub async fn load(pool: &SqlitePool) -> Result<Self, sqlx::Error> {
+        let rules = db::list_routing_rules(pool).await?;
+        let treasuries = db::get_active_treasuries(pool)
+            .await?
+            .into_iter()
+            .filter_map(|row| Some((row.name, row.address.parse().ok()?)))
+            .collect();
+        Ok(Self { rules, treasuries })
+    }
+
+    /// Where a deposit's funds go: the matching rule's split, or everything
+    /// to `default` if no rule matches
+    pub fn splits_for(
+        &self,
+        deposit: &DepositRow,
+        default: Address,
+    ) -> Result<Vec<Split>, TreasuryError> {
+        let Some(rule) = matching_rule(&self.rules, deposit, NATIVE_ASSET) else {
+            return Ok(Split::whole(default));
+        };
+
+        let specs: Vec<SplitSpec> = serde_json::from_str(&rule.splits).map_err(|e| {
+            TreasuryError::InvalidSplit(format!("rule {} is unreadable: {}", rule.id, e))
+        })?;
+        specs
+            .into_iter()
+            .map(|spec| {
+                let treasury = *self
+                    .treasuries
+                    .get(&spec.treasury)
+                    .ok_or(TreasuryError::UnknownTreasury(spec.treasury))?;
+                Ok(Split {
+                    treasury,
+                    share_bps: spec.share_bps,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The rule for a deposit: every field a rule sets must match, and the most
+/// specific rule wins (user over tenant over asset), then the oldest
+fn matching_rule<'a>(
+    rules: &'a [RuleRow],
+    deposit: &DepositRow,
+    asset: &str,
+) -> Option<&'a RuleRow> {
+    rules
+        .iter()
+        .filter(|rule| {
+            rule.user_address
+                .as_deref()
+                .is_none_or(|user| user.eq_ignore_ascii_case(&deposit.user_address))
+                && rule
+                    .tenant
+                    .as_deref()
+                    .is_none_or(|tenant| deposit.tenant.as_deref() == Some(tenant))
+                && rule
+                    .asset
+                    .as_deref()
+                    .is_none_or(|a| a.eq_ignore_ascii_case(asset))
+        })
+        .max_by_key(|rule| {
+            (
+                rule.user_address.is_some(),
+                rule.tenant.is_some(),
+                rule.asset.is_some(),
+                std::cmp::Reverse(rule.id),
+            )
+        })
+}
+
+/// Whether `treasury` is an active treasury in the registry
+pub async fn is_registered(pool: &SqlitePool, treasury: Address) -> Result<bool, sqlx::Error> {
+    Ok(db::get_active_treasuries(pool)
+        .await?
+        .iter()
+        .any(|row| row.address.parse::<Address>().is_ok_and(|a| a == treasury)))
+}
+
+/// Names of the treasuries a rule splits across
+pub fn rule_treasuries(rule: &RuleRow) -> Vec<String> {
+    serde_json::from_str::<Vec<SplitSpec>>(&rule.splits)
+        .map(|specs| specs.into_iter().map(|s| s.treasury).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(byte: u8, share_bps: u32) -> Split {
+        Split {
+            treasury: Address::repeat_byte(byte),
+            share_bps,
+        }
+    }
+
+    fn spec(treasury: &str, share_bps: u32) -> SplitSpec {
+        SplitSpec {
+            treasury: treasury.to_string(),
+            share_bps,
+        }
+    }
+
+    fn rule(id: i64, user: Option<&str>, tenant: Option<&str>, asset: Option<&str>) -> RuleRow {
+        RuleRow {
+            id,
+            user_address: user.map(String::from),
+            tenant: tenant.map(String::from),
+            asset: asset.map(String::from),
+            splits: "[]".to_string(),
+            created_at: String::new(),
+        }
+    }
+
+    fn deposit(user: &str, tenant: Option<&str>) -> DepositRow {
+        DepositRow {
+            id: 1,
+            user_address: user.to_string(),
+            salt: String::new(),
+            deposit_address: String::new(),
+            nonce: 0,
+            status: "funded".to_string(),
+            created_at: String::new(),
+            updated_at: String::new(),
+            attempts: 0,
+            last_error: None,
+            next_retry_at: None,
+            tenant: tenant.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_split_rounds_down_and_gives_remainder_to_last() {
+        let splits = [split(1, 9_500), split(2, 500)];
+        assert_eq!(
+            split_amounts(U256::from(1_000), &splits),
+            [U256::from(950), U256::from(50)]
+        );
+        // 95% of 999 is 949.05: 949 to the first, 50 to the last
+        assert_eq!(
+            split_amounts(U256::from(999), &splits),
+            [U256::from(949), U256::from(50)]
+        );
+
+        let thirds = [split(1, 3_333), split(2, 3_333), split(3, 3_334)];
+        let amounts = split_amounts(U256::from(10), &thirds);
+        assert_eq!(amounts, [U256::from(3), U256::from(3), U256::from(4)]);
+        assert_eq!(
+            split_amounts(U256::from(7), &Split::whole(Address::ZERO)),
+            [U256::from(7)]
+        );
+    }
+
+    #[test]
+    fn test_validate_splits() {
+        assert!(validate_splits(&[spec("ops", 9_500), spec("fees", 500)]).is_ok());
+        assert!(validate_splits(&[]).is_err());
+        assert!(validate_splits(&[spec("ops", 9_000)]).is_err());
+        assert!(validate_splits(&[spec("ops", 10_000), spec("fees", 0)]).is_err());
+        assert!(validate_splits(&[spec("ops", 5_000), spec("ops", 5_000)]).is_err());
+    }
+
+    #[test]
+    fn test_most_specific_rule_wins() {
+        let rules = [
+            rule(1, None, None, None),
+            rule(2, None, Some("shop"), None),
+            rule(3, Some("0xaa"), None, None),
+            rule(4, None, None, Some("USDC")),
+        ];
+        let id = |user, tenant| {
+            matching_rule(&rules, &deposit(user, tenant), NATIVE_ASSET).map(|r| r.id)
+        };
+
+        assert_eq!(id("0xAA", Some("shop")), Some(3));
+        assert_eq!(id("0xbb", Some("shop")), Some(2));
+        assert_eq!(id("0xbb", None), Some(1));
+        assert_eq!(
+            matching_rule(&rules[3..], &deposit("0xbb", None), NATIVE_ASSET).map(|r| r.id),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rule_splits_resolve_registered_treasuries() {
+        let pool = db::test_pool().await;
+        let ops = Address::repeat_byte(0x01);
+        let fees = Address::repeat_byte(0x02);
+        db::insert_treasury(&pool, "ops", &format!("{:#x}", ops))
+            .await
+            .unwrap();
+        db::insert_treasury(&pool, "fees", &format!("{:#x}", fees))
+            .await
+            .unwrap();
+        let splits = serde_json::to_string(&[spec("ops", 9_500), spec("fees", 500)]).unwrap();
+        db::insert_routing_rule(&pool, None, Some("shop"), None, &splits)
+            .await
+            .unwrap();
+
+        let default = Address::repeat_byte(0x7e);
+        let rules = RoutingRules::load(&pool).await.unwrap();
+        assert_eq!(
+            rules
+                .splits_for(&deposit("0xaa", Some("shop")), default)
+                .unwrap(),
+            [split(0x01, 9_500), split(0x02, 500)]
+        );
+        assert_eq!(
+            rules.splits_for(&deposit("0xaa", None), default).unwrap(),
+            Split::whole(default)
+        );
+
+        // A deactivated treasury stops the rule rather than falling back
+        db::deactivate_treasury(&pool, "fees").await.unwrap();
+        let rules = RoutingRules::load(&pool).await.unwrap();
+        assert!(matches!(
+            rules.splits_for(&deposit("0xaa", Some("shop")), default),
+            Err(TreasuryError::UnknownTreasury(_))
+        ));
+        assert!(is_registered(&pool, ops).await.unwrap());
+        assert!(!is_registered(&pool, fees).await.unwrap());
+    }
+}
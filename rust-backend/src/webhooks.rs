@@ -0,0 +1,882 @@
+//! Webhooks: deposit lifecycle events pushed to subscribers
+//!
+//! A subscription names a URL, the events it wants and optionally a tenant
+//! (the API key whose deposits it follows; without one it follows every
+//! deposit). Events:
+//!
+//! - `created`: the deposit address was handed out
+//! - `funded`: routing saw a balance on it
+//! - `confirmed`: its proxy deployment was confirmed on chain
+//! - `routed`: its funds reached the treasuries
+//! - `failed`: its deployment failed for good or was dead-lettered
+//!
+//! Events go through an outbox: [`notify`] stores one delivery per
+//! subscriber next to the state change, and the dispatcher started by
+//! [`spawn`] sends due deliveries. A delivery that doesn't get a 2xx answer is
+//! retried with exponential backoff and marked `failed` after the last
+//! attempt; every attempt is logged in `webhook_attempts`, and any delivery
+//! can be replayed.
+//!
+//! Requests are signed: `X-Radhat-Signature` is `sha256=` followed by the hex
+//! HMAC-SHA256, keyed with the subscription secret, of
+//! `{X-Radhat-Timestamp}.{body}`. Receivers should check it with [`verify`]
+//! or an equivalent and reject stale timestamps.
+//!
+//! Response bodies end up in the delivery log, so webhooks may only target
+//! public addresses (see [`check_target`]) unless
+//! `WEBHOOK_ALLOW_PRIVATE_TARGETS` is set, and redirects are not followed.
+//! The delivery client resolves hosts itself and drops private addresses,
+//! so a host can't pass the check and then resolve to a private address
+//! when the request connects.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::SqlitePool;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::task::JoinHandle;
+
+use crate::{
+    config::Config,
+    db::{self, DeliveryRow, DepositRow, WebhookAttempt},
+    metrics,
+    models::{WebhookDeposit, WebhookPayload},
+    telemetry,
+};
+
+pub const SIGNATURE_HEADER: &str = "x-radhat-signature";
+pub const TIMESTAMP_HEADER: &str = "x-radhat-timestamp";
+pub const EVENT_HEADER: &str = "x-radhat-event";
+pub const DELIVERY_HEADER: &str = "x-radhat-delivery";
+
+/// How often the dispatcher looks for due deliveries
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Most deliveries sent per poll
+const BATCH_SIZE: usize = 50;
+
+/// Longest a receiver may take to answer, and longest the target check may
+/// take to resolve its host
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A claimed delivery isn't picked up again for this long. Deliveries are
+/// claimed one at a time, so this only has to cover one attempt: the target
+/// check and the request, each bounded by [`REQUEST_TIMEOUT`].
+const CLAIM_SECS: u64 = 60;
+
+/// Longest wait between two attempts at a delivery
+const MAX_DELIVERY_DELAY: Duration = Duration::from_secs(3600);
+
+/// Longest receiver response kept in the log
+const MAX_LOGGED_BODY_CHARS: usize = 500;
+
+/// A deposit lifecycle event
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookEvent {
+    Created,
+    Funded,
+    Confirmed,
+    Routed,
+    Failed,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 5] = [
+        WebhookEvent::Created,
+        WebhookEvent::Funded,
+        WebhookEvent::Confirmed,
+        WebhookEvent::Routed,
+        WebhookEvent::Failed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::Created => "created",
+            WebhookEvent::Funded => "funded",
+            WebhookEvent::Confirmed => "confirmed",
+            WebhookEvent::Routed => "routed",
+            WebhookEvent::Failed => "failed",
+        }
+    }
+
+    pub fn parse(event: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|e| e.as_str() == event)
+    }
+}
+
+/// Format an event filter for the `events` column
+pub fn format_events(events: &[WebhookEvent]) -> String {
+    events
+        .iter()
+        .map(WebhookEvent::as_str)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Parse the `events` column, skipping events this version doesn't know
+pub fn parse_events(events: &str) -> Vec<WebhookEvent> {
+    events.split(',').filter_map(WebhookEvent::parse).collect()
+}
+
+/// Where deliveries may go and how failed ones are retried
+#[derive(Clone, Copy, Debug)]
+pub struct DeliveryPolicy {
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubles with each further attempt
+    pub base_delay: Duration,
+    /// Send to loopback, private and link-local addresses too
+    pub allow_private_targets: bool,
+}
+
+impl DeliveryPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_attempts: config.webhook_max_attempts,
+            base_delay: Duration::from_secs(config.webhook_base_delay_secs),
+            allow_private_targets: config.webhook_allow_private_targets,
+        }
+    }
+
+    /// When to try again after the `attempt`th failed attempt; `None` once
+    /// the attempts are used up
+    fn retry_after(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        Some(
+            self.base_delay
+                .checked_mul(factor)
+                .map_or(MAX_DELIVERY_DELAY, |delay| delay.min(MAX_DELIVERY_DELAY)),
+        )
+    }
+}
+
+/// Check that a webhook URL is http(s) and, unless `allow_private`, that its
+/// host only resolves to public addresses
+///
+/// Called when a subscription is created and again before every delivery,
+/// since DNS answers change.
+pub async fn check_target(url: &str, allow_private: bool) -> Result<(), String> {
+    let parsed = url::Url::parse(url).map_err(|e| format!("Invalid url {}: {}", url, e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err("url must be http or https".to_string());
+    }
+    let host = parsed
+        .host()
+        .ok_or_else(|| format!("url {} has no host", url))?;
+    if allow_private {
+        return Ok(());
+    }
+
+    let addresses: Vec<IpAddr> = match &host {
+        url::Host::Ipv4(ip) => vec![IpAddr::V4(*ip)],
+        url::Host::Ipv6(ip) => vec![IpAddr::V6(*ip)],
+        url::Host::Domain(domain) => {
+            let port = parsed.port_or_known_default().unwrap_or(443);
+            tokio::net::lookup_host((*domain, port))
+                .await
+                .map_err(|e| format!("Cannot resolve {}: {}", domain, e))?
+                .map(|addr| addr.ip())
+                .collect()
+        }
+    };
+    if addresses.is_empty() {
+        return Err(format!("{} does not resolve to any address", host));
+    }
+    match addresses.into_iter().find(|ip| !is_public(*ip)) {
+        Some(ip) => Err(format!(
+            "{} resolves to {}, which is not a public address",
+            host, ip
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Whether an address is reachable on the public internet, i.e. not
+/// loopback, private, link-local (cloud metadata), shared, unspecified,
+/// multicast or reserved
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // Shared address space (RFC 6598)
+                || (a == 100 && (64..128).contains(&b))
+                // 0.0.0.0/8 and reserved 240.0.0.0/4
+                || a == 0
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(v4));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local fc00::/7 and link-local fe80::/10
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Signature header value for a request body sent at `timestamp`
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    format!(
+        "sha256={}",
+        hex::encode(mac(secret, timestamp, body).finalize().into_bytes())
+    )
+}
+
+/// Check a signature header value in constant time
+pub fn verify(secret: &str, timestamp: i64, body: &[u8], signature: &str) -> bool {
+    let Some(digest) = signature
+        .strip_prefix("sha256=")
+        .and_then(|hex_digest| hex::decode(hex_digest).ok())
+    else {
+        return false;
+    };
+    mac(secret, timestamp, body).verify_slice(&digest).is_ok()
+}
+
+fn mac(secret: &str, timestamp: i64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+/// Queue `event` of a deposit for its subscribers
+///
+/// The deposit's state change has already happened, so failures are logged
+/// rather than returned.
+pub async fn notify(pool: &SqlitePool, deposit_address: &str, event: WebhookEvent) {
+    if let Err(e) = enqueue(pool, deposit_address, event).await {
+        tracing::error!(
+            deposit = %deposit_address,
+            event = event.as_str(),
+            "Failed to queue webhook deliveries: {}",
+            e
+        );
+    }
+}
+
+/// Store a delivery of `event` for every subscriber of the deposit,
+/// returning how many were queued
+pub async fn enqueue(
+    pool: &SqlitePool,
+    deposit_address: &str,
+    event: WebhookEvent,
+) -> Result<usize, sqlx::Error> {
+    let Some(deposit) = db::get_deposit_by_address(pool, deposit_address).await? else {
+        return Ok(0);
+    };
+    let subscribers: Vec<_> = db::get_webhook_subscribers(pool, deposit.tenant.as_deref())
+        .await?
+        .into_iter()
+        .filter(|sub| parse_events(&sub.events).contains(&event))
+        .collect();
+    if subscribers.is_empty() {
+        return Ok(0);
+    }
+
+    // Plain strings and numbers always serialize
+    let payload = serde_json::to_string(&event_payload(&deposit, event)).unwrap_or_default();
+    for sub in &subscribers {
+        db::insert_webhook_delivery(pool, sub.id, event.as_str(), deposit_address, &payload)
+            .await?;
+    }
+    tracing::debug!(
+        deposit = %deposit_address,
+        event = event.as_str(),
+        "Queued {} webhook deliveries",
+        subscribers.len()
+    );
+    Ok(subscribers.len())
+}
+
+/// The event's body; its `id` is shared by every subscriber's delivery
+fn event_payload(deposit: &DepositRow, event: WebhookEvent) -> WebhookPayload {
+    WebhookPayload {
+        id: uuid::Uuid::new_v4().to_string(),
+        event,
+        created_at: OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_default(),
+        deposit: WebhookDeposit {
+            deposit_address: deposit.deposit_address.clone(),
+            user: deposit.user_address.clone(),
+            nonce: deposit.nonce,
+            status: deposit.status.clone(),
+            tenant: deposit.tenant.clone(),
+            error: match event {
+                WebhookEvent::Failed => deposit.last_error.clone(),
+                _ => None,
+            },
+        },
+    }
+}
+
+/// HTTP client for deliveries
+///
+/// Redirects are not followed: they could lead to targets [`check_target`]
+/// refuses. Unless `allow_private`, hosts are resolved by [`PublicResolver`].
+pub fn client(allow_private: bool) -> reqwest::Client {
+    let builder = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .user_agent(concat!("radhat-webhooks/", env!("CARGO_PKG_VERSION")));
+    let builder = if allow_private {
+        builder
+    } else {
+        builder.dns_resolver(Arc::new(PublicResolver))
+    };
+    builder.build().expect("default TLS backend is available")
+}
+
+/// Resolves hosts like the system resolver, keeping only public addresses
+///
+/// [`check_target`] resolves the host before a delivery, but the answer may
+/// change by the time the request connects (DNS rebinding); filtering here
+/// applies the same rule to the addresses actually connected to.
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let host = name.as_str();
+            // The port is replaced by the URL's
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} does not resolve to any public address", host).into());
+            }
+            let addrs: reqwest::dns::Addrs = Box::new(addrs.into_iter());
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(addrs)
+        })
+    }
+}
+
+/// Send due deliveries in the background until the process exits
+pub fn spawn(pool: SqlitePool, policy: DeliveryPolicy) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let client = client(policy.allow_private_targets);
+        loop {
+            if let Err(e) = deliver_due(&pool, &client, policy).await {
+                tracing::warn!("Failed to load due webhook deliveries: {}", e);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    })
+}
+
+/// Send the deliveries that are due, returning how many were attempted
+///
+/// Each delivery is claimed just before it is sent, so claims never expire
+/// while earlier deliveries of the batch are still being sent.
+pub async fn deliver_due(
+    pool: &SqlitePool,
+    client: &reqwest::Client,
+    policy: DeliveryPolicy,
+) -> Result<usize, sqlx::Error> {
+    let mut attempted = 0;
+    let mut last_id = 0;
+    while attempted < BATCH_SIZE {
+        // Past the deliveries of this batch, which may be due again already
+        let Some(delivery) = db::claim_webhook_delivery(pool, last_id, CLAIM_SECS).await? else {
+            break;
+        };
+        attempted += 1;
+        last_id = delivery.id;
+        if let Err(e) = deliver(pool, client, policy, &delivery).await {
+            tracing::error!(
+                delivery_id = delivery.id,
+                "Failed to record webhook attempt: {}",
+                e
+            );
+        }
+    }
+    Ok(attempted)
+}
+
+/// Make one attempt at a delivery and record its outcome
+async fn deliver(
+    pool: &SqlitePool,
+    client: &reqwest::Client,
+    policy: DeliveryPolicy,
+    delivery: &DeliveryRow,
+) -> Result<(), sqlx::Error> {
+    let Some(sub) = db::get_webhook_subscription(pool, delivery.subscription_id).await? else {
+        return Ok(());
+    };
+
+    let timestamp = OffsetDateTime::now_utc().unix_timestamp();
+    let started = Instant::now();
+    let check = tokio::time::timeout(
+        REQUEST_TIMEOUT,
+        check_target(&sub.url, policy.allow_private_targets),
+    )
+    .await
+    .unwrap_or_else(|_| Err("resolving the host timed out".to_string()));
+    let response = match check {
+        Ok(()) => Ok(client
+            .post(&sub.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, &delivery.event)
+            .header(DELIVERY_HEADER, delivery.id.to_string())
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(
+                SIGNATURE_HEADER,
+                sign(&sub.secret, timestamp, delivery.payload.as_bytes()),
+            )
+            .body(delivery.payload.clone())
+            .send()
+            .await),
+        Err(e) => Err(format!("Target refused: {}", e)),
+    };
+
+    let (status_code, error) = match response {
+        Err(e) => (None, Some(e)),
+        Ok(Ok(response)) if response.status().is_success() => {
+            (Some(response.status().as_u16()), None)
+        }
+        Ok(Ok(response)) => {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            let body: String = body.chars().take(MAX_LOGGED_BODY_CHARS).collect();
+            (
+                Some(status.as_u16()),
+                Some(format!("HTTP {}: {}", status, body)),
+            )
+        }
+        Ok(Err(e)) => (None, Some(e.to_string())),
+    };
+    let attempt = WebhookAttempt {
+        status_code,
+        error: error.as_deref(),
+        duration_ms: started.elapsed().as_millis() as u64,
+    };
+
+    let attempts = u32::try_from(delivery.attempts + 1).unwrap_or(u32::MAX);
+    let (status, retry_in) = match &error {
+        None => ("delivered", None),
+        Some(_) => match policy.retry_after(attempts) {
+            Some(delay) => ("pending", Some(delay)),
+            None => ("failed", None),
+        },
+    };
+    metrics::webhook_delivery(status);
+    let target = telemetry::redact_url(&sub.url);
+    match (&error, retry_in) {
+        (None, _) => tracing::debug!(delivery_id = delivery.id, url = %target, "Delivered webhook"),
+        (Some(e), Some(delay)) => tracing::warn!(
+            delivery_id = delivery.id,
+            url = %target,
+            "Webhook delivery failed, retrying in {}s (attempt {} of {}): {}",
+            delay.as_secs(),
+            attempts,
+            policy.max_attempts,
+            e
+        ),
+        (Some(e), None) => tracing::error!(
+            delivery_id = delivery.id,
+            url = %target,
+            "Webhook delivery failed after {} attempts: {}",
+            attempts,
+            e
+        ),
+    }
+
+    db::record_webhook_attempt(
+        pool,
+        delivery.id,
+        &attempt,
+        status,
+        retry_in.map(|delay| delay.as_secs()),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+    use std::sync::{Arc, Mutex};
+
+    /// A request the receiver got
+    #[derive(Clone, Debug)]
+    struct Received {
+        headers: HeaderMap,
+        body: Bytes,
+    }
+
+    #[derive(Clone, Default)]
+    struct Receiver {
+        received: Arc<Mutex<Vec<Received>>>,
+        /// Statuses to answer with in turn, then 200
+        statuses: Arc<Mutex<Vec<StatusCode>>>,
+    }
+
+    async fn receive(
+        State(receiver): State<Receiver>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> StatusCode {
+        receiver
+            .received
+            .lock()
+            .unwrap()
+            .push(Received { headers, body });
+        let mut statuses = receiver.statuses.lock().unwrap();
+        if statuses.is_empty() {
+            StatusCode::OK
+        } else {
+            statuses.remove(0)
+        }
+    }
+
+    /// Local HTTP receiver answering with `statuses` in turn; returns its
+    /// URL and the requests it got
+    async fn receiver(statuses: Vec<StatusCode>) -> (String, Arc<Mutex<Vec<Received>>>) {
+        let receiver = Receiver {
+            statuses: Arc::new(Mutex::new(statuses)),
+            ..Receiver::default()
+        };
+        let received = receiver.received.clone();
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .with_state(receiver);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, received)
+    }
+
+    /// Two attempts without delay; the receivers run on loopback
+    fn policy() -> DeliveryPolicy {
+        DeliveryPolicy {
+            max_attempts: 2,
+            base_delay: Duration::ZERO,
+            allow_private_targets: true,
+        }
+    }
+
+    async fn subscribe(pool: &SqlitePool, tenant: Option<&str>, url: &str, events: &str) -> i64 {
+        db::insert_webhook_subscription(pool, tenant, url, "whsec_test", events)
+            .await
+            .unwrap()
+            .id
+    }
+
+    #[test]
+    fn test_signatures() {
+        let signature = sign("secret", 1_700_000_000, b"{}");
+        assert!(signature.starts_with("sha256=") && signature.len() == 71);
+        assert!(verify("secret", 1_700_000_000, b"{}", &signature));
+        assert!(!verify("other", 1_700_000_000, b"{}", &signature));
+        assert!(!verify("secret", 1_700_000_001, b"{}", &signature));
+        assert!(!verify("secret", 1_700_000_000, b"{ }", &signature));
+        assert!(!verify("secret", 1_700_000_000, b"{}", "sha256=zz"));
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let policy = DeliveryPolicy {
+            max_attempts: 12,
+            base_delay: Duration::from_secs(30),
+            allow_private_targets: false,
+        };
+        assert_eq!(policy.retry_after(1), Some(Duration::from_secs(30)));
+        assert_eq!(policy.retry_after(3), Some(Duration::from_secs(120)));
+        assert_eq!(policy.retry_after(11), Some(MAX_DELIVERY_DELAY));
+        assert_eq!(policy.retry_after(12), None);
+    }
+
+    #[test]
+    fn test_public_addresses() {
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00:ec2::254",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_private_targets_are_refused() {
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "https://10.0.0.5/hook",
+            "http://localhost/hook",
+        ] {
+            let error = check_target(url, false).await.unwrap_err();
+            assert!(error.contains("not a public address"), "{}: {}", url, error);
+            assert!(check_target(url, true).await.is_ok(), "{}", url);
+        }
+        assert!(check_target("https://93.184.216.34/hook", false)
+            .await
+            .is_ok());
+        assert!(check_target("ftp://93.184.216.34/", true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_events_are_queued_for_matching_subscribers() {
+        let pool = db::test_pool().await;
+        db::insert_deposit(&pool, "0xuser", "0x00", "0xaa", 0, Some("shop"))
+            .await
+            .unwrap();
+        let all = subscribe(&pool, None, "http://a", "created,funded").await;
+        let shop = subscribe(&pool, Some("shop"), "http://b", "funded").await;
+        subscribe(&pool, Some("other"), "http://c", "created,funded").await;
+
+        assert_eq!(
+            enqueue(&pool, "0xaa", WebhookEvent::Created).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            enqueue(&pool, "0xaa", WebhookEvent::Funded).await.unwrap(),
+            2
+        );
+        assert_eq!(
+            enqueue(&pool, "0xaa", WebhookEvent::Routed).await.unwrap(),
+            0
+        );
+
+        let queued = db::list_webhook_deliveries(&pool, all, 10).await.unwrap();
+        assert_eq!(queued.len(), 2);
+        let funded = db::list_webhook_deliveries(&pool, shop, 10).await.unwrap();
+        assert_eq!(funded.len(), 1);
+        let payload: WebhookPayload = serde_json::from_str(&funded[0].payload).unwrap();
+        assert_eq!(payload.event, WebhookEvent::Funded);
+        assert_eq!(payload.deposit.deposit_address, "0xaa");
+        assert_eq!(payload.deposit.tenant.as_deref(), Some("shop"));
+        // Both subscribers get the same event
+        let other: WebhookPayload = serde_json::from_str(&queued[0].payload).unwrap();
+        assert_eq!(other.id, payload.id);
+    }
+
+    #[tokio::test]
+    async fn test_deliveries_are_signed_and_retried() {
+        let pool = db::test_pool().await;
+        let (url, received) = receiver(vec![StatusCode::SERVICE_UNAVAILABLE]).await;
+        db::insert_deposit(&pool, "0xuser", "0x00", "0xaa", 0, None)
+            .await
+            .unwrap();
+        let sub = subscribe(&pool, None, &url, "created").await;
+        enqueue(&pool, "0xaa", WebhookEvent::Created).await.unwrap();
+
+        let client = client(true);
+        // The receiver is down the first time
+        assert_eq!(deliver_due(&pool, &client, policy()).await.unwrap(), 1);
+        let delivery = &db::list_webhook_deliveries(&pool, sub, 10).await.unwrap()[0];
+        assert_eq!(delivery.status, "pending");
+        assert_eq!(delivery.last_status_code, Some(503));
+
+        // Due again at once with a zero base delay
+        assert_eq!(deliver_due(&pool, &client, policy()).await.unwrap(), 1);
+        let delivery = &db::list_webhook_deliveries(&pool, sub, 10).await.unwrap()[0];
+        assert_eq!(delivery.status, "delivered");
+        assert_eq!(delivery.attempts, 2);
+        assert!(delivery.delivered_at.is_some());
+        assert_eq!(deliver_due(&pool, &client, policy()).await.unwrap(), 0);
+
+        let attempts = db::list_webhook_attempts(&pool, delivery.id).await.unwrap();
+        let codes: Vec<_> = attempts.iter().map(|a| a.status_code).collect();
+        assert_eq!(codes, [Some(503), Some(200)]);
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 2);
+        let request = &received[1];
+        let header = |name: &str| request.headers[name].to_str().unwrap().to_string();
+        let timestamp: i64 = header(TIMESTAMP_HEADER).parse().unwrap();
+        assert!(verify(
+            "whsec_test",
+            timestamp,
+            &request.body,
+            &header(SIGNATURE_HEADER)
+        ));
+        assert_eq!(header(EVENT_HEADER), "created");
+        assert_eq!(header(DELIVERY_HEADER), delivery.id.to_string());
+        let payload: WebhookPayload = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(payload.deposit.status, "pending");
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_deliveries_fail_and_can_be_replayed() {
+        let pool = db::test_pool().await;
+        let (url, received) = receiver(vec![
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ])
+        .await;
+        db::insert_deposit(&pool, "0xuser", "0x00", "0xaa", 0, None)
+            .await
+            .unwrap();
+        let sub = subscribe(&pool, None, &url, "created").await;
+        enqueue(&pool, "0xaa", WebhookEvent::Created).await.unwrap();
+
+        let client = client(true);
+        deliver_due(&pool, &client, policy()).await.unwrap();
+        deliver_due(&pool, &client, policy()).await.unwrap();
+        let delivery = &db::list_webhook_deliveries(&pool, sub, 10).await.unwrap()[0];
+        assert_eq!(delivery.status, "failed");
+        assert_eq!(deliver_due(&pool, &client, policy()).await.unwrap(), 0);
+
+        assert!(db::replay_webhook_delivery(&pool, delivery.id)
+            .await
+            .unwrap());
+        assert_eq!(deliver_due(&pool, &client, policy()).await.unwrap(), 1);
+        let replayed = db::get_webhook_delivery(&pool, delivery.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(replayed.status, "delivered");
+        assert_eq!(
+            db::list_webhook_attempts(&pool, delivery.id)
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+
+        // The replay carries the original payload
+        let received = received.lock().unwrap();
+        assert_eq!(received[0].body, received[2].body);
+    }
+
+    #[tokio::test]
+    async fn test_deliveries_to_private_targets_are_refused() {
+        let pool = db::test_pool().await;
+        let (url, received) = receiver(vec![]).await;
+        db::insert_deposit(&pool, "0xuser", "0x00", "0xaa", 0, None)
+            .await
+            .unwrap();
+        let sub = subscribe(&pool, None, &url, "created").await;
+        enqueue(&pool, "0xaa", WebhookEvent::Created).await.unwrap();
+
+        let strict = DeliveryPolicy {
+            allow_private_targets: false,
+            ..policy()
+        };
+        assert_eq!(deliver_due(&pool, &client(false), strict).await.unwrap(), 1);
+
+        assert!(received.lock().unwrap().is_empty());
+        let delivery = &db::list_webhook_deliveries(&pool, sub, 10).await.unwrap()[0];
+        assert_eq!(delivery.status, "pending");
+        assert_eq!(delivery.last_status_code, None);
+        assert!(delivery
+            .last_error
+            .as_deref()
+            .unwrap()
+            .starts_with("Target refused"));
+    }
+
+    #[tokio::test]
+    async fn test_client_does_not_connect_to_private_addresses() {
+        let (url, received) = receiver(vec![]).await;
+        // A host name passes no check before the request, as if it had
+        // resolved to a public address then
+        let url = url.replace("127.0.0.1", "localhost");
+
+        let error = client(false).post(&url).send().await.unwrap_err();
+        assert!(error.is_connect(), "{:?}", error);
+        assert!(received.lock().unwrap().is_empty());
+
+        let response = client(true).post(&url).send().await.unwrap();
+        assert!(response.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_deliveries_are_claimed_one_at_a_time() {
+        let pool = db::test_pool().await;
+        db::insert_deposit(&pool, "0xuser", "0x00", "0xaa", 0, None)
+            .await
+            .unwrap();
+        subscribe(&pool, None, "http://127.0.0.1:9/hook", "created,funded").await;
+        enqueue(&pool, "0xaa", WebhookEvent::Created).await.unwrap();
+        enqueue(&pool, "0xaa", WebhookEvent::Funded).await.unwrap();
+
+        // A claim covers one delivery; the next stays due for other
+        // dispatchers until it is claimed itself
+        let first = db::claim_webhook_delivery(&pool, 0, CLAIM_SECS)
+            .await
+            .unwrap()
+            .unwrap();
+        let second = db::claim_webhook_delivery(&pool, 0, CLAIM_SECS)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (first.event.as_str(), second.event.as_str()),
+            ("created", "funded")
+        );
+        assert!(db::claim_webhook_delivery(&pool, 0, CLAIM_SECS)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_deactivated_subscriptions_get_nothing() {
+        let pool = db::test_pool().await;
+        db::insert_deposit(&pool, "0xuser", "0x00", "0xaa", 0, None)
+            .await
+            .unwrap();
+        let sub = subscribe(&pool, None, "http://127.0.0.1:9/hook", "created").await;
+        enqueue(&pool, "0xaa", WebhookEvent::Created).await.unwrap();
+        assert!(db::deactivate_webhook_subscription(&pool, sub)
+            .await
+            .unwrap());
+
+        // Queued deliveries stay in the outbox unsent, new events aren't queued
+        assert_eq!(
+            deliver_due(&pool, &client(true), policy()).await.unwrap(),
+            0
+        );
+        assert_eq!(
+            enqueue(&pool, "0xaa", WebhookEvent::Created).await.unwrap(),
+            0
+        );
+    }
+}
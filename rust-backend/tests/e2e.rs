@@ -0,0 +1,391 @@
+//! End-to-end tests against a local dev node
+//!
+//! Deploys the compiled Hardhat artifacts to a fresh Anvil node, then drives
+//! the real axum app through `POST /deposit`, `POST /router` and
+//! `POST /deposits/{address}/route`.
+//!
+//! Requirements:
+//! - `pnpm compile` at the repository root (writes `artifacts/`)
+//! - `anvil` on `PATH`, or `RADHAT_E2E_RPC_URL` pointing at a running
+//!   Anvil/Hardhat node that uses the default dev mnemonic
+//!
+//! Run with: `cargo test --test e2e -- --ignored`
+
+use std::{
+    net::TcpListener,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::Arc,
+    time::Duration,
+};
+
+use alloy::{
+    network::{EthereumWallet, TransactionBuilder},
+    primitives::{Address, Bytes, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::TransactionRequest,
+    signers::local::PrivateKeySigner,
+    sol,
+    sol_types::SolValue,
+};
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+use sqlx::sqlite::SqlitePoolOptions;
+use tower::ServiceExt;
+
+use radhat_backend::{
+    app, checks, config::PartialConfig, db, lease::RoutingLease, rate_limit::RateLimiter,
+    rpc::RpcClient, signer::Secret, AppState,
+};
+
+/// Dev account #0: deploys the contracts and signs for the backend
+const SIGNER_KEY: &str = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+/// Dev account #1: allowlisted treasury
+const TREASURY: Address = alloy::primitives::address!("70997970C51812dc3A010C7d01b50e0d17dc79C8");
+
+const CALLER_BIT: u8 = 0x01;
+const TREASURY_BIT: u8 = 0x02;
+
+const ONE_ETH: u64 = 1_000_000_000_000_000_000;
+
+sol! {
+    #[sol(rpc)]
+    interface IFundRouterStorage {
+        function setPermissions(address who, uint8 bits) external;
+    }
+
+    #[sol(rpc)]
+    interface IDeterministicProxyDeployer {
+        function getInitCodeHash() external view returns (bytes32);
+    }
+}
+
+/// A dev node, either spawned here or provided through `RADHAT_E2E_RPC_URL`
+struct Node {
+    url: String,
+    child: Option<Child>,
+}
+
+impl Node {
+    async fn start() -> Self {
+        if let Ok(url) = std::env::var("RADHAT_E2E_RPC_URL") {
+            return Self { url, child: None };
+        }
+
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .expect("no free port")
+            .port();
+        let child = Command::new("anvil")
+            .args(["--port", &port.to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to spawn anvil; install Foundry or set RADHAT_E2E_RPC_URL");
+
+        let node = Self {
+            url: format!("http://127.0.0.1:{}", port),
+            child: Some(child),
+        };
+
+        let provider = ProviderBuilder::new().on_http(node.url.parse().unwrap());
+        for _ in 0..50 {
+            if provider.get_chain_id().await.is_ok() {
+                return node;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("anvil did not come up at {}", node.url);
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Deployed contract addresses
+struct Contracts {
+    router: Address,
+    deployer: Address,
+    init_code_hash: String,
+}
+
+/// Read creation bytecode from a Hardhat artifact
+fn artifact_bytecode(contract: &str) -> Vec<u8> {
+    let dir = std::env::var("RADHAT_ARTIFACTS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../artifacts/contracts")
+        });
+    let path = dir.join(format!("{0}.sol/{0}.json", contract));
+    let raw = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("{}: {} (run `pnpm compile`)", path.display(), e));
+    let artifact: Value = serde_json::from_str(&raw).unwrap();
+    let bytecode = artifact["bytecode"]
+        .as_str()
+        .expect("artifact has no bytecode");
+    hex::decode(bytecode.trim_start_matches("0x")).unwrap()
+}
+
+/// Deploy storage, router and proxy deployer the way `scripts/deploy.ts` does
+async fn deploy_contracts(rpc_url: &str) -> Contracts {
+    let signer: PrivateKeySigner = SIGNER_KEY.parse().unwrap();
+    let owner = signer.address();
+    let provider = ProviderBuilder::new()
+        .with_recommended_fillers()
+        .wallet(EthereumWallet::from(signer))
+        .on_http(rpc_url.parse().unwrap());
+
+    let deploy = |contract: &str, arg: Address| {
+        let mut code = artifact_bytecode(contract);
+        code.extend_from_slice(&arg.abi_encode());
+        let provider = &provider;
+        async move {
+            let tx = TransactionRequest::default().with_deploy_code(Bytes::from(code));
+            let receipt = provider
+                .send_transaction(tx)
+                .await
+                .unwrap()
+                .get_receipt()
+                .await
+                .unwrap();
+            receipt.contract_address.expect("no contract address")
+        }
+    };
+
+    let storage = deploy("FundRouterStorage", owner).await;
+    let permissions = IFundRouterStorage::new(storage, &provider);
+    for (who, bits) in [(owner, CALLER_BIT), (TREASURY, TREASURY_BIT)] {
+        permissions
+            .setPermissions(who, bits)
+            .send()
+            .await
+            .unwrap()
+            .get_receipt()
+            .await
+            .unwrap();
+    }
+
+    let router = deploy("FundRouter", storage).await;
+    let deployer = deploy("DeterministicProxyDeployer", router).await;
+    let init_code_hash = IDeterministicProxyDeployer::new(deployer, &provider)
+        .getInitCodeHash()
+        .call()
+        .await
+        .unwrap()
+        ._0;
+
+    Contracts {
+        router,
+        deployer,
+        init_code_hash: format!("{:#x}", init_code_hash),
+    }
+}
+
+async fn build_app(rpc_url: &str, contracts: &Contracts) -> Router {
+    let config = PartialConfig {
+        database_url: Some("sqlite::memory:".to_string()),
+        rpc_url: Some(rpc_url.to_string()),
+        deployer_address: Some(format!("{:#x}", contracts.deployer)),
+        router_address: Some(format!("{:#x}", contracts.router)),
+        treasury_address: Some(format!("{:#x}", TREASURY)),
+        init_code_hash: Some(contracts.init_code_hash.clone()),
+        private_key: Some(Secret::new(SIGNER_KEY)),
+        host: Some("127.0.0.1".to_string()),
+        port: Some(0),
+        // Auth is covered by unit tests; this test exercises the chain
+        require_api_keys: Some(false),
+        ..PartialConfig::default()
+    }
+    .resolve()
+    .unwrap();
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect(&config.database_url)
+        .await
+        .unwrap();
+    db::run_migrations(&pool).await.unwrap();
+
+    let chain = RpcClient::from_config(&config).await.unwrap();
+    let startup_checks = checks::startup_checks(&pool, &config, &chain).await;
+    assert!(startup_checks.ok, "{:?}", startup_checks.failures());
+    app(AppState {
+        limiter: Arc::new(RateLimiter::new(config.rate_limit_store, &pool)),
+        routing_lease: Arc::new(RoutingLease::new(config.routing_lease_ttl())),
+        db: pool,
+        config: Arc::new(config),
+        chain: Arc::new(chain),
+        startup_checks: Arc::new(startup_checks),
+    })
+}
+
+async fn call(app: &Router, method: &str, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+/// Poll a routing job until it finishes and return its result
+async fn wait_for_job(app: &Router, id: &Value) -> Value {
+    let uri = format!("/jobs/{}", id);
+    for _ in 0..300 {
+        let (status, job) = call(app, "GET", &uri, None).await;
+        assert_eq!(status, StatusCode::OK, "{}", job);
+        if job["status"] != "running" {
+            assert_eq!(job["status"], "completed", "{}", job);
+            return job["result"].clone();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("routing job {} did not finish", id);
+}
+
+async fn send_eth(rpc_url: &str, to: Address, wei: U256) {
+    let signer: PrivateKeySigner = SIGNER_KEY.parse().unwrap();
+    let provider = ProviderBuilder::new()
+        .with_recommended_fillers()
+        .wallet(EthereumWallet::from(signer))
+        .on_http(rpc_url.parse().unwrap());
+    let tx = TransactionRequest::default().with_to(to).with_value(wei);
+    let receipt = provider
+        .send_transaction(tx)
+        .await
+        .unwrap()
+        .get_receipt()
+        .await
+        .unwrap();
+    assert!(receipt.status());
+}
+
+#[tokio::test]
+#[ignore = "requires anvil and compiled contracts (pnpm compile)"]
+async fn test_deposit_and_route_against_dev_node() {
+    let node = Node::start().await;
+    let contracts = deploy_contracts(&node.url).await;
+    let app = build_app(&node.url, &contracts).await;
+    let provider = ProviderBuilder::new().on_http(node.url.parse().unwrap());
+
+    let (status, health) = call(&app, "GET", "/health", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(health["status"], "ok");
+
+    // One funded and one unfunded deposit
+    let (status, funded) = call(
+        &app,
+        "POST",
+        "/deposit",
+        Some(json!({ "user": "0x00000000000000000000000000000000c0ffee01" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", funded);
+    let (_, unfunded) = call(
+        &app,
+        "POST",
+        "/deposit",
+        Some(json!({ "user": "0x00000000000000000000000000000000c0ffee02" })),
+    )
+    .await;
+
+    let deposit_address = funded["deposit_address"].as_str().unwrap().to_string();
+    let deposit: Address = deposit_address.parse().unwrap();
+    send_eth(&node.url, deposit, U256::from(ONE_ETH)).await;
+
+    let treasury_before = provider.get_balance(TREASURY).await.unwrap();
+
+    let (status, job) = call(&app, "POST", "/router", None).await;
+    assert_eq!(status, StatusCode::ACCEPTED, "{}", job);
+    let routed = wait_for_job(&app, &job["id"]).await;
+    assert_eq!(routed["checked"], 2, "{}", routed);
+    assert_eq!(routed["funded"], 1, "{}", routed);
+    assert_eq!(routed["deployed"], 1, "{}", routed);
+
+    // The backend's CREATE2 derivation must match where deployMultiple put the proxy
+    let code = provider.get_code_at(deposit).await.unwrap();
+    assert!(!code.is_empty(), "no proxy deployed at {}", deposit_address);
+
+    // ETH sent before deployment can't be moved, so nothing reaches the treasury
+    assert_eq!(routed["routed"], 0, "{}", routed);
+    assert_eq!(routed["stranded"], 1, "{}", routed);
+    assert_eq!(
+        provider.get_balance(TREASURY).await.unwrap(),
+        treasury_before
+    );
+    assert_eq!(
+        provider.get_balance(deposit).await.unwrap(),
+        U256::from(ONE_ETH)
+    );
+
+    let (_, info) = call(&app, "GET", &format!("/deposits/{}", deposit_address), None).await;
+    assert_eq!(info["status"], "deployed");
+    let unfunded_address = unfunded["deposit_address"].as_str().unwrap();
+    let (_, info) = call(
+        &app,
+        "GET",
+        &format!("/deposits/{}", unfunded_address),
+        None,
+    )
+    .await;
+    assert_eq!(info["status"], "pending");
+
+    let (_, report) = call(&app, "GET", "/stranded", None).await;
+    assert_eq!(report["count"], 1);
+    assert_eq!(report["total_wei"], ONE_ETH.to_string());
+
+    // Once deployed, the proxy forwards new deposits straight to the FundRouter
+    let router_before = provider.get_balance(contracts.router).await.unwrap();
+    send_eth(&node.url, deposit, U256::from(ONE_ETH / 2)).await;
+    assert_eq!(
+        provider.get_balance(contracts.router).await.unwrap(),
+        router_before + U256::from(ONE_ETH / 2)
+    );
+    assert_eq!(
+        provider.get_balance(deposit).await.unwrap(),
+        U256::from(ONE_ETH)
+    );
+
+    // Routing the deposit again pays what its proxy forwarded out of the router
+    let uri = format!("/deposits/{}/route", deposit_address);
+    let (status, route) = call(&app, "POST", &uri, None).await;
+    assert_eq!(status, StatusCode::OK, "{}", route);
+    assert_eq!(route["status"], "routed", "{}", route);
+    assert_eq!(
+        provider.get_balance(TREASURY).await.unwrap(),
+        treasury_before + U256::from(ONE_ETH / 2)
+    );
+    assert_eq!(
+        provider.get_balance(contracts.router).await.unwrap(),
+        router_before
+    );
+
+    let (_, info) = call(&app, "GET", &format!("/deposits/{}", deposit_address), None).await;
+    assert_eq!(info["status"], "routed");
+    assert_eq!(
+        info["allocations"][0]["amount_wei"],
+        (ONE_ETH / 2).to_string()
+    );
+
+    // The ETH sent before the deployment is still reported
+    let (_, report) = call(&app, "GET", "/stranded", None).await;
+    assert_eq!(report["total_wei"], ONE_ETH.to_string());
+}
@@ -0,0 +1,358 @@
+//! Differential tests for the `create2` module against EVM execution
+//!
+//! Runs CREATE2 in an in-process EVM (revm) and checks that `derive_salt`,
+//! `compute_create2_address` and `proxy_init_code` agree with what the chain
+//! actually does, for arbitrary salts, callers and routers. No node required.
+//!
+//! The tests marked `#[ignore]` run the compiled `DeterministicProxyDeployer`
+//! from the Hardhat artifacts; run `pnpm compile` at the repository root, then
+//! `cargo test --test evm_differential -- --include-ignored`.
+
+use std::path::PathBuf;
+
+use alloy::{
+    primitives::{Address as AlloyAddress, FixedBytes},
+    sol,
+    sol_types::{SolCall, SolValue},
+};
+use proptest::prelude::*;
+use revm::{
+    db::{CacheDB, EmptyDB},
+    primitives::{AccountInfo, Address, Bytecode, Bytes, ExecutionResult, Output, TxKind, U256},
+    Evm,
+};
+
+use radhat_backend::create2::{
+    compute_create2_address, derive_salt, proxy_init_code, proxy_init_code_hash,
+};
+
+sol! {
+    function deployMultiple(bytes32[] calldata salts) external returns (address[] memory addrs);
+    function calculateDestinationAddresses(bytes32[] calldata salts) external view returns (address[] memory out);
+    function getInitCodeHash() external view returns (bytes32);
+    function FUND_ROUTER_ADDRESS() external view returns (address);
+}
+
+/// Minimal CREATE2 factory mirroring `deployMultiple` for a single salt
+///
+/// Calldata is `userSalt ++ initCode`. The factory derives
+/// `salt = keccak256(userSalt ++ msg.sender)` like `_deriveSalt`, runs
+/// CREATE2 with the init code and returns the new address.
+const FACTORY_RUNTIME: &[u8] = &[
+    0x60, 0x00, 0x35, // PUSH1 0 CALLDATALOAD        userSalt
+    0x60, 0x00, 0x52, // PUSH1 0 MSTORE              mem[0..32] = userSalt
+    0x33, 0x60, 0x60, 0x1b, // CALLER PUSH1 96 SHL    caller, left-aligned
+    0x60, 0x20, 0x52, // PUSH1 32 MSTORE             mem[32..52] = caller
+    0x60, 0x34, 0x60, 0x00, 0x20, // PUSH1 52 PUSH1 0 SHA3   salt
+    0x60, 0x20, 0x36, 0x03, // PUSH1 32 CALLDATASIZE SUB   len = cds - 32
+    0x80, // DUP1
+    0x60, 0x20, 0x60, 0x40, 0x37, // CALLDATACOPY(0x40, 32, len)
+    0x60, 0x40, 0x60, 0x00, 0xf5, // CREATE2(0, 0x40, len, salt)
+    0x60, 0x00, 0x52, // PUSH1 0 MSTORE
+    0x60, 0x20, 0x60, 0x00, 0xf3, // RETURN(0, 32)
+];
+
+/// Runtime that always reverts, to stand in for a router rejecting ETH
+const REVERTING_RUNTIME: &[u8] = &[0x60, 0x00, 0x80, 0xfd];
+
+const FACTORY: Address = Address::repeat_byte(0xfa);
+
+struct Chain {
+    db: CacheDB<EmptyDB>,
+}
+
+impl Chain {
+    fn new() -> Self {
+        let mut chain = Self {
+            db: CacheDB::new(EmptyDB::default()),
+        };
+        chain.set_code(FACTORY, FACTORY_RUNTIME);
+        chain
+    }
+
+    fn set_code(&mut self, address: Address, runtime: &[u8]) {
+        let code = Bytecode::new_raw(Bytes::copy_from_slice(runtime));
+        let info = AccountInfo::new(U256::ZERO, 1, code.hash_slow(), code);
+        self.db.insert_account_info(address, info);
+    }
+
+    fn set_balance(&mut self, address: Address, wei: U256) {
+        let mut info = self.account(address);
+        info.balance = wei;
+        self.db.insert_account_info(address, info);
+    }
+
+    fn account(&self, address: Address) -> AccountInfo {
+        self.db
+            .accounts
+            .get(&address)
+            .map(|a| a.info.clone())
+            .unwrap_or_default()
+    }
+
+    fn balance(&self, address: Address) -> U256 {
+        self.account(address).balance
+    }
+
+    fn code(&self, address: Address) -> Vec<u8> {
+        self.account(address)
+            .code
+            .map(|c| c.original_bytes().to_vec())
+            .unwrap_or_default()
+    }
+
+    fn transact(
+        &mut self,
+        caller: Address,
+        to: TxKind,
+        data: Vec<u8>,
+        value: U256,
+    ) -> ExecutionResult {
+        let mut evm = Evm::builder()
+            .with_db(&mut self.db)
+            .modify_tx_env(|tx| {
+                tx.caller = caller;
+                tx.transact_to = to;
+                tx.data = data.into();
+                tx.value = value;
+                tx.gas_limit = 10_000_000;
+                tx.gas_price = U256::ZERO;
+            })
+            .build();
+        evm.transact_commit().expect("EVM error")
+    }
+
+    fn call(
+        &mut self,
+        caller: Address,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+    ) -> ExecutionResult {
+        self.transact(caller, TxKind::Call(to), data, value)
+    }
+
+    /// CREATE a contract from init code and return its address
+    fn create(&mut self, caller: Address, init_code: Vec<u8>) -> Address {
+        match self.transact(caller, TxKind::Create, init_code, U256::ZERO) {
+            ExecutionResult::Success {
+                output: Output::Create(_, Some(address)),
+                ..
+            } => address,
+            other => panic!("create failed: {:?}", other),
+        }
+    }
+
+    /// Deploy a proxy for `router` through the factory, as `caller`
+    fn deploy_proxy(&mut self, caller: Address, user_salt: [u8; 32], router: [u8; 20]) -> Address {
+        let mut data = user_salt.to_vec();
+        data.extend_from_slice(&proxy_init_code(&router));
+        let out = success_output(self.call(caller, FACTORY, data, U256::ZERO));
+        Address::from_slice(&out[12..32])
+    }
+}
+
+fn success_output(result: ExecutionResult) -> Bytes {
+    match result {
+        ExecutionResult::Success {
+            output: Output::Call(out),
+            ..
+        } => out,
+        other => panic!("call failed: {:?}", other),
+    }
+}
+
+/// Address the Rust implementation predicts for a proxy deployed by `deployer`
+fn predicted(deployer: Address, caller: Address, user_salt: [u8; 32], router: [u8; 20]) -> Address {
+    let salt = derive_salt(&user_salt, &caller.into_array());
+    Address::from(compute_create2_address(
+        &deployer.into_array(),
+        &salt,
+        &proxy_init_code_hash(&router),
+    ))
+}
+
+fn caller_strategy() -> impl Strategy<Value = Address> {
+    // Keep clear of precompiles and the factory itself
+    any::<[u8; 20]>()
+        .prop_map(Address::from)
+        .prop_filter("reserved address", |a| {
+            *a != FACTORY && a.0[..19].iter().any(|b| *b != 0)
+        })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn prop_create2_matches_evm(
+        user_salt in any::<[u8; 32]>(),
+        caller in caller_strategy(),
+        router in any::<[u8; 20]>(),
+    ) {
+        let mut chain = Chain::new();
+        let deployed = chain.deploy_proxy(caller, user_salt, router);
+
+        prop_assert_eq!(deployed, predicted(FACTORY, caller, user_salt, router));
+        prop_assert_eq!(chain.code(deployed), proxy_init_code(&router)[12..].to_vec());
+    }
+
+    #[test]
+    fn prop_proxy_forwards_value_to_router(
+        user_salt in any::<[u8; 32]>(),
+        caller in caller_strategy(),
+        wei in 1u64..,
+    ) {
+        let router = Address::repeat_byte(0x77);
+        let mut chain = Chain::new();
+        let proxy = chain.deploy_proxy(caller, user_salt, router.into_array());
+
+        chain.set_balance(caller, U256::from(wei));
+        let result = chain.call(caller, proxy, vec![], U256::from(wei));
+
+        prop_assert!(result.is_success());
+        prop_assert_eq!(chain.balance(router), U256::from(wei));
+        prop_assert_eq!(chain.balance(proxy), U256::ZERO);
+    }
+}
+
+#[test]
+fn test_proxy_reverts_when_router_rejects_value() {
+    let router = Address::repeat_byte(0x77);
+    let sender = Address::repeat_byte(0x55);
+    let mut chain = Chain::new();
+    chain.set_code(router, REVERTING_RUNTIME);
+    let proxy = chain.deploy_proxy(sender, [1u8; 32], router.into_array());
+
+    chain.set_balance(sender, U256::from(1_000u64));
+    let result = chain.call(sender, proxy, vec![], U256::from(1_000u64));
+
+    assert!(matches!(result, ExecutionResult::Revert { .. }));
+    assert_eq!(chain.balance(sender), U256::from(1_000u64));
+    assert_eq!(chain.balance(proxy), U256::ZERO);
+}
+
+#[test]
+fn test_value_sent_before_deployment_stays_on_proxy() {
+    let router = Address::repeat_byte(0x77);
+    let sender = Address::repeat_byte(0x55);
+    let user_salt = [7u8; 32];
+    let mut chain = Chain::new();
+
+    // Fund the counterfactual address, then deploy and poke the proxy
+    let address = predicted(FACTORY, sender, user_salt, router.into_array());
+    chain.set_balance(address, U256::from(500u64));
+    let proxy = chain.deploy_proxy(sender, user_salt, router.into_array());
+    assert_eq!(proxy, address);
+
+    let result = chain.call(sender, proxy, vec![], U256::ZERO);
+
+    assert!(result.is_success());
+    assert_eq!(chain.balance(proxy), U256::from(500u64));
+    assert_eq!(chain.balance(router), U256::ZERO);
+}
+
+/// Creation bytecode of the real deployer from the Hardhat artifacts
+fn deployer_bytecode() -> Vec<u8> {
+    let dir = std::env::var("RADHAT_ARTIFACTS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../artifacts/contracts")
+        });
+    let path = dir.join("DeterministicProxyDeployer.sol/DeterministicProxyDeployer.json");
+    let raw = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("{}: {} (run `pnpm compile`)", path.display(), e));
+    let artifact: serde_json::Value = serde_json::from_str(&raw).unwrap();
+    let bytecode = artifact["bytecode"]
+        .as_str()
+        .expect("artifact has no bytecode");
+    hex::decode(bytecode.trim_start_matches("0x")).unwrap()
+}
+
+/// Deploy the real DeterministicProxyDeployer for `router`
+fn deploy_real_deployer(chain: &mut Chain, router: [u8; 20]) -> Address {
+    let mut init_code = deployer_bytecode();
+    init_code.extend_from_slice(&AlloyAddress::from(router).abi_encode());
+    chain.create(Address::repeat_byte(0xde), init_code)
+}
+
+fn to_salts(salts: &[[u8; 32]]) -> Vec<FixedBytes<32>> {
+    salts.iter().map(|s| FixedBytes::from(*s)).collect()
+}
+
+#[test]
+#[ignore = "requires compiled contracts (pnpm compile)"]
+fn test_real_deployer_reports_rust_init_code_hash() {
+    let router = [0x77u8; 20];
+    let mut chain = Chain::new();
+    let deployer = deploy_real_deployer(&mut chain, router);
+    let caller = Address::repeat_byte(0x55);
+
+    let out = success_output(chain.call(
+        caller,
+        deployer,
+        getInitCodeHashCall {}.abi_encode(),
+        U256::ZERO,
+    ));
+    let hash = getInitCodeHashCall::abi_decode_returns(&out, true)
+        .unwrap()
+        ._0;
+    assert_eq!(hash.0, proxy_init_code_hash(&router));
+
+    let out = success_output(chain.call(
+        caller,
+        deployer,
+        FUND_ROUTER_ADDRESSCall {}.abi_encode(),
+        U256::ZERO,
+    ));
+    let configured = FUND_ROUTER_ADDRESSCall::abi_decode_returns(&out, true)
+        .unwrap()
+        ._0;
+    assert_eq!(configured.into_array(), router);
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    #[ignore = "requires compiled contracts (pnpm compile)"]
+    fn prop_real_deployer_matches_rust(
+        salts in prop::collection::vec(any::<[u8; 32]>(), 1..4)
+            .prop_filter("distinct salts", |s| {
+                s.iter().collect::<std::collections::HashSet<_>>().len() == s.len()
+            }),
+        caller in caller_strategy(),
+        router in any::<[u8; 20]>(),
+    ) {
+        let mut chain = Chain::new();
+        let deployer = deploy_real_deployer(&mut chain, router);
+        let expected: Vec<Address> = salts
+            .iter()
+            .map(|s| predicted(deployer, caller, *s, router))
+            .collect();
+
+        let call = calculateDestinationAddressesCall { salts: to_salts(&salts) };
+        let out = success_output(chain.call(caller, deployer, call.abi_encode(), U256::ZERO));
+        let calculated: Vec<Address> = calculateDestinationAddressesCall::abi_decode_returns(&out, true)
+            .unwrap()
+            .out
+            .into_iter()
+            .map(|a| Address::from(a.into_array()))
+            .collect();
+        prop_assert_eq!(&calculated, &expected);
+
+        let call = deployMultipleCall { salts: to_salts(&salts) };
+        let out = success_output(chain.call(caller, deployer, call.abi_encode(), U256::ZERO));
+        let deployed: Vec<Address> = deployMultipleCall::abi_decode_returns(&out, true)
+            .unwrap()
+            .addrs
+            .into_iter()
+            .map(|a| Address::from(a.into_array()))
+            .collect();
+        prop_assert_eq!(&deployed, &expected);
+
+        for address in deployed {
+            prop_assert_eq!(chain.code(address), proxy_init_code(&router)[12..].to_vec());
+        }
+    }
+}
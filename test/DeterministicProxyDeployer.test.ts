@@ -192,6 +192,18 @@ describe("DeterministicProxyDeployer", function () {
       const routerBalance = await ethers.provider.getBalance(await router.getAddress());
       expect(routerBalance).to.equal(amount1 + amount2);
     });
+
+    it("should attribute forwarded ETH to the proxy", async function () {
+      const salt = ethers.id("attributed-forward");
+      const amount = ethers.parseEther("0.2");
+
+      const [proxyAddr] = await deployer.connect(user).calculateDestinationAddresses([salt]);
+      await deployer.connect(user).deployMultiple([salt]);
+
+      await expect(owner.sendTransaction({ to: proxyAddr, value: amount }))
+        .to.emit(router, "EthReceived")
+        .withArgs(proxyAddr, amount);
+    });
   });
 
   describe("Full E2E Flow", function () {
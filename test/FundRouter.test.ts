@@ -103,6 +103,15 @@ describe("FundRouter", function () {
         .to.equal(amount);
     });
 
+    it("should emit EthReceived with the sender", async function () {
+      const amount = ethers.parseEther("1.0");
+      await expect(
+        owner.sendTransaction({ to: await router.getAddress(), value: amount })
+      )
+        .to.emit(router, "EthReceived")
+        .withArgs(owner.address, amount);
+    });
+
     it("should route ETH to treasury", async function () {
       const amount = ethers.parseEther("1.0");
       